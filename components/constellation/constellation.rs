@@ -30,7 +30,7 @@ use log::{Log, LogLevel, LogLevelFilter, LogMetadata, LogRecord};
 use msg::constellation_msg::{FrameId, FrameType, PipelineId};
 use msg::constellation_msg::{Key, KeyModifiers, KeyState};
 use msg::constellation_msg::{PipelineNamespace, PipelineNamespaceId, TraversalDirection};
-use net_traits::{self, IpcSend, ResourceThreads};
+use net_traits::{self, CoreResourceControlThread, IpcSend, ResourceThreads};
 use net_traits::image_cache_thread::ImageCacheThread;
 use net_traits::pub_domains::reg_suffix;
 use net_traits::storage_thread::{StorageThreadMsg, StorageType};
@@ -113,6 +113,9 @@ pub struct Constellation<Message, LTF, STF> {
     /// Channels through which messages can be sent to the resource-related threads.
     private_resource_threads: ResourceThreads,
 
+    /// A channel for the resource thread messages that script may not send.
+    resource_control_thread: CoreResourceControlThread,
+
     /// A channel through which messages can be sent to the image cache thread.
     image_cache_thread: ImageCacheThread,
 
@@ -215,6 +218,8 @@ pub struct InitialConstellationState {
     pub public_resource_threads: ResourceThreads,
     /// A channel to the resource thread.
     pub private_resource_threads: ResourceThreads,
+    /// A channel to the resource thread for the messages that script may not send.
+    pub resource_control_thread: CoreResourceControlThread,
     /// A channel to the time profiler thread.
     pub time_profiler_chan: time::ProfilerChan,
     /// A channel to the memory profiler thread.
@@ -532,6 +537,7 @@ impl<Message, LTF, STF> Constellation<Message, LTF, STF>
                 bluetooth_thread: state.bluetooth_thread,
                 public_resource_threads: state.public_resource_threads,
                 private_resource_threads: state.private_resource_threads,
+                resource_control_thread: state.resource_control_thread,
                 image_cache_thread: state.image_cache_thread,
                 font_cache_thread: state.font_cache_thread,
                 swmanager_chan: None,
@@ -1459,6 +1465,13 @@ impl<Message, LTF, STF> Constellation<Message, LTF, STF>
         assert!(!self.pipelines.contains_key(&new_pipeline_id));
         self.pipelines.insert(new_pipeline_id, pipeline);
 
+        // The resource thread checks the requests of the new document against the origin
+        // of its parent, as there is no response for it to take one from.
+        let msg = net_traits::CoreResourceControlMsg::InheritPipelineOrigin(new_pipeline_id, parent_pipeline_id);
+        if let Err(e) = self.resource_control_thread.send(msg) {
+            warn!("Sending inherit-origin message to resource thread failed ({}).", e);
+        }
+
         self.pending_frames.push(FrameChange {
             frame_id: frame_id,
             old_pipeline_id: None,
@@ -2426,6 +2439,12 @@ impl<Message, LTF, STF> Constellation<Message, LTF, STF>
             self.pending_frames.remove(pending_index);
         }
 
+        // Abort any network requests this pipeline still has in flight.
+        let msg = net_traits::CoreResourceControlMsg::CancelAll(Some(pipeline_id));
+        if let Err(e) = self.resource_control_thread.send(msg) {
+            warn!("Sending cancel-all message to resource thread failed ({}).", e);
+        }
+
         // Inform script, compositor that this pipeline has exited.
         match exit_mode {
             ExitPipelineMode::Normal => pipeline.exit(),
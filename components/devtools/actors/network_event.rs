@@ -34,6 +34,7 @@ struct HttpRequest {
     timeStamp: i64,
     connect_time: u64,
     send_time: u64,
+    retransmit_time: u64,
 }
 
 struct HttpResponse {
@@ -153,7 +154,7 @@ struct GetResponseCookiesReply {
 
 #[derive(Serialize)]
 struct Timings {
-    blocked: u32,
+    blocked: u64,
     dns: u32,
     connect: u64,
     send: u64,
@@ -292,15 +293,17 @@ impl Actor for NetworkEventActor {
             }
             "getEventTimings" => {
                 // TODO: This is a fake timings msg
+                // A first attempt that had to be retried on a new connection held the
+                // request up, so it is shown as time blocked.
                 let timingsObj = Timings {
-                    blocked: 0,
+                    blocked: self.request.retransmit_time,
                     dns: 0,
                     connect: self.request.connect_time,
                     send: self.request.send_time,
                     wait: 0,
                     receive: 0,
                 };
-                let total = timingsObj.connect + timingsObj.send;
+                let total = timingsObj.blocked + timingsObj.connect + timingsObj.send;
                 // TODO: Send the correct values for all these fields.
                 let msg = GetEventTimingsReply {
                     from: self.name(),
@@ -339,6 +342,7 @@ impl NetworkEventActor {
                 timeStamp: time::get_time().sec,
                 send_time: 0,
                 connect_time: 0,
+                retransmit_time: 0,
             },
             response: HttpResponse {
                 headers: None,
@@ -358,6 +362,7 @@ impl NetworkEventActor {
         self.request.timeStamp = request.timeStamp;
         self.request.connect_time = request.connect_time;
         self.request.send_time = request.send_time;
+        self.request.retransmit_time = request.retransmit_time.unwrap_or(0);
         self.is_xhr = request.is_xhr;
     }
 
@@ -469,6 +474,6 @@ impl NetworkEventActor {
     }
 
     pub fn total_time(&self) -> u64 {
-        self.request.connect_time + self.request.send_time
+        self.request.retransmit_time + self.request.connect_time + self.request.send_time
     }
 }
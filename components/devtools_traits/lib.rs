@@ -301,6 +301,9 @@ pub struct HttpRequest {
     pub timeStamp: i64,
     pub connect_time: u64,
     pub send_time: u64,
+    /// The time spent on a first attempt that failed on a stale connection, and was
+    /// retried on a new one, if there was one.
+    pub retransmit_time: Option<u64>,
     pub is_xhr: bool,
 }
 
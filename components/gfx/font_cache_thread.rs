@@ -225,7 +225,11 @@ impl FontCache {
                 fetch_async(request, &self.core_resource_thread, move |response| {
                     match response {
                         FetchResponseMsg::ProcessRequestBody |
-                        FetchResponseMsg::ProcessRequestEOF => (),
+                        FetchResponseMsg::ProcessRequestBodyProgress(..) |
+                        FetchResponseMsg::ProcessRequestEOF |
+                        FetchResponseMsg::ProcessEarlyHints(_) |
+                        FetchResponseMsg::ProcessResponsePart(_) |
+                        FetchResponseMsg::ProcessResponseTrailers(_) => (),
                         FetchResponseMsg::ProcessResponse(meta_result) => {
                             *response_valid.lock().unwrap() = meta_result.is_ok();
                         }
@@ -234,7 +238,7 @@ impl FontCache {
                                 bytes.lock().unwrap().extend(new_bytes.into_iter())
                             }
                         }
-                        FetchResponseMsg::ProcessResponseEOF(response) => {
+                        FetchResponseMsg::ProcessResponseEOF(response, _) => {
                             if response.is_err() || !*response_valid.lock().unwrap() {
                                 let msg = Command::AddWebFont(family_name.clone(), sources.clone(), sender.clone());
                                 channel_to_self.send(msg).unwrap();
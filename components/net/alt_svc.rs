@@ -0,0 +1,257 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The alternative services that origins advertise with the `Alt-Svc` header, as
+//! specified in https://tools.ietf.org/html/rfc7838
+//!
+//! Only `https` origins can have alternatives: the server of an `http` origin can't
+//! prove it speaks for it, so anyone on the path could send its connections elsewhere.
+
+use servo_url::ServoUrl;
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::mem;
+use time;
+use url::percent_encoding::percent_decode;
+
+/// How long an alternative without an `ma` parameter stays usable, in seconds.
+const DEFAULT_MAX_AGE: u64 = 24 * 60 * 60;
+
+/// The protocols that an alternative can be used for. HTTP/2 alternatives are skipped
+/// until there is a client that speaks it.
+const SUPPORTED_PROTOCOLS: &'static [&'static str] = &["http/1.1"];
+
+/// One alternative listed in an `Alt-Svc` header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alternative {
+    /// The ALPN protocol ID, percent-decoded.
+    pub protocol: String,
+    /// The host to connect to, or `None` for the host of the origin itself.
+    pub host: Option<String>,
+    pub port: u16,
+    /// How long the alternative stays usable, in seconds.
+    pub max_age: u64,
+}
+
+/// The value of an `Alt-Svc` header.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AltSvc {
+    /// Forget every alternative of the origin.
+    Clear,
+    /// Use these alternatives, most preferred first, in place of those known before.
+    Alternatives(Vec<Alternative>),
+}
+
+/// Parse the value of an `Alt-Svc` header. Malformed alternatives are skipped, and
+/// `None` is returned if there are none left.
+pub fn parse_alt_svc(value: &str) -> Option<AltSvc> {
+    if value.trim() == "clear" {
+        return Some(AltSvc::Clear);
+    }
+    let alternatives: Vec<_> = split_unquoted(value, ',').into_iter().filter_map(parse_alternative).collect();
+    if alternatives.is_empty() {
+        None
+    } else {
+        Some(AltSvc::Alternatives(alternatives))
+    }
+}
+
+/// Parse `protocol-id="[host]:port"; ma=max-age; ...`.
+fn parse_alternative(value: &str) -> Option<Alternative> {
+    let mut parts = split_unquoted(value, ';').into_iter();
+    let (protocol, authority) = match parts.next().and_then(split_parameter) {
+        Some(service) => service,
+        None => return None,
+    };
+    let authority = match unquote(authority) {
+        Some(authority) => authority,
+        None => return None,
+    };
+    let colon = match authority.rfind(':') {
+        Some(colon) => colon,
+        None => return None,
+    };
+    let port = match authority[colon + 1..].parse() {
+        Ok(port) => port,
+        Err(_) => return None,
+    };
+    let host = &authority[..colon];
+    let mut max_age = DEFAULT_MAX_AGE;
+    for (name, value) in parts.filter_map(split_parameter) {
+        if name.eq_ignore_ascii_case("ma") {
+            if let Some(value) = unquote(value).and_then(|value| value.parse().ok()) {
+                max_age = value;
+            }
+        }
+    }
+    Some(Alternative {
+        protocol: percent_decode(protocol.as_bytes()).decode_utf8_lossy().into_owned(),
+        host: if host.is_empty() { None } else { Some(host.to_owned()) },
+        port: port,
+        max_age: max_age,
+    })
+}
+
+/// Split `name=value` into its trimmed halves.
+fn split_parameter(parameter: &str) -> Option<(&str, &str)> {
+    parameter.find('=').map(|equals| (parameter[..equals].trim(), parameter[equals + 1..].trim()))
+}
+
+/// The contents of a quoted string, or the value itself if it isn't quoted.
+fn unquote(value: &str) -> Option<String> {
+    if !value.starts_with('"') {
+        return Some(value.to_owned());
+    }
+    if value.len() < 2 || !value.ends_with('"') {
+        return None;
+    }
+    let mut unquoted = String::new();
+    let mut chars = value[1..value.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.push(match chars.next() {
+                Some(c) => c,
+                None => return None,
+            }),
+            c => unquoted.push(c),
+        }
+    }
+    Some(unquoted)
+}
+
+/// Split `value` at every `separator` that isn't inside a quoted string.
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if quoted && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            parts.push(&value[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&value[start..]);
+    parts.into_iter().map(str::trim).filter(|part| !part.is_empty()).collect()
+}
+
+#[derive(Clone, Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub struct AltSvcEntry {
+    pub host: String,
+    pub port: u16,
+    /// The time, in seconds since the epoch, after which the alternative is forgotten.
+    pub expires: u64,
+}
+
+impl AltSvcEntry {
+    fn is_expired(&self) -> bool {
+        time::get_time().sec as u64 >= self.expires
+    }
+}
+
+/// The alternatives that each origin has advertised and that can be used, most
+/// preferred first.
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+pub struct AltSvcCache {
+    origins: HashMap<String, Vec<AltSvcEntry>>,
+}
+
+fn origin_key(url: &ServoUrl) -> String {
+    url.origin().ascii_serialization()
+}
+
+impl AltSvcCache {
+    pub fn new() -> AltSvcCache {
+        AltSvcCache {
+            origins: HashMap::new(),
+        }
+    }
+
+    /// Act on the `Alt-Svc` header of a response to a request for `url`, unless it
+    /// isn't an `https` URL.
+    pub fn update(&mut self, url: &ServoUrl, alt_svc: AltSvc) {
+        if url.scheme() != "https" {
+            return;
+        }
+        let key = origin_key(url);
+        let alternatives = match alt_svc {
+            AltSvc::Clear => {
+                self.origins.remove(&key);
+                return;
+            }
+            AltSvc::Alternatives(alternatives) => alternatives,
+        };
+        let (origin_host, origin_port) = match (url.host_str(), url.port_or_known_default()) {
+            (Some(host), Some(port)) => (host, port),
+            _ => return,
+        };
+        let now = time::get_time().sec as u64;
+        let entries: Vec<_> = alternatives.into_iter().filter(|alternative| {
+            SUPPORTED_PROTOCOLS.contains(&&*alternative.protocol)
+        }).map(|alternative| AltSvcEntry {
+            host: alternative.host.unwrap_or_else(|| origin_host.to_owned()),
+            port: alternative.port,
+            expires: now.saturating_add(alternative.max_age),
+        }).filter(|entry| {
+            // An alternative that is the origin itself gains nothing.
+            !(entry.host.eq_ignore_ascii_case(origin_host) && entry.port == origin_port)
+        }).collect();
+        if entries.is_empty() {
+            self.origins.remove(&key);
+        } else {
+            self.origins.insert(key, entries);
+        }
+    }
+
+    /// The host and port to connect to in place of those of `url`, if its origin has an
+    /// alternative that hasn't expired.
+    pub fn lookup(&self, url: &ServoUrl) -> Option<(String, u16)> {
+        if url.scheme() != "https" {
+            return None;
+        }
+        self.origins.get(&origin_key(url)).and_then(|entries| {
+            entries.iter().find(|entry| !entry.is_expired()).map(|entry| (entry.host.clone(), entry.port))
+        })
+    }
+
+    /// Forget an alternative of the origin of `url` that couldn't be used, along with
+    /// every alternative of it that has expired.
+    pub fn remove(&mut self, url: &ServoUrl, host: &str, port: u16) {
+        let key = origin_key(url);
+        let now_empty = match self.origins.get_mut(&key) {
+            Some(entries) => {
+                entries.retain(|entry| !entry.is_expired() && !(entry.host == host && entry.port == port));
+                entries.is_empty()
+            }
+            None => false,
+        };
+        if now_empty {
+            self.origins.remove(&key);
+        }
+    }
+
+    /// Forget the alternatives of every origin on `host`.
+    pub fn clear_host(&mut self, host: &str) {
+        let keys: Vec<_> = self.origins.keys().filter(|origin| {
+            ServoUrl::parse(origin).ok().map_or(false, |origin| origin.host_str() == Some(host))
+        }).cloned().collect();
+        for key in keys {
+            self.origins.remove(&key);
+        }
+    }
+
+    /// An estimate of the heap memory held by the cache, for memory reports.
+    pub fn estimated_size(&self) -> usize {
+        self.origins.iter().map(|(origin, entries)| {
+            origin.capacity() + entries.capacity() * mem::size_of::<AltSvcEntry>() +
+            entries.iter().map(|entry| entry.host.capacity()).sum::<usize>()
+        }).sum()
+    }
+}
@@ -0,0 +1,144 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The certificates that the user chose to accept from servers whose certificate
+//! doesn't verify, such as self-signed ones on an intranet or a test server.
+
+use hsts::HstsList;
+use lock_recovery::read_lock;
+use net_traits::CertificateException;
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, RwLock};
+
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+struct ExceptionEntry {
+    host: String,
+    port: u16,
+    /// The SHA-256 fingerprint of the certificate the server is allowed to present.
+    fingerprint: Vec<u8>,
+    permanent: bool,
+}
+
+/// The certificate exceptions of a resource group, one per host and port.
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+pub struct CertificateExceptions {
+    exceptions: HashMap<String, ExceptionEntry>,
+}
+
+fn server_key(host: &str, port: u16) -> String {
+    format!("{}:{}", host.to_ascii_lowercase(), port)
+}
+
+impl CertificateExceptions {
+    pub fn new() -> CertificateExceptions {
+        CertificateExceptions {
+            exceptions: HashMap::new(),
+        }
+    }
+
+    /// Accept the certificate of `exception` from its server, in place of any certificate
+    /// accepted from it before.
+    pub fn add(&mut self, exception: CertificateException) {
+        let entry = ExceptionEntry {
+            host: exception.host.to_ascii_lowercase(),
+            port: exception.port,
+            fingerprint: exception.cert_fingerprint,
+            permanent: exception.permanent,
+        };
+        self.exceptions.insert(server_key(&entry.host, entry.port), entry);
+    }
+
+    /// Forget the exception for the server at `host` and `port`, returning whether
+    /// there was one.
+    pub fn remove(&mut self, host: &str, port: u16) -> bool {
+        self.exceptions.remove(&server_key(host, port)).is_some()
+    }
+
+    /// Every exception, ordered by host and port.
+    pub fn list(&self) -> Vec<CertificateException> {
+        let mut exceptions: Vec<_> = self.exceptions.values().map(|entry| CertificateException {
+            host: entry.host.clone(),
+            port: entry.port,
+            cert_fingerprint: entry.fingerprint.clone(),
+            permanent: entry.permanent,
+        }).collect();
+        exceptions.sort_by(|a, b| (&a.host, a.port).cmp(&(&b.host, b.port)));
+        exceptions
+    }
+
+    /// The fingerprint of the certificate accepted from the server at `host` and `port`.
+    pub fn fingerprint_for(&self, host: &str, port: u16) -> Option<&[u8]> {
+        self.exceptions.get(&server_key(host, port)).map(|entry| &*entry.fingerprint)
+    }
+
+    /// The exceptions that are kept across restarts.
+    pub fn permanent(&self) -> CertificateExceptions {
+        CertificateExceptions {
+            exceptions: self.exceptions.iter().filter(|&(_, entry)| entry.permanent).map(|(key, entry)| {
+                (key.clone(), entry.clone())
+            }).collect(),
+        }
+    }
+
+    /// An estimate of the heap memory held by the exceptions, for memory reports.
+    pub fn estimated_size(&self) -> usize {
+        self.exceptions.iter().map(|(key, entry)| {
+            key.capacity() + mem::size_of::<ExceptionEntry>() +
+            entry.host.capacity() + entry.fingerprint.capacity()
+        }).sum()
+    }
+}
+
+lazy_static! {
+    /// Servo's HSTS preload list, which a group's own list may leave out.
+    static ref PRELOADED_HSTS_LIST: HstsList = HstsList::from_servo_preload();
+}
+
+/// The certificate exceptions a connector honours. They never apply to the hosts on
+/// the HSTS list, preloaded or not, whose certificates must always verify.
+#[derive(Clone)]
+pub struct CertificateExceptionCheck {
+    exceptions: Arc<RwLock<CertificateExceptions>>,
+    hsts_list: Arc<RwLock<HstsList>>,
+    /// The public group's HSTS list, for a private group that shares it.
+    shared_hsts_list: Option<Arc<RwLock<HstsList>>>,
+}
+
+impl CertificateExceptionCheck {
+    pub fn new(exceptions: Arc<RwLock<CertificateExceptions>>, hsts_list: Arc<RwLock<HstsList>>)
+               -> CertificateExceptionCheck {
+        CertificateExceptionCheck {
+            exceptions: exceptions,
+            hsts_list: hsts_list,
+            shared_hsts_list: None,
+        }
+    }
+
+    /// Also refuse the exceptions for the hosts on `shared_hsts_list`.
+    pub fn with_shared_hsts_list(self, shared_hsts_list: Option<Arc<RwLock<HstsList>>>)
+                                 -> CertificateExceptionCheck {
+        CertificateExceptionCheck {
+            shared_hsts_list: shared_hsts_list,
+            .. self
+        }
+    }
+
+    fn is_host_secure(&self, host: &str) -> bool {
+        PRELOADED_HSTS_LIST.is_host_secure(host) ||
+            read_lock(&self.hsts_list, "HSTS list").is_host_secure(host) ||
+            self.shared_hsts_list.as_ref().map_or(false, |list| read_lock(list, "HSTS list").is_host_secure(host))
+    }
+
+    /// The fingerprint of the certificate that the server at `host` and `port` may
+    /// present even though it doesn't verify, if any.
+    pub fn fingerprint_for(&self, host: &str, port: u16) -> Option<Vec<u8>> {
+        if self.is_host_secure(host) {
+            return None;
+        }
+        let exceptions = read_lock(&self.exceptions, "certificate exceptions");
+        exceptions.fingerprint_for(host, port).map(|fingerprint| fingerprint.to_vec())
+    }
+}
@@ -0,0 +1,350 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Scheduling of fetches onto the fetch workers.
+//!
+//! Fetches waiting for a worker are started in priority order. On top of that,
+//! the number of fetches talking to a single origin at once is limited, and so is
+//! the number of connections open to a single host, whichever of its origins they
+//! are for; a fetch only starts when both have room. Fetches beyond the limits
+//! wait in a queue per origin, ordered by priority and then by arrival. When a
+//! running fetch finishes, the worker that ran it goes on to run the next queued
+//! fetch that now fits, from the same origin if it can, or from another origin on
+//! the same host, so a queued fetch never holds up a worker while it waits.
+//! Origins with an HTTP/2 session are limited by the number of streams their
+//! servers allow at once instead, and as their fetches share the session's one
+//! connection, they don't count towards the host's limit.
+
+use net_traits::request::RequestPriority;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use threadpool::ThreadPool;
+use util::prefs::PREFS;
+
+/// A `FnOnce` that can be called through a box. The argument says whether
+/// the job was cancelled while queued, rather than being run.
+pub trait FetchJob: Send {
+    fn call_box(self: Box<Self>, cancelled: bool);
+}
+
+impl<F: FnOnce(bool) + Send> FetchJob for F {
+    fn call_box(self: Box<F>, cancelled: bool) {
+        (*self)(cancelled)
+    }
+}
+
+/// The connection slots held by a running fetch, to be given back to
+/// `ConnectionLimiter::finished` once it is done.
+pub struct ConnectionSlots {
+    origin: String,
+    /// The host whose connection slot the fetch holds, unless it is multiplexed
+    /// over an HTTP/2 session.
+    host: Option<String>,
+}
+
+/// A fetch that holds its connection slots and can run now.
+pub type StartedFetch = (ConnectionSlots, Box<FetchJob>);
+
+struct QueuedFetch {
+    fetch_id: u32,
+    priority: RequestPriority,
+    job: Box<FetchJob>,
+}
+
+struct OriginState {
+    host: String,
+    /// The number of fetches to the origin that may run at once.
+    max: usize,
+    /// Whether the origin's fetches are multiplexed over an HTTP/2 session.
+    multiplexed: bool,
+    active: usize,
+    queued: VecDeque<QueuedFetch>,
+}
+
+impl OriginState {
+    /// The index of the most urgent queued fetch, the oldest one among equals.
+    fn next_index(&self) -> Option<usize> {
+        self.queued.iter()
+                   .enumerate()
+                   .min_by_key(|&(index, fetch)| (fetch.priority, index))
+                   .map(|(index, _)| index)
+    }
+}
+
+/// The number of concurrent fetches per origin, taken from the
+/// `network.http.max-connections-per-origin` pref.
+fn max_connections_per_origin() -> usize {
+    match PREFS.get("network.http.max-connections-per-origin").as_u64() {
+        Some(max) if max > 0 => max as usize,
+        _ => 6,
+    }
+}
+
+/// The number of concurrent connections per host, taken from the
+/// `network.http.max-connections-per-host` pref.
+fn max_connections_per_host() -> usize {
+    match PREFS.get("network.http.max-connections-per-host").as_u64() {
+        Some(max) if max > 0 => max as usize,
+        _ => 6,
+    }
+}
+
+pub struct ConnectionLimiter {
+    max_per_origin: usize,
+    max_per_host: usize,
+    origins: HashMap<String, OriginState>,
+    /// The number of running fetches holding a connection slot for each host.
+    hosts: HashMap<String, usize>,
+}
+
+impl ConnectionLimiter {
+    pub fn new() -> ConnectionLimiter {
+        ConnectionLimiter {
+            max_per_origin: max_connections_per_origin(),
+            max_per_host: max_connections_per_host(),
+            origins: HashMap::new(),
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// Claim a connection slot for `origin` and for `host`, the host it is on, and
+    /// return `job` to be run now, or queue it behind the fetches already talking to
+    /// that origin. An origin spoken to over HTTP/2 takes as many requests at once as
+    /// its server allows streams on the one connection, `max_streams`.
+    pub fn start_or_queue(&mut self,
+                          origin: &str,
+                          host: &str,
+                          fetch_id: u32,
+                          priority: RequestPriority,
+                          max_streams: Option<usize>,
+                          job: Box<FetchJob>)
+                          -> Option<StartedFetch> {
+        let max_per_origin = max_streams.unwrap_or(self.max_per_origin);
+        {
+            let state = self.origins.entry(origin.to_owned()).or_insert_with(|| OriginState {
+                host: host.to_owned(),
+                max: max_per_origin,
+                multiplexed: false,
+                active: 0,
+                queued: VecDeque::new(),
+            });
+            state.max = max_per_origin;
+            state.multiplexed = max_streams.is_some();
+        }
+        if self.has_room(origin) {
+            Some(self.start(origin, job))
+        } else {
+            self.origins.get_mut(origin).unwrap().queued.push_back(QueuedFetch {
+                fetch_id: fetch_id,
+                priority: priority,
+                job: job,
+            });
+            None
+        }
+    }
+
+    /// Called when the fetch holding `slots` completes. Hands them on to the next
+    /// queued fetch that fits, for the same origin if there is one, or else for
+    /// another origin on the same host.
+    pub fn finished(&mut self, slots: ConnectionSlots) -> Option<StartedFetch> {
+        if let Some(state) = self.origins.get_mut(&slots.origin) {
+            state.active -= 1;
+        }
+        if let Some(ref host) = slots.host {
+            if let Some(active) = self.hosts.get_mut(host) {
+                *active -= 1;
+            }
+        }
+        let next = self.start_next(&slots.origin).or_else(|| {
+            let host = match self.origins.get(&slots.origin) {
+                Some(state) => state.host.clone(),
+                None => return None,
+            };
+            // The most urgent fetch that fits among the other origins on the host,
+            // the one that asked first among equals.
+            let next_origin = self.origins.iter()
+                                          .filter(|&(origin, state)| *origin != slots.origin && state.host == host)
+                                          .filter(|&(origin, _)| self.has_room(origin))
+                                          .filter_map(|(origin, state)| {
+                                              state.next_index().map(|index| (&state.queued[index], origin))
+                                          })
+                                          .min_by_key(|&(queued, _)| (queued.priority, queued.fetch_id))
+                                          .map(|(_, origin)| origin.clone());
+            next_origin.and_then(|origin| self.start_next(&origin))
+        });
+        self.forget_if_idle(&slots.origin);
+        next
+    }
+
+    /// Take a fetch out of the queue, if it hasn't started yet.
+    pub fn remove_queued(&mut self, fetch_id: u32) -> Option<Box<FetchJob>> {
+        let mut removed = None;
+        for (origin, state) in &mut self.origins {
+            if let Some(index) = state.queued.iter().position(|fetch| fetch.fetch_id == fetch_id) {
+                removed = state.queued.remove(index).map(|fetch| (origin.clone(), fetch.job));
+                break;
+            }
+        }
+        removed.map(|(origin, job)| {
+            self.forget_if_idle(&origin);
+            job
+        })
+    }
+
+    /// The number of running and queued fetches for every origin that has any.
+    pub fn stats(&self) -> (HashMap<String, usize>, HashMap<String, usize>) {
+        let active = self.origins.iter().map(|(origin, state)| (origin.clone(), state.active)).collect();
+        let queued = self.origins.iter().map(|(origin, state)| (origin.clone(), state.queued.len())).collect();
+        (active, queued)
+    }
+
+    /// The number of running fetches holding a connection slot for every host that has any.
+    pub fn active_per_host(&self) -> HashMap<String, usize> {
+        self.hosts.clone()
+    }
+
+    /// Whether one more fetch to `origin` fits within the limits for it and its host.
+    fn has_room(&self, origin: &str) -> bool {
+        let state = &self.origins[origin];
+        state.active < state.max &&
+            (state.multiplexed || self.hosts.get(&state.host).cloned().unwrap_or(0) < self.max_per_host)
+    }
+
+    /// Take the slots for a fetch to `origin` that has room.
+    fn start(&mut self, origin: &str, job: Box<FetchJob>) -> StartedFetch {
+        let state = self.origins.get_mut(origin).unwrap();
+        state.active += 1;
+        let host = if state.multiplexed {
+            None
+        } else {
+            *self.hosts.entry(state.host.clone()).or_insert(0) += 1;
+            Some(state.host.clone())
+        };
+        (ConnectionSlots { origin: origin.to_owned(), host: host }, job)
+    }
+
+    /// Start the next fetch queued for `origin`, if there is one and it has room.
+    fn start_next(&mut self, origin: &str) -> Option<StartedFetch> {
+        let index = match self.origins.get(origin).and_then(OriginState::next_index) {
+            Some(index) => index,
+            None => return None,
+        };
+        if !self.has_room(origin) {
+            return None;
+        }
+        let queued = self.origins.get_mut(origin).unwrap().queued.remove(index).unwrap();
+        Some(self.start(origin, queued.job))
+    }
+
+    /// Drop the accounting for `origin`, and for its host, once nothing runs or waits there.
+    fn forget_if_idle(&mut self, origin: &str) {
+        let host = match self.origins.get(origin) {
+            Some(state) if state.active == 0 && state.queued.is_empty() => state.host.clone(),
+            _ => return,
+        };
+        self.origins.remove(origin);
+        if self.hosts.get(&host) == Some(&0) {
+            self.hosts.remove(&host);
+        }
+    }
+}
+
+/// Run `fetch`, then every queued fetch its slots are handed on to, on the current thread.
+pub fn run_started_fetches(limiter: Arc<Mutex<ConnectionLimiter>>, fetch: StartedFetch) {
+    let mut next = Some(fetch);
+    while let Some((slots, job)) = next {
+        job.call_box(false);
+        next = limiter.lock().unwrap().finished(slots);
+    }
+}
+
+struct PendingFetch {
+    priority: RequestPriority,
+    sequence: u64,
+    job: Box<FetchJob>,
+}
+
+// The heap pops its greatest element, which should be the most urgent fetch,
+// and the oldest one among equals.
+impl Ord for PendingFetch {
+    fn cmp(&self, other: &PendingFetch) -> Ordering {
+        (self.priority, self.sequence).cmp(&(other.priority, other.sequence)).reverse()
+    }
+}
+
+impl PartialOrd for PendingFetch {
+    fn partial_cmp(&self, other: &PendingFetch) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Eq for PendingFetch {}
+impl PartialEq for PendingFetch {
+    fn eq(&self, other: &PendingFetch) -> bool {
+        self.sequence == other.sequence
+    }
+}
+
+/// Fetches waiting for a free fetch worker.
+pub struct PendingFetches {
+    heap: BinaryHeap<PendingFetch>,
+    next_sequence: u64,
+}
+
+impl PendingFetches {
+    pub fn new() -> PendingFetches {
+        PendingFetches {
+            heap: BinaryHeap::new(),
+            next_sequence: 0,
+        }
+    }
+
+    pub fn push(&mut self, priority: RequestPriority, job: Box<FetchJob>) {
+        self.heap.push(PendingFetch {
+            priority: priority,
+            sequence: self.next_sequence,
+            job: job,
+        });
+        self.next_sequence += 1;
+    }
+
+    /// Take the most urgent pending fetch.
+    pub fn pop(&mut self) -> Option<Box<FetchJob>> {
+        self.heap.pop().map(|fetch| fetch.job)
+    }
+}
+
+/// Runs fetches on the fetch workers, most urgent first. Clones share the same workers,
+/// so that a running fetch can start others in the background.
+#[derive(Clone)]
+pub struct FetchScheduler {
+    pool: Arc<Mutex<ThreadPool>>,
+    /// Fetches waiting for a worker, most urgent first.
+    pending: Arc<Mutex<PendingFetches>>,
+}
+
+impl FetchScheduler {
+    pub fn new(workers: usize) -> FetchScheduler {
+        FetchScheduler {
+            pool: Arc::new(Mutex::new(ThreadPool::new_with_name("FetchWorker".to_owned(), workers))),
+            pending: Arc::new(Mutex::new(PendingFetches::new())),
+        }
+    }
+
+    /// Queue `job` to run once every more urgent fetch has been started.
+    ///
+    /// Every job handed to the pool just runs whichever pending fetch is most urgent
+    /// at the time a worker picks it up, so the pool's own FIFO order doesn't matter.
+    pub fn schedule(&self, priority: RequestPriority, job: Box<FetchJob>) {
+        self.pending.lock().unwrap().push(priority, job);
+        let pending = self.pending.clone();
+        self.pool.lock().unwrap().execute(move || {
+            let job = pending.lock().unwrap().pop();
+            if let Some(job) = job {
+                job.call_box(false);
+            }
+        });
+    }
+}
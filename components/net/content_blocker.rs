@@ -2,16 +2,88 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use content_blocker_parser::{RuleList, parse_list};
+use content_blocker_parser::{Reaction, Request as CBRequest, ResourceType, RuleList};
+use content_blocker_parser::{parse_list, process_rules_for_request};
+use net_traits::request::Destination;
+use rustc_serialize::json::Json;
+use servo_url::ServoUrl;
 use std::str;
 use std::sync::Arc;
 use util::resource_files::read_resource_file;
 
 lazy_static! {
-    pub static ref BLOCKED_CONTENT_RULES: Arc<Option<RuleList>> = Arc::new(create_rule_list());
+    pub static ref BLOCKED_CONTENT_RULES: Arc<Option<BlockedContentRules>> = Arc::new(create_rule_list());
 }
 
-fn create_rule_list() -> Option<RuleList> {
+/// The content blocker rules, kept one by one as well as all together so that a blocked
+/// request can be told which rule blocked it.
+pub struct BlockedContentRules {
+    all: RuleList,
+    /// Each rule, named by its `url-filter`, which is what users need to whitelist it.
+    rules: Vec<(String, RuleList)>,
+}
+
+impl BlockedContentRules {
+    pub fn parse(list: &str) -> Option<BlockedContentRules> {
+        let all = match parse_list(list) {
+            Ok(all) => all,
+            Err(_) => return None,
+        };
+        let rules = match Json::from_str(list) {
+            Ok(Json::Array(rules)) => rules,
+            _ => return None,
+        };
+        let rules = rules.iter().enumerate().filter_map(|(index, rule)| {
+            let name = match rule.find_path(&["trigger", "url-filter"]).and_then(|filter| filter.as_string()) {
+                Some(filter) => filter.to_owned(),
+                None => format!("rule {}", index),
+            };
+            parse_list(&format!("[{}]", rule)).ok().map(|list| (name, list))
+        }).collect();
+        Some(BlockedContentRules {
+            all: all,
+            rules: rules,
+        })
+    }
+
+    /// The name of the rule blocking a fetch of `url` for `destination`, if it is blocked.
+    pub fn blocking_rule(&self, url: &ServoUrl, destination: Destination) -> Option<String> {
+        let url = match url.as_url() {
+            Some(url) => url,
+            None => return None,
+        };
+        let request = CBRequest {
+            url: url,
+            resource_type: to_resource_type(destination),
+        };
+        let is_blocked = |rules: &RuleList| {
+            process_rules_for_request(rules, &request).iter().any(|reaction| match *reaction {
+                Reaction::Block => true,
+                _ => false,
+            })
+        };
+        // Most requests aren't blocked, so only look for the culprit once there is one.
+        if !is_blocked(&self.all) {
+            return None;
+        }
+        self.rules.iter().find(|&&(_, ref rule)| is_blocked(rule)).map(|&(ref name, _)| name.clone())
+    }
+}
+
+fn to_resource_type(destination: Destination) -> ResourceType {
+    match destination {
+        Destination::Document => ResourceType::Document,
+        Destination::Image => ResourceType::Image,
+        Destination::Media => ResourceType::Media,
+        Destination::Style | Destination::XSLT => ResourceType::StyleSheet,
+        Destination::Script | Destination::Worker | Destination::SharedWorker |
+        Destination::ServiceWorker => ResourceType::Script,
+        Destination::Font => ResourceType::Font,
+        _ => ResourceType::Raw,
+    }
+}
+
+fn create_rule_list() -> Option<BlockedContentRules> {
     let contents = match read_resource_file("blocked-content.json") {
         Ok(c) => c,
         Err(_) => return None,
@@ -22,10 +94,5 @@ fn create_rule_list() -> Option<RuleList> {
         Err(_) => return None,
     };
 
-    let list = match parse_list(&str_contents) {
-        Ok(l) => l,
-        Err(_) => return None,
-    };
-
-    Some(list)
+    BlockedContentRules::parse(&str_contents)
 }
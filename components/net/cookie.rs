@@ -6,11 +6,11 @@
 //! http://tools.ietf.org/html/rfc6265
 
 use cookie_rs;
-use net_traits::CookieSource;
-use net_traits::pub_domains::is_pub_domain;
+use net_traits::{CookieAcceptPolicy, CookieDetails, CookieSource, SameSiteContext};
+use net_traits::pub_domains::{canonical_host, is_ip_host, is_pub_domain, reg_suffix};
 use servo_url::ServoUrl;
+use std::ascii::AsciiExt;
 use std::borrow::ToOwned;
-use std::net::{Ipv4Addr, Ipv6Addr};
 use time::{Tm, now, at, Duration};
 
 /// A stored cookie that wraps the definition in cookie-rs. This is used to implement
@@ -26,6 +26,8 @@ pub struct Cookie {
     pub expiry_time: Option<Tm>,
 }
 
+pub use net_traits::SameSite;
+
 impl Cookie {
     /// http://tools.ietf.org/html/rfc6265#section-5.3
     pub fn new_wrapped(mut cookie: cookie_rs::Cookie, request: &ServoUrl, source: CookieSource)
@@ -42,7 +44,10 @@ impl Cookie {
         let url_host = request.host_str().unwrap_or("").to_owned();
 
         // Step 4
-        let mut domain = cookie.domain.clone().unwrap_or("".to_owned());
+        // The leading dot is ignored, as per http://tools.ietf.org/html/rfc6265#section-5.2.3,
+        // and the domain is made canonical like the URL's host it is compared to.
+        let domain = cookie.domain.clone().unwrap_or("".to_owned());
+        let mut domain = canonical_host(if domain.starts_with('.') { &domain[1..] } else { &domain });
 
         // Step 5
         if is_pub_domain(&domain) {
@@ -66,6 +71,9 @@ impl Cookie {
             true
         };
 
+        // The `Path` attribute as it was given, before a default is filled in.
+        let has_root_path = cookie.path.as_ref().map_or(false, |path| path == "/");
+
         // Step 7
         let mut path = cookie.path.unwrap_or("".to_owned());
         if path.chars().next() != Some('/') {
@@ -79,6 +87,16 @@ impl Cookie {
             return None;
         }
 
+        // https://tools.ietf.org/html/draft-ietf-httpbis-cookie-prefixes-00#section-3
+        // Cookies whose name says they are secure, or locked to a host, must be.
+        let secure = cookie.secure && request.scheme() == "https";
+        if cookie.name.starts_with("__Secure-") && !secure {
+            return None;
+        }
+        if cookie.name.starts_with("__Host-") && !(secure && host_only && has_root_path) {
+            return None;
+        }
+
         Some(Cookie {
             cookie: cookie,
             host_only: host_only,
@@ -137,8 +155,7 @@ impl Cookie {
         }
         if string.ends_with(domain_string) &&
             string.as_bytes()[string.len()-domain_string.len()-1] == b'.' &&
-            string.parse::<Ipv4Addr>().is_err() &&
-            string.parse::<Ipv6Addr>().is_err() {
+            !is_ip_host(string) {
             return true;
         }
         false
@@ -174,4 +191,72 @@ impl Cookie {
 
         true
     }
+
+    /// The `SameSite` attribute of this cookie. cookie-rs keeps attributes it doesn't
+    /// know about in `custom`; a missing or unrecognised value is treated as `Lax`.
+    pub fn same_site(&self) -> SameSite {
+        let value = self.cookie.custom.iter()
+                                      .find(|&(name, _)| name.eq_ignore_ascii_case("samesite"))
+                                      .map(|(_, value)| value.as_str());
+        match value {
+            Some(v) if v.eq_ignore_ascii_case("strict") => SameSite::Strict,
+            Some(v) if v.eq_ignore_ascii_case("none") => SameSite::None,
+            _ => SameSite::Lax,
+        }
+    }
+
+    /// This cookie, with the attributes derived from it.
+    pub fn details(&self) -> CookieDetails {
+        CookieDetails {
+            cookie: self.cookie.clone(),
+            expires_at: self.expiry_time.map(|time| time.to_timespec().sec),
+            same_site: self.same_site(),
+            session: !self.persistent,
+            host_only: self.host_only,
+            created_at: self.creation_time.to_timespec().sec,
+            last_accessed_at: self.last_access.to_timespec().sec,
+        }
+    }
+
+    /// https://tools.ietf.org/html/draft-ietf-httpbis-rfc6265bis-02#section-5.4 step 1
+    pub fn same_site_allows(&self, context: SameSiteContext) -> bool {
+        match (self.same_site(), context) {
+            (_, SameSiteContext::SameSite) => true,
+            (SameSite::None, _) => true,
+            (SameSite::Lax, SameSiteContext::CrossSiteTopLevelNavigation) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether two hosts share a registrable domain.
+    pub fn is_same_site(host: &str, other_host: &str) -> bool {
+        reg_suffix(host).eq_ignore_ascii_case(reg_suffix(other_host))
+    }
+
+    /// An estimate of the heap memory held by this cookie, for memory reports.
+    pub fn estimated_size(&self) -> usize {
+        let cookie = &self.cookie;
+        cookie.name.capacity() + cookie.value.capacity() +
+        cookie.domain.as_ref().map_or(0, String::capacity) +
+        cookie.path.as_ref().map_or(0, String::capacity) +
+        cookie.custom.iter().map(|(name, value)| name.capacity() + value.capacity()).sum::<usize>()
+    }
+
+    /// Whether a cookie set by `url` is third-party while the user is on `first_party`.
+    pub fn is_third_party(url: &ServoUrl, first_party: &ServoUrl) -> bool {
+        match (url.host_str(), first_party.host_str()) {
+            (Some(host), Some(first_party_host)) => !Cookie::is_same_site(host, first_party_host),
+            _ => url.origin() != first_party.origin(),
+        }
+    }
+
+    /// Whether `policy` lets a cookie be stored, given whether it is known to be third-party.
+    pub fn policy_allows(policy: CookieAcceptPolicy, third_party: Option<bool>) -> bool {
+        match policy {
+            CookieAcceptPolicy::All => true,
+            CookieAcceptPolicy::NoneAtAll => false,
+            CookieAcceptPolicy::FromOriginatingSiteOnly => third_party == Some(false),
+            CookieAcceptPolicy::NoThirdParty => third_party != Some(true),
+        }
+    }
 }
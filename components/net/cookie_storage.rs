@@ -7,11 +7,12 @@
 
 use cookie::Cookie;
 use cookie_rs;
-use net_traits::CookieSource;
+use net_traits::{CookieDetails, CookieSource, SameSiteContext};
 use net_traits::pub_domains::reg_suffix;
 use servo_url::ServoUrl;
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::mem;
 use time::Tm;
 
 extern crate time;
@@ -32,6 +33,19 @@ impl CookieStorage {
         }
     }
 
+    /// An estimate of the heap memory held by the stored cookies, for memory reports.
+    pub fn estimated_size(&self) -> usize {
+        self.cookies_map.iter().map(|(host, cookies)| {
+            host.capacity() + cookies.capacity() * mem::size_of::<Cookie>() +
+            cookies.iter().map(Cookie::estimated_size).sum::<usize>()
+        }).sum()
+    }
+
+    /// Remove every cookie of the registrable domain of `host`.
+    pub fn clear_host(&mut self, host: &str) {
+        self.cookies_map.remove(&reg_host(host));
+    }
+
     // http://tools.ietf.org/html/rfc6265#section-5.3
     pub fn remove(&mut self, cookie: &Cookie, source: CookieSource) -> Result<Option<Cookie>, ()> {
         let domain = reg_host(cookie.cookie.domain.as_ref().unwrap_or(&"".to_string()));
@@ -61,11 +75,12 @@ impl CookieStorage {
     }
 
     // http://tools.ietf.org/html/rfc6265#section-5.3
-    pub fn push(&mut self, mut cookie: Cookie, source: CookieSource) {
+    /// Returns the number of unexpired cookies that were evicted to make room for `cookie`.
+    pub fn push(&mut self, mut cookie: Cookie, source: CookieSource) -> usize {
         let old_cookie = self.remove(&cookie, source);
         if old_cookie.is_err() {
             // This new cookie is not allowed to overwrite an existing one.
-            return;
+            return 0;
         }
 
         // Step 11
@@ -78,17 +93,21 @@ impl CookieStorage {
         let domain = reg_host(&cookie.cookie.domain.as_ref().unwrap_or(&"".to_string()));
         let mut cookies = self.cookies_map.entry(domain).or_insert(vec![]);
 
-        if cookies.len() == self.max_per_host {
-            let old_len = cookies.len();
+        // When the jar is full, expired cookies go first, and only if there are none is
+        // the cookie that was least recently sent or set evicted.
+        let mut evicted = 0;
+        if cookies.len() >= self.max_per_host {
             cookies.retain(|c| !is_cookie_expired(&c));
-            let new_len = cookies.len();
-
-            // https://datatracker.ietf.org/doc/draft-ietf-httpbis-cookie-alone
-            if new_len == old_len && !evict_one_cookie(cookie.cookie.secure, cookies) {
-                return;
+            while cookies.len() >= self.max_per_host {
+                // https://datatracker.ietf.org/doc/draft-ietf-httpbis-cookie-alone
+                if !evict_one_cookie(cookie.cookie.secure, cookies) {
+                    return evicted;
+                }
+                evicted += 1;
             }
         }
         cookies.push(cookie);
+        evicted
     }
 
     pub fn cookie_comparator(a: &Cookie, b: &Cookie) -> Ordering {
@@ -107,7 +126,8 @@ impl CookieStorage {
     }
 
     // http://tools.ietf.org/html/rfc6265#section-5.4
-    pub fn cookies_for_url(&mut self, url: &ServoUrl, source: CookieSource) -> Option<String> {
+    pub fn cookies_for_url(&mut self, url: &ServoUrl, source: CookieSource, context: SameSiteContext)
+                           -> Option<String> {
         let filterer = |c: &&mut Cookie| -> bool {
             info!(" === SENT COOKIE : {} {} {:?} {:?}",
                   c.cookie.name,
@@ -117,7 +137,7 @@ impl CookieStorage {
             info!(" === SENT COOKIE RESULT {}",
                   c.appropriate_for_url(url, source));
             // Step 1
-            c.appropriate_for_url(url, source)
+            c.appropriate_for_url(url, source) && c.same_site_allows(context)
         };
 
         // Step 2
@@ -158,6 +178,18 @@ impl CookieStorage {
             c.cookie.clone()
         }))
     }
+
+    /// `cookies_data_for_url`, with the attributes derived from each cookie. Unlike it, this
+    /// only inspects the cookies, so they aren't touched.
+    pub fn cookies_details_for_url<'a>(&'a self,
+                                       url: &'a ServoUrl,
+                                       source: CookieSource)
+                                       -> Box<Iterator<Item = CookieDetails> + 'a> {
+        let domain = reg_host(url.host_str().unwrap_or(""));
+        let cookies = self.cookies_map.get(&domain).into_iter().flat_map(|cookies| cookies.iter());
+
+        Box::new(cookies.filter(move |c| c.appropriate_for_url(url, source)).map(Cookie::details))
+    }
 }
 fn reg_host<'a>(url: &'a str) -> String {
     reg_suffix(url).to_string()
@@ -182,8 +214,9 @@ fn evict_one_cookie(is_secure_cookie: bool, cookies: &mut Vec<Cookie>) -> bool {
             return false;
         }
         let oldest_accessed: Option<(usize, Tm)> = get_oldest_accessed(true, cookies);
-        if let Some((index, _)) = oldest_accessed {
-            cookies.remove(index);
+        match oldest_accessed {
+            Some((index, _)) => { cookies.remove(index); },
+            None => return false,
         }
     }
     return true;
@@ -5,6 +5,7 @@
 use hyper::mime::{Attr, Mime, SubLevel, TopLevel, Value};
 use rustc_serialize::base64::FromBase64;
 use servo_url::ServoUrl;
+use std::ascii::AsciiExt;
 use url::Position;
 use url::percent_encoding::percent_decode;
 
@@ -25,11 +26,7 @@ pub fn decode(url: &ServoUrl) -> Result<DecodeData, DecodeError> {
 
     // ";base64" must come at the end of the content type, per RFC 2397.
     // rust-http will fail to parse it because there's no =value part.
-    let mut ct_str = parts[0];
-    let is_base64 = ct_str.ends_with(";base64");
-    if is_base64 {
-        ct_str = &ct_str[..ct_str.len() - ";base64".len()];
-    }
+    let (ct_str, is_base64) = strip_base64_parameter(parts[0]);
     let ct_str = if ct_str.starts_with(";charset=") {
         format!("text/plain{}", ct_str)
     } else {
@@ -41,15 +38,56 @@ pub fn decode(url: &ServoUrl) -> Result<DecodeData, DecodeError> {
              vec![(Attr::Charset, Value::Ext("US-ASCII".to_owned()))])
     });
 
-    let mut bytes = percent_decode(parts[1].as_bytes()).collect::<Vec<_>>();
+    let bytes = percent_decode(parts[1].as_bytes()).collect::<Vec<_>>();
     if is_base64 {
-        // FIXME(#2909): It’s unclear what to do with non-alphabet characters,
-        // but Acid 3 apparently depends on spaces being ignored.
-        bytes = bytes.into_iter().filter(|&b| b != b' ').collect::<Vec<u8>>();
-        match bytes.from_base64() {
-            Err(..) => return Err(DecodeError::NonBase64DataUri),
-            Ok(data) => bytes = data,
-        }
+        return forgiving_base64_decode(bytes).map(|bytes| (content_type, bytes));
     }
     Ok((content_type, bytes))
 }
+
+/// Split a trailing `;base64`, in any case and with any whitespace around it, off the
+/// content type of a data URL.
+fn strip_base64_parameter(ct_str: &str) -> (&str, bool) {
+    let trimmed = ct_str.trim_right();
+    let split = trimmed.len().saturating_sub("base64".len());
+    if trimmed.is_char_boundary(split) {
+        let (rest, last) = trimmed.split_at(split);
+        let rest = rest.trim_right();
+        if last.eq_ignore_ascii_case("base64") && rest.ends_with(';') {
+            return (&rest[..rest.len() - 1], true);
+        }
+    }
+    (ct_str, false)
+}
+
+fn is_ascii_whitespace(byte: u8) -> bool {
+    match byte {
+        b' ' | b'\t' | b'\n' | b'\x0C' | b'\r' => true,
+        _ => false,
+    }
+}
+
+fn is_base64_alphabet(byte: u8) -> bool {
+    match byte {
+        b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'+' | b'/' => true,
+        _ => false,
+    }
+}
+
+/// https://infra.spec.whatwg.org/#forgiving-base64-decode
+fn forgiving_base64_decode(bytes: Vec<u8>) -> Result<Vec<u8>, DecodeError> {
+    let mut bytes = bytes.into_iter().filter(|&b| !is_ascii_whitespace(b)).collect::<Vec<u8>>();
+    if bytes.len() % 4 == 0 {
+        for _ in 0..2 {
+            if bytes.last() == Some(&b'=') {
+                bytes.pop();
+            }
+        }
+    }
+    // A single character left over after the last full group encodes less than a byte,
+    // so the payload must have been cut short.
+    if bytes.len() % 4 == 1 || !bytes.iter().all(|&b| is_base64_alphabet(b)) {
+        return Err(DecodeError::NonBase64DataUri);
+    }
+    bytes.from_base64().map_err(|_| DecodeError::NonBase64DataUri)
+}
@@ -0,0 +1,250 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! HTTP Digest access authentication (https://tools.ietf.org/html/rfc2617), which some
+//! servers challenge for in place of Basic authentication.
+
+use hyper::header::Headers;
+use openssl::crypto::hash::{Type, hash};
+use openssl::crypto::rand::rand_bytes;
+use rustc_serialize::hex::ToHex;
+use servo_url::ServoUrl;
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+
+/// A `WWW-Authenticate: Digest` challenge that can be answered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DigestChallenge {
+    pub realm: String,
+    pub nonce: String,
+    pub opaque: Option<String>,
+    /// Whether the `auth` quality of protection, and with it nonce counting, is used.
+    pub qop_auth: bool,
+    /// Whether the `MD5-sess` algorithm is used rather than `MD5`.
+    pub session_algorithm: bool,
+    /// Whether the challenge was made because the nonce was too old rather than
+    /// because the credentials were wrong.
+    pub stale: bool,
+}
+
+/// The `name=value` parameters of a challenge, with quoted values unquoted.
+fn auth_params(params: &str) -> Vec<(String, String)> {
+    let bytes = params.as_bytes();
+    let mut result = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b',' || bytes[i] == b' ' || bytes[i] == b'\t' {
+            i += 1;
+            continue;
+        }
+        let name_start = i;
+        while i < bytes.len() && bytes[i] != b'=' && bytes[i] != b',' {
+            i += 1;
+        }
+        let name = params[name_start..i].trim().to_owned();
+        if i == bytes.len() || bytes[i] == b',' {
+            // A parameter without a value.
+            continue;
+        }
+        i += 1;
+        while i < bytes.len() && (bytes[i] == b' ' || bytes[i] == b'\t') {
+            i += 1;
+        }
+        let value = if i < bytes.len() && bytes[i] == b'"' {
+            i += 1;
+            let mut value = vec![];
+            while i < bytes.len() && bytes[i] != b'"' {
+                if bytes[i] == b'\\' && i + 1 < bytes.len() {
+                    i += 1;
+                }
+                value.push(bytes[i]);
+                i += 1;
+            }
+            // The closing quote.
+            i += 1;
+            String::from_utf8_lossy(&value).into_owned()
+        } else {
+            let value_start = i;
+            while i < bytes.len() && bytes[i] != b',' {
+                i += 1;
+            }
+            params[value_start..i].trim().to_owned()
+        };
+        result.push((name, value));
+    }
+    result
+}
+
+/// The challenge in a `WWW-Authenticate` value, if it is a Digest challenge with an
+/// algorithm and quality of protection that are supported.
+pub fn parse_digest_challenge(value: &str) -> Option<DigestChallenge> {
+    let value = value.trim();
+    if value.len() < 7 || !value[..6].eq_ignore_ascii_case("digest") || value.as_bytes()[6] != b' ' {
+        return None;
+    }
+    let (mut realm, mut nonce, mut opaque, mut qop, mut algorithm) = (None, None, None, None, None);
+    let mut stale = false;
+    for (name, value) in auth_params(&value[7..]) {
+        match &*name.to_ascii_lowercase() {
+            "realm" => realm = Some(value),
+            "nonce" => nonce = Some(value),
+            "opaque" => opaque = Some(value),
+            "qop" => qop = Some(value),
+            "algorithm" => algorithm = Some(value),
+            "stale" => stale = value.eq_ignore_ascii_case("true"),
+            _ => {}
+        }
+    }
+    let session_algorithm = match algorithm {
+        None => false,
+        Some(ref algorithm) if algorithm.eq_ignore_ascii_case("MD5") => false,
+        Some(ref algorithm) if algorithm.eq_ignore_ascii_case("MD5-sess") => true,
+        Some(_) => return None,
+    };
+    let qop_auth = match qop {
+        None => false,
+        Some(ref qop) if qop.split(',').any(|qop| qop.trim().eq_ignore_ascii_case("auth")) => true,
+        // Only `auth-int` is offered.
+        Some(_) => return None,
+    };
+    match (realm, nonce) {
+        (Some(realm), Some(nonce)) => Some(DigestChallenge {
+            realm: realm,
+            nonce: nonce,
+            opaque: opaque,
+            qop_auth: qop_auth,
+            session_algorithm: session_algorithm,
+            stale: stale,
+        }),
+        _ => None,
+    }
+}
+
+/// The first Digest challenge among the `WWW-Authenticate` fields of `headers` that
+/// can be answered.
+pub fn digest_challenge(headers: &Headers) -> Option<DigestChallenge> {
+    headers.get_raw("WWW-Authenticate").and_then(|values| {
+        values.iter().filter_map(|value| parse_digest_challenge(&String::from_utf8_lossy(value))).next()
+    })
+}
+
+/// The realm of the first challenge among the `name` fields of `headers`, whatever its
+/// scheme, for asking the user for credentials.
+pub fn challenge_realm(headers: &Headers, name: &str) -> Option<String> {
+    headers.get_raw(name).and_then(|values| values.iter().filter_map(|value| {
+        let value = String::from_utf8_lossy(value);
+        let params = value.trim().splitn(2, ' ').nth(1).unwrap_or("").to_owned();
+        auth_params(&params).into_iter()
+                            .find(|&(ref name, _)| name.eq_ignore_ascii_case("realm"))
+                            .map(|(_, realm)| realm)
+    }).next())
+}
+
+fn md5_hex(data: &str) -> String {
+    hash(Type::MD5, data.as_bytes()).to_hex()
+}
+
+/// `value` escaped to be put in a quoted string.
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// The `Authorization` value answering `challenge` for the `count`th request made with its
+/// nonce, which has `method` and request target `uri`.
+pub fn digest_authorization(challenge: &DigestChallenge, cnonce: &str, count: u32, method: &str, uri: &str,
+                            user_name: &str, password: &str) -> String {
+    let mut ha1 = md5_hex(&format!("{}:{}:{}", user_name, challenge.realm, password));
+    if challenge.session_algorithm {
+        ha1 = md5_hex(&format!("{}:{}:{}", ha1, challenge.nonce, cnonce));
+    }
+    let ha2 = md5_hex(&format!("{}:{}", method, uri));
+    let nc = format!("{:08x}", count);
+    let response = if challenge.qop_auth {
+        md5_hex(&format!("{}:{}:{}:{}:auth:{}", ha1, challenge.nonce, nc, cnonce, ha2))
+    } else {
+        md5_hex(&format!("{}:{}:{}", ha1, challenge.nonce, ha2))
+    };
+    let mut value = format!("Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, \
+                             response=\"{}\"",
+                            quote(user_name), quote(&challenge.realm), quote(&challenge.nonce), quote(uri),
+                            if challenge.session_algorithm { "MD5-sess" } else { "MD5" }, response);
+    if challenge.qop_auth {
+        value.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+    }
+    if let Some(ref opaque) = challenge.opaque {
+        value.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+    }
+    value
+}
+
+/// What is needed to answer a challenge again without being challenged.
+struct DigestNonce {
+    challenge: DigestChallenge,
+    /// The client nonce sent with each answer to the challenge.
+    cnonce: String,
+    /// How many requests the nonce has been used for.
+    count: u32,
+}
+
+/// The Digest challenges made to a resource group, by origin and realm, so that later
+/// requests to an origin answer its last challenge without waiting to be challenged again.
+/// Origins are kept in their ASCII serialization, like the keys of the `AuthCache`.
+pub struct DigestAuthCache {
+    nonces: HashMap<(String, String), DigestNonce>,
+    /// The realm of the last challenge made by each origin.
+    realms: HashMap<String, String>,
+}
+
+impl DigestAuthCache {
+    pub fn new() -> DigestAuthCache {
+        DigestAuthCache {
+            nonces: HashMap::new(),
+            realms: HashMap::new(),
+        }
+    }
+
+    /// Remember `challenge`, made by `origin`, in place of any earlier one for its realm.
+    pub fn challenged(&mut self, origin: &str, challenge: DigestChallenge) {
+        self.realms.insert(origin.to_owned(), challenge.realm.clone());
+        self.nonces.insert((origin.to_owned(), challenge.realm.clone()), DigestNonce {
+            challenge: challenge,
+            cnonce: rand_bytes(8).to_hex(),
+            count: 0,
+        });
+    }
+
+    /// The `Authorization` value answering the last challenge made by `origin`, if there
+    /// was one, for a request with `method` and request target `uri`.
+    pub fn authorization(&mut self, origin: &str, method: &str, uri: &str, user_name: &str, password: &str)
+                         -> Option<String> {
+        let realm = match self.realms.get(origin) {
+            Some(realm) => realm.clone(),
+            None => return None,
+        };
+        self.nonces.get_mut(&(origin.to_owned(), realm)).map(|nonce| {
+            nonce.count += 1;
+            digest_authorization(&nonce.challenge, &nonce.cnonce, nonce.count, method, uri, user_name, password)
+        })
+    }
+
+    /// Forget the challenges made by every origin with `host`.
+    pub fn clear_host(&mut self, host: &str) {
+        let is_of_host = |origin: &str| ServoUrl::parse(origin).ok().and_then(|origin| {
+            origin.host_str().map(|origin_host| origin_host.eq_ignore_ascii_case(host))
+        }).unwrap_or(false);
+        let origins: Vec<_> = self.realms.keys().filter(|origin| is_of_host(origin)).cloned().collect();
+        for origin in origins {
+            self.realms.remove(&origin);
+        }
+        let keys: Vec<_> = self.nonces.keys().filter(|&&(ref origin, _)| is_of_host(origin)).cloned().collect();
+        for key in keys {
+            self.nonces.remove(&key);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.nonces.clear();
+        self.realms.clear();
+    }
+}
@@ -3,11 +3,10 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 //! An implementation of the [CORS preflight cache](https://fetch.spec.whatwg.org/#cors-preflight-cache)
-//! For now this library is XHR-specific.
-//! For stuff involving `<img>`, `<iframe>`, `<form>`, etc please check what
-//! the request mode should be and compare with the fetch spec
-//! This library will eventually become the core of the Fetch crate
-//! with CORSRequest being expanded into FetchRequest (etc)
+//!
+//! Each resource group has one, shared by its fetches, so that a preflight answered for
+//! one request spares the others to the same URL from the same origin theirs for as long
+//! as the server allowed. Entries are kept apart by whether they were made with credentials.
 
 use hyper::method::Method;
 use net_traits::request::{CredentialsMode, Origin, Request};
@@ -15,6 +14,14 @@ use servo_url::ServoUrl;
 use std::ascii::AsciiExt;
 use time::{self, Timespec};
 
+/// The longest time, in seconds, that an entry is kept, whatever `Access-Control-Max-Age`
+/// asked for: two hours, as in other browsers.
+pub const MAX_AGE_LIMIT: u32 = 2 * 60 * 60;
+
+/// The most entries the cache holds. Inserting into a full cache drops the oldest entry,
+/// so that a page preflighting a stream of distinct URLs can't grow it without bound.
+pub const MAX_ENTRIES: usize = 1024;
+
 /// Union type for CORS cache entries
 ///
 /// Each entry might pertain to a header or method
@@ -93,23 +100,26 @@ impl CorsCache {
         self.0.iter_mut().find(|e| match_headers(e, request) && e.header_or_method.match_method(&method))
     }
 
-    /// [Clear the cache](https://fetch.spec.whatwg.org/#concept-cache-clear)
+    /// [Clear the cache](https://fetch.spec.whatwg.org/#concept-cache-clear) of the entries
+    /// for the origin and current URL of `request`.
     pub fn clear(&mut self, request: &Request) {
         let CorsCache(buf) = self.clone();
         let new_buf: Vec<CorsCacheEntry> =
-            buf.into_iter().filter(|e| e.origin == *request.origin.borrow() &&
-                                       request.current_url() == e.url).collect();
+            buf.into_iter().filter(|e| !(e.origin == *request.origin.borrow() &&
+                                         request.current_url() == e.url)).collect();
         *self = CorsCache(new_buf);
     }
 
     /// Remove old entries
     pub fn cleanup(&mut self) {
-        let CorsCache(buf) = self.clone();
         let now = time::now().to_timespec();
-        let new_buf: Vec<CorsCacheEntry> = buf.into_iter()
-                                              .filter(|e| now.sec < e.created.sec + e.max_age as i64)
-                                              .collect();
-        *self = CorsCache(new_buf);
+        self.0.retain(|e| now.sec < e.created.sec + e.max_age as i64);
+    }
+
+    /// The number of entries in the cache, including any that have expired since it was
+    /// last cleaned up.
+    pub fn len(&self) -> usize {
+        self.0.len()
     }
 
     /// Returns true if an entry with a
@@ -157,9 +167,13 @@ impl CorsCache {
         }
     }
 
-    /// Insert an entry
+    /// Insert an entry, first removing old ones and, if the cache is still full, the oldest.
     pub fn insert(&mut self, entry: CorsCacheEntry) {
         self.cleanup();
+        if self.0.len() >= MAX_ENTRIES {
+            let excess = self.0.len() + 1 - MAX_ENTRIES;
+            self.0.drain(..excess);
+        }
         self.0.push(entry);
     }
 }
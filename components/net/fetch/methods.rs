@@ -3,40 +3,321 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use blob_loader::load_blob_sync;
-use data_loader::decode;
+use connection_limiter::FetchScheduler;
+use data_loader::{DecodeError, decode};
 use devtools_traits::DevtoolsControlMsg;
-use fetch::cors_cache::CorsCache;
 use filemanager_thread::FileManager;
-use http_loader::{HttpState, determine_request_referrer, http_fetch, set_default_accept_language};
+use hsts::secure_url;
+use http_loader::{HttpState, PrivacySignals, determine_request_referrer, http_fetch, set_accept_language};
 use hyper::header::{Accept, AcceptLanguage, ContentLanguage, ContentType};
-use hyper::header::{HeaderView, QualityItem, Referer as RefererHeader, q, qitem};
+use hyper::header::{HeaderView, Headers, QualityItem, Referer as RefererHeader, q, qitem};
 use hyper::method::Method;
 use hyper::mime::{Mime, SubLevel, TopLevel};
 use hyper::status::StatusCode;
+use ipc_channel::ipc::{self, IpcReceiver, IpcSender};
+use ipc_channel::router::ROUTER;
+use lock_recovery::{read_lock, write_lock};
 use mime_guess::guess_mime_type;
-use net_traits::{FetchTaskTarget, NetworkError, ReferrerPolicy};
+use net_traits::{AuthPromptRequest, FetchTaskTarget, NetStatsSnapshot, NetworkError, ReferrerPolicy, SchemeRequest};
 use net_traits::request::{RedirectMode, Referrer, Request, RequestMode, ResponseTainting};
 use net_traits::request::{Type, Origin, Window};
 use net_traits::response::{Response, ResponseBody, ResponseType};
+use net_traits::storage_thread::StorageThreadMsg;
+use profile_traits::time::ProfilerChan;
+use serde::{Deserialize, Serialize};
+use servo_url::ServoUrl;
 use std::borrow::Cow;
+use std::cmp::{max, min};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use std::mem;
 use std::rc::Rc;
-use std::sync::mpsc::{Sender, Receiver};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use util::prefs::PREFS;
 
 pub type Target = Option<Box<FetchTaskTarget + Send>>;
 
+/// The handlers embedders registered for their own URL schemes, by scheme.
+pub type SchemeHandlers = HashMap<String, IpcSender<SchemeRequest>>;
+
 pub enum Data {
     Payload(Vec<u8>),
     Done,
+    Cancelled,
+    TimedOut,
+    /// The body grew past `network.http.max-response-size`.
+    TooLarge,
+    /// The start of the next part of a `multipart/x-mixed-replace` response, with the
+    /// header fields of the part. The payloads that follow are the body of that part.
+    Part(Headers),
 }
 
 pub struct FetchContext {
     pub state: HttpState,
     pub user_agent: Cow<'static, str>,
+    /// The `Accept-Language` header to send, unless the request already has one.
+    pub accept_language: Option<AcceptLanguage>,
     pub devtools_chan: Option<Sender<DevtoolsControlMsg>>,
     pub filemanager: FileManager,
+    pub cancellation_listener: Arc<Mutex<CancellationListener>>,
+    pub body_flow_control: Option<Arc<Mutex<BodyFlowControl>>>,
+    /// Where the time spent in each phase of an HTTP request is reported, if anywhere.
+    pub time_profiler_chan: Option<ProfilerChan>,
+    /// The traffic counters of the resource group the fetch belongs to.
+    pub net_stats: Arc<NetStats>,
+    pub scheme_handlers: Arc<RwLock<SchemeHandlers>>,
+    /// Runs fetches started in the background, such as cache revalidations, if anything does.
+    pub scheduler: Option<FetchScheduler>,
+    /// Where `Clear-Site-Data` responses clear the DOM storage of their origin, if anywhere.
+    pub storage_thread: Option<IpcSender<StorageThreadMsg>>,
+    /// Where the embedder is asked for credentials that there are none usable for, if anywhere.
+    pub auth_prompt: Option<IpcSender<AuthPromptRequest>>,
+    pub privacy_signals: PrivacySignals,
+    /// Whether a `Host` header given with a request is sent in place of the one for its URL,
+    /// so that a server that routes by host name can be tested at another address. It is off
+    /// unless `network.allow-host-override` is set, so a request can't be smuggled to a host
+    /// it wasn't made for.
+    pub allow_host_override: bool,
+    /// When the fetch fails with `NetworkError::Timeout`, if it was given a timeout.
+    pub deadline: Option<Deadline>,
+    /// The most body bytes a response may have, from `network.http.max-response-size`,
+    /// unless the request asks for an unbounded body.
+    pub max_response_size: Option<usize>,
+}
+
+/// Traffic counters for the fetches of a resource group.
+pub struct NetStats {
+    bytes_sent: AtomicUsize,
+    bytes_received: AtomicUsize,
+    requests_completed: AtomicUsize,
+    requests_failed: AtomicUsize,
+    open_connections: AtomicUsize,
+    active_fetches: AtomicUsize,
+    cookies_evicted: AtomicUsize,
+    tls_sessions_resumed: AtomicUsize,
+    tls_full_handshakes: AtomicUsize,
+}
+
+impl NetStats {
+    pub fn new() -> NetStats {
+        NetStats {
+            bytes_sent: AtomicUsize::new(0),
+            bytes_received: AtomicUsize::new(0),
+            requests_completed: AtomicUsize::new(0),
+            requests_failed: AtomicUsize::new(0),
+            open_connections: AtomicUsize::new(0),
+            active_fetches: AtomicUsize::new(0),
+            cookies_evicted: AtomicUsize::new(0),
+            tls_sessions_resumed: AtomicUsize::new(0),
+            tls_full_handshakes: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn snapshot(&self) -> NetStatsSnapshot {
+        NetStatsSnapshot {
+            bytes_sent: self.bytes_sent.load(Ordering::SeqCst) as u64,
+            bytes_received: self.bytes_received.load(Ordering::SeqCst) as u64,
+            requests_completed: self.requests_completed.load(Ordering::SeqCst) as u64,
+            requests_failed: self.requests_failed.load(Ordering::SeqCst) as u64,
+            open_connections: self.open_connections.load(Ordering::SeqCst) as u64,
+            active_fetches: self.active_fetches.load(Ordering::SeqCst) as u64,
+            cookies_evicted: self.cookies_evicted.load(Ordering::SeqCst) as u64,
+            tls_sessions_resumed: self.tls_sessions_resumed.load(Ordering::SeqCst) as u64,
+            tls_full_handshakes: self.tls_full_handshakes.load(Ordering::SeqCst) as u64,
+        }
+    }
+
+    /// Zero the totals. The open connections and active fetches are current values
+    /// rather than totals, so they are left alone.
+    pub fn reset(&self) {
+        self.bytes_sent.store(0, Ordering::SeqCst);
+        self.bytes_received.store(0, Ordering::SeqCst);
+        self.requests_completed.store(0, Ordering::SeqCst);
+        self.requests_failed.store(0, Ordering::SeqCst);
+        self.cookies_evicted.store(0, Ordering::SeqCst);
+        self.tls_sessions_resumed.store(0, Ordering::SeqCst);
+        self.tls_full_handshakes.store(0, Ordering::SeqCst);
+    }
+
+    pub fn sent(&self, len: usize) {
+        self.bytes_sent.fetch_add(len, Ordering::SeqCst);
+    }
+
+    pub fn received(&self, len: usize) {
+        self.bytes_received.fetch_add(len, Ordering::SeqCst);
+    }
+
+    pub fn evicted_cookies(&self, count: usize) {
+        self.cookies_evicted.fetch_add(count, Ordering::SeqCst);
+    }
+
+    pub fn tls_handshake_done(&self, resumed: bool) {
+        if resumed {
+            self.tls_sessions_resumed.fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.tls_full_handshakes.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn fetch_started(&self) {
+        self.active_fetches.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn fetch_finished(&self, response: &Response) {
+        self.active_fetches.fetch_sub(1, Ordering::SeqCst);
+        if response.is_network_error() {
+            self.requests_failed.fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.requests_completed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Count a connection as open until the returned guard is dropped.
+    pub fn connection_opened(stats: &Arc<NetStats>) -> OpenConnection {
+        stats.open_connections.fetch_add(1, Ordering::SeqCst);
+        OpenConnection(stats.clone())
+    }
+}
+
+/// An HTTP connection that is counted as open in `NetStats` for as long as this lives.
+pub struct OpenConnection(Arc<NetStats>);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.open_connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Lets a running fetch notice that it was aborted with `CoreResourceMsg::Cancel`.
+pub struct CancellationListener {
+    cancel_chan: Option<Receiver<()>>,
+    cancelled: bool,
+}
+
+impl CancellationListener {
+    pub fn new(cancel_chan: Option<Receiver<()>>) -> CancellationListener {
+        CancellationListener {
+            cancel_chan: cancel_chan,
+            cancelled: false,
+        }
+    }
+
+    pub fn cancelled(&mut self) -> bool {
+        if !self.cancelled {
+            if let Some(ref cancel_chan) = self.cancel_chan {
+                self.cancelled = cancel_chan.try_recv().is_ok();
+            }
+        }
+        self.cancelled
+    }
+}
+
+/// Why `recv_while_fetching` stopped waiting without a reply.
+#[derive(Debug, PartialEq)]
+pub enum WaitError {
+    Cancelled,
+    TimedOut,
+    /// The sender went away without replying.
+    Disconnected,
+}
+
+/// Wait for the reply that another thread or process sends on `receiver` while a fetch
+/// waits for it, giving up once the fetch is cancelled or `deadline` passes.
+pub fn recv_while_fetching<T>(receiver: IpcReceiver<T>,
+                              cancellation_listener: &Mutex<CancellationListener>,
+                              deadline: Option<Deadline>)
+                              -> Result<T, WaitError>
+    where T: Deserialize + Serialize + Send + 'static
+{
+    let receiver = ROUTER.route_ipc_receiver_to_new_mpsc_receiver(receiver);
+    loop {
+        let timeout = match deadline {
+            Some(deadline) if deadline.has_passed() => return Err(WaitError::TimedOut),
+            Some(deadline) => min(deadline.remaining(), Duration::from_millis(100)),
+            None => Duration::from_millis(100),
+        };
+        match receiver.recv_timeout(timeout) {
+            Ok(reply) => return Ok(reply),
+            Err(RecvTimeoutError::Timeout) => {
+                if cancellation_listener.lock().unwrap().cancelled() {
+                    return Err(WaitError::Cancelled);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return Err(WaitError::Disconnected),
+        }
+    }
+}
+
+/// The time by which a fetch that was given a timeout must have received its response.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    at: Instant,
+    /// Whether the whole response body must also have arrived by then.
+    pub covers_body: bool,
+}
+
+impl Deadline {
+    pub fn after(timeout_ms: u64, covers_body: bool) -> Deadline {
+        Deadline {
+            at: Instant::now() + Duration::from_millis(timeout_ms),
+            covers_body: covers_body,
+        }
+    }
+
+    pub fn has_passed(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    /// The time left before the deadline. It is never zero, as sockets take a zero
+    /// timeout to mean none at all.
+    pub fn remaining(&self) -> Duration {
+        let now = Instant::now();
+        let remaining = if now < self.at { self.at - now } else { Duration::new(0, 0) };
+        max(remaining, Duration::from_millis(1))
+    }
+}
+
+/// Keeps a fetch from delivering more than a fixed window of response body to
+/// a consumer that hasn't acknowledged what it has already received.
+pub struct BodyFlowControl {
+    window: usize,
+    unacknowledged: usize,
+    acks: Receiver<usize>,
+}
+
+impl BodyFlowControl {
+    pub fn new(window: usize, acks: Receiver<usize>) -> BodyFlowControl {
+        BodyFlowControl {
+            window: window,
+            unacknowledged: 0,
+            acks: acks,
+        }
+    }
+
+    /// Record that `len` more bytes of body were handed to the consumer.
+    pub fn sent(&mut self, len: usize) {
+        self.unacknowledged += len;
+    }
+
+    /// Block while more than a window's worth of body is unacknowledged.
+    /// Gives up early if `cancelled` returns true or the consumer goes away.
+    pub fn wait_for_window<F>(&mut self, mut cancelled: F) where F: FnMut() -> bool {
+        while self.unacknowledged > self.window {
+            match self.acks.recv_timeout(Duration::from_millis(100)) {
+                Ok(len) => self.unacknowledged = self.unacknowledged.saturating_sub(len),
+                Err(RecvTimeoutError::Timeout) => {
+                    if cancelled() {
+                        return;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
 }
 
 pub type DoneChannel = Option<(Sender<Data>, Receiver<Data>)>;
@@ -46,14 +327,6 @@ pub fn fetch(request: Rc<Request>,
              target: &mut Target,
              context: &FetchContext)
              -> Response {
-    fetch_with_cors_cache(request, &mut CorsCache::new(), target, context)
-}
-
-pub fn fetch_with_cors_cache(request: Rc<Request>,
-                             cache: &mut CorsCache,
-                             target: &mut Target,
-                             context: &FetchContext)
-                             -> Response {
     // Step 1
     if request.window.get() == Window::Client {
         // TODO: Set window to request's client object if client is a Window object
@@ -101,7 +374,7 @@ pub fn fetch_with_cors_cache(request: Rc<Request>,
     }
 
     // Step 4
-    set_default_accept_language(&mut request.headers.borrow_mut());
+    set_accept_language(&mut request.headers.borrow_mut(), context.accept_language.as_ref());
 
     // Step 5
     // TODO: Figure out what a Priority object is
@@ -112,18 +385,24 @@ pub fn fetch_with_cors_cache(request: Rc<Request>,
     }
 
     // Step 7
-    main_fetch(request, cache, false, false, target, &mut None, &context)
+    main_fetch(request, false, false, target, &mut None, &context)
 }
 
 /// [Main fetch](https://fetch.spec.whatwg.org/#concept-main-fetch)
 pub fn main_fetch(request: Rc<Request>,
-                  cache: &mut CorsCache,
                   cors_flag: bool,
                   recursive_flag: bool,
                   target: &mut Target,
                   done_chan: &mut DoneChannel,
                   context: &FetchContext)
                   -> Response {
+    // Redirects are followed by recursing, and are counted as part of the same fetch.
+    if !recursive_flag {
+        context.net_stats.fetch_started();
+    }
+    // Taken before a redirect can drop the body.
+    let has_body = request.body.borrow().is_some() || request.body_parts.borrow().is_some();
+
     // TODO: Implement main fetch spec
 
     // Step 1
@@ -143,8 +422,29 @@ pub fn main_fetch(request: Rc<Request>,
     // Step 4
     // TODO this step, based off of http_loader.rs (upgrade)
 
+    // Not part of the spec: rewrite the URL according to any configured rules before
+    // anything is sent, so that the response's URL reflects what was actually fetched.
+    // Only the URL is affected; the request body is left alone.
+    let rewritten_url = read_lock(&context.state.url_rewriter, "URL rewriter").rewrite(&request.current_url());
+    if let Some(url) = rewritten_url {
+        *request.url_list.borrow_mut().last_mut().unwrap() = url;
+    }
+
     // Step 5
-    // TODO this step (CSP port/content blocking)
+    // TODO this step (CSP port blocking)
+    if response.is_none() {
+        if let Some(ref rules) = *context.state.blocked_content {
+            if let Some(rule) = rules.blocking_rule(&request.current_url(), request.destination) {
+                response = Some(Response::network_error(NetworkError::Blocked { rule: rule }));
+            }
+        }
+    }
+    // Not part of the spec: the mixed content check, which is done again for each redirect.
+    let mixed_content = request.is_mixed_content();
+    if response.is_none() && mixed_content && should_block_mixed_content(&request) {
+        warn!("Blocked mixed content load of {}", request.current_url());
+        response = Some(Response::network_error(NetworkError::MixedContentBlocked));
+    }
 
     // Step 6
     // TODO this step (referrer policy)
@@ -179,13 +479,22 @@ pub fn main_fetch(request: Rc<Request>,
     }
 
     // Step 9
-    // TODO this step (HSTS)
+    let should_upgrade = {
+        let url = request.current_url();
+        url.scheme() == "http" && url.domain().map_or(false, |host| context.state.is_host_secure(host))
+    };
+    if should_upgrade {
+        let mut url_list = request.url_list.borrow_mut();
+        let url = url_list.last_mut().unwrap();
+        let secure = ServoUrl::from_url(secure_url(url.as_url().unwrap()));
+        *url = secure;
+    }
 
     // Step 10
     // this step is obsoleted by fetch_async
 
     // Step 11
-    let response = match response {
+    let mut response = match response {
         Some(response) => response,
         None => {
             let current_url = request.current_url();
@@ -200,14 +509,14 @@ pub fn main_fetch(request: Rc<Request>,
                 current_url.scheme() == "file" ||
                 current_url.scheme() == "about" ||
                 request.mode == RequestMode::Navigate {
-                basic_fetch(request.clone(), cache, target, done_chan, context)
+                basic_fetch(request.clone(), target, done_chan, context)
 
             } else if request.mode == RequestMode::SameOrigin {
                 Response::network_error(NetworkError::Internal("Cross-origin response".into()))
 
             } else if request.mode == RequestMode::NoCors {
                 request.response_tainting.set(ResponseTainting::Opaque);
-                basic_fetch(request.clone(), cache, target, done_chan, context)
+                basic_fetch(request.clone(), target, done_chan, context)
 
             } else if !matches!(current_url.scheme(), "http" | "https") {
                 Response::network_error(NetworkError::Internal("Non-http scheme".into()))
@@ -218,25 +527,36 @@ pub fn main_fetch(request: Rc<Request>,
                   request.headers.borrow().iter().any(|h| !is_simple_header(&h)))) {
                 request.response_tainting.set(ResponseTainting::CorsTainting);
                 request.redirect_mode.set(RedirectMode::Error);
-                let response = http_fetch(request.clone(), cache, true, true, false,
+                let response = http_fetch(request.clone(), true, true, false,
                                           target, done_chan, context);
                 if response.is_network_error() {
-                    // TODO clear cache entries using request
+                    write_lock(&context.state.cors_cache, "CORS preflight cache").clear(&request);
                 }
                 response
 
             } else {
                 request.response_tainting.set(ResponseTainting::CorsTainting);
-                http_fetch(request.clone(), cache, true, false, false, target, done_chan, context)
+                http_fetch(request.clone(), true, false, false, target, done_chan, context)
             }
         }
     };
 
+    // A response that went through a redirect is mixed content if any of its hops was.
+    response.mixed_content |= mixed_content;
+
     // Step 12
     if recursive_flag {
         return response;
     }
 
+    // The body was sent for the last time once the final response arrived, however many
+    // redirects sent it again on the way.
+    if has_body && !response.is_network_error() {
+        if let Some(ref mut target) = *target {
+            target.process_request_eof(&request);
+        }
+    }
+
     // Step 13
     // no need to check if response is a network error, since the type would not be `Default`
     let response = if response.response_type == ResponseType::Default {
@@ -263,6 +583,7 @@ pub fn main_fetch(request: Rc<Request>,
         // Step 15
         if internal_response.url_list.borrow().is_empty() {
             *internal_response.url_list.borrow_mut() = request.url_list.borrow().clone();
+            *internal_response.redirect_statuses.borrow_mut() = request.redirect_statuses.borrow().clone();
         }
 
         // Step 16
@@ -298,23 +619,17 @@ pub fn main_fetch(request: Rc<Request>,
     // Step 19
     if request.synchronous {
         if let Some(ref mut target) = *target {
+            for links in &response.early_hints {
+                target.process_early_hints(links);
+            }
             // process_response is not supposed to be used
             // by sync fetch, but we overload it here for simplicity
             target.process_response(&response);
         }
 
+        let mut body_error = None;
         if let Some(ref ch) = *done_chan {
-            loop {
-                match ch.1.recv()
-                        .expect("fetch worker should always send Done before terminating") {
-                    Data::Payload(vec) => {
-                        if let Some(ref mut target) = *target {
-                            target.process_response_chunk(vec);
-                        }
-                    }
-                    Data::Done => break,
-                }
-            }
+            body_error = wait_for_response_body(ch, &response, target).err();
         } else {
             let body = response.body.lock().unwrap();
             if let ResponseBody::Done(ref vec) = *body {
@@ -329,43 +644,39 @@ pub fn main_fetch(request: Rc<Request>,
             }
         }
 
+        let response = match body_error {
+            Some(error) => Response::network_error(error),
+            None => response,
+        };
+
+        if !recursive_flag {
+            context.net_stats.fetch_finished(&response);
+        }
         // overloaded similarly to process_response
         if let Some(ref mut target) = *target {
+            if let Some(ref trailers) = *response.trailers.lock().unwrap() {
+                target.process_response_trailers(trailers);
+            }
             target.process_response_eof(&response);
         }
         return response;
     }
 
     // Step 20
-    if request.body.borrow().is_some() && matches!(request.current_url().scheme(), "http" | "https") {
-        if let Some(ref mut target) = *target {
-            // XXXManishearth: We actually should be calling process_request
-            // in http_network_fetch. However, we can't yet follow the request
-            // upload progress, so I'm keeping it here for now and pretending
-            // the body got sent in one chunk
-            target.process_request_body(&request);
-            target.process_request_eof(&request);
-        }
-    }
+    // The request body is reported as it is sent, by http_network_fetch.
 
     // Step 21
     if let Some(ref mut target) = *target {
+        for links in &response.early_hints {
+            target.process_early_hints(links);
+        }
         target.process_response(&response);
     }
 
     // Step 22
+    let mut body_error = None;
     if let Some(ref ch) = *done_chan {
-        loop {
-            match ch.1.recv()
-                    .expect("fetch worker should always send Done before terminating") {
-                Data::Payload(vec) => {
-                    if let Some(ref mut target) = *target {
-                        target.process_response_chunk(vec);
-                    }
-                }
-                Data::Done => break,
-            }
-        }
+        body_error = wait_for_response_body(ch, &response, target).err();
     } else if let Some(ref mut target) = *target {
         let body = response.body.lock().unwrap();
         if let ResponseBody::Done(ref vec) = *body {
@@ -378,8 +689,19 @@ pub fn main_fetch(request: Rc<Request>,
         }
     }
 
+    let response = match body_error {
+        Some(error) => Response::network_error(error),
+        None => response,
+    };
+
     // Step 24
+    if !recursive_flag {
+        context.net_stats.fetch_finished(&response);
+    }
     if let Some(ref mut target) = *target {
+        if let Some(ref trailers) = *response.trailers.lock().unwrap() {
+            target.process_response_trailers(trailers);
+        }
         target.process_response_eof(&response);
     }
 
@@ -387,9 +709,45 @@ pub fn main_fetch(request: Rc<Request>,
     return response;
 }
 
+/// Whether `request`, which is mixed content, should be blocked. Active content is blocked
+/// unless `network.mixed-content.block-active` is turned off, and passive content is only
+/// blocked if `network.mixed-content.block-passive` is turned on.
+fn should_block_mixed_content(request: &Request) -> bool {
+    if request.is_passive_content() {
+        PREFS.get("network.mixed-content.block-passive").as_boolean().unwrap_or(false)
+    } else {
+        PREFS.get("network.mixed-content.block-active").as_boolean().unwrap_or(true)
+    }
+}
+
+/// Pass body chunks and the parts of multipart responses from the fetch worker on to
+/// `target` until the body of `response` is complete. Fails if the load was cancelled,
+/// timed out or grew too large before the whole body arrived.
+fn wait_for_response_body(ch: &(Sender<Data>, Receiver<Data>), response: &Response, target: &mut Target)
+                          -> Result<(), NetworkError> {
+    loop {
+        match ch.1.recv()
+                .expect("fetch worker should always send Done before terminating") {
+            Data::Payload(vec) => {
+                if let Some(ref mut target) = *target {
+                    target.process_response_chunk(vec);
+                }
+            }
+            Data::Part(headers) => {
+                if let Some(ref mut target) = *target {
+                    target.process_response_part(&response.part(headers));
+                }
+            }
+            Data::Done => return Ok(()),
+            Data::Cancelled => return Err(NetworkError::LoadCancelled),
+            Data::TimedOut => return Err(NetworkError::Timeout),
+            Data::TooLarge => return Err(NetworkError::Internal("response too large".to_owned())),
+        }
+    }
+}
+
 /// [Basic fetch](https://fetch.spec.whatwg.org#basic-fetch)
 fn basic_fetch(request: Rc<Request>,
-               cache: &mut CorsCache,
                target: &mut Target,
                done_chan: &mut DoneChannel,
                context: &FetchContext)
@@ -405,7 +763,7 @@ fn basic_fetch(request: Rc<Request>,
         },
 
         "http" | "https" => {
-            http_fetch(request.clone(), cache, false, false, false, target, done_chan, context)
+            http_fetch(request.clone(), false, false, false, target, done_chan, context)
         },
 
         "data" => {
@@ -417,7 +775,12 @@ fn basic_fetch(request: Rc<Request>,
                         response.headers.set(ContentType(mime));
                         response
                     },
-                    Err(_) => Response::network_error(NetworkError::Internal("Decoding data URL failed".into()))
+                    Err(DecodeError::InvalidDataUri) => {
+                        Response::network_error(NetworkError::Internal("Decoding data URL failed".into()))
+                    },
+                    Err(DecodeError::NonBase64DataUri) => {
+                        Response::network_error(NetworkError::Internal("Invalid base64 in data URL".into()))
+                    },
                 }
             } else {
                 Response::network_error(NetworkError::Internal("Unexpected method for data".into()))
@@ -470,12 +833,48 @@ fn basic_fetch(request: Rc<Request>,
             }
         },
 
-        "ftp" => {
-            debug!("ftp is not implemented");
-            Response::network_error(NetworkError::Internal("Unexpected scheme".into()))
-        },
+        scheme => {
+            // The handler may take its time, so the handlers aren't kept locked meanwhile.
+            let handler = read_lock(&context.scheme_handlers, "scheme handlers").get(scheme).cloned();
+            match handler {
+                Some(handler) => scheme_handler_fetch(&request, &handler, context),
+                None => Response::network_error(NetworkError::Internal("Unexpected scheme".into())),
+            }
+        }
+    }
+}
 
-        _ => Response::network_error(NetworkError::Internal("Unexpected scheme".into()))
+/// Ask the handler an embedder registered for the scheme of `request` for its response.
+/// Waiting for it stops once the fetch is cancelled or times out.
+fn scheme_handler_fetch(request: &Request, handler: &IpcSender<SchemeRequest>, context: &FetchContext) -> Response {
+    let url = request.current_url();
+    let (response_chan, response_port) = ipc::channel().unwrap();
+    let scheme_request = SchemeRequest {
+        url: url.clone(),
+        method: request.method.borrow().clone(),
+        headers: request.headers.borrow().clone(),
+        body: request.body.borrow().clone(),
+        response_chan: response_chan,
+    };
+    if handler.send(scheme_request).is_err() {
+        return Response::network_error(NetworkError::Internal("Scheme handler went away".into()));
+    }
+    match recv_while_fetching(response_port, &context.cancellation_listener, context.deadline) {
+        Ok(Some(custom_response)) => {
+            let mut response = Response::new(url);
+            let (status, status_text) = (custom_response.raw_status.0, custom_response.raw_status.1);
+            response.status = Some(StatusCode::from_u16(status));
+            response.raw_status = Some((status, status_text.into_owned().into_bytes()));
+            response.headers = custom_response.headers;
+            *response.body.lock().unwrap() = ResponseBody::Done(custom_response.body);
+            response
+        },
+        Ok(None) => Response::network_error(NetworkError::Internal("Nothing found by the scheme handler".into())),
+        Err(WaitError::Cancelled) => Response::network_error(NetworkError::LoadCancelled),
+        Err(WaitError::TimedOut) => Response::network_error(NetworkError::Timeout),
+        Err(WaitError::Disconnected) => {
+            Response::network_error(NetworkError::Internal("Scheme handler went away".into()))
+        }
     }
 }
 
@@ -2,14 +2,20 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use hyper::header::{ContentDisposition, ContentLength, DispositionParam, Headers};
 use ipc_channel::ipc::IpcSender;
+use lock_recovery::read_lock;
 use mime_guess::guess_mime_type_opt;
+use net_traits::{DownloadProgress, FetchTaskTarget, NetworkError};
 use net_traits::blob_url_store::{BlobBuf, BlobURLStoreError};
 use net_traits::filemanager_thread::{FileManagerResult, FileManagerThreadMsg, FileOrigin, FilterPattern};
 use net_traits::filemanager_thread::{FileManagerThreadError, ReadFileProgress, RelativePos, SelectedFile};
+use net_traits::request::Request;
+use net_traits::response::Response;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::mem;
 use std::ops::Index;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
@@ -123,6 +129,21 @@ impl FileManager {
         }
     }
 
+    /// An estimate of the heap memory held by the blob store, for memory reports.
+    pub fn estimated_size(&self) -> usize {
+        read_lock(&self.store.entries, "blob store").values().map(|entry| {
+            let impl_size = match entry.file_impl {
+                FileImpl::MetaDataOnly(ref metadata) => metadata.path.as_os_str().len(),
+                FileImpl::Memory(ref buf) => {
+                    buf.bytes.capacity() + buf.type_string.capacity() +
+                    buf.filename.as_ref().map_or(0, String::capacity)
+                }
+                FileImpl::Sliced(..) => 0,
+            };
+            mem::size_of::<FileStoreEntry>() + entry.origin.capacity() + impl_size
+        }).sum()
+    }
+
     pub fn read_file(&self,
                      sender: IpcSender<FileManagerResult<ReadFileProgress>>,
                      id: Uuid,
@@ -137,6 +158,13 @@ impl FileManager {
         })
     }
 
+    /// Open the contents of the file or blob `id` to be read a piece at a time, returning
+    /// its size along with the reader.
+    pub fn open_reader(&self, id: &Uuid, origin: &FileOrigin)
+                       -> Result<(u64, Box<Read + Send>), BlobURLStoreError> {
+        self.store.open_reader(id, origin, RelativePos::full_range())
+    }
+
     pub fn promote_memory(&self,
                           blob_buf: BlobBuf,
                           set_valid: bool,
@@ -148,6 +176,20 @@ impl FileManager {
         })
     }
 
+    /// Start saving the body of a fetch to `path`, or to a file in `path` if it is a
+    /// directory, reporting the progress of the download to `reply`.
+    pub fn download(&self, path: PathBuf, reply: IpcSender<DownloadProgress>) -> FileDownload {
+        FileDownload {
+            path: path,
+            reply: reply,
+            file: None,
+            created: false,
+            received: 0,
+            total: None,
+            error: None,
+        }
+    }
+
     /// Message handler
     pub fn handle<UI>(&self,
                       msg: FileManagerThreadMsg,
@@ -189,6 +231,146 @@ impl FileManager {
     }
 }
 
+/// Saves the body of a fetch to a file as it arrives.
+pub struct FileDownload {
+    path: PathBuf,
+    reply: IpcSender<DownloadProgress>,
+    file: Option<File>,
+    /// Whether `path` was created, and so has to be removed if the download fails.
+    created: bool,
+    received: u64,
+    total: Option<u64>,
+    error: Option<NetworkError>,
+}
+
+/// The name to save `response` under in a download directory: the one suggested by its
+/// `Content-Disposition`, or the last segment of its URL.
+fn download_filename(response: &Response) -> String {
+    let suggested = response.headers.get::<ContentDisposition>().and_then(|disposition| {
+        disposition.parameters.iter().filter_map(|param| match *param {
+            DispositionParam::Filename(_, _, ref name) => Some(String::from_utf8_lossy(name).into_owned()),
+            _ => None,
+        }).next()
+    });
+    let from_url = response.url().and_then(|url| url.path().rsplit('/').next().map(str::to_owned));
+    suggested.into_iter().chain(from_url)
+             // Only the last component is kept, so that the server can't pick another directory.
+             .filter_map(|name| Path::new(&name).file_name().map(|name| name.to_string_lossy().into_owned()))
+             .next()
+             .unwrap_or_else(|| "download".to_owned())
+}
+
+/// Create the file at `path`, failing if there is one already, so that a download
+/// never overwrites (or, if it fails, removes) a file it didn't create.
+fn create_new_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Create a file called `name` in the directory `dir`, or `name (1)`, `name (2)`, and so on
+/// if that is taken, and leave `dir` set to its path.
+fn create_unique_file(dir: &mut PathBuf, name: &str) -> io::Result<File> {
+    let (stem, extension) = {
+        let name = Path::new(name);
+        (name.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned()),
+         name.extension().map(|extension| extension.to_string_lossy().into_owned()))
+    };
+    let mut attempt = 0;
+    loop {
+        let candidate = match (attempt, &extension) {
+            (0, _) => name.to_owned(),
+            (n, &Some(ref extension)) => format!("{} ({}).{}", stem, n, extension),
+            (n, &None) => format!("{} ({})", stem, n),
+        };
+        let path = dir.join(candidate);
+        match create_new_file(&path) {
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 1000 => attempt += 1,
+            result => {
+                *dir = path;
+                return result;
+            }
+        }
+    }
+}
+
+impl FetchTaskTarget for FileDownload {
+    fn process_request_body(&mut self, _: &Request) {}
+
+    fn process_request_body_progress(&mut self, _: u64, _: Option<u64>) {}
+
+    fn process_request_eof(&mut self, _: &Request) {}
+
+    fn process_early_hints(&mut self, _: &[String]) {}
+
+    fn process_response(&mut self, response: &Response) {
+        if response.is_network_error() {
+            return;
+        }
+        let response = response.actual_response();
+        self.total = response.headers.get::<ContentLength>().map(|&ContentLength(len)| len);
+        let created = if self.path.is_dir() {
+            create_unique_file(&mut self.path, &download_filename(response))
+        } else {
+            create_new_file(&self.path)
+        };
+        match created {
+            Ok(file) => {
+                self.file = Some(file);
+                self.created = true;
+            }
+            Err(e) => {
+                self.error = Some(NetworkError::Internal(format!("Couldn't create {}: {}",
+                                                                 self.path.display(), e)));
+            }
+        }
+    }
+
+    // The parts of a multipart response are saved one after another.
+    fn process_response_part(&mut self, _: &Response) {}
+
+    fn process_response_chunk(&mut self, chunk: Vec<u8>) {
+        let written = match self.file {
+            Some(ref mut file) => file.write_all(&chunk),
+            None => return,
+        };
+        match written {
+            Ok(()) => {
+                self.received += chunk.len() as u64;
+                let _ = self.reply.send(DownloadProgress::Progress {
+                    received: self.received,
+                    total: self.total,
+                });
+            }
+            Err(e) => {
+                self.file = None;
+                self.error = Some(NetworkError::Internal(format!("Couldn't write to {}: {}",
+                                                                 self.path.display(), e)));
+            }
+        }
+    }
+
+    fn process_response_trailers(&mut self, _: &Headers) {}
+
+    fn process_response_eof(&mut self, response: &Response) {
+        let mut error = response.get_network_error().cloned().or(self.error.take());
+        if let Some(file) = self.file.take() {
+            if let Err(e) = file.sync_all() {
+                error = error.or(Some(NetworkError::Internal(format!("Couldn't write to {}: {}",
+                                                                     self.path.display(), e))));
+            }
+        }
+        let progress = match error {
+            Some(error) => {
+                if self.created {
+                    let _ = fs::remove_file(&self.path);
+                }
+                DownloadProgress::Failed(error)
+            }
+            None => DownloadProgress::Complete(self.path.clone()),
+        };
+        let _ = self.reply.send(progress);
+    }
+}
+
 /// File manager's data store. It maintains a thread-safe mapping
 /// from FileID to FileStoreEntry which might have different backend implementation.
 /// Access to the content is encapsulated as methods of this struct.
@@ -448,6 +630,28 @@ impl FileManagerStore {
         }
     }
 
+    fn open_reader(&self, id: &Uuid, origin_in: &FileOrigin, rel_pos: RelativePos)
+                   -> Result<(u64, Box<Read + Send>), BlobURLStoreError> {
+        match try!(self.get_impl(id, origin_in, false)) {
+            FileImpl::Memory(buf) => {
+                let range = rel_pos.to_abs_range(buf.size as usize);
+                let bytes = buf.bytes.index(range).to_vec();
+                Ok((bytes.len() as u64, Box::new(Cursor::new(bytes))))
+            }
+            FileImpl::MetaDataOnly(metadata) => {
+                let range = rel_pos.to_abs_range(metadata.size as usize);
+                let mut file = try!(File::open(&metadata.path)
+                                   .map_err(|e| BlobURLStoreError::External(e.to_string())));
+                try!(file.seek(SeekFrom::Start(range.start as u64))
+                         .map_err(|e| BlobURLStoreError::External(e.to_string())));
+                Ok((range.len() as u64, Box::new(file.take(range.len() as u64))))
+            }
+            FileImpl::Sliced(parent_id, inner_rel_pos) => {
+                self.open_reader(&parent_id, origin_in, rel_pos.slice_inner(&inner_rel_pos))
+            }
+        }
+    }
+
     // Convenient wrapper over get_blob_buf
     fn try_read_file(&self, sender: &IpcSender<FileManagerResult<ReadFileProgress>>,
                      id: Uuid, check_url_validity: bool, origin_in: FileOrigin)
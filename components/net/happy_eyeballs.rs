@@ -0,0 +1,163 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Connecting to hosts with both IPv6 and IPv4 addresses by racing connections to them,
+//! so that a broken network for one family doesn't hold every connection up until it
+//! times out, as specified in https://tools.ietf.org/html/rfc8305
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{RecvTimeoutError, channel};
+use std::time::Duration;
+use time;
+use util::thread::spawn_named;
+
+/// How long a connection attempt is given before the next one is started alongside it.
+/// https://tools.ietf.org/html/rfc8305#section-5
+const CONNECTION_ATTEMPT_DELAY_MS: u64 = 250;
+
+/// How long, in seconds, the family of the address that won a race to a host is tried
+/// first for its next connections.
+const PREFERRED_FAMILY_TTL: i64 = 10 * 60;
+
+/// Looks up the addresses of hosts.
+pub trait Resolver: Send + Sync {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// Looks hosts up with the system's resolver.
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        // IPv6 addresses in URLs are enclosed in brackets.
+        let host = host.trim_left_matches('[').trim_right_matches(']');
+        (host, port).to_socket_addrs().map(|addrs| addrs.collect())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    fn of(addr: &SocketAddr) -> AddressFamily {
+        match *addr {
+            SocketAddr::V4(_) => AddressFamily::Ipv4,
+            SocketAddr::V6(_) => AddressFamily::Ipv6,
+        }
+    }
+}
+
+/// Order `addrs` to be connected to, alternating between the families, starting with
+/// `preferred` or, without one, with the family of the first address.
+/// https://tools.ietf.org/html/rfc8305#section-4
+pub fn sort_addresses(addrs: Vec<SocketAddr>, preferred: Option<AddressFamily>) -> Vec<SocketAddr> {
+    let first_family = match preferred.or_else(|| addrs.first().map(AddressFamily::of)) {
+        Some(family) => family,
+        None => return addrs,
+    };
+    let (first, second): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|addr| AddressFamily::of(addr) == first_family);
+    let mut sorted = Vec::with_capacity(first.len() + second.len());
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return sorted,
+            (a, b) => sorted.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Connect to the first of `addrs` that accepts, starting an attempt for the next one
+/// when the last one started fails or hasn't connected within `attempt_delay`. The
+/// attempts that are still running when one connects are abandoned, and anything they
+/// connect is closed.
+pub fn race<T, F>(addrs: Vec<SocketAddr>, attempt_delay: Duration, connect: F) -> io::Result<(T, SocketAddr)>
+    where T: Send + 'static,
+          F: Fn(SocketAddr) -> io::Result<T> + Send + Sync + 'static
+{
+    let connect = Arc::new(connect);
+    let (sender, receiver) = channel();
+    let mut addrs = addrs.into_iter();
+    let mut running = 0;
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to");
+    let mut start_next = true;
+    loop {
+        if start_next {
+            if let Some(addr) = addrs.next() {
+                let (connect, sender) = (connect.clone(), sender.clone());
+                spawn_named(format!("Connect to {}", addr), move || {
+                    let _ = sender.send((addr, connect(addr)));
+                });
+                running += 1;
+            }
+        }
+        if running == 0 {
+            return Err(last_error);
+        }
+        let result = if addrs.len() > 0 {
+            match receiver.recv_timeout(attempt_delay) {
+                Ok(result) => result,
+                Err(RecvTimeoutError::Timeout) => {
+                    start_next = true;
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => unreachable!(),
+            }
+        } else {
+            receiver.recv().expect("the sender is kept alive")
+        };
+        match result {
+            (addr, Ok(stream)) => return Ok((stream, addr)),
+            (addr, Err(error)) => {
+                debug!("Connecting to {} failed: {}", addr, error);
+                running -= 1;
+                last_error = error;
+                start_next = true;
+            }
+        }
+    }
+}
+
+/// Connects to hosts by racing their addresses, and remembers which family won the
+/// last race to each host for a while.
+#[derive(Clone)]
+pub struct HappyEyeballs {
+    resolver: Arc<Resolver>,
+    /// The family of the address each host was last connected to, and when, in seconds
+    /// since the epoch, it stops being preferred.
+    preferred_families: Arc<Mutex<HashMap<String, (AddressFamily, i64)>>>,
+    attempt_delay: Duration,
+}
+
+impl HappyEyeballs {
+    pub fn new(resolver: Arc<Resolver>) -> HappyEyeballs {
+        HappyEyeballs {
+            resolver: resolver,
+            preferred_families: Arc::new(Mutex::new(HashMap::new())),
+            attempt_delay: Duration::from_millis(CONNECTION_ATTEMPT_DELAY_MS),
+        }
+    }
+
+    /// The family of the addresses of `host` that are connected to first.
+    pub fn preferred_family(&self, host: &str) -> Option<AddressFamily> {
+        let preferred_families = self.preferred_families.lock().unwrap();
+        preferred_families.get(host).and_then(|&(family, expires)| {
+            if time::get_time().sec < expires { Some(family) } else { None }
+        })
+    }
+
+    pub fn connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let addrs = try!(self.resolver.resolve(host, port));
+        let addrs = sort_addresses(addrs, self.preferred_family(host));
+        let (stream, addr) = try!(race(addrs, self.attempt_delay, |addr| TcpStream::connect(addr)));
+        let expires = time::get_time().sec + PREFERRED_FAMILY_TTL;
+        self.preferred_families.lock().unwrap().insert(host.to_owned(), (AddressFamily::of(&addr), expires));
+        Ok(stream)
+    }
+}
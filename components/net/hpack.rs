@@ -0,0 +1,416 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Header compression for HTTP/2, as specified in https://tools.ietf.org/html/rfc7541
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// The size of the dynamic table the peer may use, which is the default as no other is
+/// advertised.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// The table entries every encoder and decoder starts with.
+/// https://tools.ietf.org/html/rfc7541#appendix-A
+const STATIC_TABLE: [(&'static str, &'static str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The code and its length in bits for each byte, and for the end of the string at 256.
+/// https://tools.ietf.org/html/rfc7541#appendix-B
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13), (0x7fffd8, 23), (0xfffffe2, 28), (0xfffffe3, 28), (0xfffffe4, 28),
+    (0xfffffe5, 28), (0xfffffe6, 28), (0xfffffe7, 28), (0xfffffe8, 28), (0xffffea, 24),
+    (0x3ffffffc, 30), (0xfffffe9, 28), (0xfffffea, 28), (0x3ffffffd, 30), (0xfffffeb, 28),
+    (0xfffffec, 28), (0xfffffed, 28), (0xfffffee, 28), (0xfffffef, 28), (0xffffff0, 28),
+    (0xffffff1, 28), (0xffffff2, 28), (0x3ffffffe, 30), (0xffffff3, 28), (0xffffff4, 28),
+    (0xffffff5, 28), (0xffffff6, 28), (0xffffff7, 28), (0xffffff8, 28), (0xffffff9, 28),
+    (0xffffffa, 28), (0xffffffb, 28), (0x14, 6), (0x3f8, 10), (0x3f9, 10),
+    (0xffa, 12), (0x1ff9, 13), (0x15, 6), (0xf8, 8), (0x7fa, 11),
+    (0x3fa, 10), (0x3fb, 10), (0xf9, 8), (0x7fb, 11), (0xfa, 8),
+    (0x16, 6), (0x17, 6), (0x18, 6), (0x0, 5), (0x1, 5),
+    (0x2, 5), (0x19, 6), (0x1a, 6), (0x1b, 6), (0x1c, 6),
+    (0x1d, 6), (0x1e, 6), (0x1f, 6), (0x5c, 7), (0xfb, 8),
+    (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10), (0x1ffa, 13),
+    (0x21, 6), (0x5d, 7), (0x5e, 7), (0x5f, 7), (0x60, 7),
+    (0x61, 7), (0x62, 7), (0x63, 7), (0x64, 7), (0x65, 7),
+    (0x66, 7), (0x67, 7), (0x68, 7), (0x69, 7), (0x6a, 7),
+    (0x6b, 7), (0x6c, 7), (0x6d, 7), (0x6e, 7), (0x6f, 7),
+    (0x70, 7), (0x71, 7), (0x72, 7), (0xfc, 8), (0x73, 7),
+    (0xfd, 8), (0x1ffb, 13), (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14),
+    (0x22, 6), (0x7ffd, 15), (0x3, 5), (0x23, 6), (0x4, 5),
+    (0x24, 6), (0x5, 5), (0x25, 6), (0x26, 6), (0x27, 6),
+    (0x6, 5), (0x74, 7), (0x75, 7), (0x28, 6), (0x29, 6),
+    (0x2a, 6), (0x7, 5), (0x2b, 6), (0x76, 7), (0x2c, 6),
+    (0x8, 5), (0x9, 5), (0x2d, 6), (0x77, 7), (0x78, 7),
+    (0x79, 7), (0x7a, 7), (0x7b, 7), (0x7ffe, 15), (0x7fc, 11),
+    (0x3ffd, 14), (0x1ffd, 13), (0xffffffc, 28), (0xfffe6, 20), (0x3fffd2, 22),
+    (0xfffe7, 20), (0xfffe8, 20), (0x3fffd3, 22), (0x3fffd4, 22), (0x3fffd5, 22),
+    (0x7fffd9, 23), (0x3fffd6, 22), (0x7fffda, 23), (0x7fffdb, 23), (0x7fffdc, 23),
+    (0x7fffdd, 23), (0x7fffde, 23), (0xffffeb, 24), (0x7fffdf, 23), (0xffffec, 24),
+    (0xffffed, 24), (0x3fffd7, 22), (0x7fffe0, 23), (0xffffee, 24), (0x7fffe1, 23),
+    (0x7fffe2, 23), (0x7fffe3, 23), (0x7fffe4, 23), (0x1fffdc, 21), (0x3fffd8, 22),
+    (0x7fffe5, 23), (0x3fffd9, 22), (0x7fffe6, 23), (0x7fffe7, 23), (0xffffef, 24),
+    (0x3fffda, 22), (0x1fffdd, 21), (0xfffe9, 20), (0x3fffdb, 22), (0x3fffdc, 22),
+    (0x7fffe8, 23), (0x7fffe9, 23), (0x1fffde, 21), (0x7fffea, 23), (0x3fffdd, 22),
+    (0x3fffde, 22), (0xfffff0, 24), (0x1fffdf, 21), (0x3fffdf, 22), (0x7fffeb, 23),
+    (0x7fffec, 23), (0x1fffe0, 21), (0x1fffe1, 21), (0x3fffe0, 22), (0x1fffe2, 21),
+    (0x7fffed, 23), (0x3fffe1, 22), (0x7fffee, 23), (0x7fffef, 23), (0xfffea, 20),
+    (0x3fffe2, 22), (0x3fffe3, 22), (0x3fffe4, 22), (0x7ffff0, 23), (0x3fffe5, 22),
+    (0x3fffe6, 22), (0x7ffff1, 23), (0x3ffffe0, 26), (0x3ffffe1, 26), (0xfffeb, 20),
+    (0x7fff1, 19), (0x3fffe7, 22), (0x7ffff2, 23), (0x3fffe8, 22), (0x1ffffec, 25),
+    (0x3ffffe2, 26), (0x3ffffe3, 26), (0x3ffffe4, 26), (0x7ffffde, 27), (0x7ffffdf, 27),
+    (0x3ffffe5, 26), (0xfffff1, 24), (0x1ffffed, 25), (0x7fff2, 19), (0x1fffe3, 21),
+    (0x3ffffe6, 26), (0x7ffffe0, 27), (0x7ffffe1, 27), (0x3ffffe7, 26), (0x7ffffe2, 27),
+    (0xfffff2, 24), (0x1fffe4, 21), (0x1fffe5, 21), (0x3ffffe8, 26), (0x3ffffe9, 26),
+    (0xffffffd, 28), (0x7ffffe3, 27), (0x7ffffe4, 27), (0x7ffffe5, 27), (0xfffec, 20),
+    (0xfffff3, 24), (0xfffed, 20), (0x1fffe6, 21), (0x3fffe9, 22), (0x1fffe7, 21),
+    (0x1fffe8, 21), (0x7ffff3, 23), (0x3fffea, 22), (0x3fffeb, 22), (0x1ffffee, 25),
+    (0x1ffffef, 25), (0xfffff4, 24), (0xfffff5, 24), (0x3ffffea, 26), (0x7ffff4, 23),
+    (0x3ffffeb, 26), (0x7ffffe6, 27), (0x3ffffec, 26), (0x3ffffed, 26), (0x7ffffe7, 27),
+    (0x7ffffe8, 27), (0x7ffffe9, 27), (0x7ffffea, 27), (0x7ffffeb, 27), (0xffffffe, 28),
+    (0x7ffffec, 27), (0x7ffffed, 27), (0x7ffffee, 27), (0x7ffffef, 27), (0x7fffff0, 27),
+    (0x3ffffee, 26), (0x3fffffff, 30),
+];
+
+const EOS: usize = 256;
+
+/// A header block that couldn't be decoded, which is a connection error.
+#[derive(Debug, PartialEq)]
+pub struct HpackError(pub &'static str);
+
+impl fmt::Display for HpackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid header block: {}", self.0)
+    }
+}
+
+/// Encodes header lists as literals that aren't added to the dynamic table, so that the
+/// encoder needs no state, with names and complete fields taken from the static table.
+pub struct Encoder;
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder
+    }
+
+    pub fn encode(&mut self, headers: &[(String, String)]) -> Vec<u8> {
+        let mut block = vec![];
+        for &(ref name, ref value) in headers {
+            let exact = STATIC_TABLE.iter().position(|&(n, v)| n == name && v == value);
+            if let Some(index) = exact {
+                // https://tools.ietf.org/html/rfc7541#section-6.1
+                encode_integer(&mut block, 0x80, 7, index + 1);
+                continue;
+            }
+            // https://tools.ietf.org/html/rfc7541#section-6.2.2
+            match STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+                Some(index) => encode_integer(&mut block, 0, 4, index + 1),
+                None => {
+                    block.push(0);
+                    encode_string(&mut block, name.as_bytes());
+                }
+            }
+            encode_string(&mut block, value.as_bytes());
+        }
+        block
+    }
+}
+
+/// https://tools.ietf.org/html/rfc7541#section-5.1
+fn encode_integer(block: &mut Vec<u8>, flags: u8, prefix_bits: u8, mut value: usize) {
+    let max_prefix = (1 << prefix_bits) - 1;
+    if value < max_prefix {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | max_prefix as u8);
+    value -= max_prefix;
+    while value >= 0x80 {
+        block.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+/// https://tools.ietf.org/html/rfc7541#section-5.2
+fn encode_string(block: &mut Vec<u8>, string: &[u8]) {
+    let huffman_len = (string.iter().map(|&byte| HUFFMAN_CODES[byte as usize].1 as usize).sum::<usize>() + 7) / 8;
+    if huffman_len < string.len() {
+        encode_integer(block, 0x80, 7, huffman_len);
+        huffman_encode(block, string);
+    } else {
+        encode_integer(block, 0, 7, string.len());
+        block.extend_from_slice(string);
+    }
+}
+
+fn huffman_encode(block: &mut Vec<u8>, string: &[u8]) {
+    let (mut bits, mut bit_len) = (0u64, 0);
+    for &byte in string {
+        let (code, len) = HUFFMAN_CODES[byte as usize];
+        bits = bits << len | code as u64;
+        bit_len += len;
+        while bit_len >= 8 {
+            bit_len -= 8;
+            block.push((bits >> bit_len) as u8);
+        }
+        bits &= (1 << bit_len) - 1;
+    }
+    // The last byte is padded with the most significant bits of the end of the string.
+    if bit_len > 0 {
+        block.push((bits << (8 - bit_len)) as u8 | 0xff >> bit_len);
+    }
+}
+
+/// A node of the tree that Huffman codes are decoded by: the symbol of a leaf, or the
+/// indices of the nodes for a 0 and a 1 bit.
+enum HuffmanNode {
+    Leaf(usize),
+    Branch([usize; 2]),
+}
+
+fn huffman_tree() -> Vec<HuffmanNode> {
+    let mut tree = vec![HuffmanNode::Branch([0, 0])];
+    for (symbol, &(code, len)) in HUFFMAN_CODES.iter().enumerate() {
+        let mut node = 0;
+        for shift in (0..len).rev() {
+            let bit = (code >> shift & 1) as usize;
+            let next = match tree[node] {
+                HuffmanNode::Branch(children) => children[bit],
+                HuffmanNode::Leaf(_) => unreachable!("Huffman codes are prefix-free"),
+            };
+            node = if next != 0 {
+                next
+            } else {
+                tree.push(if shift == 0 { HuffmanNode::Leaf(symbol) } else { HuffmanNode::Branch([0, 0]) });
+                let new = tree.len() - 1;
+                if let HuffmanNode::Branch(ref mut children) = tree[node] {
+                    children[bit] = new;
+                }
+                new
+            };
+        }
+    }
+    tree
+}
+
+/// Decodes header blocks, keeping the dynamic table they build up between them.
+pub struct Decoder {
+    dynamic_table: VecDeque<(String, String)>,
+    /// The size of the entries in the dynamic table, as counted by the specification.
+    size: usize,
+    max_size: usize,
+    huffman_tree: Vec<HuffmanNode>,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            dynamic_table: VecDeque::new(),
+            size: 0,
+            max_size: DEFAULT_TABLE_SIZE,
+            huffman_tree: huffman_tree(),
+        }
+    }
+
+    /// https://tools.ietf.org/html/rfc7541#section-6
+    pub fn decode(&mut self, mut block: &[u8]) -> Result<Vec<(String, String)>, HpackError> {
+        let mut headers = vec![];
+        while let Some(&first) = block.first() {
+            if first & 0x80 != 0 {
+                let index = try!(decode_integer(&mut block, 7));
+                headers.push(try!(self.entry(index)));
+            } else if first & 0xc0 == 0x40 {
+                let header = try!(self.decode_literal(&mut block, 6));
+                self.insert(header.clone());
+                headers.push(header);
+            } else if first & 0xe0 == 0x20 {
+                let max_size = try!(decode_integer(&mut block, 5));
+                if max_size > DEFAULT_TABLE_SIZE {
+                    return Err(HpackError("table size update above the limit"));
+                }
+                self.max_size = max_size;
+                self.evict(0);
+            } else {
+                // Literals without indexing, and literals that are never indexed.
+                headers.push(try!(self.decode_literal(&mut block, 4)));
+            }
+        }
+        Ok(headers)
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String), HpackError> {
+        if index == 0 {
+            return Err(HpackError("index 0"));
+        }
+        if index <= STATIC_TABLE.len() {
+            let (name, value) = STATIC_TABLE[index - 1];
+            return Ok((name.to_owned(), value.to_owned()));
+        }
+        self.dynamic_table.get(index - STATIC_TABLE.len() - 1).cloned().ok_or(HpackError("index out of range"))
+    }
+
+    fn decode_literal(&self, block: &mut &[u8], prefix_bits: u8) -> Result<(String, String), HpackError> {
+        let name = match try!(decode_integer(block, prefix_bits)) {
+            0 => try!(self.decode_string(block)),
+            index => try!(self.entry(index)).0,
+        };
+        let value = try!(self.decode_string(block));
+        Ok((name, value))
+    }
+
+    fn decode_string(&self, block: &mut &[u8]) -> Result<String, HpackError> {
+        let huffman = block.first().map_or(false, |&first| first & 0x80 != 0);
+        let len = try!(decode_integer(block, 7));
+        if block.len() < len {
+            return Err(HpackError("truncated string"));
+        }
+        let (string, rest) = block.split_at(len);
+        *block = rest;
+        let bytes = if huffman { try!(self.huffman_decode(string)) } else { string.to_vec() };
+        String::from_utf8(bytes).map_err(|_| HpackError("header isn't UTF-8"))
+    }
+
+    fn huffman_decode(&self, string: &[u8]) -> Result<Vec<u8>, HpackError> {
+        let mut decoded = vec![];
+        let mut node = 0;
+        // The bits read since the last symbol, which must be a short run of ones at the end.
+        let (mut pending_bits, mut pending_ones) = (0, true);
+        for &byte in string {
+            for shift in (0..8).rev() {
+                let bit = (byte >> shift & 1) as usize;
+                node = match self.huffman_tree[node] {
+                    HuffmanNode::Branch(children) => children[bit],
+                    HuffmanNode::Leaf(_) => unreachable!("decoding restarts at the root after a leaf"),
+                };
+                pending_bits += 1;
+                pending_ones &= bit == 1;
+                if let HuffmanNode::Leaf(symbol) = self.huffman_tree[node] {
+                    if symbol == EOS {
+                        return Err(HpackError("end of string symbol in a string"));
+                    }
+                    decoded.push(symbol as u8);
+                    node = 0;
+                    pending_bits = 0;
+                    pending_ones = true;
+                }
+            }
+        }
+        if pending_bits > 7 || !pending_ones {
+            return Err(HpackError("invalid Huffman padding"));
+        }
+        Ok(decoded)
+    }
+
+    /// https://tools.ietf.org/html/rfc7541#section-4.4
+    fn insert(&mut self, header: (String, String)) {
+        let size = header.0.len() + header.1.len() + 32;
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.dynamic_table.push_front(header);
+        }
+    }
+
+    /// Evict entries until there is room for an entry of `size`, or the table is empty.
+    fn evict(&mut self, size: usize) {
+        while self.size + size > self.max_size {
+            match self.dynamic_table.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + 32,
+                None => break,
+            }
+        }
+    }
+}
+
+/// https://tools.ietf.org/html/rfc7541#section-5.1
+fn decode_integer(block: &mut &[u8], prefix_bits: u8) -> Result<usize, HpackError> {
+    let max_prefix = (1usize << prefix_bits) - 1;
+    let (&first, mut rest) = match block.split_first() {
+        Some(split) => split,
+        None => return Err(HpackError("truncated integer")),
+    };
+    let mut value = first as usize & max_prefix;
+    if value == max_prefix {
+        let mut shift = 0;
+        loop {
+            let (&byte, remaining) = match rest.split_first() {
+                Some(split) => split,
+                None => return Err(HpackError("truncated integer")),
+            };
+            rest = remaining;
+            if shift > 28 {
+                return Err(HpackError("integer too large"));
+            }
+            value += (byte as usize & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+    *block = rest;
+    Ok(value)
+}
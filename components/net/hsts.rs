@@ -3,8 +3,9 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use net_traits::IncludeSubdomains;
+use net_traits::pub_domains::{canonical_host, is_ip_host};
 use rustc_serialize::json::decode;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::mem;
 use std::str::from_utf8;
 use time;
 use url::Url;
@@ -20,11 +21,11 @@ pub struct HstsEntry {
 
 impl HstsEntry {
     pub fn new(host: String, subdomains: IncludeSubdomains, max_age: Option<u64>) -> Option<HstsEntry> {
-        if host.parse::<Ipv4Addr>().is_ok() || host.parse::<Ipv6Addr>().is_ok() {
+        if is_ip_host(&host) {
             None
         } else {
             Some(HstsEntry {
-                host: host,
+                host: canonical_host(&host),
                 include_subdomains: (subdomains == IncludeSubdomains::Included),
                 max_age: max_age,
                 timestamp: Some(time::get_time().sec as u64)
@@ -77,12 +78,25 @@ impl HstsList {
             .expect("Servo HSTS preload file is invalid")
     }
 
+    /// An estimate of the heap memory held by the list, for memory reports.
+    pub fn estimated_size(&self) -> usize {
+        self.entries.capacity() * mem::size_of::<HstsEntry>() +
+        self.entries.iter().map(|entry| entry.host.capacity()).sum::<usize>()
+    }
+
     pub fn is_host_secure(&self, host: &str) -> bool {
         // TODO - Should this be faster than O(n)? The HSTS list is only a few
         // hundred or maybe thousand entries...
         //
         // Could optimise by searching for exact matches first (via a map or
         // something), then checking for subdomains.
+        //
+        // Entries are never for IP addresses, which could otherwise be taken for
+        // subdomains of an entry like `0.1`.
+        if is_ip_host(host) {
+            return false;
+        }
+        let host = &canonical_host(host);
         self.entries.iter().any(|e| {
             if e.include_subdomains {
                 e.matches_subdomain(host) || e.matches_domain(host)
@@ -0,0 +1,895 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A client for [HTTP/2](https://tools.ietf.org/html/rfc7540) that makes the requests
+//! hyper sends as streams of one connection per origin.
+//!
+//! The connection is owned by a thread of its own, which writes the frames of every
+//! stream and hands what it reads to the stream it is for. A request talks to it through
+//! an `Http2Message`, which hyper uses in place of the `Http11Message` it would make for
+//! a connection from a pool.
+//!
+//! The thread only looks at the connection while streams are open on it; an idle session
+//! waits for requests without waking up, and is closed once it has been idle for a while.
+
+use connector::{set_response_bytes_read, thread_deadline};
+use hpack::{Decoder, Encoder};
+use hyper::header::Headers;
+use hyper::http::RawStatus;
+use hyper::http::message::{HttpMessage, RequestHead, ResponseHead};
+use hyper::net::NetworkStream;
+use hyper::status::StatusCode;
+use hyper::version::HttpVersion;
+use std::ascii::AsciiExt;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::cmp::{max, min};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError, channel};
+use std::time::Duration;
+use util::thread::spawn_named;
+
+/// What the client sends first on a connection.
+/// https://tools.ietf.org/html/rfc7540#section-3.5
+pub const PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// https://tools.ietf.org/html/rfc7540#section-6
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PUSH_PROMISE: u8 = 0x5;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+pub const END_STREAM: u8 = 0x1;
+pub const ACK: u8 = 0x1;
+pub const END_HEADERS: u8 = 0x4;
+pub const PADDED: u8 = 0x8;
+pub const PRIORITY: u8 = 0x20;
+
+/// https://tools.ietf.org/html/rfc7540#section-6.5.2
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+/// https://tools.ietf.org/html/rfc7540#section-7
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const CANCEL: u32 = 0x8;
+const COMPRESSION_ERROR: u32 = 0x9;
+
+/// The largest frame either side may send before the other allows larger ones.
+const DEFAULT_MAX_FRAME_SIZE: usize = 16384;
+
+/// The flow control window of a connection or stream before any `WINDOW_UPDATE`.
+const DEFAULT_WINDOW_SIZE: i64 = 65535;
+
+/// The number of streams assumed to be allowed at once until the server says otherwise,
+/// which is the least it should allow.
+const DEFAULT_MAX_CONCURRENT_STREAMS: usize = 100;
+
+/// How long the connection thread waits for a frame, while streams are open, before
+/// looking for new requests.
+const POLL_INTERVAL_MS: u64 = 5;
+
+/// How long a session is kept without any request before it is closed.
+pub const IDLE_TIMEOUT_SECS: u64 = 60;
+
+/// A frame, as it is sent on the connection.
+/// https://tools.ietf.org/html/rfc7540#section-4.1
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(kind: u8, flags: u8, stream_id: u32, payload: Vec<u8>) -> Frame {
+        Frame {
+            kind: kind,
+            flags: flags,
+            stream_id: stream_id,
+            payload: payload,
+        }
+    }
+
+    /// A `SETTINGS` frame with the `settings` given as identifiers and values.
+    pub fn settings(settings: &[(u16, u32)]) -> Frame {
+        let mut payload = vec![];
+        for &(id, value) in settings {
+            payload.extend_from_slice(&[(id >> 8) as u8, id as u8]);
+            payload.extend_from_slice(&u32_bytes(value));
+        }
+        Frame::new(SETTINGS, 0, 0, payload)
+    }
+
+    /// The frame at the start of `bytes` and its length, if all of it is there.
+    pub fn parse(bytes: &[u8], max_size: usize) -> io::Result<Option<(Frame, usize)>> {
+        if bytes.len() < 9 {
+            return Ok(None);
+        }
+        let len = (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize;
+        if len > max_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP/2 frame too large"));
+        }
+        if bytes.len() < 9 + len {
+            return Ok(None);
+        }
+        let frame = Frame::new(bytes[3], bytes[4], read_u32(&bytes[5..9]) & 0x7fffffff, bytes[9..9 + len].to_vec());
+        Ok(Some((frame, 9 + len)))
+    }
+
+    /// Read a whole frame, waiting for it if need be.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Frame> {
+        let mut header = [0; 9];
+        try!(reader.read_exact(&mut header));
+        let len = (header[0] as usize) << 16 | (header[1] as usize) << 8 | header[2] as usize;
+        let mut bytes = header.to_vec();
+        bytes.resize(9 + len, 0);
+        try!(reader.read_exact(&mut bytes[9..]));
+        Frame::parse(&bytes, len).map(|frame| frame.expect("the whole frame was read").0)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let len = self.payload.len();
+        let mut bytes = vec![(len >> 16) as u8, (len >> 8) as u8, len as u8, self.kind, self.flags];
+        bytes.extend_from_slice(&u32_bytes(self.stream_id));
+        bytes.extend_from_slice(&self.payload);
+        writer.write_all(&bytes)
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+fn u32_bytes(value: u32) -> [u8; 4] {
+    [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+}
+
+/// The payload of a `DATA` or `HEADERS` frame without its padding.
+fn unpadded(frame: &Frame) -> io::Result<&[u8]> {
+    if frame.flags & PADDED == 0 {
+        return Ok(&frame.payload);
+    }
+    let padding = frame.payload.first().map_or(0, |&padding| padding as usize);
+    if frame.payload.is_empty() || padding >= frame.payload.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP/2 frame with invalid padding"));
+    }
+    Ok(&frame.payload[1..frame.payload.len() - padding])
+}
+
+/// What the connection thread hands on to a request.
+#[derive(Debug)]
+enum StreamEvent {
+    /// The stream was given an identifier, which it is cancelled by.
+    Opened(u32),
+    /// A header block, which ends the stream if the flag is set.
+    Headers(Vec<(String, String)>, bool),
+    /// Data, and the length of the frame it came in, which is given back to the window
+    /// of the stream once the data has been read.
+    Data(Vec<u8>, usize),
+    End,
+    Error(String),
+}
+
+enum Command {
+    Open {
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+        events: Sender<StreamEvent>,
+    },
+    Reset(u32),
+    /// The request read the data of a frame of this length, so the server may send more.
+    Consumed(u32, usize),
+}
+
+/// A request waiting for a stream, as the server allows no more at once.
+struct PendingOpen {
+    stream_id: u32,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+struct Stream {
+    events: Sender<StreamEvent>,
+    send_window: i64,
+    /// The part of the request body that flow control hasn't let through yet.
+    unsent: Vec<u8>,
+}
+
+/// The state of a connection, which lives on the thread that owns it.
+struct Connection<S> {
+    stream: S,
+    origin: String,
+    closed: Arc<AtomicBool>,
+    read_buffer: Vec<u8>,
+    encoder: Encoder,
+    decoder: Decoder,
+    next_stream_id: u32,
+    streams: HashMap<u32, Stream>,
+    pending: VecDeque<PendingOpen>,
+    /// The events senders of the pending requests, which are kept apart so that a
+    /// request can be cancelled while it waits.
+    pending_events: HashMap<u32, Sender<StreamEvent>>,
+    /// A header block that goes on in `CONTINUATION` frames, with the stream it is for and
+    /// whether it ends the stream.
+    continuation: Option<(u32, bool, Vec<u8>)>,
+    max_concurrent_streams: usize,
+    /// The same, for the session to tell the fetches waiting for a stream.
+    streams_allowed: Arc<AtomicUsize>,
+    max_frame_size: usize,
+    initial_window: i64,
+    send_window: i64,
+    /// Set once the server said it is going away, to the last stream it will process.
+    last_stream_id: Option<u32>,
+    /// Whether the settings the server sends first on the connection have been read.
+    server_settings_received: bool,
+}
+
+impl<S: NetworkStream> Connection<S> {
+    fn write_frame(&mut self, frame: Frame) -> io::Result<()> {
+        frame.write_to(&mut self.stream)
+    }
+
+    fn handle_command(&mut self, command: Command) -> io::Result<()> {
+        match command {
+            Command::Open { headers, body, events } => {
+                if self.last_stream_id.is_some() || self.next_stream_id > 0x7fffffff {
+                    let _ = events.send(StreamEvent::Error("HTTP/2 connection is going away".to_owned()));
+                    return Ok(());
+                }
+                let stream_id = self.next_stream_id;
+                self.next_stream_id += 2;
+                let _ = events.send(StreamEvent::Opened(stream_id));
+                self.pending_events.insert(stream_id, events);
+                self.pending.push_back(PendingOpen {
+                    stream_id: stream_id,
+                    headers: headers,
+                    body: body,
+                });
+                self.open_pending()
+            }
+            Command::Reset(stream_id) => {
+                if self.pending_events.remove(&stream_id).is_some() {
+                    self.pending.retain(|pending| pending.stream_id != stream_id);
+                } else if self.streams.remove(&stream_id).is_some() {
+                    try!(self.write_frame(Frame::new(RST_STREAM, 0, stream_id, u32_bytes(CANCEL).to_vec())));
+                    try!(self.open_pending());
+                }
+                Ok(())
+            }
+            Command::Consumed(stream_id, len) => {
+                if self.streams.contains_key(&stream_id) {
+                    try!(self.write_frame(Frame::new(WINDOW_UPDATE, 0, stream_id, u32_bytes(len as u32).to_vec())));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Send the requests that are waiting, as far as the server allows streams at once.
+    fn open_pending(&mut self) -> io::Result<()> {
+        while self.streams.len() < self.max_concurrent_streams {
+            let PendingOpen { stream_id, headers, body } = match self.pending.pop_front() {
+                Some(pending) => pending,
+                None => break,
+            };
+            let events = match self.pending_events.remove(&stream_id) {
+                Some(events) => events,
+                None => continue,
+            };
+            let block = self.encoder.encode(&headers);
+            let end_stream = if body.is_empty() { END_STREAM } else { 0 };
+            let mut fragments = block.chunks(self.max_frame_size);
+            let first = fragments.next().unwrap_or(&[]).to_vec();
+            let mut rest: Vec<_> = fragments.map(|fragment| fragment.to_vec()).collect();
+            let last_flags = if rest.is_empty() { END_HEADERS } else { 0 };
+            try!(self.write_frame(Frame::new(HEADERS, end_stream | last_flags, stream_id, first)));
+            let continuations = rest.len();
+            for (index, fragment) in rest.drain(..).enumerate() {
+                let flags = if index + 1 == continuations { END_HEADERS } else { 0 };
+                try!(self.write_frame(Frame::new(CONTINUATION, flags, stream_id, fragment)));
+            }
+            self.streams.insert(stream_id, Stream {
+                events: events,
+                send_window: self.initial_window,
+                unsent: body,
+            });
+            try!(self.send_data(stream_id));
+        }
+        Ok(())
+    }
+
+    /// Send as much of the body of a stream as flow control allows.
+    fn send_data(&mut self, stream_id: u32) -> io::Result<()> {
+        loop {
+            let (chunk, end_stream) = {
+                let stream = match self.streams.get_mut(&stream_id) {
+                    Some(stream) => stream,
+                    None => return Ok(()),
+                };
+                let allowed = min(min(self.send_window, stream.send_window), self.max_frame_size as i64);
+                if stream.unsent.is_empty() || allowed <= 0 {
+                    return Ok(());
+                }
+                let len = min(allowed as usize, stream.unsent.len());
+                let chunk: Vec<u8> = stream.unsent.drain(..len).collect();
+                stream.send_window -= len as i64;
+                (chunk, stream.unsent.is_empty())
+            };
+            self.send_window -= chunk.len() as i64;
+            try!(self.write_frame(Frame::new(DATA, if end_stream { END_STREAM } else { 0 }, stream_id, chunk)));
+        }
+    }
+
+    fn send_all_data(&mut self) -> io::Result<()> {
+        let ids: Vec<u32> = self.streams.keys().cloned().collect();
+        for stream_id in ids {
+            try!(self.send_data(stream_id));
+        }
+        Ok(())
+    }
+
+    fn finish_stream(&mut self, stream_id: u32, event: StreamEvent) -> io::Result<()> {
+        if let Some(stream) = self.streams.remove(&stream_id) {
+            let _ = stream.events.send(event);
+        }
+        self.open_pending()
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> io::Result<()> {
+        if self.continuation.is_some() && frame.kind != CONTINUATION {
+            return self.connection_error(PROTOCOL_ERROR, "HTTP/2 header block interrupted");
+        }
+        match frame.kind {
+            DATA => {
+                let data = try!(unpadded(&frame)).to_vec();
+                // The window of the connection is opened again straight away, so that a
+                // stream that isn't read doesn't hold up the others. That of the stream is
+                // only opened once the request has read the data, so that no more than a
+                // window of it is ever buffered.
+                if !frame.payload.is_empty() {
+                    let increment = u32_bytes(frame.payload.len() as u32).to_vec();
+                    try!(self.write_frame(Frame::new(WINDOW_UPDATE, 0, 0, increment.clone())));
+                    if data.is_empty() && frame.flags & END_STREAM == 0 && self.streams.contains_key(&frame.stream_id) {
+                        try!(self.write_frame(Frame::new(WINDOW_UPDATE, 0, frame.stream_id, increment)));
+                    }
+                }
+                if let Some(stream) = self.streams.get(&frame.stream_id) {
+                    if !data.is_empty() {
+                        let _ = stream.events.send(StreamEvent::Data(data, frame.payload.len()));
+                    }
+                }
+                if frame.flags & END_STREAM != 0 {
+                    try!(self.finish_stream(frame.stream_id, StreamEvent::End));
+                }
+            }
+            HEADERS => {
+                let mut fragment = try!(unpadded(&frame));
+                if frame.flags & PRIORITY != 0 {
+                    if fragment.len() < 5 {
+                        return self.connection_error(FRAME_SIZE_ERROR, "HTTP/2 HEADERS frame too short");
+                    }
+                    fragment = &fragment[5..];
+                }
+                let fragment = fragment.to_vec();
+                let end_stream = frame.flags & END_STREAM != 0;
+                self.continuation = Some((frame.stream_id, end_stream, fragment));
+                if frame.flags & END_HEADERS != 0 {
+                    try!(self.finish_header_block());
+                }
+            }
+            CONTINUATION => {
+                let continues = self.continuation.as_ref().map_or(false, |&(stream_id, _, _)| {
+                    stream_id == frame.stream_id
+                });
+                if !continues {
+                    return self.connection_error(PROTOCOL_ERROR, "unexpected HTTP/2 CONTINUATION frame");
+                }
+                if let Some((_, _, ref mut block)) = self.continuation {
+                    block.extend_from_slice(&frame.payload);
+                }
+                if frame.flags & END_HEADERS != 0 {
+                    try!(self.finish_header_block());
+                }
+            }
+            RST_STREAM => {
+                let code = if frame.payload.len() == 4 { read_u32(&frame.payload) } else { PROTOCOL_ERROR };
+                let error = format!("HTTP/2 stream reset by the server (error code {})", code);
+                try!(self.finish_stream(frame.stream_id, StreamEvent::Error(error)));
+            }
+            SETTINGS => {
+                if frame.flags & ACK == 0 {
+                    self.server_settings_received = true;
+                    try!(self.apply_settings(&frame.payload));
+                    try!(self.write_frame(Frame::new(SETTINGS, ACK, 0, vec![])));
+                }
+            }
+            PUSH_PROMISE => {
+                return self.connection_error(PROTOCOL_ERROR, "HTTP/2 push promised though disabled");
+            }
+            PING => {
+                if frame.flags & ACK == 0 {
+                    try!(self.write_frame(Frame::new(PING, ACK, 0, frame.payload)));
+                }
+            }
+            GOAWAY => {
+                let last_stream_id = if frame.payload.len() >= 4 { read_u32(&frame.payload) & 0x7fffffff } else { 0 };
+                self.go_away(last_stream_id);
+            }
+            WINDOW_UPDATE => {
+                if frame.payload.len() != 4 {
+                    return self.connection_error(FRAME_SIZE_ERROR, "HTTP/2 WINDOW_UPDATE frame of the wrong size");
+                }
+                let increment = (read_u32(&frame.payload) & 0x7fffffff) as i64;
+                if frame.stream_id == 0 {
+                    self.send_window += increment;
+                    try!(self.send_all_data());
+                } else if let Some(stream) = self.streams.get_mut(&frame.stream_id) {
+                    stream.send_window += increment;
+                }
+                try!(self.send_data(frame.stream_id));
+            }
+            // Frames of unknown types, and priorities, are ignored.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish_header_block(&mut self) -> io::Result<()> {
+        let (stream_id, end_stream, block) = self.continuation.take().expect("a header block was started");
+        // The block is decoded even for a stream that was cancelled, to keep the dynamic
+        // table in step with the server.
+        let headers = match self.decoder.decode(&block) {
+            Ok(headers) => headers,
+            Err(error) => return self.connection_error(COMPRESSION_ERROR, &error.to_string()),
+        };
+        if let Some(stream) = self.streams.get(&stream_id) {
+            let _ = stream.events.send(StreamEvent::Headers(headers, end_stream));
+        }
+        if end_stream {
+            if let Some(stream) = self.streams.remove(&stream_id) {
+                let _ = stream.events.send(StreamEvent::End);
+            }
+            try!(self.open_pending());
+        }
+        Ok(())
+    }
+
+    /// https://tools.ietf.org/html/rfc7540#section-6.5.2
+    fn apply_settings(&mut self, payload: &[u8]) -> io::Result<()> {
+        if payload.len() % 6 != 0 {
+            return self.connection_error(FRAME_SIZE_ERROR, "HTTP/2 SETTINGS frame of the wrong size");
+        }
+        for setting in payload.chunks(6) {
+            let value = read_u32(&setting[2..]);
+            match (setting[0] as u16) << 8 | setting[1] as u16 {
+                SETTINGS_MAX_CONCURRENT_STREAMS => {
+                    self.max_concurrent_streams = value as usize;
+                    self.streams_allowed.store(value as usize, Ordering::SeqCst);
+                }
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let delta = value as i64 - self.initial_window;
+                    self.initial_window = value as i64;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                    }
+                }
+                SETTINGS_MAX_FRAME_SIZE => self.max_frame_size = value as usize,
+                _ => {}
+            }
+        }
+        try!(self.send_all_data());
+        self.open_pending()
+    }
+
+    /// Stop opening streams, and fail those the server won't process.
+    fn go_away(&mut self, last_stream_id: u32) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.last_stream_id = Some(last_stream_id);
+        let refused: Vec<u32> = self.streams.keys().filter(|&&id| id > last_stream_id).cloned().collect();
+        for stream_id in refused {
+            if let Some(stream) = self.streams.remove(&stream_id) {
+                let _ = stream.events.send(StreamEvent::Error("HTTP/2 stream refused".to_owned()));
+            }
+        }
+        self.pending.clear();
+        for (_, events) in self.pending_events.drain() {
+            let _ = events.send(StreamEvent::Error("HTTP/2 stream refused".to_owned()));
+        }
+    }
+
+    /// https://tools.ietf.org/html/rfc7540#section-5.4.1
+    fn connection_error(&mut self, code: u32, message: &str) -> io::Result<()> {
+        // No stream is opened by the server, as push is disabled.
+        let mut payload = u32_bytes(0).to_vec();
+        payload.extend_from_slice(&u32_bytes(code));
+        let _ = self.write_frame(Frame::new(GOAWAY, 0, 0, payload));
+        Err(io::Error::new(io::ErrorKind::InvalidData, message.to_owned()))
+    }
+
+    /// Read what has arrived, and handle the frames that are complete.
+    fn read_frames(&mut self) -> io::Result<()> {
+        let mut chunk = [0; 16384];
+        let len = match self.stream.read(&mut chunk) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "HTTP/2 connection closed")),
+            Ok(len) => len,
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock ||
+                              error.kind() == io::ErrorKind::TimedOut => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        self.read_buffer.extend_from_slice(&chunk[..len]);
+        loop {
+            let (frame, len) = match Frame::parse(&self.read_buffer, DEFAULT_MAX_FRAME_SIZE) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return Ok(()),
+                Err(_) => return self.connection_error(FRAME_SIZE_ERROR, "HTTP/2 frame too large"),
+            };
+            self.read_buffer.drain(..len);
+            try!(self.handle_frame(frame));
+        }
+    }
+
+    fn run(&mut self, commands: Receiver<Command>, idle_timeout: Duration) -> io::Result<()> {
+        try!(self.stream.write_all(PREFACE));
+        try!(self.write_frame(Frame::settings(&[(SETTINGS_ENABLE_PUSH, 0)])));
+        try!(self.stream.set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL_MS))));
+        loop {
+            if self.server_settings_received && self.streams.is_empty() && self.pending.is_empty() {
+                // Nothing is awaited from the server, so what it sends meanwhile is read
+                // along with the next request.
+                match commands.recv_timeout(idle_timeout) {
+                    Ok(command) => {
+                        try!(self.read_frames());
+                        try!(self.handle_command(command));
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.closed.store(true, Ordering::SeqCst);
+                        let mut payload = u32_bytes(0).to_vec();
+                        payload.extend_from_slice(&u32_bytes(NO_ERROR));
+                        let _ = self.write_frame(Frame::new(GOAWAY, 0, 0, payload));
+                        return Ok(());
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+            }
+            loop {
+                match commands.try_recv() {
+                    Ok(command) => try!(self.handle_command(command)),
+                    Err(TryRecvError::Empty) => break,
+                    // Every request and the session itself are gone.
+                    Err(TryRecvError::Disconnected) => {
+                        if self.streams.is_empty() {
+                            return Ok(());
+                        }
+                        break;
+                    }
+                }
+            }
+            if self.last_stream_id.is_some() && self.streams.is_empty() {
+                return Ok(());
+            }
+            try!(self.read_frames());
+        }
+    }
+}
+
+/// An HTTP/2 connection to an origin, on which requests are made by `message`.
+pub struct Http2Session {
+    commands: Mutex<Sender<Command>>,
+    /// Set once the connection can take no more requests.
+    closed: Arc<AtomicBool>,
+    /// The number of streams the server allows at once.
+    streams_allowed: Arc<AtomicUsize>,
+}
+
+impl Http2Session {
+    /// Start a session on `stream`, a connection to `origin` on which HTTP/2 was agreed,
+    /// which is closed once it has gone without requests for `idle_timeout`.
+    pub fn new<S: NetworkStream>(stream: S, origin: String, idle_timeout: Duration) -> Http2Session {
+        let (sender, receiver) = channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let streams_allowed = Arc::new(AtomicUsize::new(DEFAULT_MAX_CONCURRENT_STREAMS));
+        let mut connection = Connection {
+            stream: stream,
+            origin: origin.clone(),
+            closed: closed.clone(),
+            read_buffer: vec![],
+            encoder: Encoder::new(),
+            decoder: Decoder::new(),
+            next_stream_id: 1,
+            streams: HashMap::new(),
+            pending: VecDeque::new(),
+            pending_events: HashMap::new(),
+            continuation: None,
+            max_concurrent_streams: DEFAULT_MAX_CONCURRENT_STREAMS,
+            streams_allowed: streams_allowed.clone(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            initial_window: DEFAULT_WINDOW_SIZE,
+            send_window: DEFAULT_WINDOW_SIZE,
+            last_stream_id: None,
+            server_settings_received: false,
+        };
+        spawn_named(format!("HTTP/2 connection to {}", origin), move || {
+            if let Err(error) = connection.run(receiver, idle_timeout) {
+                debug!("HTTP/2 connection to {} failed: {}", connection.origin, error);
+            }
+            connection.closed.store(true, Ordering::SeqCst);
+            let streams = connection.streams.drain().map(|(_, stream)| stream.events);
+            for events in streams.chain(connection.pending_events.drain().map(|(_, events)| events)) {
+                let _ = events.send(StreamEvent::Error("HTTP/2 connection closed".to_owned()));
+            }
+        });
+        Http2Session {
+            commands: Mutex::new(sender),
+            closed: closed,
+            streams_allowed: streams_allowed,
+        }
+    }
+
+    /// Whether the connection can take no more requests.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// The number of requests that can be made at once on the session, if it can take any.
+    pub fn max_concurrent_streams(&self) -> Option<usize> {
+        if self.is_closed() {
+            return None;
+        }
+        // A server may allow no streams for a while, but a fetch has to be let through to
+        // wait for one, as none would ever finish to let it in.
+        Some(max(self.streams_allowed.load(Ordering::SeqCst), 1))
+    }
+
+    /// A message for a request on a new stream of the session.
+    pub fn message(&self) -> Http2Message {
+        Http2Message {
+            commands: self.commands.lock().unwrap().clone(),
+            request_headers: vec![],
+            body: vec![],
+            events: None,
+            data: vec![],
+            position: 0,
+            finished: false,
+            read_timeout: Cell::new(None),
+        }
+    }
+}
+
+/// The headers that only have a meaning for a single HTTP/1.1 connection, which HTTP/2
+/// doesn't allow.
+/// https://tools.ietf.org/html/rfc7540#section-8.1.2.2
+const CONNECTION_HEADERS: &'static [&'static str] = &[
+    "connection", "host", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade",
+];
+
+/// A request made as a stream of an HTTP/2 session. The request is sent once all of its
+/// body has been written.
+#[derive(Debug)]
+pub struct Http2Message {
+    commands: Sender<Command>,
+    request_headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    /// The identifier of the stream and what it receives, once it is opened.
+    events: Option<(u32, Receiver<StreamEvent>)>,
+    /// The part of the response body that has been received, and how much of it was read.
+    data: Vec<u8>,
+    position: usize,
+    finished: bool,
+    read_timeout: Cell<Option<Duration>>,
+}
+
+impl Http2Message {
+    /// The next event of the stream, waiting until the deadline of this thread or the read
+    /// timeout, if there is either.
+    fn next_event(&mut self) -> io::Result<StreamEvent> {
+        let timeout = match thread_deadline() {
+            Some(deadline) if deadline.has_passed() => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline passed"));
+            }
+            Some(deadline) => Some(deadline.remaining()),
+            None => self.read_timeout.get(),
+        };
+        let receiver = match self.events {
+            Some((_, ref receiver)) => receiver,
+            None => return Err(io::Error::new(io::ErrorKind::NotConnected, "HTTP/2 request not sent")),
+        };
+        let event = match timeout {
+            Some(timeout) => receiver.recv_timeout(timeout).map_err(|error| match error {
+                RecvTimeoutError::Timeout => io::Error::new(io::ErrorKind::TimedOut, "HTTP/2 stream timed out"),
+                RecvTimeoutError::Disconnected => io::Error::new(io::ErrorKind::ConnectionAborted,
+                                                                 "HTTP/2 connection closed"),
+            }),
+            None => receiver.recv().map_err(|_| {
+                io::Error::new(io::ErrorKind::ConnectionAborted, "HTTP/2 connection closed")
+            }),
+        };
+        match try!(event) {
+            StreamEvent::Error(error) => {
+                self.finished = true;
+                Err(io::Error::new(io::ErrorKind::ConnectionAborted, error))
+            }
+            event => Ok(event),
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        let (sender, receiver) = channel();
+        let command = Command::Open {
+            headers: self.request_headers.clone(),
+            body: self.body.split_off(0),
+            events: sender,
+        };
+        if self.commands.send(command).is_err() {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "HTTP/2 connection closed"));
+        }
+        self.events = Some((0, receiver));
+        match try!(self.next_event()) {
+            StreamEvent::Opened(stream_id) => {
+                self.events.as_mut().unwrap().0 = stream_id;
+                Ok(())
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP/2 stream not opened")),
+        }
+    }
+}
+
+impl HttpMessage for Http2Message {
+    fn set_outgoing(&mut self, head: RequestHead) -> ::hyper::Result<RequestHead> {
+        let authority = match head.url.port() {
+            Some(port) => format!("{}:{}", head.url.host_str().unwrap_or(""), port),
+            None => head.url.host_str().unwrap_or("").to_owned(),
+        };
+        let path = match head.url.query() {
+            Some(query) => format!("{}?{}", head.url.path(), query),
+            None => head.url.path().to_owned(),
+        };
+        // https://tools.ietf.org/html/rfc7540#section-8.1.2.3
+        let mut headers = vec![
+            (":method".to_owned(), head.method.to_string()),
+            (":scheme".to_owned(), head.url.scheme().to_owned()),
+            (":authority".to_owned(), authority),
+            (":path".to_owned(), path),
+        ];
+        for header in head.headers.iter() {
+            let name = header.name().to_ascii_lowercase();
+            let value = header.value_string();
+            if CONNECTION_HEADERS.contains(&&*name) || (name == "te" && value != "trailers") {
+                continue;
+            }
+            headers.push((name, value));
+        }
+        self.request_headers = headers;
+        Ok(head)
+    }
+
+    fn get_incoming(&mut self) -> ::hyper::Result<ResponseHead> {
+        if self.events.is_none() {
+            try!(self.send());
+        }
+        loop {
+            let (headers, end_stream) = match try!(self.next_event()) {
+                StreamEvent::Headers(headers, end_stream) => (headers, end_stream),
+                StreamEvent::Data(..) | StreamEvent::End => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP/2 response without headers").into());
+                }
+                StreamEvent::Opened(_) | StreamEvent::Error(_) => unreachable!(),
+            };
+            set_response_bytes_read();
+            let status = headers.iter()
+                                .find(|&&(ref name, _)| name == ":status")
+                                .and_then(|&(_, ref value)| value.parse::<u16>().ok());
+            let status = match status {
+                Some(status) => status,
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP/2 response without status").into()),
+            };
+            // Interim responses are skipped, as they are on HTTP/1.1 connections.
+            if status >= 100 && status < 200 && !end_stream {
+                continue;
+            }
+            self.finished = end_stream;
+            let mut response_headers = Headers::new();
+            let mut names: Vec<&str> = vec![];
+            for &(ref name, _) in &headers {
+                if !name.starts_with(':') && !names.contains(&&**name) {
+                    names.push(name);
+                }
+            }
+            for name in names {
+                let values = headers.iter()
+                                    .filter(|&&(ref header, _)| header == name)
+                                    .map(|&(_, ref value)| value.clone().into_bytes())
+                                    .collect();
+                response_headers.set_raw(name.to_owned(), values);
+            }
+            let reason = StatusCode::from_u16(status).canonical_reason().unwrap_or("");
+            return Ok(ResponseHead {
+                headers: response_headers,
+                raw_status: RawStatus(status, Cow::Borrowed(reason)),
+                version: HttpVersion::Http20,
+            });
+        }
+    }
+
+    fn set_read_timeout(&self, duration: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(duration);
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _duration: Option<Duration>) -> io::Result<()> {
+        // The request is handed to the connection thread rather than written here.
+        Ok(())
+    }
+
+    fn close_connection(&mut self) -> ::hyper::Result<()> {
+        // The connection is shared with other requests, so only the stream is closed.
+        if !self.finished {
+            if let Some((stream_id, _)) = self.events {
+                let _ = self.commands.send(Command::Reset(stream_id));
+            }
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    fn has_body(&self) -> bool {
+        !self.finished || self.position < self.data.len()
+    }
+}
+
+impl Write for Http2Message {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.body.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for Http2Message {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.data.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            match try!(self.next_event()) {
+                StreamEvent::Data(data, len) => {
+                    if let Some((stream_id, _)) = self.events {
+                        let _ = self.commands.send(Command::Consumed(stream_id, len));
+                    }
+                    self.data = data;
+                    self.position = 0;
+                }
+                // Trailers are ignored.
+                StreamEvent::Headers(_, end_stream) => self.finished = end_stream,
+                StreamEvent::End => self.finished = true,
+                StreamEvent::Opened(_) | StreamEvent::Error(_) => unreachable!(),
+            }
+        }
+        let len = min(buf.len(), self.data.len() - self.position);
+        buf[..len].copy_from_slice(&self.data[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl Drop for Http2Message {
+    fn drop(&mut self) {
+        let _ = self.close_connection();
+    }
+}
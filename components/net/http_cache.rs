@@ -0,0 +1,758 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Caches of complete HTTP responses, on disk and in memory.
+//!
+//! Responses are stored if they have a freshness lifetime, explicit or guessed from when
+//! they were last modified, or a validator that lets them be revalidated with a
+//! conditional request once they go stale. Only the disk
+//! cache keeps stale responses; the memory cache drops them. Once a cache grows past its
+//! size limit, the least recently used responses are evicted. Each response is stored
+//! under the cache key of its request, `Request::cache_key`.
+//!
+//! A stale response whose `stale-while-revalidate` window hasn't run out yet may still be
+//! used, as long as it is revalidated in the background; only one such revalidation of a
+//! response is underway at a time.
+//!
+//! The disk cache keeps the bodies in files of their own, while the index of what is
+//! cached is kept in memory and written back as JSON on exit, like the rest of a
+//! resource group's state. The lock on a disk cache only guards its index; bodies are
+//! read and written with it released, by `lookup` and `store`. The memory cache lasts as
+//! long as its resource group.
+
+use hyper::header::{ContentEncoding, ContentLength, Date, ETag, Expires, Headers, HttpDate};
+use hyper::header::{IfModifiedSince, IfNoneMatch, LastModified, Range, SetCookie, Vary};
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use lock_recovery::write_lock;
+use net_traits::request::{CacheMode, Request, cache_key};
+use net_traits::response::{CacheState, Response, ResponseBody};
+use resource_thread::{read_json_from_file, write_json_to_file};
+use servo_url::ServoUrl;
+use std::ascii::AsciiExt;
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use time;
+use url::Origin as UrlOrigin;
+
+const INDEX_FILE: &'static str = "index.json";
+
+/// The longest a response is kept fresh for without an explicit lifetime: a day, past
+/// which RFC 7234 section 4.2.2 would have a warning added to it.
+const MAX_HEURISTIC_LIFETIME: i64 = 24 * 60 * 60;
+
+/// The largest number of seconds a directive is taken to give, per RFC 7234 section 1.2.1.
+const MAX_DELTA_SECONDS: u64 = 1 << 31;
+
+#[derive(RustcDecodable, RustcEncodable, Clone)]
+struct CacheEntry {
+    /// The name of the file in the cache directory holding the body.
+    file: String,
+    status: u16,
+    status_text: String,
+    headers: Vec<(String, String)>,
+    size: u64,
+    /// The time, in seconds since the epoch, after which the response is stale.
+    expires: i64,
+    /// How many seconds the response was fresh for in all, which tells its age.
+    lifetime: i64,
+    /// How many seconds past `expires` the response may still be used while it is
+    /// revalidated in the background.
+    stale_while_revalidate: i64,
+    /// When the entry was last stored or used, by the index's clock.
+    last_used: u64,
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    next_file: u64,
+    /// Ticks every time an entry is used, to tell which was used least recently.
+    clock: u64,
+}
+
+impl CacheIndex {
+    fn new() -> CacheIndex {
+        CacheIndex {
+            entries: HashMap::new(),
+            next_file: 0,
+            clock: 0,
+        }
+    }
+}
+
+pub struct HttpCache {
+    dir: PathBuf,
+    index: CacheIndex,
+    max_size: u64,
+    /// The keys of the entries being revalidated in the background.
+    revalidating: HashSet<String>,
+}
+
+/// Whether `key` is the cache key of a GET of `url`, whatever the request headers.
+fn is_key_for_url(key: &str, url: &ServoUrl) -> bool {
+    key.lines().next() == Some(&*cache_key(&Method::Get, url, &Headers::new()))
+}
+
+/// The URL of the request that `key` is the cache key of.
+fn key_url(key: &str) -> Option<ServoUrl> {
+    key.lines().next()
+       .and_then(|request_line| request_line.splitn(2, ' ').nth(1))
+       .and_then(|url| ServoUrl::parse(url).ok())
+}
+
+/// Whether `key` is the cache key of a request for a URL on `host`.
+fn is_key_for_host(key: &str, host: &str) -> bool {
+    key_url(key).map_or(false, |url| url.host_str() == Some(host))
+}
+
+/// Whether `key` is the cache key of a request for a URL of `origin`.
+fn is_key_for_origin(key: &str, origin: &UrlOrigin) -> bool {
+    key_url(key).map_or(false, |url| url.origin() == *origin)
+}
+
+fn now() -> i64 {
+    time::now_utc().to_timespec().sec
+}
+
+/// The `Cache-Control` directives of a request or response that the caches act on, per
+/// RFC 7234 section 5.2. Directives that only apply to shared caches, like `s-maxage`, are
+/// parsed but ignored, as these caches are private to a user.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CacheDirectives {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
+    pub must_revalidate: bool,
+    pub private: bool,
+    pub min_fresh: Option<u64>,
+    pub only_if_cached: bool,
+    /// https://tools.ietf.org/html/rfc5861#section-3
+    pub stale_while_revalidate: Option<u64>,
+}
+
+impl CacheDirectives {
+    /// The directives of all the `Cache-Control` headers in `headers`.
+    pub fn from_headers(headers: &Headers) -> CacheDirectives {
+        match headers.get_raw("Cache-Control") {
+            Some(values) => {
+                let values: Vec<_> = values.iter().map(|value| String::from_utf8_lossy(value)).collect();
+                CacheDirectives::parse(&values.join(","))
+            }
+            None => CacheDirectives::default(),
+        }
+    }
+
+    /// Parse a `Cache-Control` value. Unknown directives are ignored, and only the first
+    /// of the same directive counts.
+    pub fn parse(value: &str) -> CacheDirectives {
+        let mut directives = CacheDirectives::default();
+        for directive in split_directives(value) {
+            let (name, argument) = match directive.find('=') {
+                Some(index) => (&directive[..index], Some(directive[index + 1..].trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+            let seconds = argument.and_then(delta_seconds);
+            match &*name.trim().to_ascii_lowercase() {
+                "no-store" => directives.no_store = true,
+                // With field names, only those fields need revalidating, but revalidating
+                // the whole response is simpler and just as correct.
+                "no-cache" => directives.no_cache = true,
+                // A lifetime that can't be read is taken to have already run out.
+                "max-age" => directives.max_age = directives.max_age.or(Some(seconds.unwrap_or(0))),
+                "s-maxage" => directives.s_maxage = directives.s_maxage.or(Some(seconds.unwrap_or(0))),
+                "must-revalidate" => directives.must_revalidate = true,
+                "private" => directives.private = true,
+                "min-fresh" => directives.min_fresh = directives.min_fresh.or(seconds),
+                "only-if-cached" => directives.only_if_cached = true,
+                "stale-while-revalidate" => {
+                    directives.stale_while_revalidate = directives.stale_while_revalidate.or(seconds)
+                }
+                _ => {}
+            }
+        }
+        directives
+    }
+}
+
+/// Split a `Cache-Control` value into its directives, at the commas that aren't quoted.
+fn split_directives(value: &str) -> Vec<&str> {
+    let mut directives = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                directives.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    directives.push(&value[start..]);
+    directives.into_iter().map(str::trim).filter(|directive| !directive.is_empty()).collect()
+}
+
+/// https://tools.ietf.org/html/rfc7234#section-1.2.1
+fn delta_seconds(argument: &str) -> Option<u64> {
+    if argument.is_empty() || !argument.chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+    // Only too many digits can make the parse fail.
+    Some(min(argument.parse().unwrap_or(MAX_DELTA_SECONDS), MAX_DELTA_SECONDS))
+}
+
+/// The statuses of responses that may be given a heuristic freshness lifetime, per
+/// RFC 7231 section 6.1.
+const HEURISTICALLY_CACHEABLE: [u16; 11] = [200, 203, 204, 206, 300, 301, 404, 405, 410, 414, 501];
+
+/// How many seconds a response with `status` and `headers` stays fresh for, per RFC 7234
+/// section 4.2.1, or `None` if it must not be stored at all.
+fn freshness_lifetime(status: u16, headers: &Headers) -> Option<i64> {
+    let directives = CacheDirectives::from_headers(headers);
+    if directives.no_store {
+        return None;
+    }
+    // Such responses may be stored, but have to be revalidated before every use.
+    if directives.no_cache {
+        return Some(0);
+    }
+    if let Some(max_age) = directives.max_age {
+        return Some(max_age as i64);
+    }
+    let date = match headers.get::<Date>() {
+        Some(&Date(HttpDate(ref date))) => date.to_timespec().sec,
+        None => now(),
+    };
+    if let Some(&Expires(HttpDate(ref expires))) = headers.get::<Expires>() {
+        return Some(max(expires.to_timespec().sec - date, 0));
+    }
+    // An `Expires` that can't be read is in the past.
+    if headers.has::<Expires>() {
+        return Some(0);
+    }
+    // https://tools.ietf.org/html/rfc7234#section-4.2.2
+    // Otherwise a tenth of how long the resource had gone unmodified is a good guess.
+    if let Some(&LastModified(HttpDate(ref last_modified))) = headers.get::<LastModified>() {
+        if HEURISTICALLY_CACHEABLE.contains(&status) {
+            let unmodified = date - last_modified.to_timespec().sec;
+            return Some(min(max(unmodified / 10, 0), MAX_HEURISTIC_LIFETIME));
+        }
+    }
+    // Without any lifetime, the response is stale as soon as it arrives.
+    Some(0)
+}
+
+/// How usable a stored response is for a request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Freshness {
+    /// The response may be used as it is.
+    Fresh,
+    /// The response is stale, but still within its `stale-while-revalidate` window, so it
+    /// may be used while it is revalidated in the background.
+    StaleWhileRevalidate,
+    /// The response has to be revalidated before it is used.
+    Stale,
+}
+
+/// Whether a stored response, fresh until `expires` out of a `lifetime` in all, may be
+/// used without revalidation for a request with `directives`.
+fn is_fresh_for(directives: &CacheDirectives, lifetime: i64, expires: i64) -> bool {
+    let now = now();
+    if now >= expires || directives.no_cache {
+        return false;
+    }
+    let current_age = lifetime - (expires - now);
+    match directives.max_age {
+        // This is how a request asks for the response to be revalidated.
+        Some(0) => return false,
+        Some(max_age) if current_age > max_age as i64 => return false,
+        _ => {}
+    }
+    match directives.min_fresh {
+        Some(min_fresh) => expires - now >= min_fresh as i64,
+        None => true,
+    }
+}
+
+/// How usable a stored response is for a request with `directives`, given that it may
+/// be used for `stale_while_revalidate` seconds past `expires` while it is revalidated.
+fn freshness_for(directives: &CacheDirectives, lifetime: i64, expires: i64, stale_while_revalidate: i64)
+                 -> Freshness {
+    if is_fresh_for(directives, lifetime, expires) {
+        return Freshness::Fresh;
+    }
+    // A request that asks for revalidation wants it done before the response is used.
+    if directives.no_cache || directives.max_age == Some(0) || now() >= expires + stale_while_revalidate {
+        return Freshness::Stale;
+    }
+    Freshness::StaleWhileRevalidate
+}
+
+/// How many seconds past its expiry a response with `headers` may be used while it is
+/// revalidated in the background.
+fn stale_while_revalidate(headers: &Headers) -> i64 {
+    CacheDirectives::from_headers(headers).stale_while_revalidate.unwrap_or(0) as i64
+}
+
+/// Whether a stale response with `headers` may still be used when the request would
+/// rather not go to the network, as with the `force-cache` mode.
+pub fn may_serve_stale(headers: &Headers) -> bool {
+    let directives = CacheDirectives::from_headers(headers);
+    !directives.must_revalidate && !directives.no_cache
+}
+
+/// Make `request_headers` ask the server whether `cached_headers`, those of a stale
+/// cached response, still describe the current resource.
+pub fn set_conditional_headers(request_headers: &mut Headers, cached_headers: &Headers) {
+    if let Some(&ETag(ref etag)) = cached_headers.get::<ETag>() {
+        request_headers.set(IfNoneMatch::Items(vec![etag.clone()]));
+    }
+    if let Some(&LastModified(ref last_modified)) = cached_headers.get::<LastModified>() {
+        request_headers.set(IfModifiedSince(last_modified.clone()));
+    }
+}
+
+/// The headers of a cached response after a `304 Not Modified` response with `headers`
+/// revalidated it, per RFC 7234 section 4.3.4.
+pub fn revalidated_headers(cached_headers: &Headers, headers: &Headers) -> Headers {
+    let mut updated = cached_headers.clone();
+    for header in headers.iter() {
+        // The cached body is the one being used, so the headers describing it stay.
+        if header.is::<ContentLength>() || header.is::<ContentEncoding>() || header.is::<SetCookie>() {
+            continue;
+        }
+        updated.set_raw(header.name().to_owned(), vec![header.value_string().into_bytes()]);
+    }
+    updated
+}
+
+/// The number of seconds `headers` say the response had already spent in other caches.
+fn age(headers: &Headers) -> i64 {
+    headers.get_raw("Age")
+           .and_then(|values| values.first())
+           .and_then(|value| String::from_utf8_lossy(value).trim().parse().ok())
+           .unwrap_or(0)
+}
+
+/// Whether a response with `status` and `headers` may be stored, as far as the response
+/// is concerned, leaving its freshness aside.
+fn response_is_storable(status: u16, headers: &Headers) -> bool {
+    // The statuses that are cacheable by default, other than those of partial or empty responses.
+    // Which request headers a response varies on isn't recorded, so those can't be reused.
+    [200, 203, 301, 404, 410].contains(&status) && !headers.has::<Vary>()
+}
+
+/// The headers to store along with a response `body`: the body has already been decoded,
+/// and cookies are only set by responses from the network.
+fn stored_headers(headers: &Headers, body: &[u8]) -> Headers {
+    let mut headers = headers.clone();
+    headers.remove::<ContentEncoding>();
+    headers.remove::<SetCookie>();
+    headers.set(ContentLength(body.len() as u64));
+    headers
+}
+
+/// Rebuild a response to `url` from what a cache stored of it.
+fn cached_response(url: ServoUrl, raw_status: (u16, Vec<u8>), headers: Headers, body: Vec<u8>) -> Response {
+    let mut response = Response::new(url);
+    response.status = Some(StatusCode::from_u16(raw_status.0));
+    response.raw_status = Some(raw_status);
+    response.headers = headers;
+    *response.body.lock().unwrap() = ResponseBody::Done(body);
+    response.cache_state = CacheState::Local;
+    response
+}
+
+/// Whether the response to `request` may be stored, as far as the request is concerned.
+pub fn request_is_cacheable(request: &Request) -> bool {
+    // Partial responses aren't stored, and a whole stored response can't answer a
+    // request for a range. Stored bodies are decoded, so they can't answer a request for
+    // the raw body either.
+    if *request.method.borrow() != Method::Get || request.range_start.is_some() ||
+       request.headers.borrow().has::<Range>() || request.raw_body ||
+       request.cache_mode.get() == CacheMode::NoStore {
+        return false;
+    }
+    !CacheDirectives::from_headers(&request.headers.borrow()).no_store
+}
+
+impl HttpCache {
+    /// Open the cache kept in `dir`, dropping any bodies that its index doesn't list.
+    pub fn new(dir: PathBuf, max_size: u64) -> HttpCache {
+        let mut index = CacheIndex::new();
+        read_json_from_file(&mut index, &dir, INDEX_FILE);
+        if let Ok(files) = fs::read_dir(&dir) {
+            for file in files.filter_map(Result::ok) {
+                let name = file.file_name().to_string_lossy().into_owned();
+                if name != INDEX_FILE && !index.entries.values().any(|entry| entry.file == name) {
+                    let _ = fs::remove_file(file.path());
+                }
+            }
+        }
+        HttpCache {
+            dir: dir,
+            index: index,
+            max_size: max_size,
+            revalidating: HashSet::new(),
+        }
+    }
+
+    /// Find the entry stored for `request`, marking it as used. Its body is still to be
+    /// read, once the cache is no longer locked.
+    fn find(&mut self, request: &Request) -> Option<FoundEntry> {
+        if !request_is_cacheable(request) {
+            return None;
+        }
+        let key = request.cache_key();
+        let entry = match self.index.entries.get_mut(&key) {
+            Some(entry) => entry,
+            None => return None,
+        };
+        self.index.clock += 1;
+        entry.last_used = self.index.clock;
+
+        let mut headers = Headers::new();
+        for &(ref name, ref value) in &entry.headers {
+            headers.set_raw(name.clone(), vec![value.clone().into_bytes()]);
+        }
+        let freshness = freshness_for(&CacheDirectives::from_headers(&request.headers.borrow()),
+                                      entry.lifetime, entry.expires, entry.stale_while_revalidate);
+        Some(FoundEntry {
+            path: self.dir.join(&entry.file),
+            file: entry.file.clone(),
+            key: key,
+            raw_status: (entry.status, entry.status_text.clone().into_bytes()),
+            headers: headers,
+            freshness: freshness,
+        })
+    }
+
+    /// Drop the entry under `key` whose body couldn't be read from `file`, unless it has
+    /// been replaced in the meantime.
+    fn forget_unreadable(&mut self, key: &str, file: &str) {
+        if self.index.entries.get(key).map_or(false, |entry| entry.file == file) {
+            self.index.entries.remove(key);
+        }
+    }
+
+    /// Make the entry for a response with `body` to the GET request with the cache `key`,
+    /// if its `headers` allow it to be cached, along with the path to write the body to
+    /// before the entry is added with `insert`.
+    fn new_entry(&mut self, key: &str, raw_status: &(u16, Vec<u8>), headers: &Headers, body: &[u8])
+                 -> Option<(PathBuf, CacheEntry)> {
+        let (status, ref status_text) = *raw_status;
+        if !response_is_storable(status, headers) {
+            return None;
+        }
+        let lifetime = match freshness_lifetime(status, headers) {
+            Some(lifetime) => lifetime,
+            None => return None,
+        };
+        let remaining = max(lifetime - age(headers), 0);
+        let stale_while_revalidate = stale_while_revalidate(headers);
+        // A stale response is only worth keeping if it can be revalidated, or used while
+        // it is.
+        if remaining == 0 && stale_while_revalidate == 0 && !headers.has::<ETag>() &&
+           !headers.has::<LastModified>() {
+            return None;
+        }
+        if body.len() as u64 > self.max_size {
+            return None;
+        }
+
+        let file = self.index.next_file.to_string();
+        self.index.next_file += 1;
+        let headers = stored_headers(headers, body);
+        let entry = CacheEntry {
+            file: file.clone(),
+            status: status,
+            status_text: String::from_utf8_lossy(status_text).into_owned(),
+            headers: headers.iter().map(|header| (header.name().to_owned(), header.value_string())).collect(),
+            size: body.len() as u64,
+            expires: now() + remaining,
+            lifetime: lifetime,
+            stale_while_revalidate: stale_while_revalidate,
+            last_used: 0,
+        };
+        Some((self.dir.join(file), entry))
+    }
+
+    /// Add `entry` under `key`, now that its body has been written, and evict what no
+    /// longer fits. Returns the bodies that are no longer used, for the caller to remove
+    /// once the cache is no longer locked.
+    fn insert(&mut self, key: &str, mut entry: CacheEntry) -> Vec<PathBuf> {
+        self.index.clock += 1;
+        entry.last_used = self.index.clock;
+        let mut unused = vec![];
+        if let Some(old_entry) = self.index.entries.insert(key.to_owned(), entry) {
+            unused.push(self.dir.join(old_entry.file));
+        }
+        let mut size: u64 = self.index.entries.values().map(|entry| entry.size).sum();
+        while size > self.max_size {
+            let oldest = self.index.entries.iter()
+                                           .min_by_key(|&(_, entry)| entry.last_used)
+                                           .map(|(key, _)| key.clone())
+                                           .unwrap();
+            let entry = self.index.entries.remove(&oldest).unwrap();
+            unused.push(self.dir.join(entry.file));
+            size -= entry.size;
+        }
+        unused
+    }
+
+    /// Replace the headers of the response stored under `key` with the `headers` it has
+    /// after being revalidated, which also say how long it is fresh for again.
+    pub fn update_headers(&mut self, key: &str, headers: &Headers) {
+        let lifetime = match self.index.entries.get(key) {
+            Some(entry) => freshness_lifetime(entry.status, headers),
+            None => return,
+        };
+        let lifetime = match lifetime {
+            Some(lifetime) => lifetime,
+            None => return self.remove(key),
+        };
+        let entry = self.index.entries.get_mut(key).unwrap();
+        entry.headers = headers.iter().map(|header| (header.name().to_owned(), header.value_string())).collect();
+        entry.expires = now() + max(lifetime - age(headers), 0);
+        entry.lifetime = lifetime;
+        entry.stale_while_revalidate = stale_while_revalidate(headers);
+    }
+
+    /// Note that the response stored under `key` is about to be revalidated in the
+    /// background, unless that is already underway, in which case this returns false.
+    pub fn start_revalidation(&mut self, key: &str) -> bool {
+        self.revalidating.insert(key.to_owned())
+    }
+
+    /// Note that the background revalidation of the response stored under `key` is over.
+    pub fn finish_revalidation(&mut self, key: &str) {
+        self.revalidating.remove(key);
+    }
+
+    /// Forget the responses stored for GETs of `url`, which a request with an unsafe
+    /// method may have changed.
+    pub fn invalidate(&mut self, url: &ServoUrl) {
+        let keys: Vec<_> = self.index.entries.keys().filter(|key| is_key_for_url(key, url)).cloned().collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    /// Forget every response stored for a URL on `host`.
+    pub fn clear_host(&mut self, host: &str) {
+        let keys: Vec<_> = self.index.entries.keys().filter(|key| is_key_for_host(key, host)).cloned().collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    /// Forget every response stored for a URL of `origin`.
+    pub fn clear_origin(&mut self, origin: &UrlOrigin) {
+        let keys: Vec<_> = self.index.entries.keys().filter(|key| is_key_for_origin(key, origin)).cloned().collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.index.entries.remove(key) {
+            let _ = fs::remove_file(self.dir.join(entry.file));
+        }
+    }
+
+    pub fn write_index(&self) {
+        write_json_to_file(&self.index, &self.dir, INDEX_FILE);
+    }
+}
+
+/// A response found in the disk cache, whose body is still to be read from `path`.
+struct FoundEntry {
+    key: String,
+    file: String,
+    path: PathBuf,
+    raw_status: (u16, Vec<u8>),
+    headers: Headers,
+    freshness: Freshness,
+}
+
+/// Look for a response to `request` stored in `cache`, returning it along with how usable
+/// it is for the request. The cache is only locked to look the response up in its index,
+/// not while its body is read.
+pub fn lookup(cache: &RwLock<HttpCache>, request: &Request) -> Option<(Response, Freshness)> {
+    let found = match write_lock(cache, "HTTP cache").find(request) {
+        Some(found) => found,
+        None => return None,
+    };
+    let mut body = vec![];
+    if let Err(error) = File::open(&found.path).and_then(|mut file| file.read_to_end(&mut body)) {
+        warn!("Dropping the cached response to {}, whose body couldn't be read: {}",
+              request.current_url(), error);
+        write_lock(cache, "HTTP cache").forget_unreadable(&found.key, &found.file);
+        return None;
+    }
+    Some((cached_response(request.current_url(), found.raw_status, found.headers, body), found.freshness))
+}
+
+/// Store the response to the GET request with the cache `key` in `cache`, now that its
+/// whole `body` has arrived, if its `headers` allow it to be cached. The cache is only
+/// locked to update its index, not while the body is written.
+pub fn store(cache: &RwLock<HttpCache>, key: &str, raw_status: &(u16, Vec<u8>), headers: &Headers, body: &[u8]) {
+    let (path, entry) = match write_lock(cache, "HTTP cache").new_entry(key, raw_status, headers, body) {
+        Some(new_entry) => new_entry,
+        None => return,
+    };
+    let written = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| {
+        File::create(&path).and_then(|mut file| file.write_all(body))
+    });
+    if let Err(error) = written {
+        warn!("Couldn't write the cached response to {}: {}", key, error);
+        let _ = fs::remove_file(&path);
+        return;
+    }
+    let unused = write_lock(cache, "HTTP cache").insert(key, entry);
+    for path in unused {
+        let _ = fs::remove_file(path);
+    }
+}
+
+struct MemoryCacheEntry {
+    raw_status: (u16, Vec<u8>),
+    headers: Headers,
+    body: Vec<u8>,
+    /// The time, in seconds since the epoch, after which the response is stale.
+    expires: i64,
+    lifetime: i64,
+    last_used: u64,
+}
+
+/// Complete responses kept in memory, so that a resource used again and again in one
+/// session isn't fetched every time.
+pub struct MemoryCache {
+    entries: HashMap<String, MemoryCacheEntry>,
+    /// The total size of the bodies in `entries`.
+    size: usize,
+    clock: u64,
+    max_entry_size: usize,
+    max_size: usize,
+}
+
+impl MemoryCache {
+    pub fn new(max_entry_size: usize, max_size: usize) -> MemoryCache {
+        MemoryCache {
+            entries: HashMap::new(),
+            size: 0,
+            clock: 0,
+            max_entry_size: max_entry_size,
+            max_size: max_size,
+        }
+    }
+
+    /// Look for a response fresh enough for `request`. Stale responses are dropped.
+    pub fn lookup(&mut self, request: &Request) -> Option<Response> {
+        if !request_is_cacheable(request) {
+            return None;
+        }
+        let url = request.current_url();
+        let key = request.cache_key();
+        let (stale, fresh) = match self.entries.get(&key) {
+            Some(entry) => {
+                let directives = CacheDirectives::from_headers(&request.headers.borrow());
+                (now() >= entry.expires, is_fresh_for(&directives, entry.lifetime, entry.expires))
+            }
+            None => return None,
+        };
+        if stale {
+            self.remove(&key);
+        }
+        if !fresh {
+            return None;
+        }
+        self.clock += 1;
+        let entry = self.entries.get_mut(&key).unwrap();
+        entry.last_used = self.clock;
+        Some(cached_response(url, entry.raw_status.clone(), entry.headers.clone(), entry.body.clone()))
+    }
+
+    /// Store the response to the GET request with the cache `key`, now that its whole
+    /// `body` has arrived, if it is small enough and its headers allow it to be cached.
+    pub fn store(&mut self, key: &str, raw_status: &(u16, Vec<u8>), headers: &Headers, body: &[u8]) {
+        if body.len() > self.max_entry_size || !response_is_storable(raw_status.0, headers) {
+            return;
+        }
+        let lifetime = match freshness_lifetime(raw_status.0, headers) {
+            Some(lifetime) => lifetime,
+            None => return,
+        };
+        let remaining = lifetime - age(headers);
+        if remaining <= 0 {
+            return;
+        }
+
+        self.clock += 1;
+        self.remove(key);
+        self.size += body.len();
+        self.entries.insert(key.to_owned(), MemoryCacheEntry {
+            raw_status: raw_status.clone(),
+            headers: stored_headers(headers, body),
+            body: body.to_vec(),
+            expires: now() + remaining,
+            lifetime: lifetime,
+            last_used: self.clock,
+        });
+        while self.size > self.max_size {
+            let oldest = self.entries.iter()
+                                     .min_by_key(|&(_, entry)| entry.last_used)
+                                     .map(|(key, _)| key.clone())
+                                     .unwrap();
+            self.remove(&oldest);
+        }
+    }
+
+    /// Forget the responses stored for GETs of `url`, which a request with an unsafe
+    /// method may have changed.
+    pub fn invalidate(&mut self, url: &ServoUrl) {
+        let keys: Vec<_> = self.entries.keys().filter(|key| is_key_for_url(key, url)).cloned().collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    /// Forget every response stored for a URL on `host`.
+    pub fn clear_host(&mut self, host: &str) {
+        let keys: Vec<_> = self.entries.keys().filter(|key| is_key_for_host(key, host)).cloned().collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    /// Forget every response stored for a URL of `origin`.
+    pub fn clear_origin(&mut self, origin: &UrlOrigin) {
+        let keys: Vec<_> = self.entries.keys().filter(|key| is_key_for_origin(key, origin)).cloned().collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.size -= entry.body.len();
+        }
+    }
+
+    /// An estimate of the heap memory held by the cached responses, for memory reports.
+    pub fn estimated_size(&self) -> usize {
+        self.entries.iter().map(|(key, entry)| {
+            key.capacity() + mem::size_of::<MemoryCacheEntry>() + entry.body.capacity() +
+            entry.raw_status.1.capacity()
+        }).sum()
+    }
+}
@@ -2,55 +2,80 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use alt_svc::{AltSvcCache, parse_alt_svc};
 use brotli::Decompressor;
-use connector::{Connector, create_http_connector};
-use content_blocker_parser::RuleList;
+use connector::{ConnectionPools, Connector, HttpProxy, ProxyRoute, ProxySettings, TlsPolicyError, TunnelError};
+use connector::{set_deadline, set_request_url, take_certificate_failure, take_early_hints, take_handshake_time};
+use connector::{take_new_connection, take_response_bytes_read, take_trailers};
+use content_blocker::BlockedContentRules;
 use cookie;
 use cookie_storage::CookieStorage;
 use devtools_traits::{ChromeToDevtoolsControlMsg, DevtoolsControlMsg, HttpRequest as DevtoolsHttpRequest};
 use devtools_traits::{HttpResponse as DevtoolsHttpResponse, NetworkEvent};
-use fetch::cors_cache::CorsCache;
-use fetch::methods::{Data, DoneChannel, FetchContext, Target, is_simple_header, is_simple_method, main_fetch};
-use flate2::read::{DeflateDecoder, GzDecoder};
+use digest_auth::{DigestAuthCache, challenge_realm, digest_challenge};
+use filemanager_thread::FileManager;
+use fetch::cors_cache::{CorsCache, MAX_AGE_LIMIT};
+use fetch::methods::{CancellationListener, Data, DoneChannel, FetchContext, NetStats, Target};
+use fetch::methods::{is_simple_header, is_simple_method};
+use fetch::methods::{Deadline, WaitError, main_fetch, recv_while_fetching};
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
 use hsts::HstsList;
+use http_cache::{self, CacheDirectives, Freshness, HttpCache, MemoryCache, may_serve_stale, request_is_cacheable};
+use http_cache::{revalidated_headers, set_conditional_headers};
 use hyper::Error as HttpError;
 use hyper::LanguageTag;
 use hyper::client::{Pool, Request as HyperRequest, Response as HyperResponse};
 use hyper::header::{AcceptEncoding, AcceptLanguage, AccessControlAllowCredentials};
 use hyper::header::{AccessControlAllowOrigin, AccessControlAllowHeaders, AccessControlAllowMethods};
 use hyper::header::{AccessControlRequestHeaders, AccessControlMaxAge, AccessControlRequestMethod};
-use hyper::header::{Authorization, Basic, CacheControl, CacheDirective, ContentEncoding};
-use hyper::header::{ContentLength, Encoding, Header, Headers, Host, IfMatch, IfRange};
-use hyper::header::{IfUnmodifiedSince, IfModifiedSince, IfNoneMatch, Location, Pragma, Quality};
-use hyper::header::{QualityItem, Referer, SetCookie, UserAgent, qitem};
+use hyper::header::{Authorization, Basic, ByteRangeSpec, CacheControl, CacheDirective, ContentEncoding};
+use hyper::header::{ContentLength, ContentRange, ContentRangeSpec, ContentType, Encoding, Header, Headers, Host};
+use hyper::header::{IfMatch, IfRange, IfUnmodifiedSince, IfModifiedSince, IfNoneMatch, Location, Pragma};
+use hyper::header::{Quality, QualityItem, Range, Referer, ReferrerPolicy as ReferrerPolicyHeader, SetCookie};
+use hyper::header::{UserAgent, qitem};
+use hyper::http::h1::Http11Message;
+use hyper::http::message::HttpMessage;
 use hyper::method::Method;
-use hyper::net::Fresh;
+use hyper::net::{Fresh, NetworkConnector};
 use hyper::status::StatusCode;
+use hyper::version::HttpVersion;
 use hyper_serde::Serde;
+use ipc_channel::ipc::{self, IpcSender};
+use lock_recovery::{read_lock, write_lock};
 use log;
+use mime_classifier::MimeOverrides;
 use msg::constellation_msg::PipelineId;
-use net_traits::{CookieSource, FetchMetadata, NetworkError, ReferrerPolicy};
+use multipart::{MultipartEvent, MultipartSplitter, mixed_replace_boundary};
+use net_traits::{AuthPromptRequest, CertificateFailure, CookieAcceptPolicy, CookieSource, FetchMetadata, NetworkError};
+use net_traits::{ReferrerPolicy, SameSiteContext, SslValidationError};
 use net_traits::hosts::replace_hosts;
-use net_traits::request::{CacheMode, CredentialsMode, Destination, Origin};
-use net_traits::request::{RedirectMode, Referrer, Request, RequestMode, ResponseTainting};
-use net_traits::response::{HttpsState, Response, ResponseBody, ResponseType};
+use net_traits::request::{BodyPart, CacheMode, CredentialsMode, Destination, Origin, RequestPriority};
+use net_traits::request::{RedirectMode, Referrer, Request, RequestMode, ResponseTainting, Type};
+use net_traits::response::{CacheState, HttpsState, Response, ResponseBody, ResponseType};
+use net_traits::storage_thread::{StorageThreadMsg, StorageType};
 use openssl;
 use openssl::ssl::error::{OpensslError, SslError};
-use resource_thread::AuthCache;
+use profile_traits::time::{ProfilerCategory, ProfilerChan, TimerMetadata, TimerMetadataFrameType};
+use profile_traits::time::{TimerMetadataReflowType, send_profile_data};
+use resource_thread::{AuthCache, AuthCacheEntry};
 use servo_url::ServoUrl;
+use std::cell::Cell;
+use std::cmp::min;
 use std::collections::HashSet;
 use std::error::Error;
-use std::io::{self, Read, Write};
+use std::io::{self, Cursor, Read, Write};
 use std::iter::FromIterator;
 use std::mem::swap;
 use std::ops::Deref;
 use std::rc::Rc;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::sync::mpsc::{channel, Sender};
 use time;
 use time::Tm;
 use unicase::UniCase;
-use url::Origin as UrlOrigin;
+use url::{Host, Origin as UrlOrigin};
+use url_rewrite::UrlRewriter;
+use util::prefs::{PREFS, PrefValue};
 use util::thread::spawn_named;
 use uuid;
 
@@ -67,26 +92,115 @@ fn read_block<R: Read>(reader: &mut R) -> Result<Data, ()> {
     }
 }
 
+/// Read the next part start or payload of a multipart body, reading no more than one
+/// block so that cancellation is noticed between blocks.
+fn read_part_block<R: Read>(reader: &mut R, splitter: &mut MultipartSplitter) -> Result<Data, ()> {
+    if let Some(event) = splitter.next_event() {
+        return Ok(part_data(event));
+    }
+    match try!(read_block(reader)) {
+        Data::Payload(chunk) => {
+            try!(splitter.feed(&chunk));
+            Ok(splitter.next_event().map_or(Data::Payload(vec![]), part_data))
+        }
+        Data::Done => {
+            splitter.finish();
+            Ok(splitter.next_event().map_or(Data::Done, part_data))
+        }
+        data => Ok(data),
+    }
+}
+
+fn part_data(event: MultipartEvent) -> Data {
+    match event {
+        MultipartEvent::Part(headers) => Data::Part(headers),
+        MultipartEvent::Data(data) => Data::Payload(data),
+    }
+}
+
+/// The most body bytes a response may have, from `network.http.max-response-size`.
+/// Unlimited if the pref isn't a positive number.
+pub fn max_response_size_from_prefs() -> Option<usize> {
+    match PREFS.get("network.http.max-response-size").as_i64() {
+        Some(size) if size > 0 => Some(size as usize),
+        _ => None,
+    }
+}
+
+#[derive(Clone)]
 pub struct HttpState {
     pub hsts_list: Arc<RwLock<HstsList>>,
+    /// Another group's HSTS list, which is consulted but never changed by this group's fetches.
+    pub shared_hsts_list: Option<Arc<RwLock<HstsList>>>,
     pub cookie_jar: Arc<RwLock<CookieStorage>>,
+    pub cookie_policy: Arc<RwLock<CookieAcceptPolicy>>,
     pub auth_cache: Arc<RwLock<AuthCache>>,
-    pub blocked_content: Arc<Option<RuleList>>,
+    pub digest_auth: Arc<RwLock<DigestAuthCache>>,
+    pub blocked_content: Arc<Option<BlockedContentRules>>,
+    pub url_rewriter: Arc<RwLock<UrlRewriter>>,
+    pub mime_overrides: Arc<RwLock<MimeOverrides>>,
+    pub connection_pools: Arc<ConnectionPools>,
+    pub alt_svc_cache: Arc<RwLock<AltSvcCache>>,
+    /// Where complete responses are cached, if this group caches them at all.
+    pub http_cache: Option<Arc<RwLock<HttpCache>>>,
+    pub memory_cache: Arc<RwLock<MemoryCache>>,
+    /// The CORS preflight results of the group's fetches.
+    pub cors_cache: Arc<RwLock<CorsCache>>,
 }
 
 impl HttpState {
     pub fn new() -> HttpState {
         HttpState {
             hsts_list: Arc::new(RwLock::new(HstsList::new())),
+            shared_hsts_list: None,
             cookie_jar: Arc::new(RwLock::new(CookieStorage::new(150))),
+            cookie_policy: Arc::new(RwLock::new(CookieAcceptPolicy::All)),
             auth_cache: Arc::new(RwLock::new(AuthCache::new())),
+            digest_auth: Arc::new(RwLock::new(DigestAuthCache::new())),
             blocked_content: Arc::new(None),
+            url_rewriter: Arc::new(RwLock::new(UrlRewriter::new(vec![]))),
+            mime_overrides: Arc::new(RwLock::new(MimeOverrides::default())),
+            // Connections are made directly, whatever the proxy prefs say. The resource
+            // thread gives its groups pools that follow them.
+            connection_pools: Arc::new(ConnectionPools::with_proxies(ProxySettings::default())),
+            alt_svc_cache: Arc::new(RwLock::new(AltSvcCache::new())),
+            http_cache: None,
+            // Nothing fits, so that responses are only cached where it is asked for.
+            memory_cache: Arc::new(RwLock::new(MemoryCache::new(0, 0))),
+            cors_cache: Arc::new(RwLock::new(CorsCache::new())),
         }
     }
+
+    /// Whether `host` is a known HSTS host, according to either this group's HSTS list
+    /// or the one it shares.
+    pub fn is_host_secure(&self, host: &str) -> bool {
+        read_lock(&self.hsts_list, "HSTS list").is_host_secure(host) ||
+        self.shared_hsts_list.as_ref().map_or(false, |list| read_lock(list, "HSTS list").is_host_secure(host))
+    }
+}
+
+fn ns_to_ms(ns: u64) -> u64 {
+    ns / (1000 * 1000)
+}
+
+/// Sends the time spent in each phase of an HTTP request to the time profiler,
+/// attributed to the host the request was made to.
+#[derive(Clone)]
+struct NetworkTimingReporter {
+    profiler_chan: ProfilerChan,
+    host: String,
 }
 
-fn precise_time_ms() -> u64 {
-    time::precise_time_ns() / (1000 * 1000)
+impl NetworkTimingReporter {
+    fn report(&self, category: ProfilerCategory, start: u64, end: u64) {
+        // The frame and reflow types don't mean anything for a request.
+        let meta = TimerMetadata {
+            url: self.host.clone(),
+            iframe: TimerMetadataFrameType::RootWindow,
+            incremental: TimerMetadataReflowType::FirstReflow,
+        };
+        send_profile_data(category, Some(meta), self.profiler_chan.clone(), start, end, 0, 0);
+    }
 }
 
 pub struct WrappedHttpResponse {
@@ -105,33 +219,60 @@ impl WrappedHttpResponse {
         &self.response.headers
     }
 
-    fn content_encoding(&self) -> Option<Encoding> {
-        let encodings = match self.headers().get::<ContentEncoding>() {
-            Some(&ContentEncoding(ref encodings)) => encodings,
-            None => return None,
-        };
-        if encodings.contains(&Encoding::Gzip) {
-            Some(Encoding::Gzip)
-        } else if encodings.contains(&Encoding::Deflate) {
-            Some(Encoding::Deflate)
-        } else if encodings.contains(&Encoding::EncodingExt("br".to_owned())) {
-            Some(Encoding::EncodingExt("br".to_owned()))
-        } else {
-            None
+    /// The content codings of the body, in the order they were applied.
+    fn content_encodings(&self) -> Vec<Encoding> {
+        match self.headers().get::<ContentEncoding>() {
+            Some(&ContentEncoding(ref encodings)) => encodings.clone(),
+            None => vec![],
         }
     }
 }
 
-struct NetworkHttpRequestFactory {
+struct NetworkHttpRequestFactory<'a> {
     pub connector: Arc<Pool<Connector>>,
+    /// Makes a pool without idle connections, for a request that must be made on a new
+    /// connection.
+    pub fresh_connector: &'a Fn() -> Arc<Pool<Connector>>,
+    /// The proxy the connector connects to for the request to be forwarded, if any.
+    pub forward_proxy: Option<HttpProxy>,
+    /// The pools to make HTTPS requests on HTTP/2 sessions from, if HTTP/2 is enabled.
+    pub http2_pools: Option<&'a ConnectionPools>,
 }
 
-impl NetworkHttpRequestFactory {
-    fn create(&self, url: ServoUrl, method: Method, headers: Headers)
+impl<'a> NetworkHttpRequestFactory<'a> {
+    fn create(&self, url: ServoUrl, method: Method, headers: Headers, new_connection: bool)
               -> Result<HyperRequest<Fresh>, NetworkError> {
-        let connection = HyperRequest::with_connector(method,
-                                                      url.clone().into_url().unwrap(),
-                                                      &*self.connector);
+        let fresh_connector;
+        let connector = if new_connection {
+            fresh_connector = (self.fresh_connector)();
+            &*fresh_connector
+        } else {
+            &*self.connector
+        };
+        let hyper_url = url.clone().into_url().unwrap();
+        let http2_message = match self.http2_pools {
+            // A request that must be made on a new connection is made over HTTP/1.1.
+            Some(pools) if !new_connection && self.forward_proxy.is_none() && hyper_url.scheme() == "https" => {
+                let host = hyper_url.host_str().unwrap_or("").to_owned();
+                pools.http2_message(&host, hyper_url.port_or_known_default().unwrap_or(443))
+            }
+            _ => Ok(None),
+        };
+        let connection = match (http2_message, &self.forward_proxy) {
+            (Ok(Some(message)), _) => HyperRequest::with_message(method, hyper_url, message),
+            (Err(error), _) => Err(error),
+            // The request target is sent in absolute form for the proxy to forward it.
+            (Ok(None), &Some(_)) => {
+                let host = hyper_url.host_str().unwrap_or("").to_owned();
+                let port = hyper_url.port_or_known_default().unwrap_or(80);
+                connector.connect(&host, port, hyper_url.scheme()).and_then(|stream| {
+                    let mut message = Http11Message::with_stream(Box::new(stream));
+                    message.set_proxied(true);
+                    HyperRequest::with_message(method, hyper_url, Box::new(message))
+                })
+            }
+            (Ok(None), &None) => HyperRequest::with_connector(method, hyper_url, connector),
+        };
 
         if let Err(HttpError::Ssl(ref error)) = connection {
             let error: &(Error + Send + 'static) = &**error;
@@ -151,9 +292,23 @@ impl NetworkHttpRequestFactory {
                     }
 
                     let error_report = error_report.join("<br>\n");
-                    return Err(NetworkError::SslValidation(url, error_report));
+                    let (failure, chain) = take_certificate_failure().unwrap_or((CertificateFailure::Other, vec![]));
+                    return Err(NetworkError::SslValidation(url, SslValidationError {
+                        failure: failure,
+                        chain: chain,
+                        message: error_report,
+                    }));
                 }
             }
+            if let Some(error) = error.downcast_ref::<TlsPolicyError>() {
+                return Err(NetworkError::TlsPolicy(error.to_string()));
+            }
+        }
+
+        if let Err(HttpError::Io(ref error)) = connection {
+            if let Some(error) = error.get_ref().and_then(|error| error.downcast_ref::<TunnelError>()) {
+                return Err(NetworkError::ProxyTunnelFailed(error.status));
+            }
         }
 
         let mut request = match connection {
@@ -161,6 +316,9 @@ impl NetworkHttpRequestFactory {
             Err(e) => return Err(NetworkError::Internal(e.description().to_owned())),
         };
         *request.headers_mut() = headers;
+        if let Some(authorization) = self.forward_proxy.as_ref().and_then(HttpProxy::authorization) {
+            request.headers_mut().set_raw("Proxy-Authorization", vec![authorization.into_bytes()]);
+        }
 
         Ok(request)
     }
@@ -178,20 +336,72 @@ fn set_default_accept_encoding(headers: &mut Headers) {
     ]));
 }
 
-pub fn set_default_accept_language(headers: &mut Headers) {
+/// The `Accept-Language` header for a comma-separated list of language tags in order of
+/// preference, such as `fr-CA, fr, en`, with lower q-values for the later tags. A list
+/// without any valid tag gives no header at all.
+pub fn accept_language_header(languages: &str) -> Option<AcceptLanguage> {
+    let tags: Vec<LanguageTag> = languages.split(',')
+                                          .map(str::trim)
+                                          .filter(|tag| !tag.is_empty())
+                                          .filter_map(|tag| tag.parse().ok())
+                                          .collect();
+    if tags.is_empty() {
+        return None;
+    }
+    let step = 1000 / tags.len() as u16;
+    Some(AcceptLanguage(tags.into_iter().enumerate().map(|(index, tag)| {
+        QualityItem::new(tag, Quality(1000 - index as u16 * step))
+    }).collect()))
+}
+
+/// The `Accept-Language` header for the languages listed in the `intl.accept_languages`
+/// pref that `pref` looks up by name, if there are any.
+pub fn accept_language_from_pref_values<F>(pref: F) -> Option<AcceptLanguage>
+    where F: Fn(&str) -> Arc<PrefValue>
+{
+    pref("intl.accept_languages").as_string().and_then(accept_language_header)
+}
+
+pub fn set_accept_language(headers: &mut Headers, accept_language: Option<&AcceptLanguage>) {
     if headers.has::<AcceptLanguage>() {
         return;
     }
 
-    let mut en_us: LanguageTag = Default::default();
-    en_us.language = Some("en".to_owned());
-    en_us.region = Some("US".to_owned());
-    let mut en: LanguageTag = Default::default();
-    en.language = Some("en".to_owned());
-    headers.set(AcceptLanguage(vec![
-        qitem(en_us),
-        QualityItem::new(en, Quality(500)),
-    ]));
+    if let Some(accept_language) = accept_language {
+        headers.set(accept_language.clone());
+    }
+}
+
+/// The privacy signals sent with every request and WebSocket handshake.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PrivacySignals {
+    /// Whether `DNT: 1` is sent.
+    pub do_not_track: bool,
+    /// Whether `Sec-GPC: 1` is sent.
+    pub global_privacy_control: bool,
+}
+
+impl PrivacySignals {
+    /// The signals that the `privacy.donottrack.enabled` and
+    /// `privacy.globalprivacycontrol.enabled` prefs ask for at the moment.
+    pub fn from_prefs() -> PrivacySignals {
+        PrivacySignals {
+            do_not_track: PREFS.get("privacy.donottrack.enabled").as_boolean().unwrap_or(false),
+            global_privacy_control: PREFS.get("privacy.globalprivacycontrol.enabled").as_boolean()
+                                         .unwrap_or(false),
+        }
+    }
+
+    /// Set the headers of the signals that are on. Those that are off are never sent, not
+    /// even as `0`.
+    pub fn set_headers(&self, headers: &mut Headers) {
+        if self.do_not_track {
+            headers.set_raw("DNT", vec![b"1".to_vec()]);
+        }
+        if self.global_privacy_control {
+            headers.set_raw("Sec-GPC", vec![b"1".to_vec()]);
+        }
+    }
 }
 
 /// https://w3c.github.io/webappsec-referrer-policy/#referrer-policy-state-no-referrer-when-downgrade
@@ -262,85 +472,295 @@ pub fn determine_request_referrer(headers: &mut Headers,
     }
 }
 
-pub fn set_request_cookies(url: &ServoUrl, headers: &mut Headers, cookie_jar: &Arc<RwLock<CookieStorage>>) {
-    let mut cookie_jar = cookie_jar.write().unwrap();
-    if let Some(cookie_list) = cookie_jar.cookies_for_url(url, CookieSource::HTTP) {
+pub fn set_request_cookies(url: &ServoUrl, headers: &mut Headers, cookie_jar: &Arc<RwLock<CookieStorage>>,
+                           context: SameSiteContext) {
+    let mut cookie_jar = write_lock(cookie_jar, "cookie jar");
+    if let Some(cookie_list) = cookie_jar.cookies_for_url(url, CookieSource::HTTP, context) {
         let mut v = Vec::new();
         v.push(cookie_list.into_bytes());
         headers.set_raw("Cookie".to_owned(), v);
     }
 }
 
+/// Work out whether `request` is same-site with its current URL, for the purposes of
+/// sending `SameSite` cookies.
+fn same_site_context(request: &Request) -> SameSiteContext {
+    let current_url = request.current_url();
+    let same_site = match *request.origin.borrow() {
+        Origin::Client => true,
+        Origin::Origin(UrlOrigin::Tuple(_, ref host, _)) => {
+            current_url.host_str().map_or(false, |url_host| {
+                cookie::Cookie::is_same_site(&host.to_string(), url_host)
+            })
+        },
+        Origin::Origin(UrlOrigin::Opaque(_)) => false,
+    };
+    if same_site {
+        return SameSiteContext::SameSite;
+    }
+    // FIXME: nested browsing contexts also make navigation requests, but we have no
+    // way to tell them apart from top-level ones here.
+    match *request.method.borrow() {
+        Method::Get | Method::Head if request.is_navigation_request() =>
+            SameSiteContext::CrossSiteTopLevelNavigation,
+        _ => SameSiteContext::CrossSite,
+    }
+}
+
+/// The `Sec-Fetch-Site` value of `request`: how its initiator relates to each of the
+/// URLs it has been redirected through.
+/// https://w3c.github.io/webappsec-fetch-metadata/#sec-fetch-site-header
+fn fetch_metadata_site(request: &Request) -> &'static str {
+    let origin = match *request.origin.borrow() {
+        // Nothing initiated the request but the user, as when typing in a URL.
+        Origin::Client => return "none",
+        Origin::Origin(ref origin) => origin.clone(),
+    };
+    let url_list = request.url_list.borrow();
+    if url_list.iter().all(|url| url.origin() == origin) {
+        return "same-origin";
+    }
+    let same_site = match origin {
+        UrlOrigin::Tuple(_, ref host, _) => url_list.iter().all(|url| {
+            url.host_str().map_or(false, |url_host| cookie::Cookie::is_same_site(&host.to_string(), url_host))
+        }),
+        UrlOrigin::Opaque(_) => false,
+    };
+    if same_site { "same-site" } else { "cross-site" }
+}
+
+/// The `Sec-Fetch-Dest` value of `request`.
+/// https://w3c.github.io/webappsec-fetch-metadata/#sec-fetch-dest-header
+fn fetch_metadata_destination(request: &Request) -> &'static str {
+    match request.destination {
+        Destination::None => "empty",
+        Destination::Document => "document",
+        Destination::Embed => "embed",
+        Destination::Font => "font",
+        Destination::Image => "image",
+        Destination::Manifest => "manifest",
+        Destination::Media => match request.type_ {
+            Type::Audio => "audio",
+            Type::Track => "track",
+            _ => "video",
+        },
+        Destination::Object => "object",
+        Destination::Report => "report",
+        Destination::Script => "script",
+        Destination::ServiceWorker => "serviceworker",
+        Destination::SharedWorker => "sharedworker",
+        Destination::Style => "style",
+        Destination::Worker => "worker",
+        Destination::XSLT => "xslt",
+    }
+}
+
+/// Set the fetch metadata headers, which tell the server where `request` comes from.
+/// https://w3c.github.io/webappsec-fetch-metadata/
+fn set_fetch_metadata_headers(request: &Request, headers: &mut Headers) {
+    let mode = match request.mode {
+        RequestMode::Navigate => "navigate",
+        RequestMode::SameOrigin => "same-origin",
+        RequestMode::NoCors => "no-cors",
+        RequestMode::CorsMode => "cors",
+    };
+    headers.set_raw("Sec-Fetch-Site", vec![fetch_metadata_site(request).as_bytes().to_vec()]);
+    headers.set_raw("Sec-Fetch-Mode", vec![mode.as_bytes().to_vec()]);
+    headers.set_raw("Sec-Fetch-Dest", vec![fetch_metadata_destination(request).as_bytes().to_vec()]);
+    if request.mode == RequestMode::Navigate && request.user_activation {
+        headers.set_raw("Sec-Fetch-User", vec![b"?1".to_vec()]);
+    }
+}
+
+/// Tell the server how urgent `request` is, if it asked for a priority.
+/// https://tools.ietf.org/html/rfc9218
+fn set_priority_header(request: &Request, headers: &mut Headers) {
+    if let Some(priority) = request.priority {
+        if headers.get_raw("Priority").is_none() {
+            headers.set_raw("Priority", vec![format!("u={}", priority.urgency()).into_bytes()]);
+        }
+    }
+}
+
 fn set_cookie_for_url(cookie_jar: &Arc<RwLock<CookieStorage>>,
                       request: &ServoUrl,
-                      cookie_val: String) {
-    let mut cookie_jar = cookie_jar.write().unwrap();
+                      cookie_val: String,
+                      net_stats: &NetStats) {
+    let mut cookie_jar = write_lock(cookie_jar, "cookie jar");
     let source = CookieSource::HTTP;
     let header = Header::parse_header(&[cookie_val.into_bytes()]);
 
     if let Ok(SetCookie(cookies)) = header {
         for bare_cookie in cookies {
             if let Some(cookie) = cookie::Cookie::new_wrapped(bare_cookie, request, source) {
-                cookie_jar.push(cookie, source);
+                net_stats.evicted_cookies(cookie_jar.push(cookie, source));
             }
         }
     }
 }
 
-fn set_cookies_from_headers(url: &ServoUrl, headers: &Headers, cookie_jar: &Arc<RwLock<CookieStorage>>) {
+fn set_cookies_from_headers(url: &ServoUrl, headers: &Headers, cookie_jar: &Arc<RwLock<CookieStorage>>,
+                            cookie_policy: &Arc<RwLock<CookieAcceptPolicy>>, third_party: bool,
+                            net_stats: &NetStats) {
+    let policy = *read_lock(cookie_policy, "cookie policy");
+    if !cookie::Cookie::policy_allows(policy, Some(third_party)) {
+        return;
+    }
     if let Some(cookies) = headers.get_raw("set-cookie") {
         for cookie in cookies.iter() {
             if let Ok(cookie_value) = String::from_utf8(cookie.clone()) {
                 set_cookie_for_url(&cookie_jar,
                                    &url,
-                                   cookie_value);
+                                   cookie_value,
+                                   net_stats);
             }
         }
     }
 }
 
-struct StreamedResponse {
-    decoder: Decoder,
+/// The kinds of data that a `Clear-Site-Data` header asks to clear, and that can be.
+/// Execution contexts aren't among them, as the network stack has no hold on them.
+#[derive(Default)]
+struct ClearSiteDataTypes {
+    cache: bool,
+    cookies: bool,
+    storage: bool,
+}
+
+/// Parse the `Clear-Site-Data` headers, a list of quoted type names. Unknown and unquoted
+/// names are ignored.
+fn clear_site_data_types(headers: &Headers) -> ClearSiteDataTypes {
+    let mut types = ClearSiteDataTypes::default();
+    for value in headers.get_raw("Clear-Site-Data").unwrap_or(&[]) {
+        for name in String::from_utf8_lossy(value).split(',').map(str::trim) {
+            match name {
+                "\"cache\"" => types.cache = true,
+                "\"cookies\"" => types.cookies = true,
+                "\"storage\"" => types.storage = true,
+                "\"*\"" => {
+                    types = ClearSiteDataTypes {
+                        cache: true,
+                        cookies: true,
+                        storage: true,
+                    };
+                }
+                _ => (),
+            }
+        }
+    }
+    types
 }
 
+/// Whether `url` is [potentially trustworthy](https://w3c.github.io/webappsec-secure-contexts/#is-origin-trustworthy).
+fn is_potentially_trustworthy(url: &ServoUrl) -> bool {
+    if url.scheme() == "https" || url.scheme() == "wss" {
+        return true;
+    }
+    match url.host() {
+        Some(Host::Domain(domain)) => domain == "localhost" || domain.ends_with(".localhost"),
+        Some(Host::Ipv4(address)) => address.octets()[0] == 127,
+        Some(Host::Ipv6(address)) => address.is_loopback(),
+        None => false,
+    }
+}
+
+/// Clear the data of the origin of `url` that its response's `Clear-Site-Data` header
+/// asks for, unless the origin could be impersonated.
+fn clear_site_data(url: &ServoUrl, headers: &Headers, context: &FetchContext) {
+    if !is_potentially_trustworthy(url) {
+        return;
+    }
+    let types = clear_site_data_types(headers);
+    if types.cookies {
+        // Cookies are shared by every origin of the registrable domain, so all of them go.
+        // TLS sessions can identify the user just as well, so they go too.
+        if let Some(host) = url.host_str() {
+            write_lock(&context.state.cookie_jar, "cookie jar").clear_host(host);
+            context.state.connection_pools.clear_tls_sessions(host);
+        }
+    }
+    if types.cache {
+        let origin = url.origin();
+        write_lock(&context.state.memory_cache, "memory cache").clear_origin(&origin);
+        if let Some(ref http_cache) = context.state.http_cache {
+            write_lock(http_cache, "HTTP cache").clear_origin(&origin);
+        }
+    }
+    if let (true, Some(storage_thread)) = (types.storage, context.storage_thread.as_ref()) {
+        for &storage_type in &[StorageType::Local, StorageType::Session] {
+            // The storage is cleared before the response is handed on.
+            let (sender, receiver) = ipc::channel().unwrap();
+            if storage_thread.send(StorageThreadMsg::Clear(sender, url.clone(), storage_type)).is_ok() {
+                let _ = receiver.recv();
+            }
+        }
+    }
+}
+
+struct StreamedResponse {
+    decoder: Box<Read + Send>,
+}
 
 impl Read for StreamedResponse {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self.decoder {
-            Decoder::Gzip(ref mut d) => d.read(buf),
-            Decoder::Deflate(ref mut d) => d.read(buf),
-            Decoder::Brotli(ref mut d) => d.read(buf),
-            Decoder::Plain(ref mut d) => d.read(buf)
-        }
+        self.decoder.read(buf)
     }
 }
 
 impl StreamedResponse {
-    fn from_http_response(response: WrappedHttpResponse) -> io::Result<StreamedResponse> {
-        let decoder = match response.content_encoding() {
-            Some(Encoding::Gzip) => {
-                Decoder::Gzip(try!(GzDecoder::new(response)))
-            }
-            Some(Encoding::Deflate) => {
-                Decoder::Deflate(DeflateDecoder::new(response))
-            }
-            Some(Encoding::EncodingExt(ref ext)) if ext == "br" => {
-                Decoder::Brotli(Decompressor::new(response, 1024))
-            }
-            _ => {
-                Decoder::Plain(response)
-            }
-        };
+    /// Read the body of `response`, undoing its `Content-Encoding` unless `raw_body`.
+    fn from_http_response(response: WrappedHttpResponse, raw_body: bool) -> io::Result<StreamedResponse> {
+        let encodings = if raw_body { vec![] } else { response.content_encodings() };
+        let mut decoder: Box<Read + Send> = Box::new(response);
+        // The last coding listed was applied last, so it is undone first.
+        for encoding in encodings.iter().rev() {
+            decoder = match *encoding {
+                Encoding::Identity => decoder,
+                Encoding::Gzip => try!(gzip_decoder(decoder)),
+                Encoding::EncodingExt(ref ext) if ext == "x-gzip" => try!(gzip_decoder(decoder)),
+                Encoding::Deflate => try!(deflate_decoder(decoder)),
+                Encoding::EncodingExt(ref ext) if ext == "br" => Box::new(Decompressor::new(decoder, 1024)),
+                // The body can't be decoded any further, so it is passed on as it is.
+                _ => break,
+            };
+        }
         Ok(StreamedResponse { decoder: decoder })
     }
 }
 
-enum Decoder {
-    Gzip(GzDecoder<WrappedHttpResponse>),
-    Deflate(DeflateDecoder<WrappedHttpResponse>),
-    Brotli(Decompressor<WrappedHttpResponse>),
-    Plain(WrappedHttpResponse)
+/// The first `len` bytes of `body`, or all of it if it is shorter, along with a reader
+/// of the whole body.
+fn peek(mut body: Box<Read + Send>, len: u64) -> io::Result<(Vec<u8>, Box<Read + Send>)> {
+    let mut prefix = vec![];
+    try!(body.by_ref().take(len).read_to_end(&mut prefix));
+    Ok((prefix.clone(), Box::new(Cursor::new(prefix).chain(body))))
+}
+
+/// Undo gzip on `body`, unless it lacks the magic bytes every gzip member starts with, in
+/// which case it was mislabeled and is passed on as it is. Anything after the end of the
+/// first member is ignored, like other browsers do.
+fn gzip_decoder(body: Box<Read + Send>) -> io::Result<Box<Read + Send>> {
+    let (magic, body) = try!(peek(body, 2));
+    if magic != [0x1f, 0x8b] {
+        debug!("Body labeled as gzip isn't, passing it through");
+        return Ok(body);
+    }
+    Ok(Box::new(try!(GzDecoder::new(body))))
+}
+
+/// Undo deflate on `body`, which should be in the zlib format, but is often sent as the
+/// raw deflate data instead. Anything after the end of the data is ignored.
+fn deflate_decoder(body: Box<Read + Send>) -> io::Result<Box<Read + Send>> {
+    let (header, body) = try!(peek(body, 2));
+    // https://tools.ietf.org/html/rfc1950#section-2.2
+    let is_zlib = header.len() == 2 && header[0] & 0x0f == 8 &&
+                  (header[0] as u16 * 256 + header[1] as u16) % 31 == 0;
+    if is_zlib {
+        Ok(Box::new(ZlibDecoder::new(body)))
+    } else {
+        Ok(Box::new(DeflateDecoder::new(body)))
+    }
 }
 
 fn prepare_devtools_request(request_id: String,
@@ -352,6 +772,7 @@ fn prepare_devtools_request(request_id: String,
                             now: Tm,
                             connect_time: u64,
                             send_time: u64,
+                            retransmit_time: Option<u64>,
                             is_xhr: bool) -> ChromeToDevtoolsControlMsg {
     let request = DevtoolsHttpRequest {
         url: url,
@@ -363,6 +784,7 @@ fn prepare_devtools_request(request_id: String,
         timeStamp: now.to_timespec().sec,
         connect_time: connect_time,
         send_time: send_time,
+        retransmit_time: retransmit_time,
         is_xhr: is_xhr,
     };
     let net_event = NetworkEvent::HttpRequest(request);
@@ -388,7 +810,7 @@ fn send_response_to_devtools(devtools_chan: &Sender<DevtoolsControlMsg>,
 }
 
 fn auth_from_cache(auth_cache: &Arc<RwLock<AuthCache>>, origin: &UrlOrigin) -> Option<Basic> {
-    if let Some(ref auth_entry) = auth_cache.read().unwrap().entries.get(&origin.ascii_serialization()) {
+    if let Some(ref auth_entry) = read_lock(auth_cache, "auth cache").entries.get(&origin.ascii_serialization()) {
         let user_name = auth_entry.user_name.clone();
         let password  = Some(auth_entry.password.clone());
         Some(Basic { username: user_name, password: password })
@@ -397,40 +819,217 @@ fn auth_from_cache(auth_cache: &Arc<RwLock<AuthCache>>, origin: &UrlOrigin) -> O
     }
 }
 
+/// Ask the embedder for the credentials to answer a challenge from `host` with for
+/// `request`, waiting for the answer. There are none if there is no one to ask, the user
+/// declined, or the fetch stopped waiting. Only navigations and same-origin requests ask,
+/// so that a page can't have the user asked for the credentials of a site it embeds.
+fn prompt_for_credentials(request: &Request, host: String, realm: Option<String>, is_proxy: bool,
+                          context: &FetchContext) -> Option<AuthCacheEntry> {
+    let url = request.current_url();
+    let same_origin = match *request.origin.borrow() {
+        Origin::Origin(ref origin) => *origin == url.origin(),
+        Origin::Client => false,
+    };
+    if !request.is_navigation_request() && !same_origin {
+        return None;
+    }
+    let auth_prompt = match context.auth_prompt {
+        Some(ref auth_prompt) => auth_prompt,
+        None => return None,
+    };
+    let (sender, receiver) = ipc::channel().unwrap();
+    let prompt = AuthPromptRequest {
+        url: url,
+        host: host,
+        realm: realm,
+        is_proxy: is_proxy,
+        response_chan: sender,
+    };
+    if auth_prompt.send(prompt).is_err() {
+        return None;
+    }
+    let answer = recv_while_fetching(receiver, &context.cancellation_listener, context.deadline);
+    answer.ok().and_then(|credentials| credentials).map(|credentials| AuthCacheEntry {
+        user_name: credentials.user_name,
+        password: credentials.password,
+    })
+}
+
+/// Open a reader for each part of a streamed request body, returning them along with the
+/// total length of the body, if it is known. Waiting for a streamed part stops once the
+/// fetch is cancelled or `deadline` passes.
+fn open_body_parts(parts: &[BodyPart],
+                   filemanager: &FileManager,
+                   cancellation_listener: &Arc<Mutex<CancellationListener>>,
+                   deadline: Option<Deadline>)
+                   -> Result<(Option<u64>, Vec<Box<Read + Send>>), NetworkError> {
+    let mut total = Some(0);
+    let mut readers = Vec::with_capacity(parts.len());
+    for part in parts {
+        let (len, reader): (Option<u64>, Box<Read + Send>) = match *part {
+            BodyPart::Bytes(ref bytes) => (Some(bytes.len() as u64), Box::new(io::Cursor::new(bytes.clone()))),
+            BodyPart::File(ref id, ref origin) => {
+                let (len, reader) = try!(filemanager.open_reader(id, origin).map_err(|e| {
+                    NetworkError::Internal(format!("Failed to read request body: {:?}", e))
+                }));
+                (Some(len), reader)
+            }
+            BodyPart::Stream(ref pull, len) => (len, Box::new(StreamedBodyReader {
+                pull: pull.clone(),
+                chunk: Cursor::new(vec![]),
+                ended: false,
+                cancellation_listener: cancellation_listener.clone(),
+                deadline: deadline,
+            })),
+        };
+        total = match (total, len) {
+            (Some(total), Some(len)) => Some(total + len),
+            _ => None,
+        };
+        readers.push(reader);
+    }
+    Ok((total, readers))
+}
+
+/// Reads a `BodyPart::Stream`, asking for each chunk once the one before it has been read.
+struct StreamedBodyReader {
+    pull: IpcSender<IpcSender<Vec<u8>>>,
+    chunk: Cursor<Vec<u8>>,
+    ended: bool,
+    cancellation_listener: Arc<Mutex<CancellationListener>>,
+    deadline: Option<Deadline>,
+}
+
+impl Read for StreamedBodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = try!(self.chunk.read(buf));
+            if read > 0 || self.ended || buf.is_empty() {
+                return Ok(read);
+            }
+            let (sender, receiver) = try!(ipc::channel());
+            if self.pull.send(sender).is_err() {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "request body stream was dropped"));
+            }
+            let chunk = try!(recv_while_fetching(receiver, &self.cancellation_listener, self.deadline)
+                             .map_err(|error| match error {
+                WaitError::Cancelled => io::Error::new(io::ErrorKind::Other, "fetch was cancelled"),
+                WaitError::TimedOut => io::Error::new(io::ErrorKind::TimedOut, "request body stream timed out"),
+                WaitError::Disconnected => {
+                    io::Error::new(io::ErrorKind::BrokenPipe, "request body stream was dropped")
+                }
+            }));
+            self.ended = chunk.is_empty();
+            self.chunk = Cursor::new(chunk);
+        }
+    }
+}
+
+/// Whether a request body can be sent again, which it can't if it is streamed over IPC.
+fn body_is_replayable(body_parts: &Option<Vec<BodyPart>>) -> bool {
+    body_parts.as_ref().map_or(true, |parts| parts.iter().all(BodyPart::is_replayable))
+}
+
+/// How much of a request body is written at a time.
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long to wait after reporting the progress of an upload before reporting it again.
+const UPLOAD_PROGRESS_INTERVAL_NS: u64 = 50 * 1000 * 1000;
+
+/// Counts the bytes of a request body as they are written, reporting the count at most
+/// once every `UPLOAD_PROGRESS_INTERVAL_NS` and once more when the whole body has been
+/// written. Nothing is reported for an empty body.
+struct UploadProgress<'a> {
+    report: &'a mut FnMut(u64, Option<u64>),
+    /// The length of the whole body, unless it is sent in chunks of unknown length.
+    total: Option<u64>,
+    sent: u64,
+    reported: u64,
+    last_report: Option<u64>,
+}
+
+impl<'a> UploadProgress<'a> {
+    fn new(report: &'a mut FnMut(u64, Option<u64>), total: Option<u64>) -> UploadProgress<'a> {
+        UploadProgress {
+            report: report,
+            total: total,
+            sent: 0,
+            reported: 0,
+            last_report: None,
+        }
+    }
+
+    fn written(&mut self, len: usize) {
+        self.sent += len as u64;
+        let now = time::precise_time_ns();
+        if self.last_report.map_or(true, |last| now - last >= UPLOAD_PROGRESS_INTERVAL_NS) {
+            self.report_sent(now);
+        }
+    }
+
+    fn finished(&mut self) {
+        if self.sent > self.reported {
+            self.report_sent(time::precise_time_ns());
+        }
+    }
+
+    fn report_sent(&mut self, now: u64) {
+        (self.report)(self.sent, self.total);
+        self.reported = self.sent;
+        self.last_report = Some(now);
+    }
+}
+
 fn obtain_response(request_factory: &NetworkHttpRequestFactory,
                    url: &ServoUrl,
                    method: &Method,
                    request_headers: &Headers,
                    data: &Option<Vec<u8>>,
+                   body_parts: &Option<Vec<BodyPart>>,
+                   filemanager: &FileManager,
                    load_data_method: &Method,
                    pipeline_id: &Option<PipelineId>,
-                   iters: u32,
                    request_id: Option<&str>,
-                   is_xhr: bool)
+                   is_xhr: bool,
+                   timing: Option<&NetworkTimingReporter>,
+                   cancellation_listener: &Arc<Mutex<CancellationListener>>,
+                   deadline: Option<Deadline>,
+                   report_progress: &mut FnMut(u64, Option<u64>))
                    -> Result<(WrappedHttpResponse, Option<ChromeToDevtoolsControlMsg>), NetworkError> {
     let null_data = None;
     let connection_url = replace_hosts(&url);
+    // When the first attempt started, if it failed on a stale connection and was retried.
+    let mut retransmit_start = None;
 
-    // loop trying connections in connection pool
-    // they may have grown stale (disconnected), in which case we'll get
-    // a ConnectionAborted error. this loop tries again with a new
-    // connection.
+    // A connection from the pool may have been closed by the server while it was idle,
+    // which is only found out when a request is made on it. Such a request is retried
+    // once on a new connection if it is safe to make again.
     loop {
         let mut headers = request_headers.clone();
 
-        // Avoid automatically sending request body if a redirect has occurred.
-        //
-        // TODO - This is the wrong behaviour according to the RFC. However, I'm not
-        // sure how much "correctness" vs. real-world is important in this case.
-        //
-        // https://tools.ietf.org/html/rfc7231#section-6.4
-        let is_redirected_request = iters != 1;
+        // The body is sent again after a redirect that keeps it, such as a 307 or 308; the
+        // redirects that don't keep it have already dropped it from the request.
         let request_body;
-        match data {
-            &Some(ref d) if !is_redirected_request => {
+        // The parts are reopened on every attempt, as a failed one may have read some of them.
+        let mut body_readers = vec![];
+        let mut body_len = Some(0);
+        match (data, body_parts) {
+            (&Some(ref d), _) => {
+                body_len = Some(d.len() as u64);
                 headers.set(ContentLength(d.len() as u64));
                 request_body = data;
             }
+            (&None, &Some(ref parts)) => {
+                let (len, readers) = try!(open_body_parts(parts, filemanager, cancellation_listener, deadline));
+                body_len = len;
+                // A body of unknown length is sent in chunks.
+                match len {
+                    Some(len) => headers.set(ContentLength(len)),
+                    None => { headers.remove::<ContentLength>(); }
+                }
+                body_readers = readers;
+                request_body = &null_data;
+            }
             _ => {
                 if *load_data_method != Method::Get && *load_data_method != Method::Head {
                     headers.set(ContentLength(0))
@@ -447,36 +1046,77 @@ fn obtain_response(request_factory: &NetworkHttpRequestFactory,
             info!("{:?}", data);
         }
 
-        let connect_start = precise_time_ms();
+        // Forget any handshake, early hints or trailers left over from a connection that wasn't used.
+        take_handshake_time();
+        take_early_hints();
+        take_trailers();
+        take_new_connection();
+        take_response_bytes_read();
+        let connect_start = time::precise_time_ns();
 
         let request = try!(request_factory.create(connection_url.clone(), method.clone(),
-                                                  headers.clone()));
+                                                  headers.clone(), retransmit_start.is_some()));
 
-        let connect_end = precise_time_ms();
+        let connect_end = time::precise_time_ns();
+        let handshake = take_handshake_time();
 
-        let send_start = precise_time_ms();
-
-        let mut request_writer = match request.start() {
-            Ok(streaming) => streaming,
-            Err(e) => return Err(NetworkError::Internal(e.description().to_owned())),
-        };
+        let send_start = time::precise_time_ns();
 
-        if let Some(ref data) = *request_body {
-            if let Err(e) = request_writer.write_all(&data) {
-                return Err(NetworkError::Internal(e.description().to_owned()))
+        let response = request.start().and_then(|mut request_writer| {
+            // A retried attempt reports its progress from the start of the body again.
+            let mut progress = UploadProgress::new(report_progress, body_len);
+            if let Some(ref data) = *request_body {
+                for chunk in data.chunks(UPLOAD_CHUNK_SIZE) {
+                    try!(request_writer.write_all(chunk));
+                    progress.written(chunk.len());
+                }
             }
-        }
-
-        let response = match request_writer.send() {
-            Ok(w) => w,
-            Err(HttpError::Io(ref io_error)) if io_error.kind() == io::ErrorKind::ConnectionAborted => {
-                debug!("connection aborted ({:?}), possibly stale, trying new connection", io_error.description());
+            let mut buf = vec![0; UPLOAD_CHUNK_SIZE];
+            for mut reader in body_readers {
+                loop {
+                    let read = match reader.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(HttpError::Io(e)),
+                    };
+                    try!(request_writer.write_all(&buf[..read]));
+                    progress.written(read);
+                }
+            }
+            progress.finished();
+            request_writer.send()
+        });
+        let reused_connection = !take_new_connection();
+        let response = match response {
+            Ok(response) => response,
+            Err(HttpError::Io(ref io_error)) if retransmit_start.is_none() && reused_connection &&
+                                                !take_response_bytes_read() && is_idempotent(method) &&
+                                                body_is_replayable(body_parts) &&
+                                                is_stale_connection_error(io_error) => {
+                debug!("connection reset ({}), possibly stale, retrying on a new connection", io_error);
+                retransmit_start = Some(connect_start);
                 continue;
             },
+            Err(_) if cancellation_listener.lock().unwrap().cancelled() => {
+                return Err(NetworkError::LoadCancelled)
+            },
             Err(e) => return Err(NetworkError::Internal(e.description().to_owned())),
         };
 
-        let send_end = precise_time_ms();
+        let send_end = time::precise_time_ns();
+
+        if let Some(timing) = timing {
+            // A connection taken from the pool is reported as a very short connect.
+            match handshake {
+                Some((handshake_start, handshake_end)) => {
+                    timing.report(ProfilerCategory::NetDNSConnect, connect_start, handshake_start);
+                    timing.report(ProfilerCategory::NetTLSHandshake, handshake_start, handshake_end);
+                }
+                None => timing.report(ProfilerCategory::NetDNSConnect, connect_start, connect_end),
+            }
+            timing.report(ProfilerCategory::NetTimeToFirstByte, send_start, send_end);
+        }
 
         let msg = if let Some(request_id) = request_id {
             if let Some(pipeline_id) = *pipeline_id {
@@ -484,7 +1124,8 @@ fn obtain_response(request_factory: &NetworkHttpRequestFactory,
                     request_id.into(),
                     url.clone(), method.clone(), headers,
                     request_body.clone(), pipeline_id, time::now(),
-                    connect_end - connect_start, send_end - send_start, is_xhr))
+                    ns_to_ms(connect_end - connect_start), ns_to_ms(send_end - send_start),
+                    retransmit_start.map(|start| ns_to_ms(connect_start - start)), is_xhr))
             } else {
                 debug!("Not notifying devtools (no pipeline_id)");
                 None
@@ -498,6 +1139,19 @@ fn obtain_response(request_factory: &NetworkHttpRequestFactory,
     }
 }
 
+/// Whether a request with `method` can be made again without changing its effect, if it
+/// isn't known to have been received.
+fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::Get | Method::Head)
+}
+
+/// Whether `error` is how making a request on a connection that the server has closed
+/// fails: hyper reports a connection that closes before any of the response as aborted.
+fn is_stale_connection_error(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset |
+                           io::ErrorKind::BrokenPipe)
+}
+
 // FIXME: This incredibly hacky. Make it more robust, and at least test it.
 fn is_cert_verify_error(error: &OpensslError) -> bool {
     match error {
@@ -529,7 +1183,6 @@ fn format_ssl_error(error: &OpensslError) -> String {
 
 /// [HTTP fetch](https://fetch.spec.whatwg.org#http-fetch)
 pub fn http_fetch(request: Rc<Request>,
-                  cache: &mut CorsCache,
                   cors_flag: bool,
                   cors_preflight_flag: bool,
                   authentication_fetch_flag: bool,
@@ -581,18 +1234,22 @@ pub fn http_fetch(request: Rc<Request>,
     if response.is_none() {
         // Substep 1
         if cors_preflight_flag {
-            let method_cache_match = cache.match_method(&*request,
-                                                        request.method.borrow().clone());
-
-            let method_mismatch = !method_cache_match && (!is_simple_method(&request.method.borrow()) ||
-                                                          request.use_cors_preflight);
-            let header_mismatch = request.headers.borrow().iter().any(|view|
-                !cache.match_header(&*request, view.name()) && !is_simple_header(&view)
-            );
+            let (method_mismatch, header_mismatch) = {
+                let mut cache = write_lock(&context.state.cors_cache, "CORS preflight cache");
+                let method_cache_match = cache.match_method(&*request,
+                                                            request.method.borrow().clone());
+
+                let method_mismatch = !method_cache_match && (!is_simple_method(&request.method.borrow()) ||
+                                                              request.use_cors_preflight);
+                let header_mismatch = request.headers.borrow().iter().any(|view|
+                    !cache.match_header(&*request, view.name()) && !is_simple_header(&view)
+                );
+                (method_mismatch, header_mismatch)
+            };
 
             // Sub-substep 1
             if method_mismatch || header_mismatch {
-                let preflight_result = cors_preflight_fetch(request.clone(), cache, context);
+                let preflight_result = cors_preflight_fetch(request.clone(), context);
                 // Sub-substep 2
                 if let Some(e) = preflight_result.get_network_error() {
                     return Response::network_error(e.clone());
@@ -605,7 +1262,7 @@ pub fn http_fetch(request: Rc<Request>,
 
         // Substep 3
         let fetch_result = http_network_or_cache_fetch(request.clone(), credentials, authentication_fetch_flag,
-                                                       done_chan, context);
+                                                       target, done_chan, context);
 
         // Substep 4
         if cors_flag && cors_check(request.clone(), &fetch_result).is_err() {
@@ -619,6 +1276,11 @@ pub fn http_fetch(request: Rc<Request>,
     // response is guaranteed to be something by now
     let mut response = response.unwrap();
 
+    // A proxy that wants credentials before it opens a tunnel refuses `CONNECT` with a 407,
+    // which leaves no response to look at.
+    let tunnel_refused_for_credentials =
+        response.get_network_error() == Some(&NetworkError::ProxyTunnelFailed(407));
+
     // Step 5
     match response.actual_response().status {
         // Code 301, 302, 303, 307, 308
@@ -635,7 +1297,7 @@ pub fn http_fetch(request: Rc<Request>,
                 RedirectMode::Follow => {
                     // set back to default
                     response.return_internal.set(true);
-                    http_redirect_fetch(request, cache, response,
+                    http_redirect_fetch(request, response,
                                         cors_flag, target, done_chan, context)
                 }
             }
@@ -651,41 +1313,84 @@ pub fn http_fetch(request: Rc<Request>,
 
             // Step 2
             // TODO: Spec says requires testing on multiple WWW-Authenticate headers
+            if !body_is_replayable(&request.body_parts.borrow()) {
+                return Response::network_error(NetworkError::Internal("Request body can't be sent again".into()));
+            }
 
             // Step 3
-            if !request.use_url_credentials || authentication_fetch_flag {
-                // TODO: Prompt the user for username and password from the window
-                // Wrong, but will have to do until we are able to prompt the user
-                // otherwise this creates an infinite loop
-                // We basically pretend that the user declined to enter credentials
-                return response;
+            // The credentials of the URL are tried first, and are the ones sent for as long as the
+            // request has them, so the user is only asked when they can't be used. A Digest
+            // challenge can also be answered with the cached credentials, which were only sent
+            // with Basic so far.
+            let current_url = request.current_url();
+            let url_credentials = request.use_url_credentials && has_credentials(&current_url);
+            let challenge = digest_challenge(&response.actual_response().headers);
+            let cached = auth_from_cache(&context.state.auth_cache, &current_url.origin()).is_some();
+            let untried_credentials = !authentication_fetch_flag &&
+                                      (url_credentials || (challenge.is_some() && cached));
+            if !untried_credentials {
+                if url_credentials {
+                    return response;
+                }
+                let realm = challenge_realm(&response.actual_response().headers, "WWW-Authenticate");
+                let host = current_url.host_str().unwrap_or("").to_owned();
+                match prompt_for_credentials(&request, host, realm, false, context) {
+                    Some(credentials) => {
+                        write_lock(&context.state.auth_cache, "auth cache").entries.insert(
+                            current_url.origin().ascii_serialization(), credentials);
+                    }
+                    // A declined prompt leaves the page with the 401.
+                    None => return response,
+                }
+            }
+            // A Digest challenge is remembered for the requests that come after this one.
+            if let Some(challenge) = challenge {
+                write_lock(&context.state.digest_auth, "Digest auth")
+                    .challenged(&current_url.origin().ascii_serialization(), challenge);
             }
 
             // Step 4
-            return http_fetch(request, cache, cors_flag, cors_preflight_flag,
+            return http_fetch(request, cors_flag, cors_preflight_flag,
                               true, target, done_chan, context);
         }
 
-        // Code 407
-        Some(StatusCode::ProxyAuthenticationRequired) => {
+        // Code 407, from a proxy that requests are forwarded to or that refused to open a
+        // tunnel with it
+        status if status == Some(StatusCode::ProxyAuthenticationRequired) || tunnel_refused_for_credentials => {
             // Step 1
             // TODO: Figure out what to do with request window objects
 
             // Step 2
             // TODO: Spec says requires testing on Proxy-Authenticate headers
+            if !body_is_replayable(&request.body_parts.borrow()) {
+                return Response::network_error(NetworkError::Internal("Request body can't be sent again".into()));
+            }
 
             // Step 3
-            // TODO: Prompt the user for proxy authentication credentials
-            // Wrong, but will have to do until we are able to prompt the user
-            // otherwise this creates an infinite loop
-            // We basically pretend that the user declined to enter credentials
-            return response;
+            // The credentials of a proxy can only be asked for if its pref doesn't give them.
+            let current_url = request.current_url();
+            let pools = &context.state.connection_pools;
+            let host = current_url.host_str().unwrap_or("");
+            let proxy = match pools.proxy_without_credentials(current_url.scheme(), host) {
+                Some(proxy) => proxy,
+                None => return response,
+            };
+            let realm = challenge_realm(&response.actual_response().headers, "Proxy-Authenticate");
+            match prompt_for_credentials(&request, proxy.host.clone(), realm, true, context) {
+                Some(credentials) => {
+                    pools.proxy_credentials().set(&proxy, Basic {
+                        username: credentials.user_name,
+                        password: Some(credentials.password),
+                    });
+                }
+                None => return response,
+            }
 
             // Step 4
-            // return http_fetch(request, cache,
-            //                   cors_flag, cors_preflight_flag,
-            //                   authentication_fetch_flag, target,
-            //                   done_chan, context);
+            return http_fetch(request,
+                              cors_flag, cors_preflight_flag,
+                              authentication_fetch_flag, target,
+                              done_chan, context);
         }
 
         _ => { }
@@ -704,7 +1409,6 @@ pub fn http_fetch(request: Rc<Request>,
 
 /// [HTTP redirect fetch](https://fetch.spec.whatwg.org#http-redirect-fetch)
 fn http_redirect_fetch(request: Rc<Request>,
-                       cache: &mut CorsCache,
                        response: Response,
                        cors_flag: bool,
                        target: &mut Target,
@@ -714,6 +1418,13 @@ fn http_redirect_fetch(request: Rc<Request>,
     // Step 1
     assert_eq!(response.return_internal.get(), true);
 
+    if context.cancellation_listener.lock().unwrap().cancelled() {
+        return Response::network_error(NetworkError::LoadCancelled);
+    }
+    if has_timed_out(context) {
+        return Response::network_error(NetworkError::Timeout);
+    }
+
     // Step 2
     if !response.actual_response().headers.has::<Location>() {
         return response;
@@ -738,8 +1449,8 @@ fn http_redirect_fetch(request: Rc<Request>,
     }
 
     // Step 5
-    if request.redirect_count.get() >= 20 {
-        return Response::network_error(NetworkError::Internal("Too many redirects".into()));
+    if request.redirect_count.get() >= max_redirects() {
+        return Response::network_error(NetworkError::TooManyRedirects);
     }
 
     // Step 6
@@ -768,28 +1479,75 @@ fn http_redirect_fetch(request: Rc<Request>,
     }
 
     // Step 10
+    // A 307 or 308 keeps the method and the body, which is sent again.
     let status_code = response.actual_response().status.unwrap();
+    let method = request.method.borrow().clone();
     if ((status_code == StatusCode::MovedPermanently || status_code == StatusCode::Found) &&
-        *request.method.borrow() == Method::Post) ||
-        status_code == StatusCode::SeeOther {
+        method == Method::Post) ||
+        (status_code == StatusCode::SeeOther && method != Method::Get && method != Method::Head) {
         *request.method.borrow_mut() = Method::Get;
         *request.body.borrow_mut() = None;
+        *request.body_parts.borrow_mut() = None;
+        // The headers that describe the body go with it.
+        let mut headers = request.headers.borrow_mut();
+        for name in &["content-encoding", "content-language", "content-location", "content-type"] {
+            headers.remove_raw(name);
+        }
+    }
+
+    // A body that has already been streamed can't be sent where the request is redirected.
+    if !body_is_replayable(&request.body_parts.borrow()) {
+        return Response::network_error(NetworkError::Internal("Request body can't be sent again".into()));
+    }
+
+    // Never forward credentials across an https: to http: downgrade.
+    if request.current_url().scheme() == "https" && location_url.scheme() == "http" {
+        let mut headers = request.headers.borrow_mut();
+        headers.remove_raw("authorization");
+        headers.remove_raw("proxy-authorization");
     }
 
     // Step 11
     request.url_list.borrow_mut().push(location_url);
+    request.redirect_statuses.borrow_mut().push(status_code.to_u16());
 
     // Step 12
-    // TODO implement referrer policy
+    // https://w3c.github.io/webappsec-referrer-policy/#set-requests-referrer-policy-on-redirect
+    // The referrer is then worked out again for the new URL by main fetch.
+    if let Some(policy) = response.actual_response().headers.get::<ReferrerPolicyHeader>() {
+        request.referrer_policy.set(Some(ReferrerPolicy::from(policy.clone())));
+    }
 
     // Step 13
-    main_fetch(request, cache, cors_flag, true, target, done_chan, context)
+    main_fetch(request, cors_flag, true, target, done_chan, context)
+}
+
+/// The ID that ALPN uses for `version`.
+fn alpn_id(version: HttpVersion) -> &'static str {
+    match version {
+        HttpVersion::Http09 => "http/0.9",
+        HttpVersion::Http10 => "http/1.0",
+        HttpVersion::Http11 => "http/1.1",
+        HttpVersion::Http20 => "h2",
+    }
+}
+
+/// Whether the fetch was given a timeout that has run out.
+fn has_timed_out(context: &FetchContext) -> bool {
+    context.deadline.map_or(false, |deadline| deadline.has_passed())
+}
+
+/// The maximum number of redirects a single fetch will follow, taken from the
+/// `network.http.redirection-limit` pref.
+fn max_redirects() -> u32 {
+    PREFS.get("network.http.redirection-limit").as_u64().map(|limit| limit as u32).unwrap_or(20)
 }
 
 /// [HTTP network or cache fetch](https://fetch.spec.whatwg.org#http-network-or-cache-fetch)
 fn http_network_or_cache_fetch(request: Rc<Request>,
                                credentials_flag: bool,
                                authentication_fetch_flag: bool,
+                               target: &mut Target,
                                done_chan: &mut DoneChannel,
                                context: &FetchContext)
                                -> Response {
@@ -805,6 +1563,8 @@ fn http_network_or_cache_fetch(request: Rc<Request>,
     };
 
     let content_length_value = match *http_request.body.borrow() {
+        // A streamed body is measured when it is sent.
+        None if http_request.body_parts.borrow().is_some() => None,
         None =>
             match *http_request.method.borrow() {
                 // Step 3
@@ -844,6 +1604,16 @@ fn http_network_or_cache_fetch(request: Rc<Request>,
         let user_agent = context.user_agent.clone().into_owned();
         http_request.headers.borrow_mut().set(UserAgent(user_agent));
     }
+    context.privacy_signals.set_headers(&mut http_request.headers.borrow_mut());
+
+    // The connection is still made to the URL's host. A redirect goes to a host the
+    // override wasn't given for. What another host answers isn't the URL's response, so
+    // it is neither taken from the caches nor stored in them.
+    let keep_host = context.allow_host_override && http_request.headers.borrow().has::<Host>() &&
+                    http_request.url_list.borrow().len() == 1;
+    if keep_host {
+        http_request.cache_mode.set(CacheMode::NoStore);
+    }
 
     match http_request.cache_mode.get() {
         // Step 9
@@ -885,10 +1655,23 @@ fn http_network_or_cache_fetch(request: Rc<Request>,
             hostname: current_url.host_str().unwrap().to_owned(),
             port: current_url.port_or_known_default()
         };
-        headers.set(host);
+        if !keep_host {
+            headers.set(host);
+        }
         // unlike http_loader, we should not set the accept header
         // here, according to the fetch spec
         set_default_accept_encoding(headers);
+        set_fetch_metadata_headers(&http_request, headers);
+        set_priority_header(&http_request, headers);
+        if let Some(range_start) = http_request.range_start {
+            if !headers.has::<Range>() {
+                let range = match http_request.range_end {
+                    Some(range_end) => ByteRangeSpec::FromTo(range_start, range_end),
+                    None => ByteRangeSpec::AllFrom(range_start),
+                };
+                headers.set(Range::Bytes(vec![range]));
+            }
+        }
     }
 
     // Step 13
@@ -899,7 +1682,8 @@ fn http_network_or_cache_fetch(request: Rc<Request>,
         // XXXManishearth http_loader has block_cookies: support content blocking here too
         set_request_cookies(&current_url,
                             &mut *http_request.headers.borrow_mut(),
-                            &context.state.cookie_jar);
+                            &context.state.cookie_jar,
+                            same_site_context(&http_request));
         // Substep 2
         if !http_request.headers.borrow().has::<Authorization<String>>() {
             // Substep 3
@@ -923,8 +1707,25 @@ fn http_network_or_cache_fetch(request: Rc<Request>,
             }
 
             // Substep 6
+            // An origin that has made a Digest challenge gets the answer to it instead, which
+            // reuses the challenge's nonce rather than waiting to be challenged again.
             if let Some(basic) = authorization_value {
-                http_request.headers.borrow_mut().set(Authorization(basic));
+                let digest = {
+                    let mut uri = current_url.path().to_owned();
+                    if let Some(query) = current_url.query() {
+                        uri.push('?');
+                        uri.push_str(query);
+                    }
+                    let password = basic.password.as_ref().map_or("", |password| &**password);
+                    write_lock(&context.state.digest_auth, "Digest auth")
+                        .authorization(&current_url.origin().ascii_serialization(),
+                                       http_request.method.borrow().as_ref(), &uri, &basic.username, password)
+                };
+                let mut headers = http_request.headers.borrow_mut();
+                match digest {
+                    Some(digest) => headers.set_raw("Authorization", vec![digest.into_bytes()]),
+                    None => headers.set(Authorization(basic)),
+                }
             }
         }
     }
@@ -936,33 +1737,54 @@ fn http_network_or_cache_fetch(request: Rc<Request>,
     let mut response: Option<Response> = None;
 
     // Step 16
-    // TODO have a HTTP cache to check for a completed response
-    let complete_http_response_from_cache: Option<Response> = None;
-    if http_request.cache_mode.get() != CacheMode::NoStore &&
-        http_request.cache_mode.get() != CacheMode::Reload &&
-        complete_http_response_from_cache.is_some() {
-        // Substep 1
-        if http_request.cache_mode.get() == CacheMode::ForceCache {
-            // TODO pull response from HTTP cache
-            // response = http_request
+    let use_cache = http_request.cache_mode.get() != CacheMode::NoStore &&
+                    http_request.cache_mode.get() != CacheMode::Reload;
+    // The memory cache only holds fresh responses, so it is consulted first.
+    let from_memory = if use_cache {
+        write_lock(&context.state.memory_cache, "memory cache").lookup(&http_request)
+                                                                  .map(|response| (response, Freshness::Fresh))
+    } else {
+        None
+    };
+    let complete_http_response_from_cache = match context.state.http_cache {
+        Some(ref http_cache) if use_cache && from_memory.is_none() => {
+            http_cache::lookup(http_cache, &http_request)
         }
+        _ => from_memory,
+    };
+    // The stale response that the network is asked to revalidate.
+    let mut stored_response = None;
+    if let Some((mut cached_response, freshness)) = complete_http_response_from_cache {
+        let revalidation_needed = freshness != Freshness::Fresh;
+        match http_request.cache_mode.get() {
+            // Substep 1
+            // Responses that must be revalidated once stale are, even here.
+            CacheMode::ForceCache | CacheMode::OnlyIfCached
+                if !revalidation_needed || may_serve_stale(&cached_response.headers) => {
+                response = Some(cached_response)
+            }
 
-        let revalidation_needed = match response {
-            Some(ref response) => response_needs_revalidation(&response),
-            _ => false
-        };
+            // Substep 2
+            CacheMode::Default if !revalidation_needed => response = Some(cached_response),
 
-        // Substep 2
-        if !revalidation_needed && http_request.cache_mode.get() == CacheMode::Default {
-            // TODO pull response from HTTP cache
-            // response = http_request
-            // response.cache_state = CacheState::Local;
-        }
+            // https://tools.ietf.org/html/rfc5861#section-3
+            CacheMode::Default if freshness == Freshness::StaleWhileRevalidate => {
+                revalidate_in_background(&http_request, credentials_flag, context);
+                cached_response.cache_state = CacheState::StaleWhileRevalidate;
+                response = Some(cached_response);
+            }
 
-        // Substep 3
-        if revalidation_needed && http_request.cache_mode.get() == CacheMode::Default ||
-            http_request.cache_mode.get() == CacheMode::NoCache {
-            // TODO this substep
+            // Substep 3
+            CacheMode::Default | CacheMode::NoCache | CacheMode::ForceCache => {
+                set_conditional_headers(&mut http_request.headers.borrow_mut(), &cached_response.headers);
+                stored_response = Some(cached_response);
+            }
+
+            _ => {}
+        }
+        if response.is_some() {
+            // The cached body is already complete, so there is nothing to wait for.
+            *done_chan = None;
         }
 
     // Step 17
@@ -972,35 +1794,51 @@ fn http_network_or_cache_fetch(request: Rc<Request>,
         // TODO this substep
     }
 
+    // https://tools.ietf.org/html/rfc7234#section-5.2.1.7
+    // A request that may only be answered from the cache fails without a usable response.
+    if response.is_none() && (http_request.cache_mode.get() == CacheMode::OnlyIfCached ||
+                              CacheDirectives::from_headers(&http_request.headers.borrow()).only_if_cached) {
+        return Response::network_error(NetworkError::GatewayTimeout);
+    }
+
     // Step 18
     if response.is_none() {
         response = Some(http_network_fetch(http_request.clone(), credentials_flag,
-                                           done_chan, context));
+                                           target, done_chan, context));
     }
-    let response = response.unwrap();
+    let mut response = response.unwrap();
 
     // Step 19
     if let Some(status) = response.status {
         if status == StatusCode::NotModified &&
             (http_request.cache_mode.get() == CacheMode::Default ||
-            http_request.cache_mode.get() == CacheMode::NoCache) {
+            http_request.cache_mode.get() == CacheMode::NoCache ||
+            http_request.cache_mode.get() == CacheMode::ForceCache) {
             // Substep 1
-            // TODO this substep
-            // let cached_response: Option<Response> = None;
-
             // Substep 2
-            // if cached_response.is_none() {
-            //     return Response::network_error();
-            // }
-
-            // Substep 3
+            // A 304 that wasn't asked for is passed on as it is.
+            if let Some(mut cached_response) = stored_response {
+                // Substep 3
+                let headers = revalidated_headers(&cached_response.headers, &response.headers);
+                let cache_key = http_request.cache_key();
+                if let Some(ref http_cache) = context.state.http_cache {
+                    write_lock(http_cache, "HTTP cache").update_headers(&cache_key, &headers);
+                }
+                if let (Some(ref raw_status), ResponseBody::Done(ref body)) =
+                       (cached_response.raw_status.clone(), cached_response.body.lock().unwrap().clone()) {
+                    write_lock(&context.state.memory_cache, "memory cache").store(&cache_key, raw_status,
+                                                                                 &headers, body);
+                }
+                cached_response.headers = headers;
 
-            // Substep 4
-            // response = cached_response;
+                // Substep 4
+                response = cached_response;
+                // The cached body is complete, unlike that of the 304.
+                *done_chan = None;
 
-            // Substep 5
-            // TODO cache_state is immutable?
-            // response.cache_state = CacheState::Validated;
+                // Substep 5
+                response.cache_state = CacheState::Validated;
+            }
         }
     }
 
@@ -1008,9 +1846,62 @@ fn http_network_or_cache_fetch(request: Rc<Request>,
     response
 }
 
+/// Revalidate the stale cached response to `request` on the fetch workers, unless that is
+/// already underway, so that it is fresh again for the requests that come after it.
+fn revalidate_in_background(request: &Request, credentials_flag: bool, context: &FetchContext) {
+    let (http_cache, scheduler) = match (context.state.http_cache.clone(), context.scheduler.clone()) {
+        (Some(http_cache), Some(scheduler)) => (http_cache, scheduler),
+        _ => return,
+    };
+    let url = request.current_url();
+    let cache_key = request.cache_key();
+    if !write_lock(&http_cache, "HTTP cache").start_revalidation(&cache_key) {
+        return;
+    }
+    let origin = request.origin.borrow().clone();
+    let headers = request.headers.borrow().clone();
+    // Nothing is waiting on the revalidation, so it is neither reported nor cancelled.
+    let context = FetchContext {
+        state: context.state.clone(),
+        user_agent: context.user_agent.clone(),
+        accept_language: context.accept_language.clone(),
+        devtools_chan: None,
+        filemanager: context.filemanager.clone(),
+        cancellation_listener: Arc::new(Mutex::new(CancellationListener::new(None))),
+        body_flow_control: None,
+        time_profiler_chan: context.time_profiler_chan.clone(),
+        net_stats: context.net_stats.clone(),
+        scheme_handlers: context.scheme_handlers.clone(),
+        scheduler: None,
+        storage_thread: context.storage_thread.clone(),
+        // A revalidation in the background has no one to ask.
+        auth_prompt: None,
+        privacy_signals: context.privacy_signals,
+        allow_host_override: context.allow_host_override,
+        deadline: None,
+        max_response_size: context.max_response_size,
+    };
+    scheduler.schedule(RequestPriority::Idle, Box::new(move |cancelled: bool| {
+        if !cancelled {
+            let request = Request::new(url.clone(), Some(origin), false, None);
+            *request.headers.borrow_mut() = headers;
+            request.cache_mode.set(CacheMode::NoCache);
+            let mut done_chan = None;
+            http_network_or_cache_fetch(Rc::new(request), credentials_flag, false, &mut None, &mut done_chan,
+                                        &context);
+            // The response is only stored once its whole body has arrived.
+            if let Some((_, ref receiver)) = done_chan {
+                while let Ok(Data::Payload(_)) = receiver.recv() {}
+            }
+        }
+        write_lock(&http_cache, "HTTP cache").finish_revalidation(&cache_key);
+    }));
+}
+
 /// [HTTP network fetch](https://fetch.spec.whatwg.org/#http-network-fetch)
 fn http_network_fetch(request: Rc<Request>,
                       credentials_flag: bool,
+                      target: &mut Target,
                       done_chan: &mut DoneChannel,
                       context: &FetchContext)
                       -> Response {
@@ -1021,16 +1912,19 @@ fn http_network_fetch(request: Rc<Request>,
 
     // Step 2
     // TODO be able to create connection using current url's origin and credentials
-    let connection = create_http_connector();
+    let url = request.current_url();
+    let pool_host = url.host_str().unwrap_or("").to_owned();
+    let alternative = read_lock(&context.state.alt_svc_cache, "Alt-Svc cache").lookup(&url);
 
     // Step 3
     // TODO be able to tell if the connection is a failure
 
-    // Step 4
-    let factory = NetworkHttpRequestFactory {
-        connector: connection,
-    };
-    let url = request.current_url();
+    if context.cancellation_listener.lock().unwrap().cancelled() {
+        return Response::network_error(NetworkError::LoadCancelled);
+    }
+    if has_timed_out(context) {
+        return Response::network_error(NetworkError::Timeout);
+    }
 
     let request_id = context.devtools_chan.as_ref().map(|_| {
         uuid::Uuid::new_v4().simple().to_string()
@@ -1040,26 +1934,140 @@ fn http_network_fetch(request: Rc<Request>,
     // do not. Once we support other kinds of fetches we'll need to be more fine grained here
     // since things like image fetches are classified differently by devtools
     let is_xhr = request.destination == Destination::None;
-    let wrapped_response = obtain_response(&factory, &url, &request.method.borrow(),
-                                           &request.headers.borrow(),
-                                           &request.body.borrow(), &request.method.borrow(),
-                                           &request.pipeline_id.get(), request.redirect_count.get() + 1,
-                                           request_id.as_ref().map(Deref::deref), is_xhr);
+    let timing = context.time_profiler_chan.as_ref().map(|profiler_chan| NetworkTimingReporter {
+        profiler_chan: profiler_chan.clone(),
+        host: url.host_str().unwrap_or("").to_owned(),
+    });
+
+    // Step 4
+    set_request_url(url.as_url().map(|url| (**url).clone()));
+    let forward_proxy = match context.state.connection_pools.route(url.scheme(), url.host_str().unwrap_or("")) {
+        ProxyRoute::Forward(proxy) => Some(proxy),
+        _ => None,
+    };
+    let request_start = time::precise_time_ns();
+    // hyper connects, sends the request and reads the head of the response on this thread.
+    set_deadline(context.deadline);
+    // How much of the body the attempt that was answered wrote, whether it was sent from
+    // memory or streamed.
+    let body_sent = Cell::new(0);
+    let (wrapped_response, tls_info) = {
+        let mut report_progress = |sent: u64, total: Option<u64>| {
+            body_sent.set(sent);
+            if let Some(ref mut target) = *target {
+                target.process_request_body_progress(sent, total);
+            }
+        };
+        let mut obtain_response_with = |connection,
+                                        fresh_connection: &Fn() -> Arc<Pool<Connector>>,
+                                        http2_pools: Option<&ConnectionPools>| {
+            let factory = NetworkHttpRequestFactory {
+                connector: connection,
+                fresh_connector: fresh_connection,
+                forward_proxy: forward_proxy.clone(),
+                http2_pools: http2_pools,
+            };
+            obtain_response(&factory, &url, &request.method.borrow(),
+                            &request.headers.borrow(),
+                            &request.body.borrow(), &request.body_parts.borrow(),
+                            &context.filemanager, &request.method.borrow(),
+                            &request.pipeline_id.get(),
+                            request_id.as_ref().map(Deref::deref), is_xhr,
+                            timing.as_ref(), &context.cancellation_listener, context.deadline,
+                            &mut report_progress)
+        };
+        let from_alternative = alternative.and_then(|(alt_host, alt_port)| {
+            let pools = &context.state.connection_pools;
+            let (connection, tls_info) = pools.pool_for_alternative(&pool_host, &alt_host, alt_port);
+            let fresh_connection = || pools.fresh_pool_for_alternative(&pool_host, &alt_host, alt_port);
+            match obtain_response_with(connection, &fresh_connection, None) {
+                Err(NetworkError::LoadCancelled) => Some((Err(NetworkError::LoadCancelled), tls_info)),
+                Err(_) if has_timed_out(context) => Some((Err(NetworkError::Timeout), tls_info)),
+                Err(error) => {
+                    // Forget the alternative, and fall back to the origin itself.
+                    debug!("Alternative service {}:{} for {} failed: {:?}", alt_host, alt_port, url, error);
+                    write_lock(&context.state.alt_svc_cache, "Alt-Svc cache").remove(&url, &alt_host, alt_port);
+                    None
+                }
+                response => Some((response, tls_info)),
+            }
+        });
+        from_alternative.unwrap_or_else(|| {
+            let pools = &context.state.connection_pools;
+            let (connection, tls_info) = pools.pool_for(&pool_host);
+            let http2_enabled = PREFS.get("network.http.http2.enabled").as_boolean().unwrap_or(false);
+            let http2_pools = if http2_enabled { Some(pools) } else { None };
+            (obtain_response_with(connection, &|| pools.fresh_pool_for(&pool_host), http2_pools), tls_info)
+        })
+    };
+    set_deadline(None);
+    set_request_url(None);
+    let first_byte = time::precise_time_ns();
 
     let pipeline_id = request.pipeline_id.get();
     let (res, msg) = match wrapped_response {
         Ok(wrapped_response) => wrapped_response,
+        Err(_) if has_timed_out(context) => return Response::network_error(NetworkError::Timeout),
         Err(error) => return Response::network_error(error),
     };
+    // The whole body has been written once the server answers. Its end is reported by
+    // main_fetch, once no redirect will send it again.
+    let has_body = request.body.borrow().is_some() || request.body_parts.borrow().is_some();
+    if has_body {
+        if let Some(ref mut target) = *target {
+            target.process_request_body(&request);
+        }
+    }
+    let max_response_size = if request.unbounded_body { None } else { context.max_response_size };
+    let raw_body = request.raw_body;
+    // Fail before reading anything when the server announces a body that is too large.
+    if let (Some(max_size), Some(&ContentLength(len))) = (max_response_size, res.response.headers.get()) {
+        if len > max_size as u64 {
+            return Response::network_error(NetworkError::Internal("response too large".to_owned()));
+        }
+    }
+    let connection = NetStats::connection_opened(&context.net_stats);
+    context.net_stats.sent(body_sent.get() as usize);
+
+    // Alternatives advertised over a connection that isn't authenticated can't be trusted.
+    let alt_svc_header = if url.scheme() == "https" { res.response.headers.get_raw("Alt-Svc") } else { None };
+    if let Some(values) = alt_svc_header {
+        let value = values.iter().map(|value| String::from_utf8_lossy(value)).collect::<Vec<_>>().join(",");
+        if let Some(alt_svc) = parse_alt_svc(&value) {
+            write_lock(&context.state.alt_svc_cache, "Alt-Svc cache").update(&url, alt_svc);
+        }
+    }
 
     let mut response = Response::new(url.clone());
     response.status = Some(res.response.status);
     response.raw_status = Some((res.response.status_raw().0,
                                 res.response.status_raw().1.as_bytes().to_vec()));
     response.headers = res.response.headers.clone();
+    response.protocol = Some(alpn_id(res.response.version).to_owned());
+    if let Some(mime) = read_lock(&context.state.mime_overrides, "MIME overrides").lookup(&url) {
+        response.headers.set(ContentType(mime.clone()));
+    }
+    response.early_hints = take_early_hints();
+    // hyper may read a short chunked response whole along with its head.
+    let mut trailers_read_with_head = take_trailers();
+    response.timing.lock().unwrap().first_byte = Some(first_byte - request_start);
     response.referrer = request.referrer.borrow().to_url().cloned();
+    if let Some(range) = requested_range(&request) {
+        match partial_content_range(&response, range) {
+            Ok(Some((start, end, complete_length))) => {
+                response.range_start = Some(start);
+                response.range_end = end;
+                response.complete_length = complete_length;
+            }
+            Ok(None) => {}
+            Err(error) => return Response::network_error(error),
+        }
+    }
 
     let res_body = response.body.clone();
+    // A range of a resource too large to be limited, such as a media stream or a resumed
+    // download, is only passed on in chunks; keeping it too would hold all of it in memory.
+    let keep_body = max_response_size.is_some() || response.range_start.is_none();
 
     // We're about to spawn a thread to be waited on here
     let (done_sender, done_receiver) = channel();
@@ -1071,8 +2079,38 @@ fn http_network_fetch(request: Rc<Request>,
     let devtools_sender = context.devtools_chan.clone();
     let meta_status = meta.status.clone();
     let meta_headers = meta.headers.clone();
+    let cancellation_listener = context.cancellation_listener.clone();
+    let body_deadline = context.deadline.and_then(|deadline| if deadline.covers_body { Some(deadline) } else { None });
+    let body_flow_control = context.body_flow_control.clone();
+    let resource_timing = response.timing.clone();
+    // A body whose encoding isn't undone can't be split into its parts.
+    let mut multipart = if raw_body {
+        None
+    } else {
+        mixed_replace_boundary(&response.headers).map(|boundary| MultipartSplitter::new(&boundary))
+    };
+    let response_trailers = response.trailers.clone();
+    let net_stats = context.net_stats.clone();
+    // Only the last part of a multipart response would be stored.
+    let cacheable = request_is_cacheable(&request) && multipart.is_none();
+    let http_cache = match context.state.http_cache {
+        Some(ref http_cache) if cacheable => Some(http_cache.clone()),
+        _ => None,
+    };
+    let memory_cache = if cacheable {
+        Some(context.state.memory_cache.clone())
+    } else {
+        None
+    };
+    let cache_key = request.cache_key();
+    let cache_status = response.raw_status.clone().unwrap();
+    let cache_headers = response.headers.clone();
     spawn_named(format!("fetch worker thread"), move || {
-        match StreamedResponse::from_http_response(res) {
+        let _connection = connection;
+        set_deadline(body_deadline);
+        let download_start = time::precise_time_ns();
+        let mut body_size = 0;
+        match StreamedResponse::from_http_response(res, raw_body) {
             Ok(mut res) => {
                 *res_body.lock().unwrap() = ResponseBody::Receiving(vec![]);
 
@@ -1093,14 +2131,67 @@ fn http_network_fetch(request: Rc<Request>,
                 }
 
                 loop {
-                    match read_block(&mut res) {
+                    if cancellation_listener.lock().unwrap().cancelled() {
+                        // Dropping the response closes the connection.
+                        *res_body.lock().unwrap() = ResponseBody::Done(vec![]);
+                        let _ = done_sender.send(Data::Cancelled);
+                        return;
+                    }
+
+                    let block = match multipart {
+                        Some(ref mut splitter) => read_part_block(&mut res, splitter),
+                        None => read_block(&mut res),
+                    };
+                    if block.is_err() && body_deadline.map_or(false, |deadline| deadline.has_passed()) {
+                        // Dropping the response closes the connection.
+                        *res_body.lock().unwrap() = ResponseBody::Done(vec![]);
+                        let _ = done_sender.send(Data::TimedOut);
+                        return;
+                    }
+                    let body_complete = match block {
+                        Ok(Data::Done) => true,
+                        _ => false,
+                    };
+                    match block {
                         Ok(Data::Payload(chunk)) => {
+                            let chunk_len = chunk.len();
+                            net_stats.received(chunk_len);
+                            body_size += chunk_len;
+                            if max_response_size.map_or(false, |max_size| body_size > max_size) {
+                                // Dropping the response closes the connection.
+                                *res_body.lock().unwrap() = ResponseBody::Done(vec![]);
+                                let _ = done_sender.send(Data::TooLarge);
+                                return;
+                            }
                             if let ResponseBody::Receiving(ref mut body) = *res_body.lock().unwrap() {
-                                body.extend_from_slice(&chunk);
-                                let _ = done_sender.send(Data::Payload(chunk));
+                                if !chunk.is_empty() {
+                                    if keep_body {
+                                        body.extend_from_slice(&chunk);
+                                    }
+                                    let _ = done_sender.send(Data::Payload(chunk));
+                                }
+                            }
+                            if let Some(ref body_flow_control) = body_flow_control {
+                                let mut body_flow_control = body_flow_control.lock().unwrap();
+                                body_flow_control.sent(chunk_len);
+                                body_flow_control.wait_for_window(|| {
+                                    cancellation_listener.lock().unwrap().cancelled()
+                                });
                             }
                         },
+                        Ok(Data::Part(headers)) => {
+                            // Each part replaces the one before it, so only the body of the
+                            // current part is kept, and limited in size.
+                            body_size = 0;
+                            *res_body.lock().unwrap() = ResponseBody::Receiving(vec![]);
+                            let _ = done_sender.send(Data::Part(headers));
+                        }
                         Ok(Data::Done) | Err(_) => {
+                            let response_end = time::precise_time_ns();
+                            resource_timing.lock().unwrap().response_end = Some(response_end - request_start);
+                            if let Some(ref timing) = timing {
+                                timing.report(ProfilerCategory::NetBodyDownload, download_start, response_end);
+                            }
                             let mut empty_vec = Vec::new();
                             let completed_body = match *res_body.lock().unwrap() {
                                 ResponseBody::Receiving(ref mut body) => {
@@ -1110,10 +2201,23 @@ fn http_network_fetch(request: Rc<Request>,
                                 },
                                 _ => empty_vec,
                             };
+                            if body_complete {
+                                *response_trailers.lock().unwrap() =
+                                    take_trailers().or(trailers_read_with_head.take());
+                                if let Some(ref memory_cache) = memory_cache {
+                                    write_lock(memory_cache, "memory cache").store(&cache_key, &cache_status,
+                                                                                   &cache_headers, &completed_body);
+                                }
+                                if let Some(ref cache) = http_cache {
+                                    http_cache::store(cache, &cache_key, &cache_status, &cache_headers,
+                                                      &completed_body);
+                                }
+                            }
                             *res_body.lock().unwrap() = ResponseBody::Done(completed_body);
                             let _ = done_sender.send(Data::Done);
                             break;
                         }
+                        Ok(Data::Cancelled) | Ok(Data::TimedOut) | Ok(Data::TooLarge) => unreachable!(),
                     }
                 }
             }
@@ -1133,6 +2237,12 @@ fn http_network_fetch(request: Rc<Request>,
     // TODO Determine if response was retrieved over HTTPS
     // TODO Servo needs to decide what ciphers are to be treated as "deprecated"
     response.https_state = HttpsState::None;
+    if url.scheme() == "https" {
+        let connection_url = replace_hosts(&url);
+        if let Some(host) = connection_url.host_str() {
+            response.tls_info = tls_info.lock().unwrap().get(host).cloned();
+        }
+    }
 
     // TODO Read request
 
@@ -1159,12 +2269,29 @@ fn http_network_fetch(request: Rc<Request>,
         // TODO update response in the HTTP cache for request
     }
 
+    // Not part of the spec: a successful request with an unsafe method may have changed
+    // the resource, so a response cached for it can't be used any more.
+    let safe_method = matches!(*request.method.borrow(),
+                               Method::Get | Method::Head | Method::Options | Method::Trace);
+    if !safe_method && response.status.map_or(false, |status| status.is_success()) {
+        write_lock(&context.state.memory_cache, "memory cache").invalidate(&url);
+        if let Some(ref http_cache) = context.state.http_cache {
+            write_lock(http_cache, "HTTP cache").invalidate(&url);
+        }
+    }
+
+    // Not part of the spec either: https://w3c.github.io/webappsec-clear-site-data/
+    // The old cookies are cleared before the response's own ones are set.
+    clear_site_data(&url, &response.headers, context);
+
     // TODO this step isn't possible yet
     // Step 13
 
     // Step 14.
     if credentials_flag {
-        set_cookies_from_headers(&url, &response.headers, &context.state.cookie_jar);
+        let third_party = same_site_context(&request) == SameSiteContext::CrossSite;
+        set_cookies_from_headers(&url, &response.headers, &context.state.cookie_jar,
+                                 &context.state.cookie_policy, third_party, &context.net_stats);
     }
 
     // TODO these steps
@@ -1183,7 +2310,6 @@ fn http_network_fetch(request: Rc<Request>,
 
 /// [CORS preflight fetch](https://fetch.spec.whatwg.org#cors-preflight-fetch)
 fn cors_preflight_fetch(request: Rc<Request>,
-                        cache: &mut CorsCache,
                         context: &FetchContext)
                         -> Response {
     // Step 1
@@ -1213,7 +2339,7 @@ fn cors_preflight_fetch(request: Rc<Request>,
 
     // Step 6
     let preflight = Rc::new(preflight);
-    let response = http_network_or_cache_fetch(preflight.clone(), false, false, &mut None, context);
+    let response = http_network_or_cache_fetch(preflight.clone(), false, false, &mut None, &mut None, context);
 
     // Step 7
     if cors_check(request.clone(), &response).is_ok() &&
@@ -1265,8 +2391,10 @@ fn cors_preflight_fetch(request: Rc<Request>,
         // Substep 7, 8
         let max_age = response.headers.get::<AccessControlMaxAge>().map(|acma| acma.0).unwrap_or(0);
 
-        // TODO: Substep 9 - Need to define what an imposed limit on max-age is
+        // Substep 9
+        let max_age = min(max_age, MAX_AGE_LIMIT);
 
+        let mut cache = write_lock(&context.state.cors_cache, "CORS preflight cache");
         // Substep 11, 12
         for method in &methods {
             cache.match_method_and_update(&*request, method.clone(), max_age);
@@ -1338,7 +2466,49 @@ fn is_no_store_cache(headers: &Headers) -> bool {
     headers.has::<IfRange>()
 }
 
-fn response_needs_revalidation(_response: &Response) -> bool {
-    // TODO this function
-    false
+/// The first and last bytes that `request` asks for, from its `range_start` or from a
+/// `Range` header of a single byte range set by the caller. Either is `None` when the
+/// range leaves it open, as suffix ranges such as `bytes=-500` do for the first one.
+fn requested_range(request: &Request) -> Option<(Option<u64>, Option<u64>)> {
+    if let Some(range_start) = request.range_start {
+        return Some((Some(range_start), request.range_end));
+    }
+    match request.headers.borrow().get::<Range>() {
+        Some(&Range::Bytes(ref ranges)) if ranges.len() == 1 => match ranges[0] {
+            ByteRangeSpec::FromTo(start, end) => Some((Some(start), Some(end))),
+            ByteRangeSpec::AllFrom(start) => Some((Some(start), None)),
+            ByteRangeSpec::Last(_) => Some((None, None)),
+        },
+        _ => None,
+    }
 }
+
+/// Work out which part of the whole resource the body of a response to a request for
+/// `range` holds: the offsets of its first and last bytes, and the length of the whole
+/// resource, when known. A `206` must start at the requested offset and end within the
+/// range; a `200` means the server ignored the range, so the consumer has to start
+/// over from the beginning.
+fn partial_content_range(response: &Response, range: (Option<u64>, Option<u64>))
+                         -> Result<Option<(u64, Option<u64>, Option<u64>)>, NetworkError> {
+    let (range_start, range_end) = range;
+    match response.status {
+        Some(StatusCode::PartialContent) => match response.headers.get::<ContentRange>() {
+            Some(&ContentRange(ContentRangeSpec::Bytes { range: Some((start, end)), instance_length }))
+                if range_start.map_or(true, |range_start| start == range_start) && start <= end &&
+                   range_end.map_or(true, |range_end| end <= range_end) => {
+                Ok(Some((start, Some(end), instance_length)))
+            }
+            _ => Err(NetworkError::Internal("Invalid Content-Range".into())),
+        },
+        Some(StatusCode::Ok) => {
+            // A decoded body isn't as long as the `Content-Length` says.
+            let length = match response.headers.get::<ContentLength>() {
+                Some(&ContentLength(length)) if !response.headers.has::<ContentEncoding>() => Some(length),
+                _ => None,
+            };
+            Ok(Some((0, length.and_then(|length| length.checked_sub(1)), length)))
+        }
+        _ => Ok(None),
+    }
+}
+
@@ -5,7 +5,8 @@
 use immeta::load_from_buf;
 use ipc_channel::ipc::{self, IpcReceiver, IpcSender};
 use ipc_channel::router::ROUTER;
-use net_traits::{CoreResourceThread, NetworkError, fetch_async, FetchResponseMsg, FetchMetadata, Metadata};
+use net_traits::{CoreResourceMsg, CoreResourceThread, NetworkError, fetch_async, FetchResponseMsg, FetchMetadata};
+use net_traits::{Metadata, ResourceId};
 use net_traits::image::base::{Image, ImageMetadata, PixelFormat, load_from_memory};
 use net_traits::image_cache_thread::{ImageCacheChan, ImageCacheCommand, ImageCacheThread, ImageState};
 use net_traits::image_cache_thread::{ImageCacheResult, ImageOrMetadataAvailable, ImageResponse, UsePlaceholder};
@@ -184,6 +185,14 @@ struct ImageListener {
 #[derive(Eq, Hash, PartialEq, Clone, Copy)]
 struct LoadKey(u64);
 
+impl LoadKey {
+    /// The id that the fetch for this load is started with, so that it can be cancelled.
+    /// The image cache is the only one that gives fetches ids, so they are its own.
+    fn resource_id(&self) -> ResourceId {
+        ResourceId(self.0 as u32)
+    }
+}
+
 struct LoadKeyGenerator {
     counter: u64
 }
@@ -230,6 +239,8 @@ impl ImageListener {
 #[derive(Deserialize, Serialize)]
 enum ResponseAction {
     HeadersAvailable(Result<Metadata, NetworkError>),
+    /// The start of the next part of a `multipart/x-mixed-replace` response.
+    PartAvailable,
     DataAvailable(Vec<u8>),
     ResponseComplete(Result<(), NetworkError>)
 }
@@ -418,8 +429,31 @@ impl ImageCache {
     fn handle_progress(&mut self, msg: ResourceLoadInfo) {
         match (msg.action, msg.key) {
             (ResponseAction::HeadersAvailable(_), _) => {}
+            (ResponseAction::PartAvailable, key) => {
+                // The cache holds a single image for each URL, so the first part of a
+                // multipart response is decoded as soon as it is complete, and the fetch
+                // is cancelled rather than left to stream parts that would be ignored.
+                let complete_part = match self.pending_loads.get_by_key_mut(&key) {
+                    Some(pending_load) => pending_load.result.is_none() && !pending_load.bytes.is_empty(),
+                    None => false,
+                };
+                if complete_part {
+                    let _ = self.core_resource_thread.send(CoreResourceMsg::Cancel(key.resource_id()));
+                    self.handle_progress(ResourceLoadInfo {
+                        action: ResponseAction::ResponseComplete(Ok(())),
+                        key: key,
+                    });
+                }
+            }
             (ResponseAction::DataAvailable(data), _) => {
-                let pending_load = self.pending_loads.get_by_key_mut(&msg.key).unwrap();
+                let pending_load = match self.pending_loads.get_by_key_mut(&msg.key) {
+                    Some(pending_load) => pending_load,
+                    // The load already ended with an earlier part of a multipart response.
+                    None => return,
+                };
+                if pending_load.result.is_some() {
+                    return;
+                }
                 pending_load.bytes.extend_from_slice(&data);
                 //jmr0 TODO: possibly move to another task?
                 if let None = pending_load.metadata {
@@ -435,6 +469,11 @@ impl ImageCache {
                 }
             }
             (ResponseAction::ResponseComplete(result), key) => {
+                match self.pending_loads.get_by_key_mut(&key) {
+                    // The load already ended with an earlier part of a multipart response.
+                    Some(pending_load) if pending_load.result.is_none() => {}
+                    _ => return,
+                }
                 match result {
                     Ok(()) => {
                         let pending_load = self.pending_loads.get_by_key_mut(&msg.key).unwrap();
@@ -542,6 +581,7 @@ impl ImageCache {
                             type_: RequestType::Image,
                             destination: Destination::Image,
                             origin: url.clone(),
+                            resource_id: Some(load_key.resource_id()),
                             .. RequestInit::default()
                         };
 
@@ -549,7 +589,10 @@ impl ImageCache {
                         fetch_async(request, &self.core_resource_thread, move |action| {
                             let action = match action {
                                 FetchResponseMsg::ProcessRequestBody |
-                                FetchResponseMsg::ProcessRequestEOF => return,
+                                FetchResponseMsg::ProcessRequestBodyProgress(..) |
+                                FetchResponseMsg::ProcessRequestEOF |
+                                FetchResponseMsg::ProcessEarlyHints(_) |
+                                FetchResponseMsg::ProcessResponseTrailers(_) => return,
                                 FetchResponseMsg::ProcessResponse(meta_result) => {
                                     ResponseAction::HeadersAvailable(meta_result.map(|m| {
                                         match m {
@@ -558,10 +601,11 @@ impl ImageCache {
                                         }
                                     }))
                                 }
+                                FetchResponseMsg::ProcessResponsePart(_) => ResponseAction::PartAvailable,
                                 FetchResponseMsg::ProcessResponseChunk(new_bytes) => {
                                     ResponseAction::DataAvailable(new_bytes)
                                 }
-                                FetchResponseMsg::ProcessResponseEOF(response) => {
+                                FetchResponseMsg::ProcessResponseEOF(response, _) => {
                                     ResponseAction::ResponseComplete(response)
                                 }
                             };
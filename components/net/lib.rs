@@ -30,9 +30,12 @@ extern crate mime_guess;
 extern crate msg;
 extern crate net_traits;
 extern crate openssl;
+extern crate openssl_sys;
 extern crate openssl_verify;
-extern crate profile_traits;
+#[macro_use] extern crate profile_traits;
+extern crate regex;
 extern crate rustc_serialize;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate servo_url;
@@ -47,21 +50,36 @@ extern crate uuid;
 extern crate webrender_traits;
 extern crate websocket;
 
+mod alt_svc;
 mod blob_loader;
+mod certificate_exceptions;
 mod chrome_loader;
+mod connection_limiter;
 mod connector;
 mod content_blocker;
 pub mod cookie;
 pub mod cookie_storage;
 mod data_loader;
+mod digest_auth;
 pub mod filemanager_thread;
+mod happy_eyeballs;
+mod hpack;
 pub mod hsts;
+mod http2;
+pub mod http_cache;
 mod http_loader;
+mod lock_recovery;
 pub mod image_cache_thread;
 pub mod mime_classifier;
+mod multipart;
+mod pac;
+mod pipeline_origins;
 pub mod resource_thread;
 mod storage_thread;
+mod tls_session_cache;
+pub mod url_rewrite;
 mod websocket_loader;
+mod x509;
 
 /// An implementation of the [Fetch specification](https://fetch.spec.whatwg.org/)
 pub mod fetch {
@@ -71,6 +89,23 @@ pub mod fetch {
 
 /// A module for re-exports of items used in unit tests.
 pub mod test {
+    pub use alt_svc::{AltSvc, AltSvcCache, Alternative, parse_alt_svc};
+    pub use certificate_exceptions::{CertificateExceptionCheck, CertificateExceptions};
     pub use chrome_loader::resolve_chrome_url;
-    pub use http_loader::HttpState;
+    pub use connection_limiter::{ConnectionLimiter, FetchJob, FetchScheduler, PendingFetches};
+    pub use connector::{ConnectionPools, HttpProxy, NoProxyRule, ProxyRoute, ProxySettings, SocksProxy};
+    pub use connector::{TlsPolicy, TlsVersion};
+    pub use content_blocker::BlockedContentRules;
+    pub use digest_auth::{DigestAuthCache, DigestChallenge, digest_authorization, parse_digest_challenge};
+    pub use happy_eyeballs::{AddressFamily, HappyEyeballs, Resolver, race, sort_addresses};
+    pub use hpack::{Decoder as HpackDecoder, Encoder as HpackEncoder};
+    pub use http2::{Frame, Http2Session};
+    pub use http2::{ACK, DATA, END_HEADERS, END_STREAM, GOAWAY, HEADERS, PREFACE, SETTINGS};
+    pub use http2::{SETTINGS_MAX_CONCURRENT_STREAMS, WINDOW_UPDATE};
+    pub use http_loader::{HttpState, PrivacySignals, accept_language_from_pref_values, accept_language_header};
+    pub use http_loader::determine_request_referrer;
+    pub use multipart::{MultipartEvent, MultipartSplitter, mixed_replace_boundary};
+    pub use pac::{PacError, PacScript, ProxyAutoConfig, route_for_pac_result};
+    pub use tls_session_cache::{SessionStore, TlsSessionCache};
+    pub use x509::certificate_details;
 }
@@ -0,0 +1,29 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Access to the state shared between the resource manager and fetch threads that
+//! survives one of those threads panicking while holding a lock.
+//!
+//! A poisoned lock only tells us that a panic happened while it was held for writing.
+//! The cookie jar, auth cache and HSTS list are only ever updated by adding, removing
+//! or replacing whole entries, so they stay usable after such a panic, and carrying on
+//! is much better than taking down every later cookie or auth lookup with it.
+
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Lock `lock` for reading, ignoring any poisoning. `name` is used for logging.
+pub fn read_lock<'a, T>(lock: &'a RwLock<T>, name: &str) -> RwLockReadGuard<'a, T> {
+    lock.read().unwrap_or_else(|poisoned| {
+        warn!("Recovering {} from a panic in a thread that held its lock", name);
+        poisoned.into_inner()
+    })
+}
+
+/// Lock `lock` for writing, ignoring any poisoning. `name` is used for logging.
+pub fn write_lock<'a, T>(lock: &'a RwLock<T>, name: &str) -> RwLockWriteGuard<'a, T> {
+    lock.write().unwrap_or_else(|poisoned| {
+        warn!("Recovering {} from a panic in a thread that held its lock", name);
+        poisoned.into_inner()
+    })
+}
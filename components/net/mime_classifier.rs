@@ -2,10 +2,41 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use hyper::mime::TopLevel;
-use net_traits::LoadContext;
+use hyper::mime::{Mime, TopLevel};
+use net_traits::{LoadContext, UrlPattern};
+use servo_url::ServoUrl;
 use std::borrow::ToOwned;
 
+/// Content types forced on the responses for some URLs, for servers that are known to
+/// mislabel them.
+#[derive(Default)]
+pub struct MimeOverrides {
+    overrides: Vec<(UrlPattern, Mime)>,
+}
+
+impl MimeOverrides {
+    /// Overrides with the content type of the first matching pattern. Content types that
+    /// can't be parsed are left out.
+    pub fn new(overrides: Vec<(UrlPattern, String)>) -> MimeOverrides {
+        MimeOverrides {
+            overrides: overrides.into_iter().filter_map(|(pattern, content_type)| {
+                match content_type.parse() {
+                    Ok(mime) => Some((pattern, mime)),
+                    Err(_) => {
+                        warn!("Ignoring the invalid content type {:?} for {}.", content_type, pattern.0);
+                        None
+                    }
+                }
+            }).collect(),
+        }
+    }
+
+    /// The content type forced on the response for `url`, if there is one.
+    pub fn lookup(&self, url: &ServoUrl) -> Option<&Mime> {
+        self.overrides.iter().find(|&&(ref pattern, _)| pattern.matches(url)).map(|&(_, ref mime)| mime)
+    }
+}
+
 pub struct MimeClassifier {
     image_classifier: GroupedClassifier,
     audio_video_classifier: GroupedClassifier,
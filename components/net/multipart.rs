@@ -0,0 +1,183 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The splitting of `multipart/x-mixed-replace` bodies, in which each part replaces the
+//! one before it, into their parts. Camera streams and some long-polling endpoints send
+//! them, and keep the connection open for as long as there are new parts.
+
+use hyper::header::{ContentType, Headers};
+use hyper::mime::{Attr, Mime, SubLevel, TopLevel};
+use std::ascii::AsciiExt;
+use std::collections::VecDeque;
+
+/// The longest head of a part that is read before giving up on the body.
+const MAX_PART_HEAD_LEN: usize = 64 * 1024;
+
+/// The boundary of the parts of a response with `headers`, if it is a
+/// `multipart/x-mixed-replace` response that has one.
+pub fn mixed_replace_boundary(headers: &Headers) -> Option<String> {
+    let params = match headers.get() {
+        Some(&ContentType(Mime(TopLevel::Multipart, SubLevel::Ext(ref sub_level), ref params)))
+            if sub_level.eq_ignore_ascii_case("x-mixed-replace") => params,
+        _ => return None,
+    };
+    params.iter().find(|&&(ref attr, _)| *attr == Attr::Boundary).and_then(|&(_, ref value)| {
+        let boundary = value.to_string().trim_matches('"').to_owned();
+        if boundary.is_empty() { None } else { Some(boundary) }
+    })
+}
+
+/// What a `MultipartSplitter` finds in a body.
+#[derive(Debug)]
+pub enum MultipartEvent {
+    /// The start of a part, with its header fields.
+    Part(Headers),
+    /// More of the body of the current part.
+    Data(Vec<u8>),
+}
+
+/// Where a `MultipartSplitter` is in the body.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Before the first boundary.
+    Preamble,
+    /// Past a boundary, before the end of its line.
+    BoundaryLine,
+    /// The header fields of a part.
+    Head,
+    /// The body of a part.
+    Body,
+    /// After the closing boundary, where nothing is of interest.
+    Epilogue,
+}
+
+/// Splits a multipart body given a piece at a time into its parts.
+pub struct MultipartSplitter {
+    /// `--` and the boundary, which the line that starts each part begins with.
+    dash_boundary: Vec<u8>,
+    state: State,
+    /// What has been given but not yet split.
+    buffer: Vec<u8>,
+    events: VecDeque<MultipartEvent>,
+}
+
+/// Where `needle` first starts in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// The fields of the head of a part, without the empty line that ends it.
+fn parse_part_head(head: &[u8]) -> Headers {
+    let mut headers = Headers::new();
+    for line in String::from_utf8_lossy(head).split("\r\n") {
+        let colon = match line.find(':') {
+            Some(colon) if colon > 0 => colon,
+            _ => continue,
+        };
+        let name = line[..colon].trim();
+        let mut values = headers.get_raw(name).map_or(vec![], |values| values.to_vec());
+        values.push(line[colon + 1..].trim().as_bytes().to_vec());
+        headers.set_raw(name.to_owned(), values);
+    }
+    headers
+}
+
+impl MultipartSplitter {
+    pub fn new(boundary: &str) -> MultipartSplitter {
+        MultipartSplitter {
+            dash_boundary: format!("--{}", boundary).into_bytes(),
+            state: State::Preamble,
+            buffer: vec![],
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Split the next piece of the body. Fails if the head of a part is too long.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        self.buffer.extend_from_slice(bytes);
+        loop {
+            let consumed = match self.state {
+                State::Preamble => match find(&self.buffer, &self.dash_boundary) {
+                    Some(position) => {
+                        self.state = State::BoundaryLine;
+                        position + self.dash_boundary.len()
+                    }
+                    // Keep what could be the start of the boundary.
+                    None => self.buffer.len().saturating_sub(self.dash_boundary.len() - 1),
+                },
+                State::BoundaryLine => {
+                    if self.buffer.starts_with(b"--") {
+                        self.state = State::Epilogue;
+                        self.buffer.len()
+                    } else {
+                        match find(&self.buffer, b"\r\n") {
+                            Some(position) => {
+                                self.state = State::Head;
+                                position + 2
+                            }
+                            None if self.buffer.len() >= MAX_PART_HEAD_LEN => return Err(()),
+                            None => 0,
+                        }
+                    }
+                }
+                State::Head => {
+                    let len = if self.buffer.starts_with(b"\r\n") {
+                        Some(2)
+                    } else {
+                        find(&self.buffer, b"\r\n\r\n").map(|position| position + 4)
+                    };
+                    match len {
+                        Some(len) => {
+                            self.events.push_back(MultipartEvent::Part(parse_part_head(&self.buffer[..len])));
+                            self.state = State::Body;
+                            len
+                        }
+                        None if self.buffer.len() >= MAX_PART_HEAD_LEN => return Err(()),
+                        None => 0,
+                    }
+                }
+                State::Body => {
+                    let mut delimiter = b"\r\n".to_vec();
+                    delimiter.extend_from_slice(&self.dash_boundary);
+                    let (data_len, consumed) = match find(&self.buffer, &delimiter) {
+                        Some(position) => {
+                            self.state = State::BoundaryLine;
+                            (position, position + delimiter.len())
+                        }
+                        None => {
+                            // Keep what could be the start of the delimiter.
+                            let len = self.buffer.len().saturating_sub(delimiter.len() - 1);
+                            (len, len)
+                        }
+                    };
+                    if data_len > 0 {
+                        self.events.push_back(MultipartEvent::Data(self.buffer[..data_len].to_vec()));
+                    }
+                    consumed
+                }
+                State::Epilogue => self.buffer.len(),
+            };
+            self.buffer.drain(..consumed);
+            if consumed == 0 || self.buffer.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// End the body, handing out what was kept of the current part in case it was the
+    /// start of a boundary.
+    pub fn finish(&mut self) {
+        if self.state == State::Body && !self.buffer.is_empty() {
+            let data = self.buffer.split_off(0);
+            self.events.push_back(MultipartEvent::Data(data));
+        }
+        self.state = State::Epilogue;
+        self.buffer.clear();
+    }
+
+    /// The next of what has been found in the body given so far.
+    pub fn next_event(&mut self) -> Option<MultipartEvent> {
+        self.events.pop_front()
+    }
+}
@@ -0,0 +1,1171 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Proxy auto-config, where the proxy for a connection is picked by the
+//! `FindProxyForURL(url, host)` function of a PAC script.
+//!
+//! The script engine can't be used from here, so PAC scripts are run by a small
+//! interpreter for the part of JavaScript they keep to: functions, `var`, `if` and
+//! `return`, the usual operators, a few string methods, and the standard PAC functions.
+//! A script that uses anything else fails, and its connections are made directly.
+//!
+//! The script is given the whole URL of plain `http` requests, but only the scheme and
+//! host of `https` ones, whose paths are none of the proxy's business.
+
+use connector::{HttpProxy, ProxyRoute, ProxySettings, SocksProxy, create_http_connector_with_tls_info};
+use connector::set_deadline;
+use fetch::methods::Deadline;
+use happy_eyeballs::{HappyEyeballs, Resolver, SystemResolver};
+use hyper::client::Request as HyperRequest;
+use hyper::method::Method;
+use std::ascii::AsciiExt;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use time::{self, Timespec, Tm};
+use url::Url;
+use util::thread::spawn_named;
+
+/// How long, in seconds, the proxy picked for a host and scheme is used before the
+/// script is asked again, and a script that couldn't be loaded is given before it is
+/// tried again.
+const PAC_TTL: i64 = 5 * 60;
+
+/// How long, in milliseconds, loading the script may take before it is given up on.
+const PAC_LOAD_TIMEOUT_MS: u64 = 10 * 1000;
+
+/// How deeply the functions of a script may call each other.
+const MAX_CALL_DEPTH: usize = 64;
+
+/// How many routes are remembered before the expired ones are forgotten, or all of them
+/// if none have expired. Plain `http` URLs are remembered whole, so there can be many.
+const MAX_REMEMBERED_ROUTES: usize = 1024;
+
+const WEEKDAYS: [&'static str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+const MONTHS: [&'static str; 12] = ["JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC"];
+
+#[derive(Debug, PartialEq)]
+pub enum PacError {
+    Load(String),
+    Syntax(String),
+    Runtime(String),
+}
+
+impl fmt::Display for PacError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PacError::Load(ref error) => write!(f, "couldn't load the PAC script: {}", error),
+            PacError::Syntax(ref error) => write!(f, "syntax error in the PAC script: {}", error),
+            PacError::Runtime(ref error) => write!(f, "error running the PAC script: {}", error),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Identifier(String),
+    Str(String),
+    Number(f64),
+    Punctuator(&'static str),
+}
+
+/// The punctuators that are understood, longest first so that they are matched greedily.
+const PUNCTUATORS: &'static [&'static str] = &[
+    "===", "!==", "==", "!=", "<=", ">=", "&&", "||", "+=",
+    "(", ")", "{", "}", ",", ";", ".", "!", "<", ">", "+", "-", "*", "/", "%", "=", "?", ":",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, PacError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            if i == chars.len() {
+                return Err(PacError::Syntax("unterminated comment".to_owned()));
+            }
+            i += 2;
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                i += 1;
+            }
+            tokens.push(Token::Identifier(chars[start..i].iter().cloned().collect()));
+        } else if c.is_digit(10) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_digit(10) || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().cloned().collect();
+            match number.parse() {
+                Ok(number) => tokens.push(Token::Number(number)),
+                Err(_) => return Err(PacError::Syntax(format!("invalid number {}", number))),
+            }
+        } else if c == '"' || c == '\'' {
+            let mut string = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None | Some(&'\n') => return Err(PacError::Syntax("unterminated string".to_owned())),
+                    Some(&quote) if quote == c => break,
+                    Some(&'\\') => {
+                        i += 1;
+                        match chars.get(i) {
+                            Some(&'n') => string.push('\n'),
+                            Some(&'t') => string.push('\t'),
+                            Some(&escaped) => string.push(escaped),
+                            None => return Err(PacError::Syntax("unterminated string".to_owned())),
+                        }
+                    }
+                    Some(&other) => string.push(other),
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push(Token::Str(string));
+        } else {
+            let rest: String = chars[i..min(i + 3, chars.len())].iter().cloned().collect();
+            match PUNCTUATORS.iter().find(|punctuator| rest.starts_with(**punctuator)) {
+                Some(punctuator) => {
+                    tokens.push(Token::Punctuator(*punctuator));
+                    i += punctuator.len();
+                }
+                None => return Err(PacError::Syntax(format!("unexpected character {:?}", c))),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinaryOperator {
+    Equal,
+    NotEqual,
+    StrictEqual,
+    StrictNotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+#[derive(Debug)]
+enum Expression {
+    Literal(Value),
+    Variable(String),
+    Member(Box<Expression>, String),
+    Call(Box<Expression>, Vec<Expression>),
+    Not(Box<Expression>),
+    Negate(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Debug)]
+enum Statement {
+    Declare(Vec<(String, Option<Expression>)>),
+    Assign(String, Expression),
+    If(Expression, Box<Statement>, Option<Box<Statement>>),
+    Return(Option<Expression>),
+    Block(Vec<Statement>),
+    Expression(Expression),
+    Empty,
+}
+
+#[derive(Debug)]
+struct Function {
+    parameters: Vec<String>,
+    body: Vec<Statement>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, PacError> {
+        let token = try!(self.peek().cloned().ok_or(PacError::Syntax("unexpected end of script".to_owned())));
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn is_punctuator(&self, punctuator: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Punctuator(found)) => found == punctuator,
+            _ => false,
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Identifier(ref found)) => found == keyword,
+            _ => false,
+        }
+    }
+
+    fn eat(&mut self, punctuator: &str) -> bool {
+        let found = self.is_punctuator(punctuator);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, punctuator: &str) -> Result<(), PacError> {
+        if self.eat(punctuator) {
+            Ok(())
+        } else {
+            Err(PacError::Syntax(format!("expected {:?}, found {:?}", punctuator, self.peek())))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, PacError> {
+        match try!(self.next()) {
+            Token::Identifier(name) => Ok(name),
+            token => Err(PacError::Syntax(format!("expected a name, found {:?}", token))),
+        }
+    }
+
+    fn program(&mut self) -> Result<(HashMap<String, Function>, Vec<Statement>), PacError> {
+        let (mut functions, mut statements) = (HashMap::new(), vec![]);
+        while self.peek().is_some() {
+            if self.is_keyword("function") {
+                self.position += 1;
+                let name = try!(self.identifier());
+                let function = try!(self.function());
+                functions.insert(name, function);
+            } else {
+                statements.push(try!(self.statement()));
+            }
+        }
+        Ok((functions, statements))
+    }
+
+    fn function(&mut self) -> Result<Function, PacError> {
+        try!(self.expect("("));
+        let mut parameters = vec![];
+        while !self.eat(")") {
+            if !parameters.is_empty() {
+                try!(self.expect(","));
+            }
+            parameters.push(try!(self.identifier()));
+        }
+        Ok(Function {
+            parameters: parameters,
+            body: try!(self.block()),
+        })
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, PacError> {
+        try!(self.expect("{"));
+        let mut statements = vec![];
+        while !self.eat("}") {
+            statements.push(try!(self.statement()));
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Statement, PacError> {
+        if self.is_punctuator("{") {
+            return self.block().map(Statement::Block);
+        }
+        if self.eat(";") {
+            return Ok(Statement::Empty);
+        }
+        let statement = if self.is_keyword("var") || self.is_keyword("let") || self.is_keyword("const") {
+            self.position += 1;
+            let mut declarations = vec![];
+            loop {
+                let name = try!(self.identifier());
+                let value = if self.eat("=") { Some(try!(self.expression())) } else { None };
+                declarations.push((name, value));
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            Statement::Declare(declarations)
+        } else if self.is_keyword("if") {
+            self.position += 1;
+            try!(self.expect("("));
+            let condition = try!(self.expression());
+            try!(self.expect(")"));
+            let then = Box::new(try!(self.statement()));
+            let otherwise = if self.is_keyword("else") {
+                self.position += 1;
+                Some(Box::new(try!(self.statement())))
+            } else {
+                None
+            };
+            return Ok(Statement::If(condition, then, otherwise));
+        } else if self.is_keyword("return") {
+            self.position += 1;
+            if self.is_punctuator(";") || self.is_punctuator("}") {
+                Statement::Return(None)
+            } else {
+                Statement::Return(Some(try!(self.expression())))
+            }
+        } else {
+            let expression = try!(self.expression());
+            match expression {
+                Expression::Variable(name) => {
+                    if self.eat("=") {
+                        Statement::Assign(name, try!(self.expression()))
+                    } else if self.eat("+=") {
+                        let value = try!(self.expression());
+                        let sum = Expression::Binary(BinaryOperator::Add,
+                                                     Box::new(Expression::Variable(name.clone())),
+                                                     Box::new(value));
+                        Statement::Assign(name, sum)
+                    } else {
+                        Statement::Expression(Expression::Variable(name))
+                    }
+                }
+                expression => Statement::Expression(expression),
+            }
+        };
+        // Semicolons may be left out, as they often are.
+        self.eat(";");
+        Ok(statement)
+    }
+
+    fn expression(&mut self) -> Result<Expression, PacError> {
+        let condition = try!(self.or());
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let then = try!(self.expression());
+        try!(self.expect(":"));
+        let otherwise = try!(self.expression());
+        Ok(Expression::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)))
+    }
+
+    fn or(&mut self) -> Result<Expression, PacError> {
+        let mut left = try!(self.and());
+        while self.eat("||") {
+            left = Expression::Or(Box::new(left), Box::new(try!(self.and())));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, PacError> {
+        let mut left = try!(self.binary(0));
+        while self.eat("&&") {
+            left = Expression::And(Box::new(left), Box::new(try!(self.binary(0))));
+        }
+        Ok(left)
+    }
+
+    /// The binary operators from the loosest to the tightest binding.
+    fn binary(&mut self, level: usize) -> Result<Expression, PacError> {
+        const LEVELS: &'static [&'static [(&'static str, BinaryOperator)]] = &[
+            &[("===", BinaryOperator::StrictEqual), ("!==", BinaryOperator::StrictNotEqual),
+              ("==", BinaryOperator::Equal), ("!=", BinaryOperator::NotEqual)],
+            &[("<=", BinaryOperator::LessOrEqual), (">=", BinaryOperator::GreaterOrEqual),
+              ("<", BinaryOperator::Less), (">", BinaryOperator::Greater)],
+            &[("+", BinaryOperator::Add), ("-", BinaryOperator::Subtract)],
+            &[("*", BinaryOperator::Multiply), ("/", BinaryOperator::Divide), ("%", BinaryOperator::Remainder)],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = try!(self.binary(level + 1));
+        loop {
+            let operator = LEVELS[level].iter().find(|&&(punctuator, _)| self.is_punctuator(punctuator));
+            let operator = match operator {
+                Some(&(_, operator)) => operator,
+                None => return Ok(left),
+            };
+            self.position += 1;
+            left = Expression::Binary(operator, Box::new(left), Box::new(try!(self.binary(level + 1))));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, PacError> {
+        if self.eat("!") {
+            return Ok(Expression::Not(Box::new(try!(self.unary()))));
+        }
+        if self.eat("-") {
+            return Ok(Expression::Negate(Box::new(try!(self.unary()))));
+        }
+        let mut expression = try!(self.primary());
+        loop {
+            if self.eat(".") {
+                expression = Expression::Member(Box::new(expression), try!(self.identifier()));
+            } else if self.eat("(") {
+                let mut arguments = vec![];
+                while !self.eat(")") {
+                    if !arguments.is_empty() {
+                        try!(self.expect(","));
+                    }
+                    arguments.push(try!(self.expression()));
+                }
+                expression = Expression::Call(Box::new(expression), arguments);
+            } else {
+                return Ok(expression);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, PacError> {
+        match try!(self.next()) {
+            Token::Number(number) => Ok(Expression::Literal(Value::Number(number))),
+            Token::Str(string) => Ok(Expression::Literal(Value::Str(string))),
+            Token::Punctuator("(") => {
+                let expression = try!(self.expression());
+                try!(self.expect(")"));
+                Ok(expression)
+            }
+            Token::Identifier(name) => Ok(match &*name {
+                "true" => Expression::Literal(Value::Bool(true)),
+                "false" => Expression::Literal(Value::Bool(false)),
+                "null" => Expression::Literal(Value::Null),
+                "undefined" => Expression::Literal(Value::Undefined),
+                _ => Expression::Variable(name),
+            }),
+            token => Err(PacError::Syntax(format!("unexpected {:?}", token))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Undefined,
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+}
+
+impl Value {
+    fn is_truthy(&self) -> bool {
+        match *self {
+            Value::Undefined | Value::Null => false,
+            Value::Bool(value) => value,
+            Value::Number(number) => number != 0. && !number.is_nan(),
+            Value::Str(ref string) => !string.is_empty(),
+        }
+    }
+
+    fn to_number(&self) -> f64 {
+        match *self {
+            Value::Undefined => ::std::f64::NAN,
+            Value::Null => 0.,
+            Value::Bool(value) => if value { 1. } else { 0. },
+            Value::Number(number) => number,
+            Value::Str(ref string) if string.trim().is_empty() => 0.,
+            Value::Str(ref string) => string.trim().parse().unwrap_or(::std::f64::NAN),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        match *self {
+            Value::Undefined => "undefined".to_owned(),
+            Value::Null => "null".to_owned(),
+            Value::Bool(value) => value.to_string(),
+            Value::Number(number) if number.fract() == 0. && number.abs() < 1e15 => (number as i64).to_string(),
+            Value::Number(number) => number.to_string(),
+            Value::Str(ref string) => string.clone(),
+        }
+    }
+
+    /// `==`, which converts between types.
+    fn loosely_equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (&Value::Str(ref a), &Value::Str(ref b)) => a == b,
+            (&Value::Undefined, _) | (&Value::Null, _) => matches!(*other, Value::Undefined | Value::Null),
+            (_, &Value::Undefined) | (_, &Value::Null) => false,
+            _ => self.to_number() == other.to_number(),
+        }
+    }
+}
+
+/// The state of a call of `FindProxyForURL`.
+struct Evaluation<'a> {
+    script: &'a PacScript,
+    /// What host names are resolved with, unless the proxy resolves them.
+    resolver: Option<&'a Resolver>,
+    /// The time the date and time functions compare with.
+    now: Timespec,
+    globals: HashMap<String, Value>,
+    depth: usize,
+}
+
+impl<'a> Evaluation<'a> {
+    fn call(&mut self, name: &str, arguments: Vec<Value>) -> Result<Value, PacError> {
+        let script = self.script;
+        let function = match script.functions.get(name) {
+            Some(function) => function,
+            None => return self.call_builtin(name, arguments),
+        };
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(PacError::Runtime("too much recursion".to_owned()));
+        }
+        let mut locals: HashMap<String, Value> = function.parameters.iter().cloned().zip(arguments).collect();
+        for parameter in &function.parameters {
+            locals.entry(parameter.clone()).or_insert(Value::Undefined);
+        }
+        self.depth += 1;
+        let returned = self.execute_all(&function.body, &mut locals);
+        self.depth -= 1;
+        Ok(try!(returned).unwrap_or(Value::Undefined))
+    }
+
+    /// Run `statements`, giving the value returned by one of them.
+    fn execute_all(&mut self, statements: &[Statement], locals: &mut HashMap<String, Value>)
+                   -> Result<Option<Value>, PacError> {
+        for statement in statements {
+            if let Some(value) = try!(self.execute(statement, locals)) {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    fn execute(&mut self, statement: &Statement, locals: &mut HashMap<String, Value>)
+               -> Result<Option<Value>, PacError> {
+        match *statement {
+            Statement::Declare(ref declarations) => {
+                for &(ref name, ref value) in declarations {
+                    let value = match *value {
+                        Some(ref value) => try!(self.evaluate(value, locals)),
+                        None => Value::Undefined,
+                    };
+                    locals.insert(name.clone(), value);
+                }
+            }
+            Statement::Assign(ref name, ref value) => {
+                let value = try!(self.evaluate(value, locals));
+                if locals.contains_key(name) {
+                    locals.insert(name.clone(), value);
+                } else {
+                    self.globals.insert(name.clone(), value);
+                }
+            }
+            Statement::If(ref condition, ref then, ref otherwise) => {
+                if try!(self.evaluate(condition, locals)).is_truthy() {
+                    return self.execute(then, locals);
+                }
+                if let Some(ref otherwise) = *otherwise {
+                    return self.execute(otherwise, locals);
+                }
+            }
+            Statement::Return(ref value) => {
+                return Ok(Some(match *value {
+                    Some(ref value) => try!(self.evaluate(value, locals)),
+                    None => Value::Undefined,
+                }));
+            }
+            Statement::Block(ref statements) => return self.execute_all(statements, locals),
+            Statement::Expression(ref expression) => {
+                try!(self.evaluate(expression, locals));
+            }
+            Statement::Empty => {}
+        }
+        Ok(None)
+    }
+
+    fn evaluate(&mut self, expression: &Expression, locals: &mut HashMap<String, Value>) -> Result<Value, PacError> {
+        Ok(match *expression {
+            Expression::Literal(ref value) => value.clone(),
+            Expression::Variable(ref name) => {
+                match locals.get(name).or_else(|| self.globals.get(name)) {
+                    Some(value) => value.clone(),
+                    None => return Err(PacError::Runtime(format!("{} is not defined", name))),
+                }
+            }
+            Expression::Member(ref object, ref name) => {
+                match (try!(self.evaluate(object, locals)), &**name) {
+                    (Value::Str(string), "length") => Value::Number(string.chars().count() as f64),
+                    (value, _) => return Err(PacError::Runtime(format!("no property {} of {:?}", name, value))),
+                }
+            }
+            Expression::Call(ref callee, ref arguments) => {
+                let mut values = vec![];
+                for argument in arguments {
+                    values.push(try!(self.evaluate(argument, locals)));
+                }
+                match **callee {
+                    Expression::Variable(ref name) => try!(self.call(name, values)),
+                    Expression::Member(ref object, ref method) => {
+                        let object = try!(self.evaluate(object, locals));
+                        try!(call_string_method(object, method, values))
+                    }
+                    _ => return Err(PacError::Runtime("call of something that isn't a function".to_owned())),
+                }
+            }
+            Expression::Not(ref operand) => Value::Bool(!try!(self.evaluate(operand, locals)).is_truthy()),
+            Expression::Negate(ref operand) => Value::Number(-try!(self.evaluate(operand, locals)).to_number()),
+            Expression::And(ref left, ref right) => {
+                let left = try!(self.evaluate(left, locals));
+                if left.is_truthy() { try!(self.evaluate(right, locals)) } else { left }
+            }
+            Expression::Or(ref left, ref right) => {
+                let left = try!(self.evaluate(left, locals));
+                if left.is_truthy() { left } else { try!(self.evaluate(right, locals)) }
+            }
+            Expression::Conditional(ref condition, ref then, ref otherwise) => {
+                if try!(self.evaluate(condition, locals)).is_truthy() {
+                    try!(self.evaluate(then, locals))
+                } else {
+                    try!(self.evaluate(otherwise, locals))
+                }
+            }
+            Expression::Binary(operator, ref left, ref right) => {
+                let left = try!(self.evaluate(left, locals));
+                let right = try!(self.evaluate(right, locals));
+                binary_operation(operator, left, right)
+            }
+        })
+    }
+
+    /// The functions PAC scripts can call.
+    /// https://developer.mozilla.org/en-US/docs/Web/HTTP/Proxy_servers_and_tunneling/Proxy_Auto-Configuration_PAC_file
+    fn call_builtin(&mut self, name: &str, arguments: Vec<Value>) -> Result<Value, PacError> {
+        let strings: Vec<String> = arguments.iter().map(Value::to_string).collect();
+        let argument = |index: usize| strings.get(index).map_or("undefined", |string| &**string);
+        Ok(match name {
+            "isPlainHostName" => Value::Bool(!argument(0).contains('.')),
+            "dnsDomainIs" => {
+                Value::Bool(argument(0).to_ascii_lowercase().ends_with(&argument(1).to_ascii_lowercase()))
+            }
+            "localHostOrDomainIs" => {
+                let (host, domain) = (argument(0).to_ascii_lowercase(), argument(1).to_ascii_lowercase());
+                Value::Bool(host == domain || (!host.contains('.') && domain.starts_with(&format!("{}.", host))))
+            }
+            "dnsDomainLevels" => Value::Number(argument(0).matches('.').count() as f64),
+            "shExpMatch" => Value::Bool(shell_expression_matches(argument(0).as_bytes(), argument(1).as_bytes())),
+            "isResolvable" => Value::Bool(self.resolve(argument(0)).is_some()),
+            "dnsResolve" => self.resolve(argument(0)).map_or(Value::Null, |address| Value::Str(address.to_string())),
+            "isInNet" => {
+                let address = self.resolve(argument(0));
+                let (pattern, mask) = (Ipv4Addr::from_str(argument(1)), Ipv4Addr::from_str(argument(2)));
+                Value::Bool(match (address, pattern, mask) {
+                    (Some(address), Ok(pattern), Ok(mask)) => {
+                        let mask = u32::from(mask);
+                        u32::from(address) & mask == u32::from(pattern) & mask
+                    }
+                    _ => false,
+                })
+            }
+            "myIpAddress" => Value::Str(my_ip_address().to_string()),
+            "weekdayRange" => weekday_range(&arguments, self.now),
+            "dateRange" => date_range(&arguments, self.now),
+            "timeRange" => time_range(&arguments, self.now),
+            "alert" => {
+                debug!("PAC script alert: {}", argument(0));
+                Value::Undefined
+            }
+            _ => return Err(PacError::Runtime(format!("{} is not defined", name))),
+        })
+    }
+
+    /// The first IPv4 address of `host`, which may be an address itself. Names aren't
+    /// resolved when the proxy resolves them, so as not to leak them to the local resolver.
+    fn resolve(&self, host: &str) -> Option<Ipv4Addr> {
+        if let Ok(address) = Ipv4Addr::from_str(host) {
+            return Some(address);
+        }
+        let resolver = match self.resolver {
+            Some(resolver) => resolver,
+            None => return None,
+        };
+        resolver.resolve(host, 0).ok().and_then(|addrs| addrs.into_iter().filter_map(|addr| {
+            match addr.ip() {
+                IpAddr::V4(address) => Some(address),
+                IpAddr::V6(_) => None,
+            }
+        }).next())
+    }
+}
+
+fn binary_operation(operator: BinaryOperator, left: Value, right: Value) -> Value {
+    match operator {
+        BinaryOperator::Equal => Value::Bool(left.loosely_equals(&right)),
+        BinaryOperator::NotEqual => Value::Bool(!left.loosely_equals(&right)),
+        BinaryOperator::StrictEqual => Value::Bool(left == right),
+        BinaryOperator::StrictNotEqual => Value::Bool(left != right),
+        BinaryOperator::Add => match (left, right) {
+            (left @ Value::Str(_), right) | (left, right @ Value::Str(_)) => {
+                Value::Str(left.to_string() + &right.to_string())
+            }
+            (left, right) => Value::Number(left.to_number() + right.to_number()),
+        },
+        BinaryOperator::Less | BinaryOperator::Greater |
+        BinaryOperator::LessOrEqual | BinaryOperator::GreaterOrEqual => {
+            let ordering = match (&left, &right) {
+                (&Value::Str(ref a), &Value::Str(ref b)) => a.partial_cmp(b),
+                _ => left.to_number().partial_cmp(&right.to_number()),
+            };
+            Value::Bool(match (operator, ordering) {
+                (_, None) => false,
+                (BinaryOperator::Less, Some(ordering)) => ordering == ::std::cmp::Ordering::Less,
+                (BinaryOperator::Greater, Some(ordering)) => ordering == ::std::cmp::Ordering::Greater,
+                (BinaryOperator::LessOrEqual, Some(ordering)) => ordering != ::std::cmp::Ordering::Greater,
+                (_, Some(ordering)) => ordering != ::std::cmp::Ordering::Less,
+            })
+        }
+        BinaryOperator::Subtract => Value::Number(left.to_number() - right.to_number()),
+        BinaryOperator::Multiply => Value::Number(left.to_number() * right.to_number()),
+        BinaryOperator::Divide => Value::Number(left.to_number() / right.to_number()),
+        BinaryOperator::Remainder => Value::Number(left.to_number() % right.to_number()),
+    }
+}
+
+fn call_string_method(object: Value, method: &str, arguments: Vec<Value>) -> Result<Value, PacError> {
+    let string = match object {
+        Value::Str(string) => string,
+        value => return Err(PacError::Runtime(format!("no method {} of {:?}", method, value))),
+    };
+    let chars: Vec<char> = string.chars().collect();
+    // Indices are clamped to the string, as JavaScript does.
+    let index = |argument: Option<&Value>, default: usize| match argument {
+        Some(value) if *value != Value::Undefined => {
+            let number = value.to_number();
+            if number.is_nan() || number < 0. { 0 } else { min(number as usize, chars.len()) }
+        }
+        _ => default,
+    };
+    let substring = |start: usize, end: usize| chars[start..end].iter().cloned().collect();
+    Ok(match method {
+        "toLowerCase" => Value::Str(string.to_lowercase()),
+        "toUpperCase" => Value::Str(string.to_uppercase()),
+        "indexOf" | "lastIndexOf" => {
+            let needle = arguments.get(0).map_or("undefined".to_owned(), Value::to_string);
+            let position = if method == "indexOf" { string.find(&*needle) } else { string.rfind(&*needle) };
+            Value::Number(position.map_or(-1., |position| string[..position].chars().count() as f64))
+        }
+        "substring" => {
+            let (start, end) = (index(arguments.get(0), 0), index(arguments.get(1), chars.len()));
+            Value::Str(if start <= end { substring(start, end) } else { substring(end, start) })
+        }
+        "charAt" => {
+            let start = index(arguments.get(0), 0);
+            Value::Str(substring(start, min(start + 1, chars.len())))
+        }
+        _ => return Err(PacError::Runtime(format!("no method {} of strings", method))),
+    })
+}
+
+/// The time `now` as the date and time functions see it: in UTC if their last argument
+/// is `"GMT"`, and in local time otherwise. The other arguments are returned with it.
+fn clock(arguments: &[Value], now: Timespec) -> (&[Value], Tm) {
+    match arguments.split_last() {
+        Some((last, rest)) if last.to_string() == "GMT" => (rest, time::at_utc(now)),
+        _ => (arguments, time::at(now)),
+    }
+}
+
+/// Whether `value` is between `first` and `last`, inclusive. A range whose `first` is
+/// after its `last` wraps around, as `weekdayRange("FRI", "MON")` does.
+fn in_range<T: PartialOrd>(value: T, first: T, last: T) -> bool {
+    if first <= last {
+        first <= value && value <= last
+    } else {
+        first <= value || value <= last
+    }
+}
+
+fn weekday_range(arguments: &[Value], now: Timespec) -> Value {
+    let (arguments, now) = clock(arguments, now);
+    let weekday = |value: &Value| WEEKDAYS.iter().position(|day| *day == value.to_string()).map(|day| day as i32);
+    Value::Bool(match (arguments.get(0).and_then(&weekday), arguments.get(1).map(&weekday)) {
+        (Some(day), None) => now.tm_wday == day,
+        (Some(first), Some(Some(last))) => in_range(now.tm_wday, first, last),
+        _ => false,
+    })
+}
+
+/// A part of a date given to `dateRange`: a day of the month from 1 to 31, a month name,
+/// or a year.
+enum DatePart {
+    Day(i32),
+    Month(i32),
+    Year(i32),
+}
+
+fn date_part(value: &Value) -> Option<DatePart> {
+    if let Some(month) = MONTHS.iter().position(|month| *month == value.to_string()) {
+        return Some(DatePart::Month(month as i32));
+    }
+    match value.to_number() {
+        number if number >= 1. && number < 32. => Some(DatePart::Day(number as i32)),
+        number if number >= 32. && number < 10000. => Some(DatePart::Year(number as i32)),
+        _ => None,
+    }
+}
+
+fn date_range(arguments: &[Value], now: Timespec) -> Value {
+    let (arguments, now) = clock(arguments, now);
+    let today = (now.tm_year + 1900, now.tm_mon, now.tm_mday);
+    let parts: Vec<DatePart> = arguments.iter().filter_map(date_part).collect();
+    if parts.len() != arguments.len() {
+        return Value::Bool(false);
+    }
+    if parts.len() == 1 {
+        return Value::Bool(match parts[0] {
+            DatePart::Day(day) => today.2 == day,
+            DatePart::Month(month) => today.1 == month,
+            DatePart::Year(year) => today.0 == year,
+        });
+    }
+    if parts.is_empty() || parts.len() % 2 == 1 || parts.len() > 6 {
+        return Value::Bool(false);
+    }
+    // The parts that aren't given are those of the first and last day of this year,
+    // except that a range of days is one within this month.
+    let (mut first, mut last) = ((today.0, 0, 1), (today.0, 11, 31));
+    if let DatePart::Day(_) = parts[0] {
+        if parts.len() == 2 {
+            first.1 = today.1;
+            last.1 = today.1;
+        }
+    }
+    let (first_parts, last_parts) = parts.split_at(parts.len() / 2);
+    set_date_parts(&mut first, first_parts);
+    set_date_parts(&mut last, last_parts);
+    Value::Bool(in_range(today, first, last))
+}
+
+fn set_date_parts(date: &mut (i32, i32, i32), parts: &[DatePart]) {
+    for part in parts {
+        match *part {
+            DatePart::Day(day) => date.2 = day,
+            DatePart::Month(month) => date.1 = month,
+            DatePart::Year(year) => date.0 = year,
+        }
+    }
+}
+
+fn time_range(arguments: &[Value], now: Timespec) -> Value {
+    let (arguments, now) = clock(arguments, now);
+    let numbers: Vec<f64> = arguments.iter().map(Value::to_number).collect();
+    if numbers.iter().any(|number| !(*number >= 0. && *number < 60.)) {
+        return Value::Bool(false);
+    }
+    let n: Vec<i32> = numbers.into_iter().map(|number| number as i32).collect();
+    let seconds = |hour: i32, minute: i32, second: i32| (hour * 60 + minute) * 60 + second;
+    // A range ends at the end of its last hour or minute.
+    let (first, last) = match n.len() {
+        1 => return Value::Bool(now.tm_hour == n[0]),
+        2 => (seconds(n[0], 0, 0), seconds(n[1], 59, 59)),
+        4 => (seconds(n[0], n[1], 0), seconds(n[2], n[3], 59)),
+        6 => (seconds(n[0], n[1], n[2]), seconds(n[3], n[4], n[5])),
+        _ => return Value::Bool(false),
+    };
+    Value::Bool(in_range(seconds(now.tm_hour, now.tm_min, now.tm_sec), first, last))
+}
+
+/// Whether `string` matches the shell expression `pattern`, where `*` matches any run of
+/// characters and `?` any one character.
+///
+/// Only the last `*` is ever backtracked to, as whatever an earlier one could have matched
+/// the later one can match instead, so no more than one pass over `pattern` is made for each
+/// character of `string`, however many `*`s there are.
+fn shell_expression_matches(string: &[u8], pattern: &[u8]) -> bool {
+    let (mut s, mut p) = (0, 0);
+    // The position in `pattern` just after the last `*`, and the position in `string`
+    // that the `*` stops matching at in the attempt being made.
+    let mut last_star = None;
+    while s < string.len() {
+        match pattern.get(p) {
+            Some(&b'*') => {
+                p += 1;
+                last_star = Some((p, s));
+            }
+            Some(&c) if c == b'?' || c == string[s] => {
+                s += 1;
+                p += 1;
+            }
+            _ => match last_star {
+                Some((after_star, star_end)) => {
+                    p = after_star;
+                    s = star_end + 1;
+                    last_star = Some((after_star, s));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// The address of the interface that traffic to the internet leaves by. Connecting a UDP
+/// socket sends nothing; it only picks the route.
+fn my_ip_address() -> IpAddr {
+    UdpSocket::bind("0.0.0.0:0")
+        .and_then(|socket| socket.connect("198.51.100.1:80").and_then(|_| socket.local_addr()))
+        .map(|addr| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)))
+}
+
+/// A parsed PAC script.
+#[derive(Debug)]
+pub struct PacScript {
+    functions: HashMap<String, Function>,
+    statements: Vec<Statement>,
+}
+
+impl PacScript {
+    pub fn parse(source: &str) -> Result<PacScript, PacError> {
+        let mut parser = Parser {
+            tokens: try!(tokenize(source)),
+            position: 0,
+        };
+        let (functions, statements) = try!(parser.program());
+        if !functions.contains_key("FindProxyForURL") {
+            return Err(PacError::Syntax("FindProxyForURL is not defined".to_owned()));
+        }
+        Ok(PacScript {
+            functions: functions,
+            statements: statements,
+        })
+    }
+
+    /// The result of `FindProxyForURL(url, host)`, such as `PROXY proxy.example:3128; DIRECT`,
+    /// at the time `now`. Host names are only resolved if there is a `resolver`.
+    pub fn find_proxy(&self, url: &str, host: &str, resolver: Option<&Resolver>, now: Timespec)
+                      -> Result<String, PacError> {
+        let mut evaluation = Evaluation {
+            script: self,
+            resolver: resolver,
+            now: now,
+            globals: HashMap::new(),
+            depth: 0,
+        };
+        // The top-level statements are run for every call, so that no state is shared
+        // between calls made at once.
+        let mut globals = HashMap::new();
+        try!(evaluation.execute_all(&self.statements, &mut globals));
+        evaluation.globals.extend(globals);
+        let arguments = vec![Value::Str(url.to_owned()), Value::Str(host.to_owned())];
+        match try!(evaluation.call("FindProxyForURL", arguments)) {
+            Value::Str(result) => Ok(result),
+            value => Err(PacError::Runtime(format!("FindProxyForURL returned {:?}", value))),
+        }
+    }
+}
+
+/// The route given by the first entry of the result of `FindProxyForURL` that can be
+/// followed, if any can. HTTP proxies tunnel connections if `tunnel` is set, and forward
+/// requests otherwise. `SOCKS` entries are taken to be SOCKS5 proxies, as that is the
+/// only version spoken.
+pub fn route_for_pac_result(result: &str, tunnel: bool, remote_dns: bool) -> Option<ProxyRoute> {
+    for entry in result.split(';') {
+        let mut parts = entry.split_whitespace();
+        let (kind, server) = (parts.next().map(|kind| kind.to_ascii_uppercase()), parts.next());
+        let route = match (kind.as_ref().map(|kind| &**kind), server) {
+            (Some("DIRECT"), _) => Some(ProxyRoute::Direct),
+            (Some("PROXY"), Some(server)) | (Some("HTTP"), Some(server)) => {
+                HttpProxy::from_pref(server).map(|proxy| {
+                    if tunnel { ProxyRoute::Tunnel(proxy) } else { ProxyRoute::Forward(proxy) }
+                })
+            }
+            (Some("SOCKS"), Some(server)) | (Some("SOCKS5"), Some(server)) => {
+                SocksProxy::from_pref(server, remote_dns).map(ProxyRoute::Socks)
+            }
+            _ => None,
+        };
+        if route.is_some() {
+            return route;
+        }
+    }
+    None
+}
+
+/// The script of a `ProxyAutoConfig`, as far as it has been loaded.
+#[derive(Default)]
+struct ScriptState {
+    /// The script, once it has been loaded.
+    script: Option<Arc<PacScript>>,
+    /// When to try loading the script again, if it couldn't be loaded.
+    retry: i64,
+    /// Whether the script is being loaded.
+    loading: bool,
+}
+
+/// Picks the route of each connection with a PAC script, and remembers it for the URL the
+/// script was given for a while. The script is loaded in the background when it is first
+/// needed, and connections are made directly until it is ready.
+pub struct ProxyAutoConfig {
+    /// The URL or file path the script is loaded from.
+    location: String,
+    resolver: Arc<Resolver>,
+    /// Whether SOCKS proxies resolve host names, in which case the script can't either.
+    remote_dns: bool,
+    script: Arc<Mutex<ScriptState>>,
+    routes: Mutex<HashMap<String, (ProxyRoute, i64)>>,
+}
+
+impl fmt::Debug for ProxyAutoConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ProxyAutoConfig({:?})", self.location)
+    }
+}
+
+impl ProxyAutoConfig {
+    /// Proxy auto-config with the script at `location`, an `http`, `https` or `file` URL
+    /// or a file path.
+    pub fn new(location: String, resolver: Arc<Resolver>, remote_dns: bool) -> ProxyAutoConfig {
+        ProxyAutoConfig {
+            location: location,
+            resolver: resolver,
+            remote_dns: remote_dns,
+            script: Arc::new(Mutex::new(ScriptState::default())),
+            routes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Proxy auto-config with a script that is already loaded.
+    pub fn with_script(script: PacScript, resolver: Arc<Resolver>, remote_dns: bool) -> ProxyAutoConfig {
+        let config = ProxyAutoConfig::new("(script)".to_owned(), resolver, remote_dns);
+        config.script.lock().unwrap().script = Some(Arc::new(script));
+        config
+    }
+
+    /// The script, if it has been loaded. If it hasn't, and isn't being loaded, it starts
+    /// being loaded on a thread of its own, so that a slow server holds up no connection,
+    /// and the thread that asks keeps the state of the connection it is making.
+    fn script(&self) -> Option<Arc<PacScript>> {
+        let mut state = self.script.lock().unwrap();
+        if state.script.is_none() && !state.loading && time::get_time().sec >= state.retry {
+            state.loading = true;
+            let location = self.location.clone();
+            let shared_state = self.script.clone();
+            spawn_named(format!("PAC script loader for {}", location), move || {
+                set_deadline(Some(Deadline::after(PAC_LOAD_TIMEOUT_MS, true)));
+                let loaded = load_script(&location).and_then(|source| PacScript::parse(&source));
+                let mut state = shared_state.lock().unwrap();
+                match loaded {
+                    Ok(loaded) => state.script = Some(Arc::new(loaded)),
+                    Err(error) => {
+                        warn!("Connecting directly, as the PAC script at {} failed: {}", location, error);
+                        state.retry = time::get_time().sec + PAC_TTL;
+                    }
+                }
+                state.loading = false;
+            });
+        }
+        state.script.clone()
+    }
+
+    /// How a connection for `url` reaches its host, as the script says. Connections are
+    /// made directly if the script fails or hasn't been loaded yet, and that isn't remembered.
+    pub fn route(&self, url: &Url, tunnel: bool) -> ProxyRoute {
+        let host = url.host_str().unwrap_or("");
+        let url = match url.scheme() {
+            "https" | "wss" => format!("{}://{}/", url.scheme(), host),
+            _ => {
+                let mut url = url.clone();
+                url.set_fragment(None);
+                url.into_string()
+            }
+        };
+        if let Some(&(ref route, expires)) = self.routes.lock().unwrap().get(&url) {
+            if time::get_time().sec < expires {
+                return route.clone();
+            }
+        }
+        let script = match self.script() {
+            Some(script) => script,
+            None => return ProxyRoute::Direct,
+        };
+        let resolver = if self.remote_dns { None } else { Some(&*self.resolver) };
+        let route = match script.find_proxy(&url, host, resolver, time::get_time()) {
+            Ok(result) => route_for_pac_result(&result, tunnel, self.remote_dns).unwrap_or_else(|| {
+                warn!("Connecting directly, as the PAC script gave no usable proxy for {}: {:?}", url, result);
+                ProxyRoute::Direct
+            }),
+            Err(error) => {
+                warn!("Connecting directly, as the PAC script failed for {}: {}", url, error);
+                ProxyRoute::Direct
+            }
+        };
+        let mut routes = self.routes.lock().unwrap();
+        let now = time::get_time().sec;
+        if routes.len() >= MAX_REMEMBERED_ROUTES {
+            let expired: Vec<String> = routes.iter()
+                                             .filter(|&(_, &(_, expires))| expires <= now)
+                                             .map(|(url, _)| url.clone())
+                                             .collect();
+            for url in &expired {
+                routes.remove(url);
+            }
+            if routes.len() >= MAX_REMEMBERED_ROUTES {
+                routes.clear();
+            }
+        }
+        routes.insert(url, (route.clone(), now + PAC_TTL));
+        route
+    }
+}
+
+/// The source of the script at `location`, which is fetched directly for `http` and
+/// `https` URLs, by the deadline set on this thread if there is one.
+fn load_script(location: &str) -> Result<String, PacError> {
+    let url = match Url::parse(location) {
+        Ok(url) => url,
+        Err(_) => return read_file(location),
+    };
+    match url.scheme() {
+        "file" => match url.to_file_path() {
+            Ok(path) => read_file(&path.to_string_lossy()),
+            Err(_) => Err(PacError::Load(format!("{} isn't a file path", location))),
+        },
+        "http" | "https" => {
+            let pool = create_http_connector_with_tls_info(Arc::new(Mutex::new(HashMap::new())),
+                                                           Arc::new(ProxySettings::default()), None,
+                                                           HappyEyeballs::new(Arc::new(SystemResolver)), None, None);
+            let response = HyperRequest::with_connector(Method::Get, url, &*pool)
+                .and_then(|request| request.start())
+                .and_then(|request| request.send());
+            let mut response = try!(response.map_err(|error| PacError::Load(error.to_string())));
+            if !response.status.is_success() {
+                return Err(PacError::Load(format!("the server answered {}", response.status)));
+            }
+            let mut source = vec![];
+            try!(response.read_to_end(&mut source).map_err(|error| PacError::Load(error.to_string())));
+            Ok(String::from_utf8_lossy(&source).into_owned())
+        }
+        // A Windows path such as `C:\proxy.pac` parses as a URL with the scheme `c`.
+        _ if url.scheme().len() == 1 => read_file(location),
+        scheme => Err(PacError::Load(format!("{}: URLs aren't supported", scheme))),
+    }
+}
+
+fn read_file(path: &str) -> Result<String, PacError> {
+    let mut source = String::new();
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut source)) {
+        Ok(_) => Ok(source),
+        Err(error) => Err(PacError::Load(format!("{}: {}", path, error))),
+    }
+}
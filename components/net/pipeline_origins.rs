@@ -0,0 +1,135 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Checks that a fetch is one the pipeline that issued it may make.
+//!
+//! The origin of a request is supplied by the script thread, so a compromised
+//! script could otherwise claim any origin it likes and make same-origin requests
+//! to another site. To prevent that, the origin of the document loaded in each
+//! pipeline is remembered when its navigation response arrives, and later requests
+//! from that pipeline must come from the same origin. Documents that aren't fetched,
+//! such as `about:blank` iframes, are given the origin of their parent by the
+//! constellation instead, over a channel script doesn't hold.
+
+use hyper::header::Headers;
+use msg::constellation_msg::PipelineId;
+use net_traits::{FetchTaskTarget, NetworkError};
+use net_traits::request::{Origin, Request, RequestMode};
+use net_traits::response::Response;
+use servo_url::ServoUrl;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use url::Origin as UrlOrigin;
+use util::prefs::PREFS;
+
+pub struct PipelineOrigins {
+    origins: HashMap<PipelineId, UrlOrigin>,
+}
+
+impl PipelineOrigins {
+    pub fn new() -> PipelineOrigins {
+        PipelineOrigins {
+            origins: HashMap::new(),
+        }
+    }
+
+    /// Remember that the document in `pipeline_id` was loaded from `url`. Only the first
+    /// document counts, as every navigation is given a new pipeline.
+    pub fn record(&mut self, pipeline_id: PipelineId, url: &ServoUrl) {
+        let origin = url.origin();
+        // Documents with an opaque origin can't make same-origin requests, so there is
+        // nothing to check for them.
+        if origin.is_tuple() {
+            self.origins.entry(pipeline_id).or_insert(origin);
+        }
+    }
+
+    /// Give the document in `pipeline_id`, which wasn't fetched, the origin of the one in
+    /// `parent_id`. If that isn't known, neither is this one. An origin that is already
+    /// known is never replaced.
+    pub fn inherit(&mut self, pipeline_id: PipelineId, parent_id: PipelineId) {
+        if let Some(origin) = self.origins.get(&parent_id).cloned() {
+            self.origins.entry(pipeline_id).or_insert(origin);
+        }
+    }
+
+    pub fn remove(&mut self, pipeline_id: PipelineId) {
+        self.origins.remove(&pipeline_id);
+    }
+
+    /// Check that `request` has a mode it may use, and an origin matching that of the
+    /// document in the pipeline that issued it. Requests made on behalf of no pipeline,
+    /// or of one whose document hasn't been recorded, are only checked for their mode.
+    pub fn check(&self, request: &Request) -> Result<(), NetworkError> {
+        // The `navigate` mode skips the CORS check, which is also how browser.html makes
+        // cross-origin requests when mozbrowser is enabled.
+        if request.mode == RequestMode::Navigate && !request.is_navigation_request() &&
+           !PREFS.is_mozbrowser_enabled() {
+            return Err(NetworkError::Internal("Navigate mode used for a non-navigation request".into()));
+        }
+        let expected = match request.pipeline_id.get().and_then(|id| self.origins.get(&id)) {
+            Some(origin) => origin,
+            None => return Ok(()),
+        };
+        if request.is_navigation_request() {
+            return Err(NetworkError::Internal("Navigation in a pipeline that already has a document".into()));
+        }
+        match *request.origin.borrow() {
+            Origin::Origin(ref origin) if origin.is_tuple() && origin != expected =>
+                Err(NetworkError::Internal("Request origin doesn't match its pipeline".into())),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Passes fetch events on to `target`, recording the origin of a navigation response
+/// before the pipeline's document can start making requests of its own.
+pub struct RecordDocumentOrigin {
+    pub target: Box<FetchTaskTarget + Send + 'static>,
+    pub pipeline_id: PipelineId,
+    pub pipeline_origins: Arc<Mutex<PipelineOrigins>>,
+}
+
+impl FetchTaskTarget for RecordDocumentOrigin {
+    fn process_request_body(&mut self, request: &Request) {
+        self.target.process_request_body(request)
+    }
+
+    fn process_request_body_progress(&mut self, sent: u64, total: Option<u64>) {
+        self.target.process_request_body_progress(sent, total)
+    }
+
+    fn process_request_eof(&mut self, request: &Request) {
+        self.target.process_request_eof(request)
+    }
+
+    fn process_early_hints(&mut self, links: &[String]) {
+        self.target.process_early_hints(links)
+    }
+
+    fn process_response(&mut self, response: &Response) {
+        if !response.is_network_error() {
+            if let Some(url) = response.actual_response().url() {
+                self.pipeline_origins.lock().unwrap().record(self.pipeline_id, url);
+            }
+        }
+        self.target.process_response(response)
+    }
+
+    fn process_response_part(&mut self, part: &Response) {
+        self.target.process_response_part(part)
+    }
+
+    fn process_response_chunk(&mut self, chunk: Vec<u8>) {
+        self.target.process_response_chunk(chunk)
+    }
+
+    fn process_response_trailers(&mut self, trailers: &Headers) {
+        self.target.process_response_trailers(trailers)
+    }
+
+    fn process_response_eof(&mut self, response: &Response) {
+        self.target.process_response_eof(response)
+    }
+}
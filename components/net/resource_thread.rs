@@ -3,51 +3,81 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 //! A thread that takes a URL and streams back the binary data.
-use connector::{Connector, create_http_connector};
+use alt_svc::AltSvcCache;
+use certificate_exceptions::{CertificateExceptionCheck, CertificateExceptions};
+use connection_limiter::{ConnectionLimiter, FetchJob, FetchScheduler, run_started_fetches};
+use connector::ConnectionPools;
 use content_blocker::BLOCKED_CONTENT_RULES;
 use cookie;
 use cookie_rs;
 use cookie_storage::CookieStorage;
+use digest_auth::DigestAuthCache;
 use devtools_traits::DevtoolsControlMsg;
-use fetch::methods::{FetchContext, fetch};
+use fetch::cors_cache::CorsCache;
+use fetch::methods::{BodyFlowControl, CancellationListener, Deadline, FetchContext, NetStats, SchemeHandlers};
+use fetch::methods::{Target, fetch};
 use filemanager_thread::{FileManager, TFDProvider};
-use hsts::HstsList;
-use http_loader::HttpState;
-use hyper::client::pool::Pool;
-use hyper::header::{ContentType, Header, SetCookie};
+use hsts::{HstsEntry, HstsList};
+use http_cache::{HttpCache, MemoryCache};
+use http_loader::{HttpState, PrivacySignals, accept_language_from_pref_values, accept_language_header};
+use http_loader::max_response_size_from_prefs;
+use hyper::header::{AcceptLanguage, ContentType, Header, SetCookie};
 use hyper::mime::{Mime, SubLevel, TopLevel};
+use hyper::net::NetworkConnector;
 use hyper_serde::Serde;
-use ipc_channel::ipc::{self, IpcReceiver, IpcReceiverSet, IpcSender};
-use mime_classifier::{ApacheBugFlag, MimeClassifier, NoSniffFlag};
-use net_traits::{CookieSource, CoreResourceThread, Metadata, ProgressMsg};
-use net_traits::{CoreResourceMsg, FetchResponseMsg, FetchTaskTarget, LoadConsumer};
+use ipc_channel::ipc::{self, IpcReceiver, IpcReceiverSet, IpcSelectionResult, IpcSender};
+use ipc_channel::router::ROUTER;
+use lock_recovery::{read_lock, write_lock};
+use mime_classifier::{ApacheBugFlag, MimeClassifier, MimeOverrides, NoSniffFlag};
+use mime_guess::guess_mime_type_opt;
+use msg::constellation_msg::PipelineId;
+use net_traits::{AuthPromptRequest, CertificateException, CookieAcceptPolicy, CookieSource, CoreResourceThread};
+use net_traits::ProgressMsg;
+use net_traits::{CoreResourceControlMsg, CoreResourceControlThread, CoreResourceMsg, FetchTaskTarget, LoadConsumer};
 use net_traits::{CustomResponseMediator, LoadResponse, NetworkError, ResourceId};
-use net_traits::{ResourceThreads, WebSocketCommunicate, WebSocketConnectData};
+use net_traits::{Metadata, NetworkStats};
+use net_traits::{ResourceGroupId, ResourceThreads, SchemeRequest, SessionId, WebSocketCommunicate};
+use net_traits::WebSocketConnectData;
 use net_traits::LoadContext;
+use net_traits::filemanager_thread::FileManagerThreadMsg;
+use net_traits::hosts::replace_hosts;
 use net_traits::ProgressMsg::Done;
-use net_traits::request::{Request, RequestInit};
+use net_traits::request::{Request, RequestInit, RequestPriority};
+use net_traits::response::Response;
 use net_traits::storage_thread::StorageThreadMsg;
+use pipeline_origins::{PipelineOrigins, RecordDocumentOrigin};
+use profile_traits::mem::{ProfilerChan as MemProfilerChan, ProfilerMsg, Report, ReportKind, Reporter};
+use profile_traits::mem::{ReporterRequest, ReportsChan};
 use profile_traits::time::ProfilerChan;
 use rustc_serialize::{Decodable, Encodable};
 use rustc_serialize::json;
 use servo_url::ServoUrl;
+use std::ascii::AsciiExt;
 use std::borrow::{Cow, ToOwned};
 use std::collections::HashMap;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
+use std::mem;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::{Arc, RwLock};
-use std::sync::mpsc::Sender;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::sync::mpsc::{Sender, channel};
+use std::time::{Duration, Instant};
 use storage_thread::StorageThreadFactory;
+use tls_session_cache::TlsSessionCache;
+use url_rewrite::UrlRewriter;
 use util::prefs::PREFS;
 use util::thread::spawn_named;
 use websocket_loader;
 
 const TFD_PROVIDER: &'static TFDProvider = &TFDProvider;
 
+/// How many times in a row selecting on the resource thread channels may fail before the
+/// thread gives up on them.
+const MAX_SELECT_FAILURES: u32 = 10;
+
 pub enum ProgressSender {
     Channel(IpcSender<ProgressMsg>),
 }
@@ -55,9 +85,39 @@ pub enum ProgressSender {
 #[derive(Clone)]
 pub struct ResourceGroup {
     cookie_jar: Arc<RwLock<CookieStorage>>,
+    cookie_policy: Arc<RwLock<CookieAcceptPolicy>>,
     auth_cache: Arc<RwLock<AuthCache>>,
+    /// The Digest challenges made to this group, which are never saved.
+    digest_auth: Arc<RwLock<DigestAuthCache>>,
     hsts_list: Arc<RwLock<HstsList>>,
-    connector: Arc<Pool<Connector>>,
+    /// The public group's HSTS list, if this private group may consult it.
+    shared_hsts_list: Option<Arc<RwLock<HstsList>>>,
+    url_rewriter: Arc<RwLock<UrlRewriter>>,
+    mime_overrides: Arc<RwLock<MimeOverrides>>,
+    connection_pools: Arc<ConnectionPools>,
+    /// The alternative services advertised to this group, which no other group uses.
+    alt_svc_cache: Arc<RwLock<AltSvcCache>>,
+    /// The certificates accepted by this group's connections even though they don't verify.
+    certificate_exceptions: Arc<RwLock<CertificateExceptions>>,
+    /// The `User-Agent` sent with this group's requests and WebSocket handshakes.
+    user_agent: Arc<RwLock<Cow<'static, str>>>,
+    /// The `Accept-Language` given with `SetAcceptLanguage`, if any, which is `None` when the
+    /// message asked for none. Until then the pref's is sent, whatever it is at the time.
+    accept_language: Arc<RwLock<Option<Option<AcceptLanguage>>>>,
+    net_stats: Arc<NetStats>,
+    /// Only the public group caches responses on disk, and only if it has a config directory.
+    http_cache: Option<Arc<RwLock<HttpCache>>>,
+    memory_cache: Arc<RwLock<MemoryCache>>,
+    /// The CORS preflight results of this group's fetches, which are never saved.
+    cors_cache: Arc<RwLock<CorsCache>>,
+    /// Whether this group is used for private browsing.
+    is_private: bool,
+    /// The private browsing session this group belongs to, if it isn't the public or
+    /// the default private group.
+    session_id: Option<SessionId>,
+    /// The directory this group's state is read from and written back to on exit,
+    /// if it is persistent.
+    config_dir: Option<PathBuf>,
 }
 
 impl ProgressSender {
@@ -78,12 +138,43 @@ pub fn send_error(url: ServoUrl, err: NetworkError, start_chan: LoadConsumer) {
     }
 }
 
+/// Which content loaders sniff the type of.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MimeSniffing {
+    /// Whether any content is sniffed.
+    pub enabled: bool,
+    /// Whether `file:` URLs are sniffed even if their extension gives their type.
+    pub local_files: bool,
+}
+
+impl MimeSniffing {
+    /// The sniffing that the `network.mime.sniff` and `network.mime.sniff-local-files`
+    /// prefs ask for at the moment.
+    pub fn from_prefs() -> MimeSniffing {
+        MimeSniffing {
+            enabled: PREFS.get("network.mime.sniff").as_boolean().unwrap_or(false),
+            local_files: PREFS.get("network.mime.sniff-local-files").as_boolean().unwrap_or(false),
+        }
+    }
+}
+
 /// For use by loaders in responding to a Load message that allows content sniffing.
+/// The content type of a URL with an override is never sniffed, nor is any other unless
+/// `sniffing` is enabled.
 pub fn start_sending_sniffed_opt(start_chan: LoadConsumer, mut metadata: Metadata,
                                  classifier: Arc<MimeClassifier>, partial_body: &[u8],
-                                 context: LoadContext)
+                                 context: LoadContext, mime_overrides: &MimeOverrides, sniffing: MimeSniffing)
                                  -> Result<ProgressSender, ()> {
-    if PREFS.get("network.mime.sniff").as_boolean().unwrap_or(false) {
+    if let Some(mime) = mime_overrides.lookup(&metadata.final_url) {
+        metadata.content_type = Some(Serde(ContentType(mime.clone())));
+        return start_sending_opt(start_chan, metadata);
+    }
+    if sniffing.enabled {
+        if let Some(mime) = file_type_from_extension(&metadata.final_url, sniffing) {
+            metadata.content_type = Some(Serde(ContentType(mime)));
+            return start_sending_opt(start_chan, metadata);
+        }
+
         // TODO: should be calculated in the resource loader, from pull requeset #4094
         let mut no_sniff = NoSniffFlag::Off;
         let mut check_for_apache_bug = ApacheBugFlag::Off;
@@ -109,6 +200,8 @@ pub fn start_sending_sniffed_opt(start_chan: LoadConsumer, mut metadata: Metadat
                                                        &supplied_type,
                                                        &partial_body);
         let mime_tp: TopLevel = toplevel.into();
+        // The subtype may be the server's, but `SubLevel`'s parser keeps any subtype it doesn't
+        // know as `Ext`, so this can't fail.
         let mime_sb: SubLevel = sublevel.parse().unwrap();
         metadata.content_type =
             Some(Serde(ContentType(Mime(mime_tp, mime_sb, vec![]))));
@@ -117,6 +210,27 @@ pub fn start_sending_sniffed_opt(start_chan: LoadConsumer, mut metadata: Metadat
     start_sending_opt(start_chan, metadata)
 }
 
+/// The type of a `file:` URL going by its extension alone, so that local files with a
+/// well-known extension needn't be sniffed. `None` if the extension is unknown or too
+/// vague to be trusted, or if `sniffing` asks for every file to be sniffed.
+fn file_type_from_extension(url: &ServoUrl, sniffing: MimeSniffing) -> Option<Mime> {
+    if url.scheme() != "file" || sniffing.local_files {
+        return None;
+    }
+    let path = match url.to_file_path() {
+        Ok(path) => path,
+        Err(_) => return None,
+    };
+    guess_mime_type_opt(path).and_then(|mime| {
+        let vague = {
+            let Mime(ref toplevel, ref sublevel, _) = mime;
+            *toplevel == TopLevel::Text && *sublevel == SubLevel::Plain ||
+            *toplevel == TopLevel::Application && format!("{}", sublevel) == "octet-stream"
+        };
+        if vague { None } else { Some(mime) }
+    })
+}
+
 /// For use by loaders in responding to a Load message.
 /// It takes an optional NetworkError, so that we can extract the SSL Validation errors
 /// and take it to the HTML parser
@@ -136,161 +250,651 @@ fn start_sending_opt(start_chan: LoadConsumer, metadata: Metadata) -> Result<Pro
     }
 }
 
-/// Returns a tuple of (public, private) senders to the new threads.
+/// Create the public and private resource threads.
+///
+/// If `profile` is given, the persistent state is kept in `profiles/<profile>`
+/// under `config_dir` instead of directly in it.
+///
+/// `initial_cookies` are added to the public group's cookie jar after the one saved
+/// in the config directory is loaded, so that embedders can restore a session.
 pub fn new_resource_threads(user_agent: Cow<'static, str>,
                             devtools_chan: Option<Sender<DevtoolsControlMsg>>,
                             profiler_chan: ProfilerChan,
-                            config_dir: Option<PathBuf>)
-                            -> (ResourceThreads, ResourceThreads) {
-    let (public_core, private_core) = new_core_resource_thread(
+                            mem_profiler_chan: MemProfilerChan,
+                            config_dir: Option<PathBuf>,
+                            profile: Option<String>,
+                            initial_cookies: Vec<(ServoUrl, cookie_rs::Cookie)>)
+                            -> (ResourceThreads, ResourceThreads, CoreResourceControlThread) {
+    let config_dir = config_dir.map(|config_dir| {
+        profile_config_dir(&config_dir, profile.as_ref().map(Deref::deref))
+    });
+    let (public_core, private_core, control) = new_core_resource_thread(
         user_agent,
         devtools_chan,
         profiler_chan,
-        config_dir.clone());
+        config_dir.clone(),
+        None,
+        initial_cookies);
     let storage: IpcSender<StorageThreadMsg> = StorageThreadFactory::new(config_dir);
+    // Both groups share a resource manager, so it only needs to be told once.
+    public_core.send(CoreResourceMsg::StorageThread(storage.clone())).unwrap();
+
+    // The memory profiler's requests are passed on to the resource thread's own loop,
+    // so that the sizes are measured in between the messages that change them.
+    let (reporter_sender, reporter_receiver) = ipc::channel().unwrap();
+    let reports_resource_thread = public_core.clone();
+    ROUTER.add_route(reporter_receiver.to_opaque(), box move |message| {
+        let request: ReporterRequest = message.to().unwrap();
+        let msg = CoreResourceMsg::CollectMemoryReports(request.reports_channel);
+        if let Err(e) = reports_resource_thread.send(msg) {
+            warn!("Failed to ask the resource thread for memory reports ({}).", e);
+        }
+    });
+    let reporter_name = "resource-thread".to_owned();
+    mem_profiler_chan.send(ProfilerMsg::RegisterReporter(reporter_name.clone(), Reporter(reporter_sender)));
+    public_core.send(CoreResourceMsg::MemoryReporter(mem_profiler_chan, reporter_name)).unwrap();
     (ResourceThreads::new(public_core, storage.clone()),
-     ResourceThreads::new(private_core, storage))
+     ResourceThreads::new(private_core, storage),
+     control)
 }
 
 
+/// The directory holding the state of `profile`, or `config_dir` itself if no
+/// profile is in use.
+pub fn profile_config_dir(config_dir: &Path, profile: Option<&str>) -> PathBuf {
+    match profile {
+        Some(profile) => config_dir.join("profiles").join(profile),
+        None => config_dir.to_path_buf(),
+    }
+}
+
 /// Create a CoreResourceThread
+///
+/// `config_dir` and `private_config_dir` are the directories that the public and
+/// private resource groups persist their cookies, auth cache and HSTS list to.
+/// Passing `None` keeps the corresponding group in memory only.
+///
+/// `initial_cookies` are set in the public group's cookie jar, as if by HTTP responses
+/// from their URLs.
+///
+/// The `CoreResourceControlThread` takes the messages that script must not be able to
+/// send, so it should only be given to the constellation and the embedder.
 pub fn new_core_resource_thread(user_agent: Cow<'static, str>,
                                 devtools_chan: Option<Sender<DevtoolsControlMsg>>,
                                 profiler_chan: ProfilerChan,
-                                config_dir: Option<PathBuf>)
-                                -> (CoreResourceThread, CoreResourceThread) {
+                                config_dir: Option<PathBuf>,
+                                private_config_dir: Option<PathBuf>,
+                                initial_cookies: Vec<(ServoUrl, cookie_rs::Cookie)>)
+                                -> (CoreResourceThread, CoreResourceThread, CoreResourceControlThread) {
     let (public_setup_chan, public_setup_port) = ipc::channel().unwrap();
     let (private_setup_chan, private_setup_port) = ipc::channel().unwrap();
+    let (control_chan, control_port) = ipc::channel().unwrap();
     spawn_named("ResourceManager".to_owned(), move || {
         let resource_manager = CoreResourceManager::new(
-            user_agent, devtools_chan, profiler_chan
+            devtools_chan, profiler_chan
         );
 
         let mut channel_manager = ResourceChannelManager {
             resource_manager: resource_manager,
+            user_agent: user_agent,
+            private_sessions: HashMap::new(),
             config_dir: config_dir,
+            private_config_dir: private_config_dir,
         };
         channel_manager.start(public_setup_port,
-                              private_setup_port);
+                              private_setup_port,
+                              control_port,
+                              initial_cookies);
     });
-    (public_setup_chan, private_setup_chan)
+    (public_setup_chan, private_setup_chan, control_chan)
 }
 
 struct ResourceChannelManager {
     resource_manager: CoreResourceManager,
+    /// The `User-Agent` each group starts out with.
+    user_agent: Cow<'static, str>,
+    /// The groups of the private browsing sessions created at runtime.
+    private_sessions: HashMap<SessionId, ResourceGroup>,
     config_dir: Option<PathBuf>,
+    private_config_dir: Option<PathBuf>,
 }
 
-fn create_resource_groups(config_dir: Option<&Path>)
-                          -> (ResourceGroup, ResourceGroup) {
-    let mut hsts_list = HstsList::from_servo_preload();
+/// The HSTS list a group starts out with: the preload list for public browsing, and
+/// for private browsing too if the `network.private-browsing.hsts-preload` pref is set.
+fn initial_hsts_list(is_private: bool) -> HstsList {
+    if is_private && !PREFS.get("network.private-browsing.hsts-preload").as_boolean().unwrap_or(false) {
+        HstsList::new()
+    } else {
+        HstsList::from_servo_preload()
+    }
+}
+
+/// The HSTS list of `public_group` that private groups consult, if the
+/// `network.private-browsing.share-hsts` pref is set. Private groups never add to it,
+/// so that private browsing leaves no trace in public browsing.
+fn shared_hsts_list(public_group: &ResourceGroup) -> Option<Arc<RwLock<HstsList>>> {
+    if PREFS.get("network.private-browsing.share-hsts").as_boolean().unwrap_or(false) {
+        Some(public_group.hsts_list.clone())
+    } else {
+        None
+    }
+}
+
+/// The size, in bytes, that the HTTP cache is kept under, taken from the
+/// `network.http-cache.size` pref.
+fn http_cache_size() -> u64 {
+    PREFS.get("network.http-cache.size").as_u64().unwrap_or(50 * 1024 * 1024)
+}
+
+/// A memory cache with the per-entry and total size limits, in bytes, taken from the
+/// `network.memory-cache.entry-size` and `network.memory-cache.size` prefs.
+fn new_memory_cache() -> MemoryCache {
+    let max_entry_size = PREFS.get("network.memory-cache.entry-size").as_u64().unwrap_or(1024 * 1024);
+    let max_size = PREFS.get("network.memory-cache.size").as_u64().unwrap_or(32 * 1024 * 1024);
+    MemoryCache::new(max_entry_size as usize, max_size as usize)
+}
+
+/// The `Accept-Language` header for the languages listed in the `intl.accept_languages`
+/// pref at the moment, if there are any.
+fn accept_language_from_prefs() -> Option<AcceptLanguage> {
+    accept_language_from_pref_values(|name| PREFS.get(name))
+}
+
+fn create_resource_group(user_agent: Cow<'static, str>, is_private: bool, config_dir: Option<&Path>,
+                         shared_hsts_list: Option<Arc<RwLock<HstsList>>>)
+                         -> ResourceGroup {
+    let mut hsts_list = initial_hsts_list(is_private);
     let mut auth_cache = AuthCache::new();
     let mut cookie_jar = CookieStorage::new(150);
+    let mut alt_svc_cache = AltSvcCache::new();
+    let mut certificate_exceptions = CertificateExceptions::new();
     if let Some(config_dir) = config_dir {
         read_json_from_file(&mut auth_cache, config_dir, "auth_cache.json");
         read_json_from_file(&mut hsts_list, config_dir, "hsts_list.json");
+        read_json_from_file(&mut alt_svc_cache, config_dir, "alt_svc.json");
         read_json_from_file(&mut cookie_jar, config_dir, "cookie_jar.json");
+        read_json_from_file(&mut certificate_exceptions, config_dir, "certificate_exceptions.json");
     }
-    let resource_group = ResourceGroup {
+    let hsts_list = Arc::new(RwLock::new(hsts_list));
+    let certificate_exceptions = Arc::new(RwLock::new(certificate_exceptions));
+    let net_stats = Arc::new(NetStats::new());
+    let connection_pools = ConnectionPools::new().with_certificate_exceptions(
+        CertificateExceptionCheck::new(certificate_exceptions.clone(), hsts_list.clone())
+            .with_shared_hsts_list(shared_hsts_list.clone()))
+        .with_tls_session_cache(Arc::new(TlsSessionCache::new(net_stats.clone())));
+    // Private browsing must not leave anything on disk, so it never gets a cache.
+    let http_cache = match config_dir {
+        Some(config_dir) if !is_private => {
+            Some(Arc::new(RwLock::new(HttpCache::new(config_dir.join("cache"), http_cache_size()))))
+        }
+        _ => None,
+    };
+    ResourceGroup {
         cookie_jar: Arc::new(RwLock::new(cookie_jar)),
+        cookie_policy: Arc::new(RwLock::new(CookieAcceptPolicy::All)),
         auth_cache: Arc::new(RwLock::new(auth_cache)),
-        hsts_list: Arc::new(RwLock::new(hsts_list.clone())),
-        connector: create_http_connector(),
-    };
-    let private_resource_group = ResourceGroup {
-        cookie_jar: Arc::new(RwLock::new(CookieStorage::new(150))),
-        auth_cache: Arc::new(RwLock::new(AuthCache::new())),
-        hsts_list: Arc::new(RwLock::new(HstsList::new())),
-        connector: create_http_connector(),
-    };
+        digest_auth: Arc::new(RwLock::new(DigestAuthCache::new())),
+        hsts_list: hsts_list,
+        shared_hsts_list: shared_hsts_list,
+        url_rewriter: Arc::new(RwLock::new(UrlRewriter::new(vec![]))),
+        mime_overrides: Arc::new(RwLock::new(MimeOverrides::default())),
+        connection_pools: Arc::new(connection_pools),
+        alt_svc_cache: Arc::new(RwLock::new(alt_svc_cache)),
+        certificate_exceptions: certificate_exceptions,
+        user_agent: Arc::new(RwLock::new(user_agent)),
+        accept_language: Arc::new(RwLock::new(None)),
+        net_stats: net_stats,
+        http_cache: http_cache,
+        memory_cache: Arc::new(RwLock::new(new_memory_cache())),
+        cors_cache: Arc::new(RwLock::new(CorsCache::new())),
+        is_private: is_private,
+        session_id: None,
+        config_dir: config_dir.map(Path::to_path_buf),
+    }
+}
+
+/// The number of requests that can be made at once to the origin of `url` over the
+/// HTTP/2 session `group` has with it, if there is one.
+fn http2_streams_allowed(url: &ServoUrl, group: &ResourceGroup) -> Option<usize> {
+    match (url.scheme(), url.host_str(), url.port_or_known_default()) {
+        ("https", Some(host), Some(port)) => group.connection_pools.max_concurrent_streams(host, port),
+        _ => None,
+    }
+}
+
+/// Whether the group's cookie policy lets `url` store cookies while the user is on
+/// `first_party`.
+fn cookies_allowed(url: &ServoUrl, first_party: Option<&ServoUrl>, group: &ResourceGroup) -> bool {
+    let policy = *read_lock(&group.cookie_policy, "cookie policy");
+    let third_party = first_party.map(|first_party| cookie::Cookie::is_third_party(url, first_party));
+    cookie::Cookie::policy_allows(policy, third_party)
+}
+
+/// Store the cookies of the `Set-Cookie` value `cookie_list` set by `request`.
+fn store_cookies(cookie_jar: &mut CookieStorage, request: &ServoUrl, cookie_list: String, source: CookieSource,
+                 net_stats: &NetStats) {
+    let header = Header::parse_header(&[cookie_list.into_bytes()]);
+    if let Ok(SetCookie(cookies)) = header {
+        for bare_cookie in cookies {
+            if let Some(cookie) = cookie::Cookie::new_wrapped(bare_cookie, request, source) {
+                net_stats.evicted_cookies(cookie_jar.push(cookie, source));
+            }
+        }
+    }
+}
+
+fn create_resource_groups(user_agent: Cow<'static, str>,
+                          config_dir: Option<&Path>,
+                          private_config_dir: Option<&Path>,
+                          initial_cookies: Vec<(ServoUrl, cookie_rs::Cookie)>)
+                          -> (ResourceGroup, ResourceGroup) {
+    let resource_group = create_resource_group(user_agent.clone(), false, config_dir, None);
+    {
+        let mut cookie_jar = write_lock(&resource_group.cookie_jar, "cookie jar");
+        for (url, cookie) in initial_cookies {
+            if let Some(cookie) = cookie::Cookie::new_wrapped(cookie, &url, CookieSource::HTTP) {
+                resource_group.net_stats.evicted_cookies(cookie_jar.push(cookie, CookieSource::HTTP));
+            }
+        }
+    }
+    let private_resource_group =
+        create_resource_group(user_agent, true, private_config_dir, shared_hsts_list(&resource_group));
     (resource_group, private_resource_group)
 }
 
+impl ResourceGroup {
+    /// The `Accept-Language` to send with this group's requests, if any.
+    fn accept_language(&self) -> Option<AcceptLanguage> {
+        read_lock(&self.accept_language, "accept language").clone().unwrap_or_else(accept_language_from_prefs)
+    }
+
+    /// Estimates of the memory held by each of this group's stores, reported under `name`.
+    fn memory_reports(&self, name: &str) -> Vec<Report> {
+        let report = |store: &str, size: usize| Report {
+            path: path!["network", name, store],
+            kind: ReportKind::ExplicitJemallocHeapSize,
+            size: size,
+        };
+        vec![
+            report("cookie-jar", read_lock(&self.cookie_jar, "cookie jar").estimated_size()),
+            report("hsts-list", read_lock(&self.hsts_list, "HSTS list").estimated_size()),
+            report("auth-cache", read_lock(&self.auth_cache, "auth cache").estimated_size()),
+            report("connection-pools", self.connection_pools.estimated_size()),
+            report("alt-svc-cache", read_lock(&self.alt_svc_cache, "Alt-Svc cache").estimated_size()),
+            report("certificate-exceptions",
+                   read_lock(&self.certificate_exceptions, "certificate exceptions").estimated_size()),
+            report("memory-cache", read_lock(&self.memory_cache, "memory cache").estimated_size()),
+        ]
+    }
+}
+
+/// Write the persistent state of `group` to its own config directory, if it has one.
+fn write_resource_group(group: &ResourceGroup) {
+    if let Some(ref config_dir) = group.config_dir {
+        let auth_cache = read_lock(&group.auth_cache, "auth cache");
+        write_json_to_file(&*auth_cache, config_dir, "auth_cache.json");
+        let jar = read_lock(&group.cookie_jar, "cookie jar");
+        write_json_to_file(&*jar, config_dir, "cookie_jar.json");
+        let hsts = read_lock(&group.hsts_list, "HSTS list");
+        write_json_to_file(&*hsts, config_dir, "hsts_list.json");
+        let alt_svc_cache = read_lock(&group.alt_svc_cache, "Alt-Svc cache");
+        write_json_to_file(&*alt_svc_cache, config_dir, "alt_svc.json");
+        let certificate_exceptions = read_lock(&group.certificate_exceptions, "certificate exceptions");
+        write_json_to_file(&certificate_exceptions.permanent(), config_dir, "certificate_exceptions.json");
+    }
+    if let Some(ref http_cache) = group.http_cache {
+        read_lock(http_cache, "HTTP cache").write_index();
+    }
+}
+
 impl ResourceChannelManager {
     #[allow(unsafe_code)]
     fn start(&mut self,
              public_receiver: IpcReceiver<CoreResourceMsg>,
-             private_receiver: IpcReceiver<CoreResourceMsg>) {
+             private_receiver: IpcReceiver<CoreResourceMsg>,
+             control_receiver: IpcReceiver<CoreResourceControlMsg>,
+             initial_cookies: Vec<(ServoUrl, cookie_rs::Cookie)>) {
         let (public_resource_group, private_resource_group) =
-            create_resource_groups(self.config_dir.as_ref().map(Deref::deref));
+            create_resource_groups(self.user_agent.clone(),
+                                   self.config_dir.as_ref().map(Deref::deref),
+                                   self.private_config_dir.as_ref().map(Deref::deref),
+                                   initial_cookies);
+        let groups = [public_resource_group, private_resource_group];
 
         let mut rx_set = IpcReceiverSet::new().unwrap();
         let private_id = rx_set.add(private_receiver).unwrap();
         let public_id = rx_set.add(public_receiver).unwrap();
+        let control_id = rx_set.add(control_receiver).unwrap();
 
+        let mut select_failures = 0;
         loop {
-            for (id, data) in rx_set.select().unwrap().into_iter().map(|m| m.unwrap()) {
+            // A message that can't be received or decoded only loses that message; the
+            // other tabs still need the resource thread. A set of channels that keeps
+            // failing to select won't start working again, though.
+            let results = match rx_set.select() {
+                Ok(results) => results,
+                Err(e) => {
+                    warn!("Failed to receive a resource thread message ({:?}).", e);
+                    select_failures += 1;
+                    if select_failures >= MAX_SELECT_FAILURES {
+                        error!("Giving up on the resource thread channels.");
+                        self.exit(&groups);
+                        return;
+                    }
+                    continue;
+                }
+            };
+            select_failures = 0;
+            for result in results {
+                let (id, data) = match result {
+                    IpcSelectionResult::MessageReceived(id, data) => (id, data),
+                    // Once every sender of the public channel is gone, nothing can ask for
+                    // anything again. The others closing leaves the rest of them working.
+                    IpcSelectionResult::ChannelClosed(id) if id == public_id => {
+                        debug!("The public resource thread channel closed.");
+                        self.exit(&groups);
+                        return;
+                    }
+                    IpcSelectionResult::ChannelClosed(id) => {
+                        debug!("Resource thread channel {} closed.", id);
+                        continue;
+                    }
+                };
+                if id == control_id {
+                    match data.to() {
+                        Ok(msg) => self.process_control_msg(msg, &groups),
+                        Err(e) => warn!("Dropping a malformed resource control message ({:?}).", e),
+                    }
+                    continue;
+                }
                 let group = if id == private_id {
-                    &private_resource_group
+                    &groups[1]
                 } else {
                     assert_eq!(id, public_id);
-                    &public_resource_group
+                    &groups[0]
                 };
-                if let Ok(msg) = data.to() {
-                    if !self.process_msg(msg, group) {
-                        return;
+                match data.to() {
+                    Ok(msg) => {
+                        if !self.process_msg(msg, group, &groups) {
+                            return;
+                        }
                     }
+                    Err(e) => warn!("Dropping a malformed resource thread message ({:?}).", e),
                 }
             }
         }
     }
 
+    fn collect_memory_reports(&self, reports_chan: ReportsChan, all_groups: &[ResourceGroup]) {
+        let mut reports = all_groups[0].memory_reports("public");
+        reports.extend(all_groups[1].memory_reports("private"));
+        for (session_id, group) in &self.private_sessions {
+            reports.extend(group.memory_reports(&format!("private-session-{}", session_id.0)));
+        }
+        reports.push(Report {
+            path: path!["network", "file-manager"],
+            kind: ReportKind::ExplicitJemallocHeapSize,
+            size: self.resource_manager.filemanager.estimated_size(),
+        });
+        reports_chan.send(reports);
+    }
+
+    /// The group `id` names, unless it is a private session that doesn't exist.
+    fn group_by_id(&self, id: ResourceGroupId, all_groups: &[ResourceGroup]) -> Option<ResourceGroup> {
+        match id {
+            ResourceGroupId::Public => Some(all_groups[0].clone()),
+            ResourceGroupId::Private => Some(all_groups[1].clone()),
+            ResourceGroupId::Session(session_id) => self.private_sessions.get(&session_id).cloned(),
+        }
+    }
+
+    /// Handle a message from the constellation or the embedder, which concerns the
+    /// group it names, or the public group if it names none.
+    fn process_control_msg(&mut self, msg: CoreResourceControlMsg, all_groups: &[ResourceGroup]) {
+        let public_group = &all_groups[0];
+        match msg {
+            CoreResourceControlMsg::DumpCookieJar(consumer) => {
+                let cookie_jar = read_lock(&public_group.cookie_jar, "cookie jar");
+                let _ = consumer.send(json::encode(&*cookie_jar).unwrap_or_else(|why| {
+                    warn!("Could not encode the cookie jar: {}", why);
+                    String::new()
+                }));
+            }
+            CoreResourceControlMsg::RestoreCookieJar(dump) => match json::decode::<CookieStorage>(&dump) {
+                Ok(cookie_jar) => *write_lock(&public_group.cookie_jar, "cookie jar") = cookie_jar,
+                Err(why) => warn!("Could not decode the cookie jar: {}", why),
+            },
+            CoreResourceControlMsg::InheritPipelineOrigin(pipeline_id, parent_id) =>
+                self.resource_manager.pipeline_origins.lock().unwrap().inherit(pipeline_id, parent_id),
+            CoreResourceControlMsg::CancelAll(Some(pipeline_id)) => {
+                self.resource_manager.cancel_fetches(|fetch| fetch.pipeline_id == Some(pipeline_id));
+                self.resource_manager.pipeline_origins.lock().unwrap().remove(pipeline_id);
+            }
+            CoreResourceControlMsg::CancelAll(None) =>
+                self.resource_manager.cancel_fetches(|_| true),
+            CoreResourceControlMsg::FetchToFile { group, mut init, path, reply } => {
+                match self.group_by_id(group, all_groups) {
+                    Some(group) => {
+                        // A download is written to disk as it arrives, so it may be of any size.
+                        init.unbounded_body = true;
+                        let download = self.resource_manager.filemanager.download(path, reply);
+                        self.resource_manager.fetch(init, download, &group);
+                    }
+                    None => warn!("Dropping download for unknown resource group {:?}", group),
+                }
+            }
+            CoreResourceControlMsg::SetUrlRewriteRules(group, rules) => match self.group_by_id(group, all_groups) {
+                Some(group) => *write_lock(&group.url_rewriter, "URL rewriter") = UrlRewriter::new(rules),
+                None => warn!("Dropping URL rewrite rules for unknown resource group {:?}", group),
+            },
+            CoreResourceControlMsg::SetMimeOverrides(group, overrides) => match self.group_by_id(group, all_groups) {
+                Some(group) => *write_lock(&group.mime_overrides, "MIME overrides") = MimeOverrides::new(overrides),
+                None => warn!("Dropping MIME overrides for unknown resource group {:?}", group),
+            },
+            CoreResourceControlMsg::AddCertificateException { group, host, port, cert_fingerprint, permanent } => {
+                let exception = CertificateException {
+                    host: host,
+                    port: port,
+                    cert_fingerprint: cert_fingerprint,
+                    permanent: permanent,
+                };
+                match self.group_by_id(group, all_groups) {
+                    Some(group) => write_lock(&group.certificate_exceptions, "certificate exceptions").add(exception),
+                    None => warn!("Dropping certificate exception for unknown resource group {:?}", group),
+                }
+            }
+            CoreResourceControlMsg::RemoveCertificateException { group, host, port } => {
+                match self.group_by_id(group, all_groups) {
+                    Some(group) => {
+                        let removed = write_lock(&group.certificate_exceptions, "certificate exceptions")
+                            .remove(&host, port);
+                        // The connections and TLS sessions started under the exception mustn't be
+                        // used any more.
+                        if removed {
+                            group.connection_pools.clear_host(&host);
+                            group.connection_pools.clear_tls_sessions(&host);
+                        }
+                    }
+                    None => warn!("Dropping certificate exception for unknown resource group {:?}", group),
+                }
+            }
+            CoreResourceControlMsg::SetAuthPromptChannel(auth_prompt) => {
+                self.resource_manager.auth_prompt = Some(auth_prompt)
+            }
+            CoreResourceControlMsg::RegisterSchemeHandler { scheme, handler } => {
+                self.resource_manager.register_scheme_handler(scheme, handler)
+            }
+            CoreResourceControlMsg::Synchronize(sender) => {
+                let _ = sender.send(());
+            }
+        }
+    }
+
     /// Returns false if the thread should exit.
     fn process_msg(&mut self,
                    msg: CoreResourceMsg,
-                   group: &ResourceGroup) -> bool {
+                   group: &ResourceGroup,
+                   all_groups: &[ResourceGroup]) -> bool {
         match msg {
             CoreResourceMsg::Fetch(init, sender) =>
                 self.resource_manager.fetch(init, sender, group),
+            CoreResourceMsg::Preconnect(url, done) =>
+                self.resource_manager.preconnect(url, done, group),
             CoreResourceMsg::WebsocketConnect(connect, connect_data) =>
                 self.resource_manager.websocket_connect(connect, connect_data, group),
-            CoreResourceMsg::SetCookiesForUrl(request, cookie_list, source) =>
-                self.resource_manager.set_cookies_for_url(request, cookie_list, source, group),
-            CoreResourceMsg::SetCookiesForUrlWithData(request, cookie, source) =>
-                self.resource_manager.set_cookies_for_url_with_data(request, cookie, source, group),
-            CoreResourceMsg::GetCookiesForUrl(url, consumer, source) => {
-                let mut cookie_jar = group.cookie_jar.write().unwrap();
-                consumer.send(cookie_jar.cookies_for_url(&url, source)).unwrap();
+            CoreResourceMsg::SetCookiesForUrl(request, cookie_list, source, first_party) =>
+                self.resource_manager.set_cookies_for_url(request, cookie_list, source, first_party, group),
+            CoreResourceMsg::SetCookiesBatch(batch) =>
+                self.resource_manager.set_cookies_batch(batch, group),
+            CoreResourceMsg::SetCookiesForUrlWithData(request, cookie, source, first_party) =>
+                self.resource_manager.set_cookies_for_url_with_data(request, cookie, source, first_party, group),
+            CoreResourceMsg::SetUserAgent(user_agent) => {
+                *write_lock(&group.user_agent, "user agent") = user_agent;
+            }
+            CoreResourceMsg::SetAcceptLanguage(languages) => {
+                *write_lock(&group.accept_language, "accept language") = Some(accept_language_header(&languages));
+            }
+            CoreResourceMsg::SetCookieAcceptPolicy(policy) => {
+                *write_lock(&group.cookie_policy, "cookie policy") = policy;
+            }
+            CoreResourceMsg::GetCookiesForUrl(url, consumer, source, context) => {
+                let mut cookie_jar = write_lock(&group.cookie_jar, "cookie jar");
+                consumer.send(cookie_jar.cookies_for_url(&url, source, context)).unwrap();
             }
             CoreResourceMsg::NetworkMediator(mediator_chan) => {
                 self.resource_manager.swmanager_chan = Some(mediator_chan)
             }
+            CoreResourceMsg::StorageThread(storage_thread) => {
+                self.resource_manager.storage_thread = Some(storage_thread)
+            }
+            CoreResourceMsg::MemoryReporter(mem_profiler_chan, reporter_name) => {
+                self.resource_manager.memory_reporter = Some((mem_profiler_chan, reporter_name))
+            }
             CoreResourceMsg::GetCookiesDataForUrl(url, consumer, source) => {
-                let mut cookie_jar = group.cookie_jar.write().unwrap();
+                let mut cookie_jar = write_lock(&group.cookie_jar, "cookie jar");
                 let cookies = cookie_jar.cookies_data_for_url(&url, source).map(Serde).collect();
                 consumer.send(cookies).unwrap();
             }
-            CoreResourceMsg::Cancel(res_id) => {
-                if let Some(cancel_sender) = self.resource_manager.cancel_load_map.get(&res_id) {
-                    let _ = cancel_sender.send(());
+            CoreResourceMsg::GetCookiesDetailedForUrl(url, consumer, source) => {
+                let cookie_jar = read_lock(&group.cookie_jar, "cookie jar");
+                let cookies = cookie_jar.cookies_details_for_url(&url, source).collect();
+                let _ = consumer.send(cookies);
+            }
+            CoreResourceMsg::Cancel(res_id) =>
+                self.resource_manager.cancel_fetches(|fetch| fetch.resource_id == Some(res_id)),
+            CoreResourceMsg::AckResponseBody(res_id, len) =>
+                self.resource_manager.ack_response_body(res_id, len),
+            CoreResourceMsg::SetHstsEntryForHost(host, include_subdomains, max_age) => {
+                if let Some(entry) = HstsEntry::new(host, include_subdomains, Some(max_age)) {
+                    write_lock(&group.hsts_list, "HSTS list").push(entry);
+                }
+            }
+            CoreResourceMsg::ResetHsts => {
+                let hsts_list = initial_hsts_list(group.is_private);
+                *write_lock(&group.hsts_list, "HSTS list") = hsts_list;
+            }
+            CoreResourceMsg::GetCertificateExceptions(sender) => {
+                let _ = sender.send(read_lock(&group.certificate_exceptions, "certificate exceptions").list());
+            }
+            CoreResourceMsg::CloseIdleConnections(host) => match host {
+                Some(host) => group.connection_pools.clear_host(&host),
+                None => group.connection_pools.clear(),
+            },
+            CoreResourceMsg::ClearAuthCache(host) => {
+                let mut auth_cache = write_lock(&group.auth_cache, "auth cache");
+                match host {
+                    Some(ref host) => auth_cache.clear_host(host),
+                    None => auth_cache.entries.clear(),
+                }
+                group.connection_pools.proxy_credentials().clear(host.as_ref().map(|host| &**host));
+                let mut digest_auth = write_lock(&group.digest_auth, "Digest auth");
+                match host {
+                    Some(host) => digest_auth.clear_host(&host),
+                    None => digest_auth.clear(),
+                }
+            }
+            CoreResourceMsg::ClearSiteData(host) => {
+                write_lock(&group.cookie_jar, "cookie jar").clear_host(&host);
+                if let Some(ref http_cache) = group.http_cache {
+                    write_lock(http_cache, "HTTP cache").clear_host(&host);
+                }
+                write_lock(&group.memory_cache, "memory cache").clear_host(&host);
+                write_lock(&group.alt_svc_cache, "Alt-Svc cache").clear_host(&host);
+                group.connection_pools.clear_host(&host);
+                group.connection_pools.clear_tls_sessions(&host);
+            }
+            CoreResourceMsg::GetNetworkStats(sender, reset) => {
+                let (active, queued, active_per_host) = {
+                    let limiter = self.resource_manager.connection_limiter.lock().unwrap();
+                    let (active, queued) = limiter.stats();
+                    (active, queued, limiter.active_per_host())
+                };
+                let mut groups = vec![("public".to_owned(), &all_groups[0]), ("private".to_owned(), &all_groups[1])];
+                groups.extend(self.private_sessions.iter().map(|(session_id, group)| {
+                    (format!("private-session-{}", session_id.0), group)
+                }));
+                let per_group = groups.into_iter().map(|(name, group)| {
+                    let snapshot = group.net_stats.snapshot();
+                    if reset {
+                        group.net_stats.reset();
+                    }
+                    (name, snapshot)
+                }).collect();
+                let _ = sender.send(NetworkStats {
+                    active_per_origin: active,
+                    queued_per_origin: queued,
+                    active_per_host: active_per_host,
+                    per_group: per_group,
+                });
+            }
+            CoreResourceMsg::CreatePrivateSession(session_id) => {
+                let user_agent = self.user_agent.clone();
+                self.private_sessions.entry(session_id).or_insert_with(|| ResourceGroup {
+                    session_id: Some(session_id),
+                    .. create_resource_group(user_agent, true, None, shared_hsts_list(&all_groups[0]))
+                });
+            }
+            CoreResourceMsg::RemovePrivateSession(session_id) => {
+                if self.private_sessions.remove(&session_id).is_some() {
+                    self.resource_manager.cancel_fetches(|fetch| fetch.session_id == Some(session_id));
                 }
-                self.resource_manager.cancel_load_map.remove(&res_id);
+            }
+            CoreResourceMsg::ForSession(session_id, msg) => {
+                match self.private_sessions.get(&session_id).cloned() {
+                    Some(session_group) => return self.process_msg(*msg, &session_group, all_groups),
+                    None => warn!("Dropping message for unknown private session {:?}", session_id),
+                }
+            }
+            CoreResourceMsg::CollectMemoryReports(reports_chan) => {
+                self.collect_memory_reports(reports_chan, all_groups);
             }
             CoreResourceMsg::Synchronize(sender) => {
                 let _ = sender.send(());
             }
-            CoreResourceMsg::ToFileManager(msg) => self.resource_manager.filemanager.handle(msg, TFD_PROVIDER),
+            CoreResourceMsg::ToFileManager(msg) => {
+                let _ = self.resource_manager.filemanager_chan.send(msg);
+            }
             CoreResourceMsg::Exit(sender) => {
-                if let Some(ref config_dir) = self.config_dir {
-                    match group.auth_cache.read() {
-                        Ok(auth_cache) => write_json_to_file(&*auth_cache, config_dir, "auth_cache.json"),
-                        Err(_) => warn!("Error writing auth cache to disk"),
-                    }
-                    match group.cookie_jar.read() {
-                        Ok(jar) => write_json_to_file(&*jar, config_dir, "cookie_jar.json"),
-                        Err(_) => warn!("Error writing cookie jar to disk"),
-                    }
-                    match group.hsts_list.read() {
-                        Ok(hsts) => write_json_to_file(&*hsts, config_dir, "hsts_list.json"),
-                        Err(_) => warn!("Error writing hsts list to disk"),
-                    }
-                }
+                self.exit(all_groups);
                 let _ = sender.send(());
                 return false;
             }
         }
         true
     }
+
+    /// Let the running fetches finish and save the state of every group, before the
+    /// thread exits.
+    fn exit(&mut self, all_groups: &[ResourceGroup]) {
+        // Nothing is left to answer the memory profiler's requests.
+        if let Some((mem_profiler_chan, reporter_name)) = self.resource_manager.memory_reporter.take() {
+            mem_profiler_chan.send(ProfilerMsg::UnregisterReporter(reporter_name));
+        }
+        self.resource_manager.drain_fetches();
+        for group in all_groups {
+            write_resource_group(group);
+        }
+    }
 }
 
 pub fn read_json_from_file<T>(data: &mut T, config_dir: &Path, filename: &str)
@@ -330,6 +934,9 @@ pub fn write_json_to_file<T>(data: &T, config_dir: &Path, filename: &str)
         Ok(d) => json_encoded = d,
         Err(_) => return,
     }
+    if let Err(why) = fs::create_dir_all(config_dir) {
+        panic!("couldn't create {}: {}", config_dir.display(), Error::description(&why));
+    }
     let path = config_dir.join(filename);
     let display = path.display();
 
@@ -362,6 +969,26 @@ impl AuthCache {
             entries: HashMap::new()
         }
     }
+
+    /// Forget the credentials for every origin with `host`.
+    pub fn clear_host(&mut self, host: &str) {
+        let keys: Vec<_> = self.entries.keys().filter(|origin| {
+            ServoUrl::parse(origin).ok().and_then(|origin| origin.host_str().map(|origin_host| {
+                origin_host.eq_ignore_ascii_case(host)
+            })).unwrap_or(false)
+        }).cloned().collect();
+        for key in keys {
+            self.entries.remove(&key);
+        }
+    }
+
+    /// An estimate of the heap memory held by the cache, for memory reports.
+    pub fn estimated_size(&self) -> usize {
+        self.entries.iter().map(|(origin, entry)| {
+            origin.capacity() + mem::size_of::<AuthCacheEntry>() +
+            entry.user_name.capacity() + entry.password.capacity()
+        }).sum()
+    }
 }
 
 #[derive(RustcDecodable, RustcEncodable, Clone)]
@@ -370,24 +997,197 @@ pub struct AuthCache {
     pub entries: HashMap<String, AuthCacheEntry>,
 }
 
+/// A fetch that hasn't finished yet, and the means to cancel it.
+struct InFlightFetch {
+    resource_id: Option<ResourceId>,
+    pipeline_id: Option<PipelineId>,
+    session_id: Option<SessionId>,
+    cancel_sender: Sender<()>,
+    /// Forwards `AckResponseBody` messages, if the fetch is flow controlled.
+    ack_sender: Option<Sender<usize>>,
+}
+
+/// Counts the fetches that have been started but haven't finished, so that exit
+/// can wait for them.
+struct FetchCounter {
+    count: Mutex<usize>,
+    all_finished: Condvar,
+}
+
+/// Counts a fetch as running until it is dropped, however the fetch ends.
+struct RunningFetch(Arc<FetchCounter>);
+
+impl FetchCounter {
+    fn new() -> FetchCounter {
+        FetchCounter {
+            count: Mutex::new(0),
+            all_finished: Condvar::new(),
+        }
+    }
+
+    fn start(counter: &Arc<FetchCounter>) -> RunningFetch {
+        *counter.count.lock().unwrap() += 1;
+        RunningFetch(counter.clone())
+    }
+
+    /// Wait for every running fetch to finish, for at most `timeout`. On timeout,
+    /// returns the number still running.
+    fn wait_until_finished(&self, timeout: Duration) -> Result<(), usize> {
+        let deadline = Instant::now() + timeout;
+        let mut count = self.count.lock().unwrap();
+        while *count > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(*count);
+            }
+            count = self.all_finished.wait_timeout(count, deadline - now).unwrap().0;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RunningFetch {
+    fn drop(&mut self) {
+        // This may run while a fetch is unwinding from a panic.
+        let mut count = self.0.count.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *count -= 1;
+        if *count == 0 {
+            self.0.all_finished.notify_all();
+        }
+    }
+}
+
 pub struct CoreResourceManager {
-    user_agent: Cow<'static, str>,
     devtools_chan: Option<Sender<DevtoolsControlMsg>>,
     swmanager_chan: Option<IpcSender<CustomResponseMediator>>,
+    /// The storage thread, once it has been sent, for `Clear-Site-Data` responses to clear.
+    storage_thread: Option<IpcSender<StorageThreadMsg>>,
+    /// Where the embedder is asked for credentials, if anywhere.
+    auth_prompt: Option<IpcSender<AuthPromptRequest>>,
+    /// The memory profiler this thread is registered with, and the name it reports under.
+    memory_reporter: Option<(MemProfilerChan, String)>,
     filemanager: FileManager,
-    cancel_load_map: HashMap<ResourceId, Sender<()>>,
+    /// Messages for `filemanager`, handled in order on a thread of their own so that blob
+    /// reads and file dialogs don't hold up the resource loop.
+    filemanager_chan: Sender<FileManagerThreadMsg>,
+    /// Every fetch that is still running, keyed by an id local to this manager.
+    /// Entries are removed by the fetch itself once it completes.
+    in_flight_fetches: Arc<Mutex<HashMap<u32, InFlightFetch>>>,
+    next_fetch_id: u32,
+    /// The workers that run fetches, so that a page with many subresources
+    /// doesn't spawn an OS thread per request.
+    fetch_scheduler: FetchScheduler,
+    /// Keeps the number of concurrent HTTP fetches to any one host in check.
+    connection_limiter: Arc<Mutex<ConnectionLimiter>>,
+    /// The origin of the document in each pipeline, which its requests are checked against.
+    pipeline_origins: Arc<Mutex<PipelineOrigins>>,
+    /// Unlike `in_flight_fetches`, this still counts cancelled fetches until they stop.
+    running_fetches: Arc<FetchCounter>,
+    profiler_chan: ProfilerChan,
+    scheme_handlers: Arc<RwLock<SchemeHandlers>>,
+}
+
+/// The schemes fetch handles itself, which embedders can't register handlers for.
+const BUILTIN_SCHEMES: [&'static str; 6] = ["about", "blob", "data", "file", "http", "https"];
+
+/// Report `error` to the consumer of a fetch that was never started.
+fn end_with_network_error(target: &mut Target, error: NetworkError) {
+    let response = Response::network_error(error);
+    if let Some(ref mut target) = *target {
+        target.process_response(&response);
+        target.process_response_eof(&response);
+    }
+}
+
+/// The number of fetch workers, taken from the `network.fetch.pool-size` pref.
+fn fetch_pool_size() -> usize {
+    match PREFS.get("network.fetch.pool-size").as_u64() {
+        Some(size) if size > 0 => size as usize,
+        _ => 16,
+    }
 }
 
 impl CoreResourceManager {
-    pub fn new(user_agent: Cow<'static, str>,
-               devtools_channel: Option<Sender<DevtoolsControlMsg>>,
-               _profiler_chan: ProfilerChan) -> CoreResourceManager {
+    pub fn new(devtools_channel: Option<Sender<DevtoolsControlMsg>>,
+               profiler_chan: ProfilerChan) -> CoreResourceManager {
+        let filemanager = FileManager::new();
+        let (filemanager_chan, filemanager_port) = channel();
+        let worker_filemanager = filemanager.clone();
+        spawn_named("FileManager".to_owned(), move || {
+            // Ends once the manager, and with it the sender, is dropped.
+            for msg in filemanager_port.iter() {
+                worker_filemanager.handle(msg, TFD_PROVIDER);
+            }
+        });
         CoreResourceManager {
-            user_agent: user_agent,
             devtools_chan: devtools_channel,
             swmanager_chan: None,
-            filemanager: FileManager::new(),
-            cancel_load_map: HashMap::new(),
+            storage_thread: None,
+            auth_prompt: None,
+            memory_reporter: None,
+            filemanager: filemanager,
+            filemanager_chan: filemanager_chan,
+            in_flight_fetches: Arc::new(Mutex::new(HashMap::new())),
+            next_fetch_id: 0,
+            fetch_scheduler: FetchScheduler::new(fetch_pool_size()),
+            connection_limiter: Arc::new(Mutex::new(ConnectionLimiter::new())),
+            pipeline_origins: Arc::new(Mutex::new(PipelineOrigins::new())),
+            running_fetches: Arc::new(FetchCounter::new()),
+            profiler_chan: profiler_chan,
+            scheme_handlers: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Have `handler` answer the fetches of URLs with `scheme` from now on.
+    fn register_scheme_handler(&mut self, scheme: String, handler: IpcSender<SchemeRequest>) {
+        let scheme = scheme.to_ascii_lowercase();
+        if BUILTIN_SCHEMES.contains(&&*scheme) {
+            return warn!("Ignoring a handler for the built-in {} scheme", scheme);
+        }
+        write_lock(&self.scheme_handlers, "scheme handlers").insert(scheme, handler);
+    }
+
+    /// Signal every in-flight fetch matching `predicate` to stop, and forget about it.
+    fn cancel_fetches<F>(&mut self, predicate: F)
+        where F: Fn(&InFlightFetch) -> bool
+    {
+        let mut in_flight_fetches = self.in_flight_fetches.lock().unwrap();
+        let cancelled: Vec<u32> = in_flight_fetches.iter()
+                                                   .filter(|&(_, fetch)| predicate(fetch))
+                                                   .map(|(id, _)| *id)
+                                                   .collect();
+        for id in &cancelled {
+            if let Some(fetch) = in_flight_fetches.remove(id) {
+                let _ = fetch.cancel_sender.send(());
+            }
+        }
+        drop(in_flight_fetches);
+        // Fetches still waiting for a connection are dropped from their queue
+        // and told straight away that they were cancelled.
+        for id in cancelled {
+            let job = self.connection_limiter.lock().unwrap().remove_queued(id);
+            if let Some(job) = job {
+                job.call_box(true);
+            }
+        }
+    }
+
+    /// Pass on a consumer's acknowledgement of response body bytes to the fetch it belongs to.
+    fn ack_response_body(&self, resource_id: ResourceId, len: usize) {
+        let in_flight_fetches = self.in_flight_fetches.lock().unwrap();
+        let fetch = in_flight_fetches.values().find(|fetch| fetch.resource_id == Some(resource_id));
+        if let Some(ack_sender) = fetch.and_then(|fetch| fetch.ack_sender.as_ref()) {
+            let _ = ack_sender.send(len);
+        }
+    }
+
+    /// Cancel every fetch, and wait for them to stop, for at most the time given by
+    /// the `network.fetch.exit-timeout-ms` pref.
+    fn drain_fetches(&mut self) {
+        self.cancel_fetches(|_| true);
+        let timeout = PREFS.get("network.fetch.exit-timeout-ms").as_u64().unwrap_or(2000);
+        if let Err(count) = self.running_fetches.wait_until_finished(Duration::from_millis(timeout)) {
+            warn!("{} fetches still running after {}ms at exit", count, timeout);
         }
     }
 
@@ -395,60 +1195,242 @@ impl CoreResourceManager {
                            request: ServoUrl,
                            cookie_list: String,
                            source: CookieSource,
+                           first_party: Option<ServoUrl>,
                            resource_group: &ResourceGroup) {
-        let header = Header::parse_header(&[cookie_list.into_bytes()]);
-        if let Ok(SetCookie(cookies)) = header {
-            for bare_cookie in cookies {
-                if let Some(cookie) = cookie::Cookie::new_wrapped(bare_cookie, &request, source) {
-                    let mut cookie_jar = resource_group.cookie_jar.write().unwrap();
-                    cookie_jar.push(cookie, source);
-                }
-            }
+        if !cookies_allowed(&request, first_party.as_ref(), resource_group) {
+            return;
+        }
+        let mut cookie_jar = write_lock(&resource_group.cookie_jar, "cookie jar");
+        store_cookies(&mut cookie_jar, &request, cookie_list, source, &resource_group.net_stats);
+    }
+
+    /// Like `set_cookies_for_url` for each of `batch`, with the cookie jar only locked once.
+    fn set_cookies_batch(&mut self,
+                         batch: Vec<(ServoUrl, String, CookieSource, Option<ServoUrl>)>,
+                         resource_group: &ResourceGroup) {
+        let batch: Vec<_> = batch.into_iter().filter(|&(ref request, _, _, ref first_party)| {
+            cookies_allowed(request, first_party.as_ref(), resource_group)
+        }).collect();
+        if batch.is_empty() {
+            return;
+        }
+        let mut cookie_jar = write_lock(&resource_group.cookie_jar, "cookie jar");
+        for (request, cookie_list, source, _) in batch {
+            store_cookies(&mut cookie_jar, &request, cookie_list, source, &resource_group.net_stats);
         }
     }
 
     fn set_cookies_for_url_with_data(&mut self, request: ServoUrl, cookie: cookie_rs::Cookie, source: CookieSource,
-                                     resource_group: &ResourceGroup) {
+                                     first_party: Option<ServoUrl>, resource_group: &ResourceGroup) {
+        if !cookies_allowed(&request, first_party.as_ref(), resource_group) {
+            return;
+        }
         if let Some(cookie) = cookie::Cookie::new_wrapped(cookie, &request, source) {
-            let mut cookie_jar = resource_group.cookie_jar.write().unwrap();
-            cookie_jar.push(cookie, source)
+            let mut cookie_jar = write_lock(&resource_group.cookie_jar, "cookie jar");
+            resource_group.net_stats.evicted_cookies(cookie_jar.push(cookie, source));
         }
     }
 
-    fn fetch(&self,
-             init: RequestInit,
-             sender: IpcSender<FetchResponseMsg>,
-             group: &ResourceGroup) {
+    fn fetch<T>(&mut self,
+                init: RequestInit,
+                sender: T,
+                group: &ResourceGroup)
+        where T: FetchTaskTarget + Send + 'static
+    {
         let http_state = HttpState {
             hsts_list: group.hsts_list.clone(),
+            shared_hsts_list: group.shared_hsts_list.clone(),
             cookie_jar: group.cookie_jar.clone(),
+            cookie_policy: group.cookie_policy.clone(),
             auth_cache: group.auth_cache.clone(),
+            digest_auth: group.digest_auth.clone(),
             blocked_content: BLOCKED_CONTENT_RULES.clone(),
+            url_rewriter: group.url_rewriter.clone(),
+            mime_overrides: group.mime_overrides.clone(),
+            connection_pools: group.connection_pools.clone(),
+            alt_svc_cache: group.alt_svc_cache.clone(),
+            http_cache: group.http_cache.clone(),
+            memory_cache: group.memory_cache.clone(),
+            cors_cache: group.cors_cache.clone(),
+        };
+        let ua = read_lock(&group.user_agent, "user agent").clone();
+        let accept_language = match init.accept_language {
+            Some(ref languages) => accept_language_header(languages),
+            None => group.accept_language(),
         };
-        let ua = self.user_agent.clone();
         let dc = self.devtools_chan.clone();
         let filemanager = self.filemanager.clone();
-        spawn_named(format!("fetch thread for {}", init.url), move || {
+        let net_stats = group.net_stats.clone();
+        let scheme_handlers = self.scheme_handlers.clone();
+        let storage_thread = self.storage_thread.clone();
+        let auth_prompt = self.auth_prompt.clone();
+        // Read for each fetch, so that changing the prefs applies to the next one.
+        let privacy_signals = PrivacySignals::from_prefs();
+        let allow_host_override = PREFS.get("network.allow-host-override").as_boolean().unwrap_or(false);
+        let max_response_size = max_response_size_from_prefs();
+        let fetch_scheduler = self.fetch_scheduler.clone();
+        // Sending the timings of every request has a cost, even when nothing is profiling.
+        let time_profiler_chan = if PREFS.get("network.time-profiling.enabled").as_boolean().unwrap_or(true) {
+            Some(self.profiler_chan.clone())
+        } else {
+            None
+        };
+        // The timeout starts now, so that time spent queued for a connection counts.
+        let deadline = init.timeout_ms.map(|timeout_ms| Deadline::after(timeout_ms, init.timeout_covers_body));
+        let (cancel_sender, cancel_receiver) = channel();
+        let (ack_sender, body_flow_control) = match (init.resource_id, init.response_body_window) {
+            (Some(_), Some(window)) => {
+                let (ack_sender, ack_receiver) = channel();
+                (Some(ack_sender), Some(Arc::new(Mutex::new(BodyFlowControl::new(window, ack_receiver)))))
+            }
+            _ => (None, None),
+        };
+        let fetch_id = self.next_fetch_id;
+        self.next_fetch_id = self.next_fetch_id.wrapping_add(1);
+        self.in_flight_fetches.lock().unwrap().insert(fetch_id, InFlightFetch {
+            resource_id: init.resource_id,
+            pipeline_id: init.pipeline_id,
+            session_id: group.session_id,
+            cancel_sender: cancel_sender,
+            ack_sender: ack_sender,
+        });
+        let in_flight_fetches = self.in_flight_fetches.clone();
+        let pipeline_origins = self.pipeline_origins.clone();
+        let running = FetchCounter::start(&self.running_fetches);
+        let priority = init.scheduling_priority();
+        let origin = match (init.url.scheme(), init.url.host_str()) {
+            ("http", Some(host)) | ("https", Some(host)) => {
+                Some((init.url.origin().ascii_serialization(), host.to_owned()))
+            }
+            _ => None,
+        };
+        let max_streams = http2_streams_allowed(&init.url, group);
+        let job = move |cancelled: bool| {
+            let _running = running;
+            let mut target: Target = Some(Box::new(sender));
+            // A fetch cancelled while it waited for a fetch worker isn't started at all.
+            if cancelled || cancel_receiver.try_recv().is_ok() {
+                end_with_network_error(&mut target, NetworkError::LoadCancelled);
+                in_flight_fetches.lock().unwrap().remove(&fetch_id);
+                return;
+            }
             let request = Request::from_init(init);
-            // XXXManishearth: Check origin against pipeline id (also ensure that the mode is allowed)
+            if let Err(error) = pipeline_origins.lock().unwrap().check(&request) {
+                warn!("Refusing fetch of {}: {:?}", request.current_url(), error);
+                end_with_network_error(&mut target, error);
+                in_flight_fetches.lock().unwrap().remove(&fetch_id);
+                return;
+            }
+            if let (true, Some(pipeline_id)) = (request.is_navigation_request(), request.pipeline_id.get()) {
+                target = target.map(|target| Box::new(RecordDocumentOrigin {
+                    target: target,
+                    pipeline_id: pipeline_id,
+                    pipeline_origins: pipeline_origins,
+                }) as Box<FetchTaskTarget + Send>);
+            }
             // todo load context / mimesniff in fetch
-            // todo referrer policy?
             // todo service worker stuff
-            let mut target = Some(Box::new(sender) as Box<FetchTaskTarget + Send + 'static>);
             let context = FetchContext {
                 state: http_state,
                 user_agent: ua,
+                accept_language: accept_language,
                 devtools_chan: dc,
                 filemanager: filemanager,
+                cancellation_listener: Arc::new(Mutex::new(CancellationListener::new(Some(cancel_receiver)))),
+                body_flow_control: body_flow_control,
+                time_profiler_chan: time_profiler_chan,
+                net_stats: net_stats,
+                scheme_handlers: scheme_handlers,
+                scheduler: Some(fetch_scheduler),
+                storage_thread: storage_thread,
+                auth_prompt: auth_prompt,
+                privacy_signals: privacy_signals,
+                allow_host_override: allow_host_override,
+                deadline: deadline,
+                max_response_size: max_response_size,
             };
             fetch(Rc::new(request), &mut target, &context);
-        })
+            in_flight_fetches.lock().unwrap().remove(&fetch_id);
+        };
+        match origin {
+            // FIXME: the fetch stays accounted to the origin and host it started on, even if
+            // it is redirected elsewhere.
+            Some((origin, host)) => {
+                let started = self.connection_limiter.lock().unwrap().start_or_queue(&origin, &host, fetch_id,
+                                                                                     priority, max_streams,
+                                                                                     Box::new(job));
+                if let Some(started) = started {
+                    let connection_limiter = self.connection_limiter.clone();
+                    let job = move |_: bool| run_started_fetches(connection_limiter, started);
+                    self.fetch_scheduler.schedule(priority, Box::new(job));
+                }
+            }
+            None => self.fetch_scheduler.schedule(priority, Box::new(job)),
+        }
+    }
+
+    /// Open a connection to the origin of `url` and leave it idle in the pool of `group`,
+    /// for a fetch from the origin to use. It takes a connection slot for the origin and
+    /// its host like a fetch does, and is only made once every more urgent fetch has started. Nothing is
+    /// done for URLs that aren't `http` or `https`.
+    fn preconnect(&mut self, url: ServoUrl, done: Option<IpcSender<()>>, group: &ResourceGroup) {
+        let host = match (url.scheme(), url.host_str()) {
+            ("http", Some(host)) | ("https", Some(host)) => host.to_owned(),
+            _ => return,
+        };
+        let is_host_secure = read_lock(&group.hsts_list, "HSTS list").is_host_secure(&host) ||
+            group.shared_hsts_list.as_ref().map_or(false, |list| read_lock(list, "HSTS list").is_host_secure(&host));
+        // The upgrade keeps an explicit port, as the one a fetch makes does.
+        let (scheme, port) = if url.scheme() == "http" && is_host_secure {
+            ("https", url.port().unwrap_or(443))
+        } else {
+            (url.scheme(), url.port_or_known_default().unwrap_or(80))
+        };
+        let scheme = scheme.to_owned();
+        // Pools are keyed by the host the page asked for, but the connection is made to the
+        // address it is replaced by, just as a fetch does.
+        let connect_host = replace_hosts(&url).host_str().unwrap_or(&host).to_owned();
+        let origin = url.origin().ascii_serialization();
+        let limited_host = host.clone();
+        let max_streams = http2_streams_allowed(&url, group);
+        let connection_pools = group.connection_pools.clone();
+        let running = FetchCounter::start(&self.running_fetches);
+        let job = move |cancelled: bool| {
+            let _running = running;
+            if !cancelled {
+                let (pool, _) = connection_pools.pool_for(&host);
+                // The connection goes back to the pool when it is dropped unused.
+                if let Err(error) = pool.connect(&connect_host, port, &scheme) {
+                    debug!("Preconnecting to {}://{}:{} failed: {}", scheme, connect_host, port, error);
+                }
+            }
+            if let Some(done) = done {
+                let _ = done.send(());
+            }
+        };
+        let fetch_id = self.next_fetch_id;
+        self.next_fetch_id = self.next_fetch_id.wrapping_add(1);
+        let priority = RequestPriority::Idle;
+        let started = self.connection_limiter.lock().unwrap().start_or_queue(&origin, &limited_host, fetch_id,
+                                                                             priority, max_streams, Box::new(job));
+        if let Some(started) = started {
+            let connection_limiter = self.connection_limiter.clone();
+            let job = move |_: bool| run_started_fetches(connection_limiter, started);
+            self.fetch_scheduler.schedule(priority, Box::new(job));
+        }
     }
 
     fn websocket_connect(&self,
                          connect: WebSocketCommunicate,
                          connect_data: WebSocketConnectData,
                          resource_grp: &ResourceGroup) {
-        websocket_loader::init(connect, connect_data, resource_grp.cookie_jar.clone());
+        let user_agent = read_lock(&resource_grp.user_agent, "user agent").clone();
+        let accept_language = resource_grp.accept_language();
+        let route = {
+            let url = &connect_data.resource_url;
+            resource_grp.connection_pools.route(url.scheme(), url.host_str().unwrap_or(""))
+        };
+        websocket_loader::init(connect, connect_data, resource_grp.cookie_jar.clone(), user_agent, accept_language,
+                               PrivacySignals::from_prefs(), route);
     }
 }
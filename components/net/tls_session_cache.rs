@@ -0,0 +1,165 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The TLS sessions of a resource group, kept so that a new connection to a server
+//! resumes the last session with it rather than doing a full handshake. They are
+//! only ever held in memory.
+
+use fetch::methods::NetStats;
+use openssl::ssl::Ssl;
+use openssl_sys::SSL;
+use std::ascii::AsciiExt;
+use std::collections::{HashMap, VecDeque};
+use std::os::raw::{c_int, c_long, c_void};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use util::prefs::PREFS;
+
+#[allow(non_camel_case_types)]
+enum SSL_SESSION {}
+
+/// rust-openssl doesn't expose sessions, so they are handled through OpenSSL itself.
+extern "C" {
+    fn SSL_get1_session(ssl: *mut SSL) -> *mut SSL_SESSION;
+    fn SSL_set_session(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int;
+    fn SSL_SESSION_free(session: *mut SSL_SESSION);
+    fn SSL_ctrl(ssl: *mut SSL, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+}
+
+/// `SSL_session_reused` is a macro for this.
+const SSL_CTRL_GET_SESSION_REUSED: c_int = 8;
+
+/// A reference to a session, which OpenSSL frees once nothing refers to it any more.
+struct Session(*mut SSL_SESSION);
+
+// OpenSSL's reference counting of sessions is thread-safe, and a session isn't changed
+// once its handshake is done.
+unsafe impl Send for Session {}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        unsafe { SSL_SESSION_free(self.0) }
+    }
+}
+
+/// The most recent value stored for each server, keeping at most `capacity` of them
+/// by dropping the ones stored longest ago.
+pub struct SessionStore<S> {
+    entries: HashMap<String, S>,
+    /// The keys of `entries`, least recently stored first.
+    order: VecDeque<String>,
+    capacity: usize,
+}
+
+fn server_key(host: &str, port: u16) -> String {
+    format!("{}:{}", host.to_ascii_lowercase(), port)
+}
+
+impl<S> SessionStore<S> {
+    pub fn new(capacity: usize) -> SessionStore<S> {
+        SessionStore {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            capacity: capacity,
+        }
+    }
+
+    pub fn get(&self, host: &str, port: u16) -> Option<&S> {
+        self.entries.get(&server_key(host, port))
+    }
+
+    pub fn insert(&mut self, host: &str, port: u16, value: S) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = server_key(host, port);
+        self.order.retain(|stored| *stored != key);
+        while self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.push_back(key.clone());
+        self.entries.insert(key, value);
+    }
+
+    /// Forget the values for every port of `host`.
+    pub fn clear_host(&mut self, host: &str) {
+        let prefix = format!("{}:", host.to_ascii_lowercase());
+        self.order.retain(|key| !key.starts_with(&prefix));
+        let keys: Vec<_> = self.entries.keys().filter(|key| key.starts_with(&prefix)).cloned().collect();
+        for key in keys {
+            self.entries.remove(&key);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.order.clear();
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// The TLS sessions of a resource group, by host and port, counting in the group's
+/// `NetStats` how many handshakes resumed one.
+pub struct TlsSessionCache {
+    /// Each session, along with whether its certificate was accepted through a
+    /// certificate exception, which resumed connections are reported with too.
+    sessions: Mutex<SessionStore<(Session, bool)>>,
+    net_stats: Arc<NetStats>,
+}
+
+impl TlsSessionCache {
+    /// A cache of the number of sessions set in the `network.tls.session-cache-size` pref.
+    pub fn new(net_stats: Arc<NetStats>) -> TlsSessionCache {
+        let capacity = PREFS.get("network.tls.session-cache-size").as_u64().unwrap_or(256);
+        TlsSessionCache {
+            sessions: Mutex::new(SessionStore::new(capacity as usize)),
+            net_stats: net_stats,
+        }
+    }
+
+    /// Offer the session last used with the server at `host` and `port` in the
+    /// handshake of `ssl`, if there is one.
+    pub fn offer_session(&self, ssl: &Ssl, host: &str, port: u16) {
+        if let Some(&(ref session, _)) = self.sessions.lock().unwrap().get(host, port) {
+            // A session OpenSSL can't use only makes for a full handshake.
+            unsafe { SSL_set_session(ssl.raw(), session.0) };
+        }
+    }
+
+    /// Count the handshake of `ssl` with the server at `host` and `port`, and keep its
+    /// session for the next connection. Gives whether the certificate the session was
+    /// started with was accepted through a certificate exception, if it was resumed.
+    pub fn handshake_done(&self, ssl: &Ssl, host: &str, port: u16, used_exception: bool) -> bool {
+        let resumed = unsafe { SSL_ctrl(ssl.raw(), SSL_CTRL_GET_SESSION_REUSED, 0, ptr::null_mut()) != 0 };
+        self.net_stats.tls_handshake_done(resumed);
+        let mut sessions = self.sessions.lock().unwrap();
+        let used_exception = match sessions.get(host, port) {
+            Some(&(_, session_used_exception)) if resumed => session_used_exception,
+            _ => used_exception,
+        };
+        let session = unsafe { SSL_get1_session(ssl.raw()) };
+        if !session.is_null() {
+            sessions.insert(host, port, (Session(session), used_exception));
+        }
+        used_exception
+    }
+
+    /// Forget the sessions with every port of `host`.
+    pub fn clear_host(&self, host: &str) {
+        self.sessions.lock().unwrap().clear_host(host);
+    }
+
+    pub fn clear(&self) {
+        self.sessions.lock().unwrap().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+}
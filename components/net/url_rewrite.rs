@@ -0,0 +1,117 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Rewriting of outgoing request URLs according to user-supplied rules.
+
+use net_traits::{RewriteAction, RewriteRule};
+use regex::Regex;
+use servo_url::ServoUrl;
+
+enum Action {
+    RemoveQueryParams(Vec<String>),
+    RegexReplace(Regex, String),
+}
+
+struct Rule {
+    host: String,
+    action: Action,
+}
+
+impl Rule {
+    fn matches_host(&self, host: &str) -> bool {
+        if self.host == "*" {
+            return true;
+        }
+        if self.host.starts_with("*.") {
+            let domain = &self.host[2..];
+            return host == domain || host.ends_with(&format!(".{}", domain));
+        }
+        host == self.host
+    }
+}
+
+/// Whether the query parameter `name` is one of `names`, which may end in `*`
+/// to match by prefix.
+fn is_listed_param(name: &str, names: &[String]) -> bool {
+    names.iter().any(|listed| {
+        if listed.ends_with('*') {
+            name.starts_with(&listed[..listed.len() - 1])
+        } else {
+            name == listed
+        }
+    })
+}
+
+pub struct UrlRewriter {
+    rules: Vec<Rule>,
+}
+
+impl UrlRewriter {
+    pub fn new(rules: Vec<RewriteRule>) -> UrlRewriter {
+        let rules = rules.into_iter().filter_map(|rule| {
+            let action = match rule.action {
+                RewriteAction::RemoveQueryParams(names) => Action::RemoveQueryParams(names),
+                RewriteAction::RegexReplace(pattern, replacement) => match Regex::new(&pattern) {
+                    Ok(regex) => Action::RegexReplace(regex, replacement),
+                    Err(e) => {
+                        warn!("Ignoring URL rewrite rule with invalid pattern {}: {}", pattern, e);
+                        return None;
+                    }
+                },
+            };
+            Some(Rule {
+                host: rule.host.to_lowercase(),
+                action: action,
+            })
+        }).collect();
+        UrlRewriter {
+            rules: rules,
+        }
+    }
+
+    /// Apply every rule matching the host of `url` in turn, returning the rewritten URL
+    /// if anything changed.
+    pub fn rewrite(&self, url: &ServoUrl) -> Option<ServoUrl> {
+        let mut rewritten = url.clone();
+        for rule in &self.rules {
+            let matches = rewritten.host_str().map_or(false, |host| rule.matches_host(host));
+            if !matches {
+                continue;
+            }
+            match rule.action {
+                Action::RemoveQueryParams(ref names) => {
+                    let pairs: Vec<(String, String)> = match rewritten.as_url() {
+                        Some(url) => url.query_pairs().into_owned().collect(),
+                        None => continue,
+                    };
+                    if !pairs.iter().any(|&(ref name, _)| is_listed_param(name, names)) {
+                        continue;
+                    }
+                    let kept: Vec<_> = pairs.into_iter()
+                                            .filter(|&(ref name, _)| !is_listed_param(name, names))
+                                            .collect();
+                    if let Some(url) = rewritten.as_mut_url() {
+                        if kept.is_empty() {
+                            url.set_query(None);
+                        } else {
+                            url.query_pairs_mut().clear().extend_pairs(kept);
+                        }
+                    }
+                }
+                Action::RegexReplace(ref regex, ref replacement) => {
+                    let replaced = regex.replace_all(rewritten.as_str(), replacement.as_str());
+                    match ServoUrl::parse(&replaced) {
+                        Ok(url) => rewritten = url,
+                        Err(e) => warn!("URL rewrite produced an invalid URL {}: {}", replaced, e),
+                    }
+                }
+            }
+        }
+        if rewritten == *url {
+            None
+        } else {
+            Some(rewritten)
+        }
+    }
+}
@@ -2,20 +2,28 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use connector::{ProxyRoute, create_ssl_client, open_socks, open_tunnel};
+use cookie::Cookie;
 use cookie_storage::CookieStorage;
-use http_loader;
-use hyper::header::Host;
+use http_loader::{self, PrivacySignals};
+use hyper::net::HttpStream;
+use hyper::header::{AcceptLanguage, Host, UserAgent};
 use net_traits::{WebSocketCommunicate, WebSocketConnectData, WebSocketDomAction, WebSocketNetworkEvent};
-use net_traits::MessageData;
+use net_traits::{MessageData, SameSiteContext};
 use net_traits::hosts::replace_hosts;
 use net_traits::unwrap_websocket_protocol;
 use servo_url::ServoUrl;
 use std::ascii::AsciiExt;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::net::TcpStream;
 use std::sync::{Arc, Mutex, RwLock};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use util::thread::spawn_named;
-use websocket::{Client, Message};
+use websocket::Message;
+use websocket::client::request::Request;
 use websocket::header::{Headers, Origin, WebSocketProtocol};
 use websocket::message::Type;
 use websocket::receiver::Receiver;
@@ -26,24 +34,76 @@ use websocket::ws::receiver::Receiver as WSReceiver;
 use websocket::ws::sender::Sender as Sender_Object;
 use websocket::ws::util::url::parse_url;
 
+/// Start a WebSocket handshake, with the connection made along `route`. The connection
+/// is made here rather than by the `websocket` crate so that it goes through proxies, and
+/// so that `wss` keeps to the same TLS policy as HTTPS.
+fn connect(route: &ProxyRoute, net_url: (Host, String, bool))
+           -> WebSocketResult<Request<WebSocketStream, WebSocketStream>> {
+    let port = net_url.0.port.unwrap_or(if net_url.2 { 443 } else { 80 });
+    let stream = match *route {
+        ProxyRoute::Tunnel(ref proxy) => {
+            let HttpStream(stream) = try!(open_tunnel(proxy, &net_url.0.hostname, port));
+            stream
+        }
+        ProxyRoute::Socks(ref proxy) => {
+            let HttpStream(stream) = try!(open_socks(proxy, &net_url.0.hostname, port));
+            stream
+        }
+        // WebSocket connections are never forwarded.
+        ProxyRoute::Direct | ProxyRoute::Forward(_) => try!(TcpStream::connect((&*net_url.0.hostname, port))),
+    };
+    let stream = if net_url.2 {
+        let ssl_client = create_ssl_client(Arc::new(Mutex::new(HashMap::new())));
+        match ssl_client.wrap_stream(stream, &net_url.0.hostname, port) {
+            Ok(stream) => WebSocketStream::Ssl(stream),
+            Err(error) => return Err(match ssl_client.explain_error(error, &net_url.0.hostname) {
+                Ok(error) => WebSocketError::from(error),
+                Err(policy_error) => {
+                    warn!("{}", policy_error);
+                    WebSocketError::IoError(io::Error::new(io::ErrorKind::Other, policy_error))
+                }
+            }),
+        }
+    } else {
+        WebSocketStream::Tcp(stream)
+    };
+    Request::new(net_url, try!(stream.try_clone()), stream)
+}
+
 /// *Establish a WebSocket Connection* as defined in RFC 6455.
 fn establish_a_websocket_connection(resource_url: &ServoUrl, net_url: (Host, String, bool),
                                     origin: String, protocols: Vec<String>,
-                                    cookie_jar: Arc<RwLock<CookieStorage>>)
+                                    cookie_jar: Arc<RwLock<CookieStorage>>,
+                                    user_agent: String,
+                                    accept_language: Option<AcceptLanguage>,
+                                    privacy_signals: PrivacySignals,
+                                    route: ProxyRoute)
     -> WebSocketResult<(Headers, Sender<WebSocketStream>, Receiver<WebSocketStream>)> {
     let host = Host {
         hostname: resource_url.host_str().unwrap().to_owned(),
         port: resource_url.port_or_known_default(),
     };
 
-    let mut request = try!(Client::connect(net_url));
-    request.headers.set(Origin(origin));
+    let mut request = try!(connect(&route, net_url));
+    request.headers.set(Origin(origin.clone()));
     request.headers.set(host);
+    request.headers.set(UserAgent(user_agent));
+    http_loader::set_accept_language(&mut request.headers, accept_language.as_ref());
+    privacy_signals.set_headers(&mut request.headers);
     if !protocols.is_empty() {
         request.headers.set(WebSocketProtocol(protocols.clone()));
     };
 
-    http_loader::set_request_cookies(&resource_url, &mut request.headers, &cookie_jar);
+    // A WebSocket handshake is never a navigation, so only same-site connections
+    // carry `SameSite` cookies.
+    let same_site = ServoUrl::parse(&origin).ok().map_or(false, |origin_url| {
+        match (origin_url.host_str(), resource_url.host_str()) {
+            (Some(origin_host), Some(url_host)) => Cookie::is_same_site(origin_host, url_host),
+            _ => false,
+        }
+    });
+    let context = if same_site { SameSiteContext::SameSite } else { SameSiteContext::CrossSite };
+    http_loader::set_request_cookies(&resource_url, &mut request.headers, &cookie_jar, context);
 
     let response = try!(request.send());
     try!(response.validate());
@@ -63,7 +123,9 @@ fn establish_a_websocket_connection(resource_url: &ServoUrl, net_url: (Host, Str
 
 }
 
-pub fn init(connect: WebSocketCommunicate, connect_data: WebSocketConnectData, cookie_jar: Arc<RwLock<CookieStorage>>) {
+pub fn init(connect: WebSocketCommunicate, connect_data: WebSocketConnectData, cookie_jar: Arc<RwLock<CookieStorage>>,
+            user_agent: Cow<'static, str>, accept_language: Option<AcceptLanguage>,
+            privacy_signals: PrivacySignals, route: ProxyRoute) {
     spawn_named(format!("WebSocket connection to {}", connect_data.resource_url), move || {
         // Step 8: Protocols.
 
@@ -84,7 +146,11 @@ pub fn init(connect: WebSocketCommunicate, connect_data: WebSocketConnectData, c
                                                        net_url,
                                                        connect_data.origin,
                                                        connect_data.protocols.clone(),
-                                                       cookie_jar);
+                                                       cookie_jar,
+                                                       user_agent.into_owned(),
+                                                       accept_language,
+                                                       privacy_signals,
+                                                       route);
         let (_, ws_sender, mut receiver) = match channel {
             Ok(channel) => {
                 let _ = connect.event_sender.send(WebSocketNetworkEvent::ConnectionEstablished(channel.0.clone(),
@@ -0,0 +1,170 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Just enough of a DER reader to describe the certificates that servers present,
+//! whose fields rust-openssl doesn't expose.
+
+use net_traits::CertificateDetails;
+use openssl::crypto::hash::{Type, hash};
+
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const BMP_STRING: u8 = 0x1e;
+/// The explicitly tagged version of a `TBSCertificate`, left out for version 1.
+const VERSION: u8 = 0xa0;
+
+/// The short names of the attributes commonly found in distinguished names.
+const ATTRIBUTE_NAMES: &'static [(&'static str, &'static str)] = &[
+    ("2.5.4.3", "CN"),
+    ("2.5.4.6", "C"),
+    ("2.5.4.7", "L"),
+    ("2.5.4.8", "ST"),
+    ("2.5.4.10", "O"),
+    ("2.5.4.11", "OU"),
+    ("1.2.840.113549.1.9.1", "emailAddress"),
+];
+
+/// The tag and contents of each element of a DER encoding, stopping at the first
+/// malformed one.
+struct Elements<'a>(&'a [u8]);
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        let input = self.0;
+        self.0 = &[];
+        if input.len() < 2 {
+            return None;
+        }
+        let (len, header_len) = match input[1] {
+            len if len < 0x80 => (len as usize, 2),
+            0x81...0x84 => {
+                let len_len = (input[1] & 0x7f) as usize;
+                if input.len() < 2 + len_len {
+                    return None;
+                }
+                let len = input[2..2 + len_len].iter().fold(0, |len, &byte| (len << 8) | byte as usize);
+                (len, 2 + len_len)
+            }
+            _ => return None,
+        };
+        if input.len() - header_len < len {
+            return None;
+        }
+        self.0 = &input[header_len + len..];
+        Some((input[0], &input[header_len..header_len + len]))
+    }
+}
+
+/// The subject, issuer, validity period and fingerprint of the certificate encoded as
+/// `der`. The fields that can't be read are left empty.
+pub fn certificate_details(der: Vec<u8>) -> CertificateDetails {
+    let (subject, issuer, not_before, not_after) = read_fields(&der).unwrap_or_else(|| {
+        (String::new(), String::new(), String::new(), String::new())
+    });
+    CertificateDetails {
+        fingerprint: hash(Type::SHA256, &der),
+        der: der,
+        subject: subject,
+        issuer: issuer,
+        not_before: not_before,
+        not_after: not_after,
+    }
+}
+
+fn read_fields(der: &[u8]) -> Option<(String, String, String, String)> {
+    let tbs_certificate = match Elements(der).next().and_then(|(_, certificate)| Elements(certificate).next()) {
+        Some((SEQUENCE, tbs_certificate)) => tbs_certificate,
+        _ => return None,
+    };
+    let mut fields = Elements(tbs_certificate).peekable();
+    if fields.peek().map(|&(tag, _)| tag) == Some(VERSION) {
+        fields.next();
+    }
+    // The serial number and signature algorithm come first.
+    let mut fields = fields.skip(2);
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some((SEQUENCE, issuer)), Some((SEQUENCE, validity)), Some((SEQUENCE, subject))) => {
+            let mut times = Elements(validity).map(|(tag, time)| format_time(tag, time));
+            let not_before = times.next().unwrap_or_else(String::new);
+            let not_after = times.next().unwrap_or_else(String::new);
+            Some((format_name(subject), format_name(issuer), not_before, not_after))
+        }
+        _ => None,
+    }
+}
+
+/// A distinguished name in the usual `C=US, O=Example, CN=example.com` form.
+fn format_name(name: &[u8]) -> String {
+    let attributes: Vec<_> = Elements(name).filter(|&(tag, _)| tag == SET).flat_map(|(_, set)| {
+        Elements(set).filter_map(|(tag, attribute)| {
+            if tag != SEQUENCE {
+                return None;
+            }
+            let mut parts = Elements(attribute);
+            match (parts.next(), parts.next()) {
+                (Some((OBJECT_IDENTIFIER, oid)), Some((tag, value))) => {
+                    let oid = format_oid(oid);
+                    let name = match ATTRIBUTE_NAMES.iter().find(|&&(known, _)| known == oid) {
+                        Some(&(_, name)) => name.to_owned(),
+                        None => oid,
+                    };
+                    Some(format!("{}={}", name, decode_string(tag, value)))
+                }
+                _ => None,
+            }
+        })
+    }).collect();
+    attributes.join(", ")
+}
+
+/// An object identifier in dotted form, such as `2.5.4.3`.
+fn format_oid(oid: &[u8]) -> String {
+    let mut arcs = vec![];
+    let mut arc = 0u64;
+    for &byte in oid {
+        arc = (arc << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = if arc < 80 { arc / 40 } else { 2 };
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        }
+    }
+    arcs.iter().map(u64::to_string).collect::<Vec<_>>().join(".")
+}
+
+fn decode_string(tag: u8, value: &[u8]) -> String {
+    if tag == BMP_STRING {
+        let units: Vec<u16> = value.chunks(2).filter(|pair| pair.len() == 2).map(|pair| {
+            (pair[0] as u16) << 8 | pair[1] as u16
+        }).collect();
+        return String::from_utf16_lossy(&units);
+    }
+    String::from_utf8_lossy(value).into_owned()
+}
+
+/// A `UTCTime` or `GeneralizedTime` in the `2017-01-31T12:00:00Z` form.
+fn format_time(tag: u8, time: &[u8]) -> String {
+    let digits = time.iter().take_while(|&&byte| byte >= b'0' && byte <= b'9').count();
+    let (year, rest) = match tag {
+        UTC_TIME if digits >= 12 => {
+            // Two-digit years from 50 on are in the twentieth century.
+            let century = if time[0] >= b'5' { "19" } else { "20" };
+            (format!("{}{}", century, String::from_utf8_lossy(&time[..2])), &time[2..12])
+        }
+        GENERALIZED_TIME if digits >= 14 => (String::from_utf8_lossy(&time[..4]).into_owned(), &time[4..14]),
+        _ => return String::from_utf8_lossy(time).into_owned(),
+    };
+    let rest = String::from_utf8_lossy(rest);
+    format!("{}-{}-{}T{}:{}:{}Z", year, &rest[0..2], &rest[2..4], &rest[4..6], &rest[6..8], &rest[8..10])
+}
@@ -24,6 +24,7 @@ extern crate lazy_static;
 extern crate log;
 extern crate msg;
 extern crate num_traits;
+extern crate profile_traits;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -38,17 +39,22 @@ use bluetooth_traits::{BluetoothResponseListener, BluetoothResponseResult};
 use cookie_rs::Cookie;
 use filemanager_thread::FileManagerThreadMsg;
 use heapsize::HeapSizeOf;
-use hyper::header::{ContentType, Headers};
+use hyper::header::{ContentType, Headers, ReferrerPolicy as ReferrerPolicyHeader};
 use hyper::http::RawStatus;
+use hyper::method::Method;
 use hyper::mime::{Attr, Mime};
 use hyper_serde::Serde;
 use ipc_channel::ipc::{self, IpcReceiver, IpcSender};
 use ipc_channel::router::ROUTER;
 use msg::constellation_msg::PipelineId;
+use profile_traits::mem::{ProfilerChan as MemProfilerChan, ReportsChan};
 use request::{Request, RequestInit};
-use response::{HttpsState, Response};
+use response::{HttpsState, ResourceTiming, Response, TlsInfo};
 use servo_url::ServoUrl;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io::Error as IOError;
+use std::path::PathBuf;
 use storage_thread::StorageThreadMsg;
 use websocket::header;
 
@@ -111,6 +117,44 @@ pub struct CustomResponseMediator {
     pub load_url: ServoUrl,
 }
 
+/// A fetch of a URL whose scheme an embedder handles, sent to the handler it registered with
+/// `CoreResourceControlMsg::RegisterSchemeHandler`. The handler answers on `response_chan`, with
+/// `None` if it has nothing at that URL.
+#[derive(Deserialize, Serialize)]
+pub struct SchemeRequest {
+    pub url: ServoUrl,
+    #[serde(deserialize_with = "::hyper_serde::deserialize",
+            serialize_with = "::hyper_serde::serialize")]
+    pub method: Method,
+    #[serde(deserialize_with = "::hyper_serde::deserialize",
+            serialize_with = "::hyper_serde::serialize")]
+    pub headers: Headers,
+    pub body: Option<Vec<u8>>,
+    pub response_chan: IpcSender<Option<CustomResponse>>,
+}
+
+/// A request for the credentials to answer a `401` or `407` response with, sent to the channel
+/// given with `CoreResourceControlMsg::SetAuthPromptChannel`. The embedder answers on `response_chan`,
+/// with `None` if the user declined.
+#[derive(Deserialize, Serialize)]
+pub struct AuthPromptRequest {
+    /// The URL being fetched.
+    pub url: ServoUrl,
+    /// The host asking for credentials, which is the proxy's if `is_proxy`.
+    pub host: String,
+    /// The realm of the challenge, if it gave one.
+    pub realm: Option<String>,
+    pub is_proxy: bool,
+    pub response_chan: IpcSender<Option<Credentials>>,
+}
+
+/// A user name and password given in answer to an `AuthPromptRequest`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Credentials {
+    pub user_name: String,
+    pub password: String,
+}
+
 /// [Policies](https://w3c.github.io/webappsec-referrer-policy/#referrer-policy-states)
 /// for providing a referrer header for a request
 #[derive(Clone, Copy, Debug, Deserialize, HeapSizeOf, Serialize)]
@@ -133,15 +177,40 @@ pub enum ReferrerPolicy {
     StrictOriginWhenCrossOrigin,
 }
 
+impl From<ReferrerPolicyHeader> for ReferrerPolicy {
+    fn from(policy: ReferrerPolicyHeader) -> ReferrerPolicy {
+        match policy {
+            ReferrerPolicyHeader::NoReferrer => ReferrerPolicy::NoReferrer,
+            ReferrerPolicyHeader::NoReferrerWhenDowngrade => ReferrerPolicy::NoReferrerWhenDowngrade,
+            ReferrerPolicyHeader::SameOrigin => ReferrerPolicy::SameOrigin,
+            ReferrerPolicyHeader::Origin => ReferrerPolicy::Origin,
+            ReferrerPolicyHeader::OriginWhenCrossOrigin => ReferrerPolicy::OriginWhenCrossOrigin,
+            ReferrerPolicyHeader::UnsafeUrl => ReferrerPolicy::UnsafeUrl,
+            ReferrerPolicyHeader::StrictOrigin => ReferrerPolicy::StrictOrigin,
+            ReferrerPolicyHeader::StrictOriginWhenCrossOrigin => ReferrerPolicy::StrictOriginWhenCrossOrigin,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub enum FetchResponseMsg {
-    // todo: should have fields for transmitted/total bytes
     ProcessRequestBody,
+    /// How many bytes of the request body have been sent so far, out of how many if the
+    /// length of the body is known
+    ProcessRequestBodyProgress(u64, Option<u64>),
     ProcessRequestEOF,
+    /// The `Link` values of a 103 Early Hints response, which comes before the response
+    ProcessEarlyHints(Vec<String>),
     // todo: send more info about the response (or perhaps the entire Response)
     ProcessResponse(Result<FetchMetadata, NetworkError>),
+    /// The start of the next part of a `multipart/x-mixed-replace` response, whose body
+    /// replaces the one before it, with the metadata of the part
+    ProcessResponsePart(Result<FetchMetadata, NetworkError>),
     ProcessResponseChunk(Vec<u8>),
-    ProcessResponseEOF(Result<(), NetworkError>),
+    /// The trailer fields that came after a chunked response body, before `ProcessResponseEOF`
+    ProcessResponseTrailers(Serde<Headers>),
+    /// The end of the response, with its timing as it is once the whole body has arrived
+    ProcessResponseEOF(Result<(), NetworkError>, ResourceTiming),
 }
 
 pub trait FetchTaskTarget {
@@ -150,19 +219,36 @@ pub trait FetchTaskTarget {
     /// Fired when a chunk of the request body is transmitted
     fn process_request_body(&mut self, request: &Request);
 
+    /// Fired as the request body is written to the network, with the number of bytes
+    /// written so far and the length of the whole body, if it is known. Fired at most
+    /// every 50ms until the whole body has been written, and never for an empty body
+    fn process_request_body_progress(&mut self, sent: u64, total: Option<u64>);
+
     /// https://fetch.spec.whatwg.org/#process-request-end-of-file
     ///
     /// Fired when the entire request finishes being transmitted
     fn process_request_eof(&mut self, request: &Request);
 
+    /// Fired with the `Link` values of each 103 Early Hints response, before
+    /// `process_response`
+    fn process_early_hints(&mut self, links: &[String]);
+
     /// https://fetch.spec.whatwg.org/#process-response
     ///
     /// Fired when headers are received
     fn process_response(&mut self, response: &Response);
 
+    /// Fired at the start of each part of a `multipart/x-mixed-replace` response, with
+    /// the response as it is for that part. The chunks that follow are the body of the part
+    fn process_response_part(&mut self, part: &Response);
+
     /// Fired when a chunk of response content is received
     fn process_response_chunk(&mut self, chunk: Vec<u8>);
 
+    /// Fired with the trailer fields of a chunked response, once its whole body has
+    /// been received and before `process_response_eof`
+    fn process_response_trailers(&mut self, trailers: &Headers);
+
     /// https://fetch.spec.whatwg.org/#process-response-end-of-file
     ///
     /// Fired when the response is fully fetched
@@ -186,9 +272,24 @@ pub enum FetchMetadata {
 
 pub trait FetchResponseListener {
     fn process_request_body(&mut self);
+    /// Only listeners that report upload progress care about how much of the request
+    /// body has been sent.
+    fn process_request_body_progress(&mut self, _sent: u64, _total: Option<u64>) {}
     fn process_request_eof(&mut self);
+    /// Only listeners that can start preloads care about the `Link` values of an early
+    /// hints response.
+    fn process_early_hints(&mut self, _links: Vec<String>) {}
     fn process_response(&mut self, metadata: Result<FetchMetadata, NetworkError>);
+    /// Only listeners that show the parts of a multipart response one after another care
+    /// about where each one starts.
+    fn process_response_part(&mut self, _metadata: Result<FetchMetadata, NetworkError>) {}
     fn process_response_chunk(&mut self, chunk: Vec<u8>);
+    /// Only listeners that expose the trailer fields of a response care about them.
+    fn process_response_trailers(&mut self, _trailers: Headers) {}
+    /// Only listeners that expose resource timing care about when the whole body arrived,
+    /// which is only known once the response has been fully fetched. Called right before
+    /// `process_response_eof`.
+    fn process_response_timing(&mut self, _timing: ResourceTiming) {}
     fn process_response_eof(&mut self, response: Result<(), NetworkError>);
 }
 
@@ -197,26 +298,47 @@ impl FetchTaskTarget for IpcSender<FetchResponseMsg> {
         let _ = self.send(FetchResponseMsg::ProcessRequestBody);
     }
 
+    fn process_request_body_progress(&mut self, sent: u64, total: Option<u64>) {
+        let _ = self.send(FetchResponseMsg::ProcessRequestBodyProgress(sent, total));
+    }
+
     fn process_request_eof(&mut self, _: &Request) {
         let _ = self.send(FetchResponseMsg::ProcessRequestEOF);
     }
 
+    fn process_early_hints(&mut self, links: &[String]) {
+        let _ = self.send(FetchResponseMsg::ProcessEarlyHints(links.to_vec()));
+    }
+
     fn process_response(&mut self, response: &Response) {
         let _ = self.send(FetchResponseMsg::ProcessResponse(response.metadata()));
     }
 
+    fn process_response_part(&mut self, part: &Response) {
+        let _ = self.send(FetchResponseMsg::ProcessResponsePart(part.metadata()));
+    }
+
     fn process_response_chunk(&mut self, chunk: Vec<u8>) {
         let _ = self.send(FetchResponseMsg::ProcessResponseChunk(chunk));
     }
 
+    fn process_response_trailers(&mut self, trailers: &Headers) {
+        let _ = self.send(FetchResponseMsg::ProcessResponseTrailers(Serde(trailers.clone())));
+    }
+
     fn process_response_eof(&mut self, response: &Response) {
-        if response.is_network_error() {
-            // todo: finer grained errors
-            let _ = self.send(FetchResponseMsg::ProcessResponseEOF(
-                              Err(NetworkError::Internal("Network error".into()))));
-        } else {
-            let _ = self.send(FetchResponseMsg::ProcessResponseEOF(Ok(())));
-        }
+        let _ = self.send(FetchResponseMsg::ProcessResponseEOF(response_eof_result(response),
+                                                               response.timing.lock().unwrap().clone()));
+    }
+}
+
+/// The result reported to a consumer once `response` has been fully fetched.
+fn response_eof_result(response: &Response) -> Result<(), NetworkError> {
+    match response.get_network_error() {
+        Some(&NetworkError::LoadCancelled) => Err(NetworkError::LoadCancelled),
+        // todo: finer grained errors
+        Some(_) => Err(NetworkError::Internal("Network error".into())),
+        None => Ok(()),
     }
 }
 
@@ -230,10 +352,19 @@ impl<T: FetchResponseListener> Action<T> for FetchResponseMsg {
     fn process(self, listener: &mut T) {
         match self {
             FetchResponseMsg::ProcessRequestBody => listener.process_request_body(),
+            FetchResponseMsg::ProcessRequestBodyProgress(sent, total) =>
+                listener.process_request_body_progress(sent, total),
             FetchResponseMsg::ProcessRequestEOF => listener.process_request_eof(),
+            FetchResponseMsg::ProcessEarlyHints(links) => listener.process_early_hints(links),
             FetchResponseMsg::ProcessResponse(meta) => listener.process_response(meta),
+            FetchResponseMsg::ProcessResponsePart(meta) => listener.process_response_part(meta),
             FetchResponseMsg::ProcessResponseChunk(data) => listener.process_response_chunk(data),
-            FetchResponseMsg::ProcessResponseEOF(data) => listener.process_response_eof(data),
+            FetchResponseMsg::ProcessResponseTrailers(trailers) =>
+                listener.process_response_trailers(trailers.into_inner()),
+            FetchResponseMsg::ProcessResponseEOF(data, timing) => {
+                listener.process_response_timing(timing);
+                listener.process_response_eof(data)
+            }
         }
     }
 }
@@ -254,6 +385,10 @@ pub enum LoadConsumer {
 /// Handle to a resource thread
 pub type CoreResourceThread = IpcSender<CoreResourceMsg>;
 
+/// Handle to a resource thread for the messages that only the constellation and the
+/// embedder may send. Unlike a `CoreResourceThread`, it is never handed to script.
+pub type CoreResourceControlThread = IpcSender<CoreResourceControlMsg>;
+
 pub type IpcSendResult = Result<(), IOError>;
 
 /// Abstraction of the ability to send a particular type of message,
@@ -356,31 +491,149 @@ pub struct WebSocketConnectData {
     pub protocols: Vec<String>,
 }
 
+/// The messages a resource thread accepts on its `CoreResourceControlThread`.
+#[derive(Deserialize, Serialize)]
+pub enum CoreResourceControlMsg {
+    /// Retrieve the public group's whole cookie jar, HttpOnly cookies included, encoded as it
+    /// is saved to `cookie_jar.json`, so that the embedder can back it up wherever it likes
+    DumpCookieJar(IpcSender<String>),
+    /// Replace the public group's whole cookie jar with one encoded by `DumpCookieJar`. A jar
+    /// that can't be decoded is ignored
+    RestoreCookieJar(String),
+    /// The document in the first pipeline has the origin of the document in the second,
+    /// which its requests are checked against, as an `about:blank` iframe has that of its
+    /// parent. Documents that are fetched get the origin of their response instead, and a
+    /// pipeline whose origin is already known keeps it
+    InheritPipelineOrigin(PipelineId, PipelineId),
+    /// Cancel every in-flight fetch that was started on behalf of the given pipeline, or
+    /// every in-flight fetch at all if there is none
+    CancelAll(Option<PipelineId>),
+    /// Fetch a resource for the given group and save its body to `path`, or to the file named
+    /// by the response in `path` if it is a directory, reporting the progress of the download
+    /// to `reply`
+    FetchToFile {
+        group: ResourceGroupId,
+        init: RequestInit,
+        path: PathBuf,
+        reply: IpcSender<DownloadProgress>,
+    },
+    /// Replace the rules the given group uses to rewrite the URLs of outgoing requests
+    SetUrlRewriteRules(ResourceGroupId, Vec<RewriteRule>),
+    /// Replace the content types the given group forces on the responses for URLs matching
+    /// each pattern, whatever the server or sniffing says. The first matching pattern wins.
+    SetMimeOverrides(ResourceGroupId, Vec<(UrlPattern, String)>),
+    /// Have the given group accept the certificate with the given fingerprint from the server at
+    /// the given host and port, even though it doesn't verify. Exceptions never apply to hosts on
+    /// the HSTS list, and permanent ones are saved with the rest of the group's state
+    AddCertificateException {
+        group: ResourceGroupId,
+        host: String,
+        port: u16,
+        cert_fingerprint: Vec<u8>,
+        permanent: bool,
+    },
+    /// Forget the given group's certificate exception for the server at the given host and port
+    RemoveCertificateException {
+        group: ResourceGroupId,
+        host: String,
+        port: u16,
+    },
+    /// Ask the embedder on this channel for the credentials to answer `401` and `407` responses
+    /// that there are no usable credentials for, in place of any channel given before
+    SetAuthPromptChannel(IpcSender<AuthPromptRequest>),
+    /// Answer the fetches of URLs with this scheme by asking the given handler, in place of
+    /// any handler registered for it before. The schemes fetch supports itself can't be taken over
+    RegisterSchemeHandler {
+        scheme: String,
+        handler: IpcSender<SchemeRequest>,
+    },
+    /// Synchronization message solely for knowing that the messages sent before it have
+    /// been handled
+    Synchronize(IpcSender<()>),
+}
+
 #[derive(Deserialize, Serialize)]
 pub enum CoreResourceMsg {
     Fetch(RequestInit, IpcSender<FetchResponseMsg>),
+    /// Open a connection to the origin of a URL, and keep it for a later fetch from the
+    /// origin to use, as `<link rel=preconnect>` asks; the sender, if any, is told once the
+    /// connection is in the pool or the attempt has failed
+    Preconnect(ServoUrl, Option<IpcSender<()>>),
     /// Try to make a websocket connection to a URL.
     WebsocketConnect(WebSocketCommunicate, WebSocketConnectData),
-    /// Store a set of cookies for a given originating URL
-    SetCookiesForUrl(ServoUrl, String, CookieSource),
-    /// Store a set of cookies for a given originating URL
+    /// Store a set of cookies for a given originating URL, set while the user was on the
+    /// given first-party URL, if known
+    SetCookiesForUrl(ServoUrl, String, CookieSource, Option<ServoUrl>),
+    /// Store the cookies of many `SetCookiesForUrl` messages at once
+    SetCookiesBatch(Vec<(ServoUrl, String, CookieSource, Option<ServoUrl>)>),
+    /// Store a set of cookies for a given originating URL, set while the user was on the
+    /// given first-party URL, if known
     SetCookiesForUrlWithData(
         ServoUrl,
         #[serde(deserialize_with = "::hyper_serde::deserialize",
                 serialize_with = "::hyper_serde::serialize")]
         Cookie,
-        CookieSource
+        CookieSource,
+        Option<ServoUrl>
     ),
+    /// Decide which cookies are stored from now on
+    SetCookieAcceptPolicy(CookieAcceptPolicy),
+    /// Replace the `User-Agent` sent with fetches and WebSocket handshakes started from now on
+    SetUserAgent(Cow<'static, str>),
+    /// Replace the `Accept-Language` sent with fetches and WebSocket handshakes started from now
+    /// on, given a comma-separated list of language tags in order of preference; an empty list
+    /// omits it. Until this is sent, the `intl.accept_languages` pref is followed
+    SetAcceptLanguage(String),
     /// Retrieve the stored cookies for a given URL
-    GetCookiesForUrl(ServoUrl, IpcSender<Option<String>>, CookieSource),
+    GetCookiesForUrl(ServoUrl, IpcSender<Option<String>>, CookieSource, SameSiteContext),
     /// Get a cookie by name for a given originating URL
     GetCookiesDataForUrl(ServoUrl, IpcSender<Vec<Serde<Cookie>>>, CookieSource),
-    /// Cancel a network request corresponding to a given `ResourceId`
+    /// Get the cookies for a given originating URL, along with the attributes the cookie
+    /// jar derives from them
+    GetCookiesDetailedForUrl(ServoUrl, IpcSender<Vec<CookieDetails>>, CookieSource),
+    /// Cancel a network request corresponding to a given `ResourceId`, as passed in
+    /// `RequestInit::resource_id`
     Cancel(ResourceId),
+    /// Tell a fetch started with a `response_body_window` that its consumer has processed
+    /// this many more bytes of the response body
+    AckResponseBody(ResourceId, usize),
+    /// Add an HSTS entry for a host, with the given max-age in seconds
+    SetHstsEntryForHost(String, IncludeSubdomains, u64),
+    /// Discard every HSTS entry added at runtime, returning the list to its initial state
+    ResetHsts,
+    /// Retrieve the certificate exceptions, ordered by host and port
+    GetCertificateExceptions(IpcSender<Vec<CertificateException>>),
+    /// Close the idle pooled connections to the given host, or to every host
+    CloseIdleConnections(Option<String>),
+    /// Forget the credentials cached for the given host, or for every host, by the group the
+    /// message is sent to, including any given for a proxy in answer to a prompt
+    ClearAuthCache(Option<String>),
+    /// Forget the cookies, cached responses, alternative services and idle connections of
+    /// the given host, for the group the message is sent to
+    ClearSiteData(String),
+    /// Report how many fetches are running and queued for each origin, and the traffic of
+    /// each resource group, resetting the traffic counters afterwards if the flag is set
+    GetNetworkStats(IpcSender<NetworkStats>, bool),
+    /// Start a private browsing session, whose state is kept apart from that of every other
+    /// session and of the public and private groups
+    CreatePrivateSession(SessionId),
+    /// Discard all the state of a private browsing session, and cancel its in-flight fetches
+    RemovePrivateSession(SessionId),
+    /// Handle a message on behalf of the given private browsing session
+    ForSession(SessionId, Box<CoreResourceMsg>),
+    /// Report the memory held by the cookie jars, HSTS lists, auth caches, connection pools
+    /// memory caches and blob store
+    CollectMemoryReports(ReportsChan),
+    /// The memory profiler that CoreResourceThread is registered with as the reporter
+    /// named here, which it unregisters from when it exits
+    MemoryReporter(MemProfilerChan, String),
     /// Synchronization message solely for knowing the state of the ResourceChannelManager loop
     Synchronize(IpcSender<()>),
     /// Send the network sender in constellation to CoreResourceThread
     NetworkMediator(IpcSender<CustomResponseMediator>),
+    /// Send the storage thread, whose data `Clear-Site-Data` responses can clear, to
+    /// CoreResourceThread
+    StorageThread(IpcSender<StorageThreadMsg>),
     /// Message forwarded to file manager's handler
     ToFileManager(FileManagerThreadMsg),
     /// Break the load handler loop, send a reply when done cleaning up local resources
@@ -428,6 +681,13 @@ pub struct Metadata {
     /// Final URL after redirects.
     pub final_url: ServoUrl,
 
+    /// The URL of each hop of the fetch, from the one asked for to the final one.
+    pub url_list: Vec<ServoUrl>,
+
+    /// The status of the response to each hop that redirected, so one fewer than
+    /// there are URLs.
+    pub redirect_statuses: Vec<u16>,
+
     #[ignore_heap_size_of = "Defined in hyper"]
     /// MIME type / subtype.
     pub content_type: Option<Serde<ContentType>>,
@@ -445,14 +705,46 @@ pub struct Metadata {
     /// Is successful HTTPS connection
     pub https_state: HttpsState,
 
+    /// The negotiated TLS parameters, for HTTPS loads
+    pub tls_info: Option<TlsInfo>,
+
+    /// The HTTP version the response came over, as its ALPN ID, e.g. "http/1.1" or "h2"
+    pub protocol: Option<String>,
+
     /// Referrer Url
     pub referrer: Option<ServoUrl>,
+
+    /// For a ranged request, the offset in the whole resource at which the body starts.
+    /// This is `Some(0)` if the server ignored the range and sent the entire resource.
+    pub range_start: Option<u64>,
+
+    /// For a ranged request, the offset in the whole resource of the last byte of the
+    /// body, from the `Content-Range` of a `206` or the `Content-Length` of a `200`.
+    pub range_end: Option<u64>,
+
+    /// For a ranged request, the length of the whole resource, if the server said.
+    pub complete_length: Option<u64>,
+
+    /// Whether this is a stale cached response, used while it is revalidated.
+    pub served_stale: bool,
+
+    /// When the response and its body arrived. The end of the body is only known in
+    /// metadata taken once it has arrived, such as in `process_response_eof`.
+    pub timing: ResourceTiming,
+
+    /// Whether this is mixed content, loaded over an unauthenticated connection for a
+    /// document from `https`. Such loads are only allowed for passive content, such as
+    /// images, unless the `network.mixed-content` prefs say otherwise; the others fail
+    /// with `NetworkError::MixedContentBlocked`.
+    pub mixed_content: bool,
 }
 
 impl Metadata {
     /// Metadata with defaults for everything optional.
     pub fn default(url: ServoUrl) -> Self {
         Metadata {
+            url_list: vec![url.clone()],
+            redirect_statuses: vec![],
             final_url:    url,
             content_type: None,
             charset:      None,
@@ -460,7 +752,15 @@ impl Metadata {
             // https://fetch.spec.whatwg.org/#concept-response-status-message
             status: Some((200, b"OK".to_vec())),
             https_state: HttpsState::None,
+            tls_info: None,
+            protocol: None,
             referrer: None,
+            range_start: None,
+            range_end: None,
+            complete_length: None,
+            served_stale: false,
+            timing: ResourceTiming::default(),
+            mixed_content: false,
         }
     }
 
@@ -490,6 +790,89 @@ impl Metadata {
     }
 }
 
+/// How far a fetch started with `CoreResourceControlMsg::FetchToFile` has got.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum DownloadProgress {
+    /// This many bytes of the body have been saved, out of the total if the response gave it.
+    Progress { received: u64, total: Option<u64> },
+    /// The whole body has been saved to this file.
+    Complete(PathBuf),
+    /// The download failed, and whatever was saved of it has been removed.
+    Failed(NetworkError),
+}
+
+/// A snapshot of the resource thread's fetch accounting, keyed by serialized origin or by host.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NetworkStats {
+    /// The number of fetches currently talking to each origin.
+    pub active_per_origin: HashMap<String, usize>,
+    /// The number of fetches waiting for a connection to each origin.
+    pub queued_per_origin: HashMap<String, usize>,
+    /// The number of connection slots currently taken for each host.
+    pub active_per_host: HashMap<String, usize>,
+    /// The traffic of each resource group: `public`, `private`, and `private-session-<id>`
+    /// for each private browsing session.
+    pub per_group: HashMap<String, NetStatsSnapshot>,
+}
+
+/// The traffic of a resource group since it was created, or since its counters were reset.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct NetStatsSnapshot {
+    /// Bytes of request bodies sent.
+    pub bytes_sent: u64,
+    /// Bytes of response bodies received, after any decompression.
+    pub bytes_received: u64,
+    pub requests_completed: u64,
+    /// Fetches that ended in a network error, including cancelled ones.
+    pub requests_failed: u64,
+    /// HTTP connections carrying a request or response right now. Idle connections
+    /// kept in the pools aren't counted. Never reset.
+    pub open_connections: u64,
+    /// Fetches that haven't finished yet. Never reset.
+    pub active_fetches: u64,
+    /// Unexpired cookies evicted from the cookie jar because it was full.
+    pub cookies_evicted: u64,
+    /// TLS handshakes that resumed an earlier session with the server.
+    pub tls_sessions_resumed: u64,
+    /// TLS handshakes that had no session to resume, or whose session the server refused.
+    pub tls_full_handshakes: u64,
+}
+
+/// A rule for rewriting the URL of outgoing requests, e.g. to strip tracking parameters.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RewriteRule {
+    /// The hosts this rule applies to: an exact host name, `*.example.com` for
+    /// `example.com` and all of its subdomains, or `*` for every host.
+    pub host: String,
+    pub action: RewriteAction,
+}
+
+/// A pattern for URLs: an exact URL, or a URL ending in `*`, such as
+/// `https://example.com/api/*`, for every URL that starts with what comes before it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct UrlPattern(pub String);
+
+impl UrlPattern {
+    pub fn matches(&self, url: &ServoUrl) -> bool {
+        if self.0.ends_with('*') {
+            url.as_str().starts_with(&self.0[..self.0.len() - 1])
+        } else {
+            url.as_str() == self.0
+        }
+    }
+}
+
+/// What a `RewriteRule` does to a matching URL.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum RewriteAction {
+    /// Remove the query parameters with these names. A name ending in `*`,
+    /// such as `utm_*`, removes every parameter starting with that prefix.
+    RemoveQueryParams(Vec<String>),
+    /// Replace every match of a regular expression in the serialized URL.
+    /// The replacement may refer to capture groups as `$1`, `$name`, etc.
+    RegexReplace(String, String),
+}
+
 /// The creator of a given cookie
 #[derive(PartialEq, Copy, Clone, Deserialize, Serialize)]
 pub enum CookieSource {
@@ -499,6 +882,88 @@ pub enum CookieSource {
     NonHTTP,
 }
 
+/// A certificate that is accepted from a server even though it doesn't verify, added with
+/// `CoreResourceControlMsg::AddCertificateException`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CertificateException {
+    pub host: String,
+    pub port: u16,
+    /// The SHA-256 fingerprint of the certificate, as in `TlsInfo::certificate_fingerprints`
+    pub cert_fingerprint: Vec<u8>,
+    /// Whether the exception is kept across restarts, rather than for this run only
+    pub permanent: bool,
+}
+
+/// Identifies a private browsing session started with `CoreResourceMsg::CreatePrivateSession`
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Deserialize, Serialize)]
+pub struct SessionId(pub u32);
+
+/// Names one of a resource thread's groups to the messages on its `CoreResourceControlThread`
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum ResourceGroupId {
+    Public,
+    Private,
+    Session(SessionId),
+}
+
+/// Which cookies are stored when a site tries to set them
+#[derive(PartialEq, Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum CookieAcceptPolicy {
+    /// Store every cookie
+    All,
+    /// Store no cookies at all
+    NoneAtAll,
+    /// Only store cookies set by the same site as the first party; cookies set without
+    /// a known first party are rejected
+    FromOriginatingSiteOnly,
+    /// Reject cookies set by a site other than the first party; cookies set without
+    /// a known first party are stored
+    NoThirdParty,
+}
+
+/// The value of a cookie's `SameSite` attribute.
+/// https://tools.ietf.org/html/draft-ietf-httpbis-rfc6265bis-02#section-5.3.7
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A stored cookie, along with the attributes that the cookie jar derives from it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CookieDetails {
+    /// The cookie as stored, whose domain and path have been filled in
+    #[serde(deserialize_with = "::hyper_serde::deserialize",
+            serialize_with = "::hyper_serde::serialize")]
+    pub cookie: Cookie,
+    /// When the cookie expires, in seconds since the Unix epoch, whether it was set with
+    /// `Max-Age` or `Expires`; `None` for session cookies
+    pub expires_at: Option<i64>,
+    /// The `SameSite` attribute, `Lax` if it was missing or unrecognised
+    pub same_site: SameSite,
+    /// Whether the cookie goes away at the end of the session
+    pub session: bool,
+    /// Whether the cookie is only sent to the host that set it, rather than to its subdomains too
+    pub host_only: bool,
+    /// When the cookie was stored, in seconds since the Unix epoch
+    pub created_at: i64,
+    /// When the cookie was last sent or read, in seconds since the Unix epoch
+    pub last_accessed_at: i64,
+}
+
+/// How the site that initiated a request relates to the site of the requested URL,
+/// which decides whether cookies carrying a `SameSite` attribute are sent with it
+#[derive(PartialEq, Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum SameSiteContext {
+    /// The request is made from the same registrable domain as the requested URL
+    SameSite,
+    /// A cross-site top-level navigation using a safe method
+    CrossSiteTopLevelNavigation,
+    /// Any other cross-site request
+    CrossSite,
+}
+
 /// Messages sent in response to a `Load` message
 #[derive(PartialEq, Debug, Deserialize, Serialize)]
 pub enum ProgressMsg {
@@ -520,7 +985,9 @@ pub fn load_whole_resource(request: RequestInit,
     loop {
         match action_receiver.recv().unwrap() {
             FetchResponseMsg::ProcessRequestBody |
-            FetchResponseMsg::ProcessRequestEOF => (),
+            FetchResponseMsg::ProcessRequestBodyProgress(..) |
+            FetchResponseMsg::ProcessRequestEOF |
+            FetchResponseMsg::ProcessEarlyHints(_) => (),
             FetchResponseMsg::ProcessResponse(Ok(m)) => {
                 metadata = Some(match m {
                     FetchMetadata::Unfiltered(m) => m,
@@ -528,9 +995,9 @@ pub fn load_whole_resource(request: RequestInit,
                 })
             },
             FetchResponseMsg::ProcessResponseChunk(data) => buf.extend_from_slice(&data),
-            FetchResponseMsg::ProcessResponseEOF(Ok(())) => return Ok((metadata.unwrap(), buf)),
+            FetchResponseMsg::ProcessResponseEOF(Ok(()), _) => return Ok((metadata.unwrap(), buf)),
             FetchResponseMsg::ProcessResponse(Err(e)) |
-            FetchResponseMsg::ProcessResponseEOF(Err(e)) => return Err(e)
+            FetchResponseMsg::ProcessResponseEOF(Err(e), _) => return Err(e)
         }
     }
 }
@@ -550,6 +1017,54 @@ pub enum ConstellationMsg {
     IsPrivate(PipelineId, IpcSender<bool>),
 }
 
+/// Why the certificate of a server didn't verify
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize, HeapSizeOf)]
+pub enum CertificateFailure {
+    /// The certificate is past its `notAfter` time
+    Expired,
+    /// The `notBefore` time of the certificate is yet to come
+    NotYetValid,
+    /// The certificate isn't for the host that was connected to
+    HostnameMismatch,
+    /// The certificate is signed by itself rather than by a trusted authority
+    SelfSigned,
+    /// The certificate chain doesn't lead to a trusted authority
+    UnknownIssuer,
+    /// The certificate was revoked by its issuer
+    Revoked,
+    /// Any other reason, such as a bad signature
+    Other,
+}
+
+/// A certificate presented by a server
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize, HeapSizeOf)]
+pub struct CertificateDetails {
+    /// The DER encoding of the certificate
+    pub der: Vec<u8>,
+    /// The SHA-256 fingerprint of the certificate, which `AddCertificateException` takes to
+    /// accept it from the server
+    pub fingerprint: Vec<u8>,
+    /// The distinguished name of the subject, e.g. "C=US, O=Example, CN=example.com"
+    pub subject: String,
+    /// The distinguished name of the issuer
+    pub issuer: String,
+    /// The start of the validity period, in UTC, e.g. "2017-01-31T12:00:00Z"
+    pub not_before: String,
+    /// The end of the validity period, in UTC
+    pub not_after: String,
+}
+
+/// The details of a TLS handshake that failed because the server's certificate
+/// didn't verify, for error pages to explain
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize, HeapSizeOf)]
+pub struct SslValidationError {
+    pub failure: CertificateFailure,
+    /// The certificates the server presented, its own first, as far as verification got
+    pub chain: Vec<CertificateDetails>,
+    /// The errors OpenSSL reported, as HTML
+    pub message: String,
+}
+
 /// Network errors that have to be exported out of the loaders
 #[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize, HeapSizeOf)]
 pub enum NetworkError {
@@ -557,7 +1072,24 @@ pub enum NetworkError {
     Internal(String),
     LoadCancelled,
     /// SSL validation error that has to be handled in the HTML parser
-    SslValidation(ServoUrl, String),
+    SslValidation(ServoUrl, SslValidationError),
+    /// The HTTP proxy refused to open a tunnel, answering `CONNECT` with this status.
+    ProxyTunnelFailed(u16),
+    /// The TLS handshake failed because the server agreed on no TLS version or cipher suite
+    /// that the TLS policy allows; the message names the host and the policy.
+    TlsPolicy(String),
+    /// The fetch didn't finish within the timeout it was given.
+    Timeout,
+    /// The content blocker blocked the request, because of the rule with this `url-filter`.
+    Blocked { rule: String },
+    /// The request could only be answered from the cache, which had nothing usable for it;
+    /// the equivalent of a `504 Gateway Timeout` from a cache.
+    GatewayTimeout,
+    /// The fetch was redirected more times than `network.http.redirection-limit` allows.
+    TooManyRedirects,
+    /// The request was blocked as mixed content, because it would load active content, such
+    /// as a script, over an unauthenticated connection for a document from `https`.
+    MixedContentBlocked,
 }
 
 /// Normalize `slice`, as defined by
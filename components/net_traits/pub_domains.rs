@@ -16,7 +16,9 @@
 
 use std::collections::HashSet;
 use std::iter::FromIterator;
+use std::net::Ipv6Addr;
 use std::str::from_utf8;
+use url::Host;
 use util::resource_files::read_resource_file;
 
 #[derive(Clone,Debug)]
@@ -138,3 +140,26 @@ pub fn is_pub_domain(domain: &str) -> bool {
 pub fn is_reg_domain(domain: &str) -> bool {
     PUB_DOMAINS.is_registrable_suffix(domain)
 }
+
+/// The host that `host` would be serialized as in a URL: domains are lowercased and
+/// punycode-encoded, and IPv6 addresses are compressed and bracketed. Hosts that can't
+/// be parsed are returned as they are.
+pub fn canonical_host(host: &str) -> String {
+    parse_host(host).map_or_else(|| host.to_owned(), |host| host.to_string())
+}
+
+/// Whether `host` is an IPv4 or IPv6 address rather than a domain.
+pub fn is_ip_host(host: &str) -> bool {
+    match parse_host(host) {
+        Some(Host::Domain(_)) | None => false,
+        Some(Host::Ipv4(_)) | Some(Host::Ipv6(_)) => true,
+    }
+}
+
+fn parse_host(host: &str) -> Option<Host> {
+    // `Host::parse` only accepts IPv6 addresses between brackets, as they appear in URLs.
+    match host.parse::<Ipv6Addr>() {
+        Ok(address) => Some(Host::Ipv6(address)),
+        Err(_) => Host::parse(host).ok(),
+    }
+}
@@ -3,13 +3,36 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use ReferrerPolicy;
+use ResourceId;
+use filemanager_thread::FileOrigin;
 use hyper::header::Headers;
 use hyper::method::Method;
+use ipc_channel::ipc::IpcSender;
 use msg::constellation_msg::PipelineId;
 use servo_url::ServoUrl;
 use std::cell::{Cell, RefCell};
 use std::default::Default;
-use url::{Origin as UrlOrigin};
+use url::{Origin as UrlOrigin, Position};
+use uuid::Uuid;
+
+/// The request headers whose values are part of a cache key. These are the headers that
+/// differ between requests for the same URL, as `Accept` does with the destination and
+/// `Accept-Language` with the user's preferences, and that responses commonly vary on.
+pub const CACHE_KEY_HEADERS: [&'static str; 2] = ["Accept", "Accept-Language"];
+
+/// The key under which the response to a request with `method` for `url` and `headers`
+/// is cached. It is made of the method, the URL without its fragment and then, on lines
+/// of their own, the name and value of each header of `CACHE_KEY_HEADERS` that is set.
+pub fn cache_key(method: &Method, url: &ServoUrl, headers: &Headers) -> String {
+    let mut key = format!("{} {}", method, &url[..Position::AfterQuery]);
+    for name in &CACHE_KEY_HEADERS {
+        if let Some(values) = headers.get_raw(name) {
+            let values: Vec<_> = values.iter().map(|value| String::from_utf8_lossy(value)).collect();
+            key.push_str(&format!("\n{}: {}", name, values.join(",")));
+        }
+    }
+    key
+}
 
 /// An [initiator](https://fetch.spec.whatwg.org/#concept-request-initiator)
 #[derive(Copy, Clone, PartialEq, HeapSizeOf)]
@@ -36,6 +59,44 @@ pub enum Destination {
     SharedWorker, Style, Worker, XSLT
 }
 
+/// How urgently a fetch should be scheduled relative to other pending fetches,
+/// from most to least urgent. `Idle` fetches only run when nothing else is waiting.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, HeapSizeOf)]
+pub enum RequestPriority {
+    Highest,
+    High,
+    Normal,
+    Low,
+    Idle,
+}
+
+impl RequestPriority {
+    /// The priority of a fetch for `destination` that didn't ask for a specific one.
+    pub fn for_destination(destination: Destination) -> RequestPriority {
+        match destination {
+            Destination::Document => RequestPriority::Highest,
+            Destination::Font | Destination::Script | Destination::Style | Destination::XSLT =>
+                RequestPriority::High,
+            Destination::Embed | Destination::Image | Destination::Media | Destination::Object =>
+                RequestPriority::Low,
+            Destination::Report => RequestPriority::Idle,
+            _ => RequestPriority::Normal,
+        }
+    }
+
+    /// The urgency sent for this priority in the `Priority` header, from 0 (most
+    /// urgent) to 7. https://tools.ietf.org/html/rfc9218#section-4.1
+    pub fn urgency(&self) -> u8 {
+        match *self {
+            RequestPriority::Highest => 0,
+            RequestPriority::High => 1,
+            RequestPriority::Normal => 3,
+            RequestPriority::Low => 5,
+            RequestPriority::Idle => 7,
+        }
+    }
+}
+
 /// A request [origin](https://fetch.spec.whatwg.org/#concept-request-origin)
 #[derive(Clone, PartialEq, Debug, HeapSizeOf)]
 pub enum Origin {
@@ -111,6 +172,29 @@ pub enum CorsSettings {
     UseCredentials
 }
 
+/// A piece of a request body that is streamed rather than sent from memory.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum BodyPart {
+    Bytes(Vec<u8>),
+    /// The contents of a file or blob in the `FileManager`, read as the body is sent.
+    File(Uuid, FileOrigin),
+    /// Bytes made by the sender's owner, with their length if it is known. Each chunk is
+    /// asked for once the one before it has been sent, by sending the sender that it is to
+    /// be sent back on; an empty chunk ends the stream. It can only be sent once, so a
+    /// request with it fails rather than send it again after a redirect or a challenge.
+    Stream(IpcSender<IpcSender<Vec<u8>>>, Option<u64>),
+}
+
+impl BodyPart {
+    /// Whether the part can be read again to send the body another time.
+    pub fn is_replayable(&self) -> bool {
+        match *self {
+            BodyPart::Bytes(_) | BodyPart::File(..) => true,
+            BodyPart::Stream(..) => false,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, HeapSizeOf)]
 pub struct RequestInit {
     #[serde(deserialize_with = "::hyper_serde::deserialize",
@@ -124,6 +208,10 @@ pub struct RequestInit {
     pub headers: Headers,
     pub unsafe_request: bool,
     pub body: Option<Vec<u8>>,
+    /// A body sent by streaming its parts one after the other, such as a file upload or a
+    /// multipart form with file parts. Ignored if `body` is set.
+    #[ignore_heap_size_of = "Defined in uuid and ipc-channel"]
+    pub body_parts: Option<Vec<BodyPart>>,
     // TODO: client object
     pub type_: Type,
     pub destination: Destination,
@@ -141,6 +229,49 @@ pub struct RequestInit {
     pub referrer_policy: Option<ReferrerPolicy>,
     pub pipeline_id: Option<PipelineId>,
     pub redirect_mode: RedirectMode,
+    /// Byte offset to resume the body from, sent as `Range: bytes=N-`.
+    pub range_start: Option<u64>,
+    /// Byte offset of the last byte wanted, which makes the range sent `bytes=N-M`.
+    /// Ignored unless `range_start` is set.
+    pub range_end: Option<u64>,
+    /// Identifier that can be passed to `CoreResourceMsg::Cancel` to abort this fetch.
+    pub resource_id: Option<ResourceId>,
+    /// If set, the fetch stops reading the response body once this many bytes have been
+    /// delivered without being acknowledged with `CoreResourceMsg::AckResponseBody`.
+    /// Requires `resource_id` to be set.
+    pub response_body_window: Option<usize>,
+    /// How urgently to schedule this fetch. If unset, it is chosen based on `destination`.
+    pub priority: Option<RequestPriority>,
+    /// If set, the fetch fails with `NetworkError::Timeout` unless the response has arrived
+    /// this many milliseconds after the resource thread received it. The time spent
+    /// connecting, in the TLS handshake and following redirects all counts.
+    pub timeout_ms: Option<u64>,
+    /// Whether the whole response body must also have arrived within `timeout_ms`.
+    pub timeout_covers_body: bool,
+    /// Whether the fetch was started by the user, such as by clicking a link, which is
+    /// sent as `Sec-Fetch-User` with navigations.
+    pub user_activation: bool,
+    /// Whether the response body may be larger than `network.http.max-response-size`,
+    /// such as for a download that is written to a file.
+    pub unbounded_body: bool,
+    /// Whether the response body is delivered as the server sent it, without undoing its
+    /// `Content-Encoding`. Such fetches neither use nor fill the caches, which hold
+    /// decoded bodies.
+    pub raw_body: bool,
+    /// The scheme of the document that started the fetch, if any. Loads from `http` for a
+    /// document from `https` are mixed content.
+    pub document_scheme: Option<String>,
+    /// A comma-separated list of language tags to send as `Accept-Language` instead of the
+    /// resource group's, such as for a tab that reports a different locale. An empty list
+    /// sends none.
+    pub accept_language: Option<String>,
+}
+
+impl RequestInit {
+    /// The priority the resource thread schedules this fetch with.
+    pub fn scheduling_priority(&self) -> RequestPriority {
+        self.priority.unwrap_or_else(|| RequestPriority::for_destination(self.destination))
+    }
 }
 
 impl Default for RequestInit {
@@ -151,6 +282,7 @@ impl Default for RequestInit {
             headers: Headers::new(),
             unsafe_request: false,
             body: None,
+            body_parts: None,
             type_: Type::None,
             destination: Destination::None,
             synchronous: false,
@@ -164,6 +296,18 @@ impl Default for RequestInit {
             referrer_policy: None,
             pipeline_id: None,
             redirect_mode: RedirectMode::Follow,
+            range_start: None,
+            range_end: None,
+            resource_id: None,
+            response_body_window: None,
+            priority: None,
+            timeout_ms: None,
+            timeout_covers_body: false,
+            user_activation: false,
+            unbounded_body: false,
+            raw_body: false,
+            document_scheme: None,
+            accept_language: None,
         }
     }
 }
@@ -179,6 +323,8 @@ pub struct Request {
     pub headers: RefCell<Headers>,
     pub unsafe_request: bool,
     pub body: RefCell<Option<Vec<u8>>>,
+    #[ignore_heap_size_of = "Defined in uuid and ipc-channel"]
+    pub body_parts: RefCell<Option<Vec<BodyPart>>>,
     // TODO: client object
     pub is_service_worker_global_scope: bool,
     pub window: Cell<Window>,
@@ -188,7 +334,8 @@ pub struct Request {
     pub initiator: Initiator,
     pub type_: Type,
     pub destination: Destination,
-    // TODO: priority object
+    /// The priority the request asked for, sent to the server as the `Priority` header.
+    pub priority: Option<RequestPriority>,
     pub origin: RefCell<Origin>,
     pub omit_origin_header: Cell<bool>,
     /// https://fetch.spec.whatwg.org/#concept-request-referrer
@@ -207,7 +354,21 @@ pub struct Request {
     // first method to act as spec url field
     pub url_list: RefCell<Vec<ServoUrl>>,
     pub redirect_count: Cell<u32>,
+    /// The status of each redirect that has been followed, in order.
+    pub redirect_statuses: RefCell<Vec<u16>>,
     pub response_tainting: Cell<ResponseTainting>,
+    /// Byte offset to resume the body from, if this is a resumed download.
+    pub range_start: Option<u64>,
+    /// Byte offset of the last byte wanted, if `range_start` is set.
+    pub range_end: Option<u64>,
+    /// Whether the request was started by the user.
+    pub user_activation: bool,
+    /// Whether the response body is exempt from `network.http.max-response-size`.
+    pub unbounded_body: bool,
+    /// Whether the response body is delivered without undoing its `Content-Encoding`.
+    pub raw_body: bool,
+    /// The scheme of the document that started the request, if any.
+    pub document_scheme: Option<String>,
 }
 
 impl Request {
@@ -222,6 +383,7 @@ impl Request {
             headers: RefCell::new(Headers::new()),
             unsafe_request: false,
             body: RefCell::new(None),
+            body_parts: RefCell::new(None),
             is_service_worker_global_scope: is_service_worker_global_scope,
             window: Cell::new(Window::Client),
             keep_alive: Cell::new(false),
@@ -229,6 +391,7 @@ impl Request {
             initiator: Initiator::None,
             type_: Type::None,
             destination: Destination::None,
+            priority: None,
             origin: RefCell::new(origin.unwrap_or(Origin::Client)),
             omit_origin_header: Cell::new(false),
             referrer: RefCell::new(Referrer::Client),
@@ -244,7 +407,14 @@ impl Request {
             integrity_metadata: RefCell::new(String::new()),
             url_list: RefCell::new(vec![url]),
             redirect_count: Cell::new(0),
+            redirect_statuses: RefCell::new(vec![]),
             response_tainting: Cell::new(ResponseTainting::Basic),
+            range_start: None,
+            range_end: None,
+            user_activation: false,
+            unbounded_body: false,
+            raw_body: false,
+            document_scheme: None,
         }
     }
 
@@ -256,8 +426,10 @@ impl Request {
         *req.headers.borrow_mut() = init.headers;
         req.unsafe_request = init.unsafe_request;
         *req.body.borrow_mut() = init.body;
+        *req.body_parts.borrow_mut() = init.body_parts;
         req.type_ = init.type_;
         req.destination = init.destination;
+        req.priority = init.priority;
         req.synchronous = init.synchronous;
         req.mode = init.mode;
         req.use_cors_preflight = init.use_cors_preflight;
@@ -272,6 +444,12 @@ impl Request {
         req.referrer_policy.set(init.referrer_policy);
         req.pipeline_id.set(init.pipeline_id);
         req.redirect_mode.set(init.redirect_mode);
+        req.range_start = init.range_start;
+        req.range_end = init.range_end;
+        req.user_activation = init.user_activation;
+        req.unbounded_body = init.unbounded_body;
+        req.raw_body = init.raw_body;
+        req.document_scheme = init.document_scheme;
         req
     }
 
@@ -283,6 +461,13 @@ impl Request {
         self.url_list.borrow().last().unwrap().clone()
     }
 
+    /// The key under which the response to this request is cached, which two requests
+    /// share exactly when they are the same request as far as caching is concerned.
+    /// See `cache_key` for what it is made of.
+    pub fn cache_key(&self) -> String {
+        cache_key(&self.method.borrow(), &self.current_url(), &self.headers.borrow())
+    }
+
     pub fn is_navigation_request(&self) -> bool {
         self.destination == Destination::Document
     }
@@ -296,6 +481,42 @@ impl Request {
             _ => false
         }
     }
+
+    /// Whether the request is [optionally-blockable](https://w3c.github.io/webappsec-mixed-content/#category-optionally-blockable)
+    /// mixed content if it is mixed content at all, that is whether it only loads passive
+    /// content such as an image, which can't act on the rest of the page.
+    pub fn is_passive_content(&self) -> bool {
+        match self.destination {
+            Destination::Image | Destination::Media => true,
+            Destination::None => match self.type_ {
+                Type::Audio | Type::Image | Type::Video => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Whether fetching the current URL is [mixed content](https://w3c.github.io/webappsec-mixed-content/#mixed-content):
+    /// a load from a URL that isn't authenticated for a document that was.
+    pub fn is_mixed_content(&self) -> bool {
+        if self.is_navigation_request() {
+            return false;
+        }
+        match self.document_scheme {
+            Some(ref scheme) => is_mixed_content(scheme, &self.current_url()),
+            None => false,
+        }
+    }
+}
+
+/// Whether loading `url` for a document whose URL has the scheme `document_scheme` is
+/// [mixed content](https://w3c.github.io/webappsec-mixed-content/#mixed-content).
+pub fn is_mixed_content(document_scheme: &str, url: &ServoUrl) -> bool {
+    let secure_document = document_scheme == "https" || document_scheme == "wss";
+    secure_document && match url.scheme() {
+        "http" | "ws" | "ftp" => true,
+        _ => false,
+    }
 }
 
 impl Referrer {
@@ -57,7 +57,9 @@ pub enum CacheState {
     None,
     Local,
     Validated,
-    Partial
+    Partial,
+    /// A stale local response, used while it is revalidated in the background.
+    StaleWhileRevalidate,
 }
 
 /// [Https state](https://fetch.spec.whatwg.org/#concept-response-https-state)
@@ -68,6 +70,34 @@ pub enum HttpsState {
     Modern
 }
 
+/// Details of the TLS connection that a response was received over
+#[derive(Clone, Debug, Deserialize, Serialize, HeapSizeOf)]
+pub struct TlsInfo {
+    /// The negotiated protocol version, e.g. "TLSv1.2"
+    pub protocol_version: String,
+    /// The negotiated cipher suite, using OpenSSL's naming
+    pub cipher_suite: String,
+    /// SHA-256 fingerprints of the peer's certificate chain, leaf first
+    pub certificate_fingerprints: Vec<Vec<u8>>,
+    /// The application protocol agreed on through ALPN, e.g. "http/1.1", if the server
+    /// took part in it. Without it, HTTP/1.1 is used.
+    pub alpn_protocol: Option<String>,
+    /// Whether the certificate didn't verify, and was only accepted because of a
+    /// certificate exception. The connection is then no more secure than the user judged.
+    pub certificate_exception: bool,
+}
+
+/// When a response reached the phases of its fetch, in nanoseconds since its request
+/// started. The times are taken with a monotonic clock, and relative ones mean the same
+/// on both sides of IPC.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, HeapSizeOf)]
+pub struct ResourceTiming {
+    /// When the head of the response arrived.
+    pub first_byte: Option<u64>,
+    /// When the whole body had arrived.
+    pub response_end: Option<u64>,
+}
+
 pub enum ResponseMsg {
     Chunk(Vec<u8>),
     Finished,
@@ -81,6 +111,8 @@ pub struct Response {
     pub termination_reason: Option<TerminationReason>,
     url: Option<ServoUrl>,
     pub url_list: RefCell<Vec<ServoUrl>>,
+    /// The status of each redirect that was followed to get this response.
+    pub redirect_statuses: RefCell<Vec<u16>>,
     /// `None` can be considered a StatusCode of `0`.
     #[ignore_heap_size_of = "Defined in hyper"]
     pub status: Option<StatusCode>,
@@ -91,7 +123,32 @@ pub struct Response {
     pub body: Arc<Mutex<ResponseBody>>,
     pub cache_state: CacheState,
     pub https_state: HttpsState,
+    pub tls_info: Option<TlsInfo>,
+    /// The ALPN ID of the HTTP version the response came over, e.g. "h2", for responses
+    /// from the network
+    pub protocol: Option<String>,
     pub referrer: Option<ServoUrl>,
+    /// Offset of the first body byte within the whole resource, when the
+    /// request asked for a range
+    pub range_start: Option<u64>,
+    /// Offset of the last body byte within the whole resource, when the request asked
+    /// for a range and it is known
+    pub range_end: Option<u64>,
+    /// The length of the whole resource, when the request asked for a range and it is known
+    pub complete_length: Option<u64>,
+    /// The `Link` values of each 103 Early Hints response that came before this one
+    pub early_hints: Vec<Vec<String>>,
+    /// When the response and its body arrived, which is only known for responses from
+    /// the network. It is shared with the thread that receives the body.
+    #[ignore_heap_size_of = "Mutex heap size undefined"]
+    pub timing: Arc<Mutex<ResourceTiming>>,
+    /// The trailer fields that came after the body, which only chunked responses from the
+    /// network have. It is shared with the thread that receives the body.
+    #[ignore_heap_size_of = "Mutex heap size undefined"]
+    pub trailers: Arc<Mutex<Option<Headers>>>,
+    /// Whether the response was loaded as mixed content, which is only allowed for passive
+    /// content unless `network.mixed-content.block-active` is turned off.
+    pub mixed_content: bool,
     /// [Internal response](https://fetch.spec.whatwg.org/#concept-internal-response), only used if the Response
     /// is a filtered response
     pub internal_response: Option<Box<Response>>,
@@ -106,13 +163,23 @@ impl Response {
             termination_reason: None,
             url: Some(url),
             url_list: RefCell::new(Vec::new()),
+            redirect_statuses: RefCell::new(vec![]),
             status: Some(StatusCode::Ok),
             raw_status: Some((200, b"OK".to_vec())),
             headers: Headers::new(),
             body: Arc::new(Mutex::new(ResponseBody::Empty)),
             cache_state: CacheState::None,
             https_state: HttpsState::None,
+            tls_info: None,
+            protocol: None,
             referrer: None,
+            range_start: None,
+            range_end: None,
+            complete_length: None,
+            early_hints: vec![],
+            timing: Arc::new(Mutex::new(ResourceTiming::default())),
+            trailers: Arc::new(Mutex::new(None)),
+            mixed_content: false,
             internal_response: None,
             return_internal: Cell::new(true)
         }
@@ -124,13 +191,23 @@ impl Response {
             termination_reason: None,
             url: None,
             url_list: RefCell::new(vec![]),
+            redirect_statuses: RefCell::new(vec![]),
             status: None,
             raw_status: None,
             headers: Headers::new(),
             body: Arc::new(Mutex::new(ResponseBody::Empty)),
             cache_state: CacheState::None,
             https_state: HttpsState::None,
+            tls_info: None,
+            protocol: None,
             referrer: None,
+            range_start: None,
+            range_end: None,
+            complete_length: None,
+            early_hints: vec![],
+            timing: Arc::new(Mutex::new(ResourceTiming::default())),
+            trailers: Arc::new(Mutex::new(None)),
+            mixed_content: false,
             internal_response: None,
             return_internal: Cell::new(true)
         }
@@ -208,7 +285,7 @@ impl Response {
 
                 let headers = old_headers.iter().filter(|header| {
                     match &*header.name().to_ascii_lowercase() {
-                        "cache-control" | "content-language" | "content-type" |
+                        "cache-control" | "content-language" | "content-length" | "content-type" |
                         "expires" | "last-modified" | "pragma" => true,
                         "set-cookie" | "set-cookie2" => false,
                         header => {
@@ -223,27 +300,55 @@ impl Response {
 
             ResponseType::Opaque => {
                 response.url_list = RefCell::new(vec![]);
+                response.redirect_statuses = RefCell::new(vec![]);
                 response.url = None;
                 response.headers = Headers::new();
                 response.status = None;
+                response.raw_status = None;
                 response.body = Arc::new(Mutex::new(ResponseBody::Empty));
                 response.cache_state = CacheState::None;
+                response.early_hints = vec![];
+                response.timing = Arc::new(Mutex::new(ResourceTiming::default()));
+                response.trailers = Arc::new(Mutex::new(None));
             },
 
             ResponseType::OpaqueRedirect => {
                 response.headers = Headers::new();
                 response.status = None;
+                response.raw_status = None;
                 response.body = Arc::new(Mutex::new(ResponseBody::Empty));
                 response.cache_state = CacheState::None;
+                response.early_hints = vec![];
+                response.timing = Arc::new(Mutex::new(ResourceTiming::default()));
+                response.trailers = Arc::new(Mutex::new(None));
             }
         }
 
         response
     }
 
+    /// The response as it is for a part of a `multipart/x-mixed-replace` response, which
+    /// has the header fields of the part in place of those of the whole response. An
+    /// opaque response keeps its empty headers.
+    pub fn part(&self, headers: Headers) -> Response {
+        let mut part = self.clone();
+        if let Some(ref mut internal_response) = part.internal_response {
+            internal_response.headers = headers.clone();
+        }
+        match part.response_type {
+            ResponseType::Opaque | ResponseType::OpaqueRedirect => {}
+            _ => part.headers = headers,
+        }
+        part
+    }
+
     pub fn metadata(&self) -> Result<FetchMetadata, NetworkError> {
         fn init_metadata(response: &Response, url: &ServoUrl) -> Metadata {
             let mut metadata = Metadata::default(url.clone());
+            if !response.url_list.borrow().is_empty() {
+                metadata.url_list = response.url_list.borrow().clone();
+                metadata.redirect_statuses = response.redirect_statuses.borrow().clone();
+            }
             metadata.set_content_type(match response.headers.get() {
                 Some(&ContentType(ref mime)) => Some(mime),
                 None => None
@@ -251,7 +356,18 @@ impl Response {
             metadata.headers = Some(Serde(response.headers.clone()));
             metadata.status = response.raw_status.clone();
             metadata.https_state = response.https_state;
+            metadata.tls_info = response.tls_info.clone();
+            metadata.protocol = response.protocol.clone();
             metadata.referrer = response.referrer.clone();
+            metadata.range_start = response.range_start;
+            metadata.range_end = response.range_end;
+            metadata.complete_length = response.complete_length;
+            metadata.timing = response.timing.lock().unwrap().clone();
+            metadata.mixed_content = response.mixed_content;
+            metadata.served_stale = match response.cache_state {
+                CacheState::StaleWhileRevalidate => true,
+                _ => false,
+            };
             metadata
         };
 
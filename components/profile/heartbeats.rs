@@ -37,6 +37,10 @@ pub fn init() {
     maybe_create_heartbeat(&mut hbs, ProfilerCategory::LayoutParallelWarmup);
     maybe_create_heartbeat(&mut hbs, ProfilerCategory::LayoutDispListBuild);
     maybe_create_heartbeat(&mut hbs, ProfilerCategory::NetHTTPRequestResponse);
+    maybe_create_heartbeat(&mut hbs, ProfilerCategory::NetDNSConnect);
+    maybe_create_heartbeat(&mut hbs, ProfilerCategory::NetTLSHandshake);
+    maybe_create_heartbeat(&mut hbs, ProfilerCategory::NetTimeToFirstByte);
+    maybe_create_heartbeat(&mut hbs, ProfilerCategory::NetBodyDownload);
     maybe_create_heartbeat(&mut hbs, ProfilerCategory::PaintingPerTile);
     maybe_create_heartbeat(&mut hbs, ProfilerCategory::PaintingPrepBuff);
     maybe_create_heartbeat(&mut hbs, ProfilerCategory::Painting);
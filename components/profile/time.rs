@@ -122,6 +122,10 @@ impl Formattable for ProfilerCategory {
             ProfilerCategory::LayoutParallelWarmup => "Parallel Warmup",
             ProfilerCategory::LayoutDispListBuild => "Display List Construction",
             ProfilerCategory::NetHTTPRequestResponse => "Network HTTP Request/Response",
+            ProfilerCategory::NetDNSConnect => "Network DNS and Connect",
+            ProfilerCategory::NetTLSHandshake => "Network TLS Handshake",
+            ProfilerCategory::NetTimeToFirstByte => "Network Time To First Byte",
+            ProfilerCategory::NetBodyDownload => "Network Body Download",
             ProfilerCategory::PaintingPerTile => "Painting Per Tile",
             ProfilerCategory::PaintingPrepBuff => "Buffer Prep",
             ProfilerCategory::Painting => "Painting",
@@ -58,6 +58,10 @@ pub enum ProfilerCategory {
     LayoutParallelWarmup = 0x1d,
     LayoutDispListBuild = 0x1e,
     NetHTTPRequestResponse = 0x30,
+    NetDNSConnect = 0x31,
+    NetTLSHandshake = 0x32,
+    NetTimeToFirstByte = 0x33,
+    NetBodyDownload = 0x34,
     PaintingPerTile = 0x41,
     PaintingPrepBuff = 0x42,
     Painting = 0x43,
@@ -165,6 +165,15 @@ impl Blob {
         }
     }
 
+    /// Get the FileID of a file-based Blob, whose content the file manager
+    /// can read itself, such as when it is sent as a request body
+    pub fn file_id(&self) -> Option<Uuid> {
+        match *self.blob_impl.borrow() {
+            BlobImpl::File(ref f) => Some(f.id.clone()),
+            _ => None,
+        }
+    }
+
     /// Get a FileID representing the Blob content,
     /// used by URL.createObjectURL
     pub fn get_blob_url_id(&self) -> Uuid {
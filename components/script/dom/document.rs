@@ -98,7 +98,7 @@ use js::jsapi::{JSContext, JSObject, JSRuntime};
 use js::jsapi::JS_GetRuntime;
 use msg::constellation_msg::{ALT, CONTROL, SHIFT, SUPER};
 use msg::constellation_msg::{FrameId, Key, KeyModifiers, KeyState};
-use net_traits::{FetchResponseMsg, IpcSend, ReferrerPolicy};
+use net_traits::{FetchResponseMsg, IpcSend, ReferrerPolicy, SameSiteContext};
 use net_traits::CookieSource::NonHTTP;
 use net_traits::CoreResourceMsg::{GetCookiesForUrl, SetCookiesForUrl};
 use net_traits::request::RequestInit;
@@ -1556,8 +1556,11 @@ impl Document {
     }
 
     pub fn fetch_async(&self, load: LoadType,
-                       request: RequestInit,
+                       mut request: RequestInit,
                        fetch_target: IpcSender<FetchResponseMsg>) {
+        if request.document_scheme.is_none() {
+            request.document_scheme = Some(self.url().scheme().to_owned());
+        }
         let mut loader = self.loader.borrow_mut();
         loader.fetch_async(load, request, fetch_target);
     }
@@ -2945,7 +2948,7 @@ impl DocumentMethods for Document {
         let _ = self.window
             .upcast::<GlobalScope>()
             .resource_threads()
-            .send(GetCookiesForUrl(url, tx, NonHTTP));
+            .send(GetCookiesForUrl(url, tx, NonHTTP, SameSiteContext::SameSite));
         let cookies = rx.recv().unwrap();
         Ok(cookies.map_or(DOMString::new(), DOMString::from))
     }
@@ -2961,10 +2964,13 @@ impl DocumentMethods for Document {
         }
 
         let url = self.url();
+        // FIXME: the first party of a nested document is its top-level document, which
+        // may live in another script thread.
+        let first_party = if self.window.is_top_level() { Some(url.clone()) } else { None };
         let _ = self.window
                     .upcast::<GlobalScope>()
                     .resource_threads()
-                    .send(SetCookiesForUrl(url, String::from(cookie), NonHTTP));
+                    .send(SetCookiesForUrl(url, String::from(cookie), NonHTTP, first_party));
         Ok(())
     }
 
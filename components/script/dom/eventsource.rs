@@ -358,6 +358,7 @@ impl EventSource {
             url: url_record,
             origin: global.get_url(),
             pipeline_id: Some(global.pipeline_id()),
+            document_scheme: Some(global.get_url().scheme().to_owned()),
             // https://html.spec.whatwg.org/multipage/#create-a-potential-cors-request
             use_url_credentials: true,
             mode: RequestMode::CorsMode,
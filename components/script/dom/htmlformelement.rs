@@ -44,11 +44,14 @@ use html5ever_atoms::LocalName;
 use hyper::header::{Charset, ContentDisposition, ContentType, DispositionParam, DispositionType};
 use hyper::method::Method;
 use msg::constellation_msg::PipelineId;
+use net_traits::blob_url_store::FileOrigin;
+use net_traits::request::BodyPart;
 use rand::random;
 use script_thread::{MainThreadScriptMsg, Runnable};
 use script_traits::LoadData;
 use std::borrow::ToOwned;
 use std::cell::Cell;
+use std::mem;
 use std::sync::mpsc::Sender;
 use style::attr::AttrValue;
 use style::str::split_html_space_chars;
@@ -960,6 +963,22 @@ impl Runnable for PlannedNavigation {
 // https://html.spec.whatwg.org/multipage/#multipart/form-data-encoding-algorithm
 pub fn encode_multipart_form_data(form_data: &mut Vec<FormDatum>,
                                   boundary: String, encoding: EncodingRef) -> Vec<u8> {
+    let mut result = vec![];
+    for part in encode_multipart_form_data_parts(form_data, boundary, encoding, None) {
+        if let BodyPart::Bytes(mut bytes) = part {
+            result.append(&mut bytes);
+        }
+    }
+    result
+}
+
+/// Like `encode_multipart_form_data`, but when `file_origin` is given, the content of
+/// files that the file manager holds is left for it to read as the body is sent.
+pub fn encode_multipart_form_data_parts(form_data: &mut Vec<FormDatum>,
+                                        boundary: String, encoding: EncodingRef,
+                                        file_origin: Option<&FileOrigin>) -> Vec<BodyPart> {
+    let mut parts = vec![];
+
     // Step 1
     let mut result = vec![];
 
@@ -1004,9 +1023,17 @@ pub fn encode_multipart_form_data(form_data: &mut Vec<FormDatum>,
                                              content_type).into_bytes();
                 result.append(&mut type_bytes);
 
-                let mut bytes = f.upcast::<Blob>().get_bytes().unwrap_or(vec![]);
+                match (file_origin, f.upcast::<Blob>().file_id()) {
+                    (Some(origin), Some(id)) => {
+                        parts.push(BodyPart::Bytes(mem::replace(&mut result, vec![])));
+                        parts.push(BodyPart::File(id, origin.clone()));
+                    }
+                    _ => {
+                        let mut bytes = f.upcast::<Blob>().get_bytes().unwrap_or(vec![]);
 
-                result.append(&mut bytes);
+                        result.append(&mut bytes);
+                    }
+                }
             }
         }
     }
@@ -1014,7 +1041,8 @@ pub fn encode_multipart_form_data(form_data: &mut Vec<FormDatum>,
     let mut boundary_bytes = format!("\r\n--{}--", boundary).into_bytes();
     result.append(&mut boundary_bytes);
 
-    result
+    parts.push(BodyPart::Bytes(result));
+    parts
 }
 
 // https://tools.ietf.org/html/rfc7578#section-4.1
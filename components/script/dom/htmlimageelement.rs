@@ -26,12 +26,14 @@ use ipc_channel::ipc;
 use ipc_channel::router::ROUTER;
 use net_traits::image::base::{Image, ImageMetadata};
 use net_traits::image_cache_thread::{ImageResponder, ImageResponse};
+use net_traits::request::is_mixed_content;
 use script_thread::Runnable;
 use servo_url::ServoUrl;
 use std::i32;
 use std::sync::Arc;
 use style::attr::{AttrValue, LengthOrPercentageOrAuto};
 use task_source::TaskSource;
+use util::prefs::PREFS;
 
 #[derive(JSTraceable, HeapSizeOf)]
 #[allow(dead_code)]
@@ -131,8 +133,18 @@ impl HTMLImageElement {
                 self.current_request.borrow_mut().image = None;
             }
             Some((src, base_url)) => {
-                let img_url = base_url.join(&src);
-                if let Ok(img_url) = img_url {
+                // Images are loaded through the image cache, which shares them between
+                // documents, so passive mixed content is blocked here rather than in net.
+                let img_url = base_url.join(&src).ok().and_then(|img_url| {
+                    if is_mixed_content(document.url().scheme(), &img_url) &&
+                       PREFS.get("network.mixed-content.block-passive").as_boolean().unwrap_or(false) {
+                        warn!("Blocked mixed content load of {}", img_url);
+                        None
+                    } else {
+                        Some(img_url)
+                    }
+                });
+                if let Some(img_url) = img_url {
                     self.current_request.borrow_mut().parsed_url = Some(img_url.clone());
                     self.current_request.borrow_mut().source_url = Some(src);
 
@@ -155,7 +167,7 @@ impl HTMLImageElement {
                 } else {
                     // https://html.spec.whatwg.org/multipage/#update-the-image-data
                     // Step 11 (error substeps)
-                    debug!("Failed to parse URL {} with base {}", src, base_url);
+                    debug!("Failed to parse URL {} with base {}, or it was blocked", src, base_url);
                     let mut req = self.current_request.borrow_mut();
 
                     // Substeps 1,2
@@ -4,15 +4,18 @@
 
 use dom::bindings::codegen::Bindings::ElementBinding::ElementBinding::ElementMethods;
 use dom::bindings::codegen::Bindings::HTMLTimeElementBinding;
-use dom::bindings::codegen::Bindings::HTMLTimeElementBinding::HTMLTimeElementMethods;
+use dom::bindings::codegen::Bindings::HTMLTimeElementBinding::{DurationValue, HTMLTimeElementMethods};
 use dom::bindings::inheritance::Castable;
 use dom::bindings::js::Root;
+use dom::bindings::num::Finite;
 use dom::bindings::str::DOMString;
 use dom::document::Document;
 use dom::element::Element;
 use dom::htmlelement::HTMLElement;
 use dom::node::Node;
 use html5ever_atoms::LocalName;
+use std::ascii::AsciiExt;
+use style::str::HTML_SPACE_CHARACTERS;
 
 #[dom_struct]
 pub struct HTMLTimeElement {
@@ -53,4 +56,148 @@ impl HTMLTimeElementMethods for HTMLTimeElement {
 
     // https://html.spec.whatwg.org/multipage/#dom-time-datetime
     make_setter!(SetDateTime, "datetime");
+
+    // https://html.spec.whatwg.org/multipage/#concept-time-duration
+    fn GetDuration(&self) -> Option<DurationValue> {
+        parse_duration(&self.DateTime()).map(|milliseconds| {
+            let seconds = milliseconds / 1000;
+            DurationValue {
+                weeks: seconds / SECONDS_PER_WEEK,
+                days: seconds % SECONDS_PER_WEEK / SECONDS_PER_DAY,
+                hours: seconds % SECONDS_PER_DAY / 3600,
+                minutes: seconds % 3600 / 60,
+                seconds: Finite::wrap((milliseconds % 60000) as f64 / 1000.),
+            }
+        })
+    }
+}
+
+const SECONDS_PER_DAY: u64 = 24 * 3600;
+const SECONDS_PER_WEEK: u64 = 7 * SECONDS_PER_DAY;
+
+/// https://html.spec.whatwg.org/multipage/#valid-duration-string
+///
+/// The length of time `value` describes, in milliseconds, if it is a valid duration
+/// string. Components are added up, so `1h90m` is as long as `2h30m`.
+pub fn parse_duration(value: &str) -> Option<u64> {
+    let components = if value.starts_with('P') || value.starts_with('p') {
+        iso_duration_components(&value[1..])
+    } else {
+        informal_duration_components(value)
+    };
+    let components = match components {
+        Some(components) => components,
+        None => return None,
+    };
+    components.into_iter().fold(Some(0), |total, (thousandths, unit)| {
+        let unit_seconds = match unit {
+            'W' => SECONDS_PER_WEEK,
+            'D' => SECONDS_PER_DAY,
+            'H' => 3600,
+            'M' => 60,
+            _ => 1,
+        };
+        total.and_then(|total| thousandths.checked_mul(unit_seconds).and_then(|ms| total.checked_add(ms)))
+    })
+}
+
+/// The components of the ISO 8601 form of a duration, `PnDTnHnMnS`, after the `P`.
+fn iso_duration_components(value: &str) -> Option<Vec<(u64, char)>> {
+    let (date, time) = match value.find(|c| c == 'T' || c == 't') {
+        Some(index) => (&value[..index], Some(&value[index + 1..])),
+        None => (value, None),
+    };
+    let mut components = match duration_components(date, false) {
+        Some(components) => components,
+        None => return None,
+    };
+    if components.len() > 1 || components.iter().any(|&(_, unit)| unit != 'D') {
+        return None;
+    }
+    if let Some(time) = time {
+        let time_components = match duration_components(time, false) {
+            Some(time_components) => time_components,
+            None => return None,
+        };
+        // Each of the hours, minutes and seconds may be given once, in that order.
+        let units: String = time_components.iter().map(|&(_, unit)| unit).collect();
+        if units.is_empty() || !("HMS".contains(&*units) || units == "HS") {
+            return None;
+        }
+        components.extend(time_components);
+    }
+    if components.is_empty() {
+        return None;
+    }
+    Some(components)
+}
+
+/// The components of the informal form of a duration, like `1h 30m`, in which the units
+/// may come in any order, but only once each.
+fn informal_duration_components(value: &str) -> Option<Vec<(u64, char)>> {
+    let components = match duration_components(value, true) {
+        Some(components) => components,
+        None => return None,
+    };
+    let mut units: Vec<char> = components.iter().map(|&(_, unit)| unit).collect();
+    units.sort();
+    units.dedup();
+    if components.is_empty() || units.len() != components.len() {
+        return None;
+    }
+    Some(components)
+}
+
+/// Split `value` into numbers, in thousandths, and the upper-case unit letters after them,
+/// with whitespace around the numbers if `whitespace` is set. Only seconds may have a
+/// fraction, of up to three digits.
+fn duration_components(value: &str, whitespace: bool) -> Option<Vec<(u64, char)>> {
+    let mut components = vec![];
+    let mut rest = value;
+    loop {
+        if whitespace {
+            rest = rest.trim_left_matches(HTML_SPACE_CHARACTERS);
+        }
+        if rest.is_empty() {
+            return Some(components);
+        }
+        let number_length = rest.find(|c: char| !c.is_digit(10) && c != '.').unwrap_or(rest.len());
+        let (number, after_number) = rest.split_at(number_length);
+        let after_number = if whitespace {
+            after_number.trim_left_matches(HTML_SPACE_CHARACTERS)
+        } else {
+            after_number
+        };
+        let unit = match after_number.chars().next() {
+            Some(unit) => unit.to_ascii_uppercase(),
+            None => return None,
+        };
+        if !"WDHMS".contains(unit) {
+            return None;
+        }
+        let (whole, fraction) = match number.find('.') {
+            Some(index) => (&number[..index], Some(&number[index + 1..])),
+            None => (number, None),
+        };
+        if whole.is_empty() {
+            return None;
+        }
+        let fraction = match fraction {
+            None => 0,
+            Some(fraction) if unit == 'S' && !fraction.is_empty() && fraction.len() <= 3 &&
+                              !fraction.contains('.') => {
+                // Pad to thousandths, so that `.5` is 500.
+                format!("{:0<3}", fraction).parse().unwrap()
+            }
+            Some(_) => return None,
+        };
+        let thousandths = match whole.parse::<u64>().ok()
+                                     .and_then(|whole| whole.checked_mul(1000))
+                                     .and_then(|whole| whole.checked_add(fraction)) {
+            Some(thousandths) => thousandths,
+            None => return None,
+        };
+        components.push((thousandths, unit));
+        rest = &after_number[unit.len_utf8()..];
+    }
 }
@@ -381,6 +381,10 @@ impl Response {
         *self.url.borrow_mut() = Some(final_url);
     }
 
+    pub fn set_url_list(&self, url_list: Vec<ServoUrl>) {
+        *self.url_list.borrow_mut() = url_list;
+    }
+
     #[allow(unrooted_must_root)]
     pub fn finish(&self, body: Vec<u8>) {
         *self.body.borrow_mut() = NetTraitsResponseBody::Done(body);
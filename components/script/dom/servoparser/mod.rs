@@ -27,7 +27,8 @@ use hyper::header::ContentType;
 use hyper::mime::{Mime, SubLevel, TopLevel};
 use hyper_serde::Serde;
 use msg::constellation_msg::PipelineId;
-use net_traits::{FetchMetadata, FetchResponseListener, Metadata, NetworkError};
+use net_traits::{CertificateDetails, CertificateFailure, FetchMetadata, FetchResponseListener, Metadata};
+use net_traits::{NetworkError, SslValidationError};
 use network_listener::PreInvoke;
 use profile_traits::time::{TimerMetadata, TimerMetadataFrameType};
 use profile_traits::time::{TimerMetadataReflowType, ProfilerCategory, profile};
@@ -424,6 +425,42 @@ impl ParserContext {
     }
 }
 
+/// Fill in the placeholders of the certificate error page with the details of `error`:
+/// `${reason}` with OpenSSL's report, `${failure}` with why the certificate didn't
+/// verify, and `${subject}`, `${issuer}`, `${not_before}`, `${not_after}` and
+/// `${fingerprint}` with the fields of the server's certificate, so that the user can
+/// tell it is the certificate they mean to accept.
+fn fill_in_certificate_error(page: String, error: &SslValidationError) -> String {
+    let failure = match error.failure {
+        CertificateFailure::Expired => "The certificate has expired.",
+        CertificateFailure::NotYetValid => "The certificate is not valid yet.",
+        CertificateFailure::HostnameMismatch => "The certificate is for a different site.",
+        CertificateFailure::SelfSigned => "The certificate is self-signed.",
+        CertificateFailure::UnknownIssuer => "The certificate is not issued by a trusted authority.",
+        CertificateFailure::Revoked => "The certificate has been revoked.",
+        CertificateFailure::Other => "The certificate could not be verified.",
+    };
+    let (subject, issuer, not_before, not_after, fingerprint) = match error.chain.first() {
+        Some(&CertificateDetails { ref subject, ref issuer, ref not_before, ref not_after, ref fingerprint, .. }) => {
+            (&**subject, &**issuer, &**not_before, &**not_after, &**fingerprint)
+        }
+        None => ("", "", "", "", &[][..]),
+    };
+    let fingerprint: Vec<_> = fingerprint.iter().map(|byte| format!("{:02X}", byte)).collect();
+    page.replace("${reason}", &error.message)
+        .replace("${failure}", failure)
+        .replace("${subject}", &ascii_escape_html(subject))
+        .replace("${issuer}", &ascii_escape_html(issuer))
+        .replace("${not_before}", &ascii_escape_html(not_before))
+        .replace("${not_after}", &ascii_escape_html(not_after))
+        .replace("${fingerprint}", &fingerprint.join(":"))
+}
+
+/// `text`, with the characters that are special in HTML escaped.
+fn ascii_escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
 impl FetchResponseListener for ParserContext {
     fn process_request_body(&mut self) {}
 
@@ -439,8 +476,8 @@ impl FetchResponseListener for ParserContext {
                     FetchMetadata::Filtered { unsafe_, .. } => unsafe_
                 })
             },
-            Err(NetworkError::SslValidation(url, reason)) => {
-                ssl_error = Some(reason);
+            Err(NetworkError::SslValidation(url, error)) => {
+                ssl_error = Some(error);
                 let mut meta = Metadata::default(url);
                 let mime: Option<Mime> = "text/html".parse().ok();
                 meta.set_content_type(mime.as_ref());
@@ -480,11 +517,11 @@ impl FetchResponseListener for ParserContext {
                 parser.tokenizer.borrow_mut().set_plaintext_state();
             },
             Some(ContentType(Mime(TopLevel::Text, SubLevel::Html, _))) => { // Handle text/html
-                if let Some(reason) = ssl_error {
+                if let Some(error) = ssl_error {
                     self.is_synthesized_document = true;
                     let page_bytes = read_resource_file("badcert.html").unwrap();
                     let page = String::from_utf8(page_bytes).unwrap();
-                    let page = page.replace("${reason}", &reason);
+                    let page = fill_in_certificate_error(page, &error);
                     parser.push_input_chunk(page);
                     parser.parse_sync();
                 }
@@ -500,7 +500,7 @@ impl Runnable for ConnectionEstablishedTask {
             for cookie in cookies.iter() {
                 if let Ok(cookie_value) = String::from_utf8(cookie.clone()) {
                     let _ = ws.global().core_resource_thread().send(
-                        SetCookiesForUrl(ws.url.clone(), cookie_value, HTTP));
+                        SetCookiesForUrl(ws.url.clone(), cookie_value, HTTP, Some(ws.global().get_url())));
                 }
             }
         }
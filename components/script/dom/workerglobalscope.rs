@@ -217,6 +217,7 @@ impl WorkerGlobalScopeMethods for WorkerGlobalScope {
                 use_url_credentials: true,
                 origin: self.worker_url.clone(),
                 pipeline_id: Some(self.upcast::<GlobalScope>().pipeline_id()),
+                document_scheme: Some(self.worker_url.scheme().to_owned()),
                 referrer_url: None,
                 referrer_policy: None,
                 .. NetRequestInit::default()
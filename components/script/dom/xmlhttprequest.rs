@@ -25,7 +25,8 @@ use dom::event::{Event, EventBubbles, EventCancelable};
 use dom::eventtarget::EventTarget;
 use dom::globalscope::GlobalScope;
 use dom::headers::is_forbidden_header_name;
-use dom::htmlformelement::{encode_multipart_form_data, generate_boundary};
+use dom::htmlformelement::{FormDatumValue, encode_multipart_form_data, encode_multipart_form_data_parts};
+use dom::htmlformelement::generate_boundary;
 use dom::progressevent::ProgressEvent;
 use dom::servoparser::ServoParser;
 use dom::window::Window;
@@ -47,9 +48,10 @@ use js::jsapi::{JSContext, JS_ParseJSON};
 use js::jsapi::JS_ClearPendingException;
 use js::jsval::{JSVal, NullValue, UndefinedValue};
 use net_traits::{FetchMetadata, FilteredMetadata};
+use net_traits::blob_url_store::{FileOrigin, get_blob_origin};
 use net_traits::{FetchResponseListener, NetworkError, ReferrerPolicy};
 use net_traits::CoreResourceMsg::Fetch;
-use net_traits::request::{CredentialsMode, Destination, RequestInit, RequestMode};
+use net_traits::request::{BodyPart, CredentialsMode, Destination, RequestInit, RequestMode};
 use net_traits::trim_http_whitespace;
 use network_listener::{NetworkListener, PreInvoke};
 use servo_atoms::Atom;
@@ -220,6 +222,10 @@ impl XMLHttpRequest {
                 // todo
             }
 
+            fn process_request_body_progress(&mut self, sent: u64, total: Option<u64>) {
+                self.xhr.root().process_upload_progress(self.gen_id, sent, total);
+            }
+
             fn process_request_eof(&mut self) {
                 // todo
             }
@@ -491,9 +497,20 @@ impl XMLHttpRequestMethods for XMLHttpRequest {
             _ => data
         };
         // Step 4 (first half)
-        let extracted = data.as_ref().map(|d| d.extract());
+        // The content of files is read by the file manager as the body is sent, rather
+        // than read into script first.
+        let origin = get_blob_origin(&self.global().get_url());
+        let streamed = data.as_ref().and_then(|d| extract_streamed(d, &origin));
+        let (extracted, body_len) = match streamed {
+            Some((_, len, ref content_type)) => (Some((vec![], content_type.clone())), len as usize),
+            None => {
+                let extracted = data.as_ref().map(|d| d.extract());
+                let len = extracted.as_ref().map_or(0, |e| e.0.len());
+                (extracted, len)
+            }
+        };
 
-        self.request_body_len.set(extracted.as_ref().map_or(0, |e| e.0.len()));
+        self.request_body_len.set(body_len);
 
         // todo preserved headers?
 
@@ -502,7 +519,7 @@ impl XMLHttpRequestMethods for XMLHttpRequest {
         // Step 7
         self.upload_complete.set(match extracted {
             None => true,
-            Some(ref e) if e.0.is_empty() => true,
+            Some(_) if body_len == 0 => true,
             _ => false
         });
         // Step 8
@@ -562,7 +579,8 @@ impl XMLHttpRequestMethods for XMLHttpRequest {
             headers: (*self.request_headers.borrow()).clone(),
             unsafe_request: true,
             // XXXManishearth figure out how to avoid this clone
-            body: extracted.as_ref().map(|e| e.0.clone()),
+            body: if streamed.is_some() { None } else { extracted.as_ref().map(|e| e.0.clone()) },
+            body_parts: streamed.map(|(parts, _, _)| parts),
             // XXXManishearth actually "subresource", but it doesn't exist
             // https://github.com/whatwg/xhr/issues/71
             destination: Destination::None,
@@ -575,6 +593,7 @@ impl XMLHttpRequestMethods for XMLHttpRequest {
             referrer_url: self.referrer_url.clone(),
             referrer_policy: self.referrer_policy.clone(),
             pipeline_id: Some(self.global().pipeline_id()),
+            document_scheme: Some(self.global().get_url().scheme().to_owned()),
             .. RequestInit::default()
         };
 
@@ -855,6 +874,16 @@ impl XMLHttpRequest {
         Ok(())
     }
 
+    // https://xhr.spec.whatwg.org/#the-send()-method (processing request body)
+    fn process_upload_progress(&self, gen_id: GenerationId, sent: u64, total: Option<u64>) {
+        // The upload of a fetch that has been replaced or has already finished sending
+        // its body has nothing more to report.
+        if self.generation_id.get() != gen_id || self.sync.get() || self.upload_complete.get() {
+            return;
+        }
+        self.dispatch_progress_event(true, atom!("progress"), sent, total);
+    }
+
     fn process_data_available(&self, gen_id: GenerationId, payload: Vec<u8>) {
         self.process_partial_response(XHRProgress::Loading(gen_id, ByteString::new(payload)));
     }
@@ -898,7 +927,6 @@ impl XMLHttpRequest {
             XHRProgress::HeadersReceived(_, headers, status) => {
                 assert!(self.ready_state.get() == XMLHttpRequestState::Opened);
                 // For synchronous requests, this should not fire any events, and just store data
-                // XXXManishearth Find a way to track partial progress of the send (onprogresss for XHRUpload)
 
                 // Part of step 13, send() (processing request end of file)
                 // Substep 1
@@ -1364,6 +1392,42 @@ impl Extractable for BodyInit {
     }
 }
 
+/// Extract `body` as parts that the file manager reads as the body is sent, along with
+/// their total length and the content type, if any of it is the content of a file that
+/// the file manager holds.
+fn extract_streamed(body: &BodyInit, origin: &FileOrigin) -> Option<(Vec<BodyPart>, u64, Option<DOMString>)> {
+    match *body {
+        BodyInit::Blob(ref b) => b.file_id().map(|id| {
+            let content_type = if b.Type().as_ref().is_empty() {
+                None
+            } else {
+                Some(b.Type())
+            };
+            (vec![BodyPart::File(id, origin.clone())], b.Size(), content_type)
+        }),
+        BodyInit::FormData(ref formdata) => {
+            let mut datums = formdata.datums();
+            let file_sizes: Vec<u64> = datums.iter().filter_map(|datum| match datum.value {
+                FormDatumValue::File(ref f) => f.upcast::<Blob>().file_id().map(|_| f.upcast::<Blob>().Size()),
+                FormDatumValue::String(_) => None,
+            }).collect();
+            if file_sizes.is_empty() {
+                return None;
+            }
+            let boundary = generate_boundary();
+            let parts = encode_multipart_form_data_parts(&mut datums, boundary.clone(),
+                                                         UTF_8 as EncodingRef, Some(origin));
+            let bytes_len = parts.iter().map(|part| match *part {
+                BodyPart::Bytes(ref bytes) => bytes.len() as u64,
+                _ => 0,
+            }).sum::<u64>();
+            let len = bytes_len + file_sizes.iter().sum::<u64>();
+            Some((parts, len, Some(DOMString::from(format!("multipart/form-data;boundary={}", boundary)))))
+        }
+        BodyInit::String(_) | BodyInit::URLSearchParams(_) => None,
+    }
+}
+
 /// Returns whether `bs` is a `field-value`, as defined by
 /// [RFC 2616](http://tools.ietf.org/html/rfc2616#page-32).
 pub fn is_field_value(slice: &[u8]) -> bool {
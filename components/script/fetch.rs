@@ -33,6 +33,9 @@ struct FetchContext {
     fetch_promise: Option<TrustedPromise>,
     response_object: Trusted<Response>,
     body: Vec<u8>,
+    /// Whether the response is opaque, in which case its body must not be
+    /// exposed to script.
+    opaque: bool,
 }
 
 fn from_referrer_to_referrer_url(request: &NetTraitsRequest) -> Option<ServoUrl> {
@@ -40,7 +43,7 @@ fn from_referrer_to_referrer_url(request: &NetTraitsRequest) -> Option<ServoUrl>
     referrer.to_url().map(|url| url.clone())
 }
 
-fn request_init_from_request(request: NetTraitsRequest) -> NetTraitsRequestInit {
+fn request_init_from_request(request: NetTraitsRequest, document_url: &ServoUrl) -> NetTraitsRequestInit {
     NetTraitsRequestInit {
         method: request.method.borrow().clone(),
         url: request.url(),
@@ -62,6 +65,7 @@ fn request_init_from_request(request: NetTraitsRequest) -> NetTraitsRequestInit
         referrer_policy: request.referrer_policy.get(),
         pipeline_id: request.pipeline_id.get(),
         redirect_mode: request.redirect_mode.get(),
+        document_scheme: Some(document_url.scheme().to_owned()),
         ..NetTraitsRequestInit::default()
     }
 }
@@ -83,7 +87,7 @@ pub fn Fetch(global: &GlobalScope, input: RequestInfo, init: &RequestInit) -> Rc
         },
         Ok(r) => r.get_request(),
     };
-    let request_init = request_init_from_request(request);
+    let request_init = request_init_from_request(request, &global.get_url());
 
     // Step 3
     response.Headers().set_guard(Guard::Immutable);
@@ -94,6 +98,7 @@ pub fn Fetch(global: &GlobalScope, input: RequestInfo, init: &RequestInit) -> Rc
         fetch_promise: Some(TrustedPromise::new(promise.clone())),
         response_object: Trusted::new(&*response),
         body: vec![],
+        opaque: false,
     }));
     let listener = NetworkListener {
         context: fetch_context,
@@ -145,8 +150,15 @@ impl FetchResponseListener for FetchContext {
                     FetchMetadata::Filtered { filtered, .. } => match filtered {
                         FilteredMetadata::Transparent(m) =>
                             fill_headers_with_metadata(self.response_object.root(), m),
-                        FilteredMetadata::Opaque =>
-                            self.response_object.root().set_type(DOMResponseType::Opaque),
+                        FilteredMetadata::Opaque => {
+                            // An opaque filtered response has status 0, an empty
+                            // header list and a null body.
+                            self.opaque = true;
+                            let response = self.response_object.root();
+                            response.set_type(DOMResponseType::Opaque);
+                            response.set_headers(None);
+                            response.set_raw_status(Some((0, vec![])));
+                        }
                     }
                 }
             }
@@ -159,6 +171,9 @@ impl FetchResponseListener for FetchContext {
     }
 
     fn process_response_chunk(&mut self, mut chunk: Vec<u8>) {
+        if self.opaque {
+            return;
+        }
         self.body.append(&mut chunk);
     }
 
@@ -177,4 +192,5 @@ fn fill_headers_with_metadata(r: Root<Response>, m: Metadata) {
     r.set_headers(m.headers);
     r.set_raw_status(m.status);
     r.set_final_url(m.final_url);
+    r.set_url_list(m.url_list);
 }
@@ -1768,24 +1768,7 @@ impl ScriptThread {
         };
 
         let referrer_policy = if let Some(headers) = metadata.headers {
-            headers.get::<ReferrerPolicyHeader>().map(|h| match *h {
-                ReferrerPolicyHeader::NoReferrer =>
-                    ReferrerPolicy::NoReferrer,
-                ReferrerPolicyHeader::NoReferrerWhenDowngrade =>
-                    ReferrerPolicy::NoReferrerWhenDowngrade,
-                ReferrerPolicyHeader::SameOrigin =>
-                    ReferrerPolicy::SameOrigin,
-                ReferrerPolicyHeader::Origin =>
-                    ReferrerPolicy::Origin,
-                ReferrerPolicyHeader::OriginWhenCrossOrigin =>
-                    ReferrerPolicy::OriginWhenCrossOrigin,
-                ReferrerPolicyHeader::UnsafeUrl =>
-                    ReferrerPolicy::UnsafeUrl,
-                ReferrerPolicyHeader::StrictOrigin =>
-                    ReferrerPolicy::StrictOrigin,
-                ReferrerPolicyHeader::StrictOriginWhenCrossOrigin =>
-                    ReferrerPolicy::StrictOriginWhenCrossOrigin,
-            })
+            headers.get::<ReferrerPolicyHeader>().map(|h| ReferrerPolicy::from(h.clone()))
         } else {
             None
         };
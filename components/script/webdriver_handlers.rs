@@ -243,13 +243,13 @@ pub fn handle_add_cookie(documents: &Documents,
         (true, _) => Err(WebDriverCookieError::InvalidDomain),
         (false, Some(ref domain)) if url.host_str().map(|x| { x == &**domain }).unwrap_or(false) => {
             let _ = document.window().upcast::<GlobalScope>().resource_threads().send(
-                SetCookiesForUrlWithData(url, cookie, method)
+                SetCookiesForUrlWithData(url.clone(), cookie, method, Some(url))
                 );
             Ok(())
         },
         (false, None) => {
             let _ = document.window().upcast::<GlobalScope>().resource_threads().send(
-                SetCookiesForUrlWithData(url, cookie, method)
+                SetCookiesForUrlWithData(url.clone(), cookie, method, Some(url))
                 );
             Ok(())
         },
@@ -82,7 +82,7 @@ use ipc_channel::ipc::{self, IpcSender};
 use log::{Log, LogMetadata, LogRecord};
 use net::image_cache_thread::new_image_cache_thread;
 use net::resource_thread::new_resource_threads;
-use net_traits::IpcSend;
+use net_traits::{CoreResourceControlThread, IpcSend};
 use profile::mem as profile_mem;
 use profile::time as profile_time;
 use profile_traits::mem;
@@ -114,6 +114,7 @@ pub use gleam::gl;
 pub struct Browser<Window: WindowMethods + 'static> {
     compositor: IOCompositor<Window>,
     constellation_chan: Sender<ConstellationMsg>,
+    resource_control_thread: CoreResourceControlThread,
 }
 
 impl<Window> Browser<Window> where Window: WindowMethods + 'static {
@@ -181,16 +182,18 @@ impl<Window> Browser<Window> where Window: WindowMethods + 'static {
         // Create the constellation, which maintains the engine
         // pipelines, including the script and layout threads, as well
         // as the navigation context.
-        let (constellation_chan, sw_senders) = create_constellation(opts.user_agent.clone(),
-                                                                    opts.config_dir.clone(),
-                                                                    opts.url.clone(),
-                                                                    compositor_proxy.clone_compositor_proxy(),
-                                                                    time_profiler_chan.clone(),
-                                                                    mem_profiler_chan.clone(),
-                                                                    debugger_chan,
-                                                                    devtools_chan,
-                                                                    supports_clipboard,
-                                                                    webrender_api_sender.clone());
+        let (constellation_chan, sw_senders, resource_control_thread) =
+            create_constellation(opts.user_agent.clone(),
+                                 opts.config_dir.clone(),
+                                 opts.profile.clone(),
+                                 opts.url.clone(),
+                                 compositor_proxy.clone_compositor_proxy(),
+                                 time_profiler_chan.clone(),
+                                 mem_profiler_chan.clone(),
+                                 debugger_chan,
+                                 devtools_chan,
+                                 supports_clipboard,
+                                 webrender_api_sender.clone());
 
         // Send the constellation's swmanager sender to service worker manager thread
         script::init_service_workers(sw_senders);
@@ -216,9 +219,16 @@ impl<Window> Browser<Window> where Window: WindowMethods + 'static {
         Browser {
             compositor: compositor,
             constellation_chan: constellation_chan,
+            resource_control_thread: resource_control_thread,
         }
     }
 
+    /// The channel for the resource thread messages that script may not send, such as
+    /// those to back up and restore the cookie jar.
+    pub fn resource_control_thread(&self) -> &CoreResourceControlThread {
+        &self.resource_control_thread
+    }
+
     pub fn handle_events(&mut self, events: Vec<WindowEvent>) -> bool {
         self.compositor.handle_events(events)
     }
@@ -250,6 +260,7 @@ impl<Window> Browser<Window> where Window: WindowMethods + 'static {
 
 fn create_constellation(user_agent: Cow<'static, str>,
                         config_dir: Option<PathBuf>,
+                        profile: Option<String>,
                         url: Option<ServoUrl>,
                         compositor_proxy: Box<CompositorProxy + Send>,
                         time_profiler_chan: time::ProfilerChan,
@@ -258,14 +269,17 @@ fn create_constellation(user_agent: Cow<'static, str>,
                         devtools_chan: Option<Sender<devtools_traits::DevtoolsControlMsg>>,
                         supports_clipboard: bool,
                         webrender_api_sender: webrender_traits::RenderApiSender)
-                        -> (Sender<ConstellationMsg>, SWManagerSenders) {
+                        -> (Sender<ConstellationMsg>, SWManagerSenders, CoreResourceControlThread) {
     let bluetooth_thread: IpcSender<BluetoothRequest> = BluetoothThreadFactory::new();
 
-    let (public_resource_threads, private_resource_threads) =
+    let (public_resource_threads, private_resource_threads, resource_control_thread) =
         new_resource_threads(user_agent,
                              devtools_chan.clone(),
                              time_profiler_chan.clone(),
-                             config_dir);
+                             mem_profiler_chan.clone(),
+                             config_dir,
+                             profile,
+                             vec![]);
     let image_cache_thread = new_image_cache_thread(public_resource_threads.sender(),
                                                     webrender_api_sender.create_api());
     let font_cache_thread = FontCacheThread::new(public_resource_threads.sender(),
@@ -282,6 +296,7 @@ fn create_constellation(user_agent: Cow<'static, str>,
         font_cache_thread: font_cache_thread,
         public_resource_threads: public_resource_threads,
         private_resource_threads: private_resource_threads,
+        resource_control_thread: resource_control_thread.clone(),
         time_profiler_chan: time_profiler_chan,
         mem_profiler_chan: mem_profiler_chan,
         supports_clipboard: supports_clipboard,
@@ -302,7 +317,7 @@ fn create_constellation(user_agent: Cow<'static, str>,
         resource_sender: resource_sender
     };
 
-    (constellation_chan, sw_senders)
+    (constellation_chan, sw_senders, resource_control_thread)
 }
 
 // A logger that logs to two downstream loggers.
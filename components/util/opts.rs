@@ -221,6 +221,10 @@ pub struct Opts {
     /// Directory for a default config directory
     pub config_dir: Option<PathBuf>,
 
+    /// The name of the profile whose state is kept under `profiles/<name>` in the
+    /// config directory, so that several sessions can share one config directory.
+    pub profile: Option<String>,
+
     // don't skip any backtraces on panic
     pub full_backtraces: bool,
 
@@ -560,6 +564,7 @@ pub fn default_opts() -> Opts {
         webrender_stats: false,
         use_msaa: false,
         config_dir: None,
+        profile: None,
         full_backtraces: false,
         is_printing_version: false,
         webrender_debug: false,
@@ -624,6 +629,8 @@ pub fn from_cmdline_args(args: &[String]) -> ArgumentParsingResult {
     opts.optopt("G", "graphics", "Select graphics backend (gl or es2)", "gl");
     opts.optopt("", "config-dir",
                     "config directory following xdg spec on linux platform", "");
+    opts.optopt("", "profile",
+                    "Keep cookies, storage and other state in a named profile in the config directory", "default");
     opts.optflag("v", "version", "Display servo version information");
 
     let opt_match = match opts.parse(args) {
@@ -862,6 +869,7 @@ pub fn from_cmdline_args(args: &[String]) -> ArgumentParsingResult {
         webrender_stats: debug_options.webrender_stats,
         use_msaa: debug_options.use_msaa,
         config_dir: opt_match.opt_str("config-dir").map(Into::into),
+        profile: opt_match.opt_str("profile"),
         full_backtraces: debug_options.full_backtraces,
         is_printing_version: is_printing_version,
         webrender_debug: debug_options.webrender_debug,
@@ -0,0 +1,124 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use net::test::{AltSvc, AltSvcCache, Alternative, parse_alt_svc};
+use servo_url::ServoUrl;
+
+#[test]
+fn test_parse_alt_svc_clear() {
+    assert_eq!(parse_alt_svc(" clear "), Some(AltSvc::Clear));
+}
+
+#[test]
+fn test_parse_alt_svc_alternatives() {
+    let alt_svc = parse_alt_svc(r#"h2="alt.example.com:443"; ma=60, http%2F1.1=":8080"; persist=1"#);
+    assert_eq!(alt_svc, Some(AltSvc::Alternatives(vec![
+        Alternative {
+            protocol: "h2".to_owned(),
+            host: Some("alt.example.com".to_owned()),
+            port: 443,
+            max_age: 60,
+        },
+        Alternative {
+            protocol: "http/1.1".to_owned(),
+            host: None,
+            port: 8080,
+            max_age: 86400,
+        },
+    ])));
+}
+
+#[test]
+fn test_parse_alt_svc_skips_malformed_alternatives() {
+    let alt_svc = parse_alt_svc(r#"h2, h2="no-port", h2="[::1]:8443"; ma="30", h2="a,b:1"; ma=soon"#);
+    assert_eq!(alt_svc, Some(AltSvc::Alternatives(vec![
+        Alternative {
+            protocol: "h2".to_owned(),
+            host: Some("[::1]".to_owned()),
+            port: 8443,
+            max_age: 30,
+        },
+        Alternative {
+            protocol: "h2".to_owned(),
+            host: Some("a,b".to_owned()),
+            port: 1,
+            max_age: 86400,
+        },
+    ])));
+    assert_eq!(parse_alt_svc(r#"h2="host:99999""#), None);
+    assert_eq!(parse_alt_svc(""), None);
+}
+
+#[test]
+fn test_alt_svc_cache_only_keeps_supported_alternatives() {
+    let url = ServoUrl::parse("https://example.com/page").unwrap();
+    let mut cache = AltSvcCache::new();
+    cache.update(&url, parse_alt_svc(r#"h2=":443", http%2F1.1="alt.example.com:8443""#).unwrap());
+    assert_eq!(cache.lookup(&url), Some(("alt.example.com".to_owned(), 8443)));
+
+    // The alternatives are those of the origin, not of the URL.
+    let other_path = ServoUrl::parse("https://example.com/other").unwrap();
+    assert_eq!(cache.lookup(&other_path), Some(("alt.example.com".to_owned(), 8443)));
+    let other_scheme = ServoUrl::parse("http://example.com/page").unwrap();
+    assert_eq!(cache.lookup(&other_scheme), None);
+
+    cache.update(&url, parse_alt_svc(r#"h2=":8443""#).unwrap());
+    assert_eq!(cache.lookup(&url), None);
+}
+
+#[test]
+fn test_alt_svc_cache_ignores_http_origins() {
+    let url = ServoUrl::parse("http://example.com/").unwrap();
+    let mut cache = AltSvcCache::new();
+    cache.update(&url, parse_alt_svc(r#"http%2F1.1="attacker.example:80""#).unwrap());
+    assert_eq!(cache.lookup(&url), None);
+    assert_eq!(cache.lookup(&ServoUrl::parse("https://example.com/").unwrap()), None);
+}
+
+#[test]
+fn test_alt_svc_cache_ignores_the_origin_itself() {
+    let url = ServoUrl::parse("https://example.com/").unwrap();
+    let mut cache = AltSvcCache::new();
+    cache.update(&url, parse_alt_svc(r#"http%2F1.1=":443""#).unwrap());
+    assert_eq!(cache.lookup(&url), None);
+}
+
+#[test]
+fn test_alt_svc_cache_expiry_and_clear() {
+    let url = ServoUrl::parse("https://example.com/").unwrap();
+    let mut cache = AltSvcCache::new();
+    cache.update(&url, parse_alt_svc(r#"http%2F1.1=":8443"; ma=0"#).unwrap());
+    assert_eq!(cache.lookup(&url), None);
+
+    cache.update(&url, parse_alt_svc(r#"http%2F1.1=":8443""#).unwrap());
+    assert_eq!(cache.lookup(&url), Some(("example.com".to_owned(), 8443)));
+    cache.update(&url, AltSvc::Clear);
+    assert_eq!(cache.lookup(&url), None);
+}
+
+#[test]
+fn test_alt_svc_cache_remove_falls_back_to_next_alternative() {
+    let url = ServoUrl::parse("https://example.com/").unwrap();
+    let mut cache = AltSvcCache::new();
+    cache.update(&url, parse_alt_svc(r#"http%2F1.1="a.example.com:1", http%2F1.1="b.example.com:2""#).unwrap());
+    cache.remove(&url, "a.example.com", 1);
+    assert_eq!(cache.lookup(&url), Some(("b.example.com".to_owned(), 2)));
+    cache.remove(&url, "b.example.com", 2);
+    assert_eq!(cache.lookup(&url), None);
+}
+
+#[test]
+fn test_alt_svc_cache_clear_host() {
+    let first = ServoUrl::parse("https://example.com/").unwrap();
+    let second = ServoUrl::parse("https://example.com:8000/").unwrap();
+    let other = ServoUrl::parse("https://example.org/").unwrap();
+    let mut cache = AltSvcCache::new();
+    for url in &[&first, &second, &other] {
+        cache.update(url, parse_alt_svc(r#"http%2F1.1=":9000""#).unwrap());
+    }
+    cache.clear_host("example.com");
+    assert_eq!(cache.lookup(&first), None);
+    assert_eq!(cache.lookup(&second), None);
+    assert_eq!(cache.lookup(&other), Some(("example.org".to_owned(), 9000)));
+}
@@ -0,0 +1,65 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use net::hsts::{HstsEntry, HstsList};
+use net::test::{CertificateExceptionCheck, CertificateExceptions};
+use net_traits::{CertificateException, IncludeSubdomains};
+use std::sync::{Arc, RwLock};
+
+fn exception(host: &str, port: u16, permanent: bool) -> CertificateException {
+    CertificateException {
+        host: host.to_owned(),
+        port: port,
+        cert_fingerprint: vec![0xab; 32],
+        permanent: permanent,
+    }
+}
+
+#[test]
+fn test_certificate_exceptions_are_per_host_and_port() {
+    let mut exceptions = CertificateExceptions::new();
+    exceptions.add(exception("Intranet.example", 8443, false));
+    exceptions.add(exception("test.example", 443, true));
+    assert_eq!(exceptions.fingerprint_for("intranet.example", 8443), Some(&[0xab; 32][..]));
+    assert_eq!(exceptions.fingerprint_for("intranet.example", 443), None);
+    assert_eq!(exceptions.list(),
+               vec![exception("intranet.example", 8443, false), exception("test.example", 443, true)]);
+    assert_eq!(exceptions.permanent().list(), vec![exception("test.example", 443, true)]);
+
+    assert!(exceptions.remove("intranet.example", 8443));
+    assert!(!exceptions.remove("intranet.example", 8443));
+    assert_eq!(exceptions.list(), vec![exception("test.example", 443, true)]);
+}
+
+#[test]
+fn test_certificate_exceptions_never_apply_to_hsts_hosts() {
+    let mut exceptions = CertificateExceptions::new();
+    exceptions.add(exception("secure.example", 443, true));
+    exceptions.add(exception("www.secure.example", 443, true));
+    exceptions.add(exception("intranet.example", 443, true));
+    let mut hsts_list = HstsList::new();
+    hsts_list.push(HstsEntry::new("secure.example".to_owned(), IncludeSubdomains::Included, None).unwrap());
+    let check = CertificateExceptionCheck::new(Arc::new(RwLock::new(exceptions)), Arc::new(RwLock::new(hsts_list)));
+    assert_eq!(check.fingerprint_for("secure.example", 443), None);
+    assert_eq!(check.fingerprint_for("www.secure.example", 443), None);
+    assert_eq!(check.fingerprint_for("intranet.example", 443), Some(vec![0xab; 32]));
+}
+
+#[test]
+fn test_certificate_exceptions_never_apply_to_preloaded_or_shared_hsts_hosts() {
+    let preloaded_host = HstsList::from_servo_preload().entries[0].host.clone();
+    let mut exceptions = CertificateExceptions::new();
+    exceptions.add(exception(&preloaded_host, 443, true));
+    exceptions.add(exception("public.example", 443, true));
+    exceptions.add(exception("intranet.example", 443, true));
+    let mut shared_hsts_list = HstsList::new();
+    shared_hsts_list.push(HstsEntry::new("public.example".to_owned(), IncludeSubdomains::NotIncluded, None).unwrap());
+    // A private group's own list starts out empty.
+    let own_hsts_list = Arc::new(RwLock::new(HstsList::new()));
+    let check = CertificateExceptionCheck::new(Arc::new(RwLock::new(exceptions)), own_hsts_list)
+        .with_shared_hsts_list(Some(Arc::new(RwLock::new(shared_hsts_list))));
+    assert_eq!(check.fingerprint_for(&preloaded_host, 443), None);
+    assert_eq!(check.fingerprint_for("public.example", 443), None);
+    assert_eq!(check.fingerprint_for("intranet.example", 443), Some(vec![0xab; 32]));
+}
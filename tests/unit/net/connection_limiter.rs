@@ -0,0 +1,115 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use net::test::{ConnectionLimiter, FetchJob, PendingFetches};
+use net_traits::request::{Destination, RequestInit, RequestPriority};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn test_pending_fetches_run_most_urgent_first() {
+    let log = Arc::new(Mutex::new(vec![]));
+    let mut pending = PendingFetches::new();
+    let burst = [(RequestPriority::Idle, "idle"), (RequestPriority::Low, "image"),
+                 (RequestPriority::Highest, "document"), (RequestPriority::Normal, "xhr"),
+                 (RequestPriority::Low, "second image"), (RequestPriority::High, "stylesheet")];
+    for &(priority, name) in &burst {
+        let log = log.clone();
+        pending.push(priority, Box::new(move |_: bool| log.lock().unwrap().push(name)));
+    }
+    while let Some(job) = pending.pop() {
+        job.call_box(false);
+    }
+    assert_eq!(*log.lock().unwrap(),
+               vec!["document", "stylesheet", "xhr", "image", "second image", "idle"]);
+}
+
+#[test]
+fn test_origin_queue_releases_most_urgent_fetch_first() {
+    let log = Arc::new(Mutex::new(vec![]));
+    let mut limiter = ConnectionLimiter::new();
+    let mut fetch_id = 0;
+    let mut running = vec![];
+    // Fill every connection slot for the origin.
+    while let Some(started) = limiter.start_or_queue("http://example.com", "example.com", fetch_id,
+                                                     RequestPriority::Normal, None, Box::new(|_: bool| ())) {
+        running.push(started);
+        fetch_id += 1;
+    }
+    assert_eq!(limiter.stats().1.get("http://example.com"), Some(&1));
+    // Let the fetch that didn't fit go first, then queue a mixed burst behind it.
+    let (slots, first) = limiter.finished(running.pop().unwrap().0).unwrap();
+    first.call_box(false);
+    for &(priority, name) in &[(RequestPriority::Idle, "idle"), (RequestPriority::Low, "image"),
+                               (RequestPriority::High, "script"), (RequestPriority::Low, "second image")] {
+        fetch_id += 1;
+        let log = log.clone();
+        let job = Box::new(move |_: bool| log.lock().unwrap().push(name));
+        assert!(limiter.start_or_queue("http://example.com", "example.com", fetch_id, priority, None, job).is_none());
+    }
+    let mut next = limiter.finished(slots);
+    while let Some((slots, job)) = next {
+        job.call_box(false);
+        next = limiter.finished(slots);
+    }
+    assert_eq!(*log.lock().unwrap(), vec!["script", "image", "second image", "idle"]);
+}
+
+#[test]
+fn test_origins_are_limited_independently() {
+    let mut limiter = ConnectionLimiter::new();
+    let mut fetch_id = 0;
+    while limiter.start_or_queue("http://example.com", "example.com", fetch_id, RequestPriority::Normal, None,
+                                 Box::new(|_: bool| ())).is_some() {
+        fetch_id += 1;
+    }
+    // An origin on another host has slots of its own.
+    assert!(limiter.start_or_queue("http://example.org", "example.org", fetch_id + 1, RequestPriority::Normal,
+                                   None, Box::new(|_: bool| ())).is_some());
+
+    // A cancelled fetch leaves the queue without taking a slot.
+    assert!(limiter.remove_queued(fetch_id).is_some());
+    assert_eq!(limiter.stats().1.get("http://example.com"), Some(&0));
+}
+
+#[test]
+fn test_host_limit_covers_every_origin_on_the_host() {
+    let log = Arc::new(Mutex::new(vec![]));
+    let logged = |name: &'static str| -> Box<FetchJob> {
+        let log = log.clone();
+        Box::new(move |_: bool| log.lock().unwrap().push(name))
+    };
+    let mut limiter = ConnectionLimiter::new();
+    let mut fetch_id = 0;
+    let mut running = vec![];
+    while let Some(started) = limiter.start_or_queue("http://example.com", "example.com", fetch_id,
+                                                     RequestPriority::Normal, None, logged("queued http")) {
+        running.push(started);
+        fetch_id += 1;
+    }
+    // Another scheme or port is another origin, but its fetches wait for the host's slots too.
+    assert!(limiter.start_or_queue("https://example.com", "example.com", fetch_id + 1, RequestPriority::High, None,
+                                   logged("https")).is_none());
+    assert_eq!(limiter.stats().0.get("https://example.com"), Some(&0));
+    assert_eq!(limiter.active_per_host().get("example.com"), Some(&running.len()));
+
+    // A finished fetch hands its slots on to its own origin first, then to the others on the host.
+    let mut next = limiter.finished(running.pop().unwrap().0);
+    while let Some((slots, job)) = next {
+        job.call_box(false);
+        next = limiter.finished(slots);
+    }
+    assert_eq!(*log.lock().unwrap(), vec!["queued http", "https"]);
+    assert_eq!(limiter.active_per_host().get("example.com"), Some(&running.len()));
+}
+
+#[test]
+fn test_priority_defaults_by_destination() {
+    let init = |destination| RequestInit { destination: destination, .. RequestInit::default() };
+    assert_eq!(init(Destination::Document).scheduling_priority(), RequestPriority::Highest);
+    assert_eq!(init(Destination::Style).scheduling_priority(), RequestPriority::High);
+    assert_eq!(init(Destination::None).scheduling_priority(), RequestPriority::Normal);
+    assert_eq!(init(Destination::Image).scheduling_priority(), RequestPriority::Low);
+    let explicit = RequestInit { priority: Some(RequestPriority::Idle), .. init(Destination::Document) };
+    assert_eq!(explicit.scheduling_priority(), RequestPriority::Idle);
+}
@@ -4,9 +4,9 @@
 
 use cookie_rs;
 use hyper::header::{Header, SetCookie};
-use net::cookie::Cookie;
+use net::cookie::{Cookie, SameSite};
 use net::cookie_storage::CookieStorage;
-use net_traits::CookieSource;
+use net_traits::{CookieSource, SameSiteContext};
 use servo_url::ServoUrl;
 
 #[test]
@@ -100,6 +100,92 @@ fn fn_cookie_constructor() {
     assert!(Cookie::new_wrapped(cookie, u, CookieSource::HTTP).is_some());
 }
 
+#[test]
+fn test_idn_cookie_domain_matches_punycode_host() {
+    let url = ServoUrl::parse("http://www.café.example/").unwrap();
+    assert_eq!(url.host_str(), Some("www.xn--caf-dma.example"));
+
+    let cookie = cookie_rs::Cookie::parse("baz=bar; Domain=.CAFÉ.example").unwrap();
+    let cookie = Cookie::new_wrapped(cookie, &url, CookieSource::HTTP).unwrap();
+    assert_eq!(cookie.cookie.domain, Some("xn--caf-dma.example".to_owned()));
+    assert!(!cookie.host_only);
+
+    let mut storage = CookieStorage::new(150);
+    storage.push(cookie, CookieSource::HTTP);
+    let punycode_url = ServoUrl::parse("http://xn--caf-dma.example/").unwrap();
+    assert_eq!(storage.cookies_for_url(&punycode_url, CookieSource::HTTP, SameSiteContext::SameSite),
+               Some("baz=bar".to_owned()));
+}
+
+#[test]
+fn test_ipv6_literal_is_never_subdomain_matched() {
+    assert!(Cookie::domain_match("[::1]", "[::1]"));
+    assert!(!Cookie::domain_match("[::ffff:0.0.0.1]", "0.1]"));
+
+    let url = ServoUrl::parse("http://[0:0::1]/").unwrap();
+    let cookie = cookie_rs::Cookie::parse("baz=bar; Domain=[::1]").unwrap();
+    let cookie = Cookie::new_wrapped(cookie, &url, CookieSource::HTTP).unwrap();
+    assert_eq!(cookie.cookie.domain, Some("[::1]".to_owned()));
+}
+
+fn wrap(cookie: &str, url: &str) -> Option<Cookie> {
+    let cookie = cookie_rs::Cookie::parse(cookie).unwrap();
+    Cookie::new_wrapped(cookie, &ServoUrl::parse(url).unwrap(), CookieSource::HTTP)
+}
+
+#[test]
+fn test_cookie_scoped_to_a_public_suffix_is_refused() {
+    assert!(wrap("SID=1; Domain=com", "http://example.com/").is_none());
+    assert!(wrap("SID=1; Domain=.co.uk", "http://www.example.co.uk/").is_none());
+    assert!(wrap("SID=1; Domain=github.io", "https://servo.github.io/").is_none());
+
+    let cookie = wrap("SID=1; Domain=example.com", "http://www.example.com/").unwrap();
+    assert_eq!(cookie.cookie.domain, Some("example.com".to_owned()));
+    assert!(!cookie.host_only);
+    let cookie = wrap("SID=1; Domain=servo.github.io", "https://servo.github.io/").unwrap();
+    assert_eq!(cookie.cookie.domain, Some("servo.github.io".to_owned()));
+
+    // A host that is itself a public suffix can still set a cookie for itself alone.
+    let cookie = wrap("SID=1; Domain=github.io", "https://github.io/").unwrap();
+    assert_eq!(cookie.cookie.domain, Some("github.io".to_owned()));
+    assert!(cookie.host_only);
+}
+
+#[test]
+fn test_secure_prefix_requires_secure_attribute() {
+    assert!(wrap("__Secure-SID=12345; Domain=example.com", "https://example.com").is_none());
+    assert!(wrap("__Secure-SID=12345; Secure; Domain=example.com", "https://example.com").is_some());
+}
+
+#[test]
+fn test_secure_prefix_requires_secure_origin() {
+    assert!(wrap("__Secure-SID=12345; Secure; Domain=example.com", "http://example.com").is_none());
+}
+
+#[test]
+fn test_host_prefix_requires_secure_attribute() {
+    assert!(wrap("__Host-SID=12345; Path=/", "https://example.com").is_none());
+    assert!(wrap("__Host-SID=12345; Secure; Path=/", "http://example.com").is_none());
+}
+
+#[test]
+fn test_host_prefix_rejects_domain_attribute() {
+    assert!(wrap("__Host-SID=12345; Secure; Path=/; Domain=example.com", "https://example.com").is_none());
+}
+
+#[test]
+fn test_host_prefix_requires_root_path() {
+    assert!(wrap("__Host-SID=12345; Secure", "https://example.com/").is_none());
+    assert!(wrap("__Host-SID=12345; Secure; Path=/foo", "https://example.com/foo/").is_none());
+}
+
+#[test]
+fn test_valid_host_prefixed_cookie_is_accepted() {
+    let cookie = wrap("__Host-SID=12345; Secure; Path=/", "https://example.com/foo/bar").unwrap();
+    assert!(cookie.host_only);
+    assert_eq!(cookie.cookie.path, Some("/".to_owned()));
+}
+
 #[cfg(target_os = "windows")]
 fn delay_to_ensure_different_timestamp() {
     use std::thread;
@@ -155,7 +241,7 @@ fn add_retrieve_cookies(set_location: &str,
 
     // Get cookies for the test location
     let url = ServoUrl::parse(final_location).unwrap();
-    storage.cookies_for_url(&url, source).unwrap_or("".to_string())
+    storage.cookies_for_url(&url, source, SameSiteContext::SameSite).unwrap_or("".to_string())
 }
 
 
@@ -231,3 +317,121 @@ fn test_cookie_eviction_all_nonsecure_new_nonsecure() {
                                  &vec, "https://home.example.org:8888/cookie-parser-result?0001");
     assert_eq!(&r, "extra2=bar; extra3=bar; extra4=bar; extra5=bar; foo=bar");
 }
+
+
+#[test]
+fn test_cookie_eviction_keeps_the_most_recently_sent_cookie() {
+    let mut storage = CookieStorage::new(3);
+    let url = ServoUrl::parse("http://example.com/").unwrap();
+    let source = CookieSource::HTTP;
+    for path in &["a", "b", "c"] {
+        let cookie = cookie_rs::Cookie::parse(&format!("{}=1; Path=/{}", path, path)).unwrap();
+        assert_eq!(storage.push(Cookie::new_wrapped(cookie, &url, source).unwrap(), source), 0);
+        delay_to_ensure_different_timestamp();
+    }
+
+    // Sending the oldest cookie makes the next oldest the least recently used.
+    let a_url = ServoUrl::parse("http://example.com/a").unwrap();
+    assert_eq!(storage.cookies_for_url(&a_url, source, SameSiteContext::SameSite), Some("a=1".to_owned()));
+    delay_to_ensure_different_timestamp();
+
+    let cookie = cookie_rs::Cookie::parse("d=1; Path=/d").unwrap();
+    assert_eq!(storage.push(Cookie::new_wrapped(cookie, &url, source).unwrap(), source), 1);
+
+    let cookies_at = |storage: &mut CookieStorage, path: &str| {
+        let url = ServoUrl::parse(&format!("http://example.com/{}", path)).unwrap();
+        storage.cookies_for_url(&url, source, SameSiteContext::SameSite)
+    };
+    assert_eq!(cookies_at(&mut storage, "a"), Some("a=1".to_owned()));
+    assert_eq!(cookies_at(&mut storage, "b"), None);
+    assert_eq!(cookies_at(&mut storage, "c"), Some("c=1".to_owned()));
+    assert_eq!(cookies_at(&mut storage, "d"), Some("d=1".to_owned()));
+}
+
+#[test]
+fn test_inspecting_cookie_details_leaves_the_eviction_order_alone() {
+    let mut storage = CookieStorage::new(2);
+    let url = ServoUrl::parse("http://example.com/").unwrap();
+    let source = CookieSource::HTTP;
+    for cookie in &["a=1", "b=1"] {
+        let cookie = cookie_rs::Cookie::parse(*cookie).unwrap();
+        assert_eq!(storage.push(Cookie::new_wrapped(cookie, &url, source).unwrap(), source), 0);
+        delay_to_ensure_different_timestamp();
+    }
+
+    let details: Vec<_> = storage.cookies_details_for_url(&url, source).map(|c| c.cookie.name).collect();
+    assert_eq!(details, vec!["a".to_owned(), "b".to_owned()]);
+    delay_to_ensure_different_timestamp();
+
+    let cookie = cookie_rs::Cookie::parse("c=1").unwrap();
+    assert_eq!(storage.push(Cookie::new_wrapped(cookie, &url, source).unwrap(), source), 1);
+    assert_eq!(storage.cookies_for_url(&url, source, SameSiteContext::SameSite), Some("b=1; c=1".to_owned()));
+}
+
+#[test]
+fn test_cookie_eviction_drops_expired_cookies_before_live_ones() {
+    let mut storage = CookieStorage::new(2);
+    let url = ServoUrl::parse("http://example.com/").unwrap();
+    let source = CookieSource::HTTP;
+    for cookie in &["old=1; expires=Sun, 18-Apr-2000 21:06:29 GMT", "live=1", "new=1"] {
+        let cookie = cookie_rs::Cookie::parse(*cookie).unwrap();
+        assert_eq!(storage.push(Cookie::new_wrapped(cookie, &url, source).unwrap(), source), 0);
+    }
+    assert_eq!(storage.cookies_for_url(&url, source, SameSiteContext::SameSite),
+               Some("live=1; new=1".to_owned()));
+}
+
+
+fn same_site_storage() -> CookieStorage {
+    let mut storage = CookieStorage::new(5);
+    let url = ServoUrl::parse("http://example.com/").unwrap();
+    let source = CookieSource::HTTP;
+    for str_cookie in &["strict=1; SameSite=Strict", "lax=2; SameSite=Lax",
+                        "none=3; SameSite=None", "default=4"] {
+        let header = Header::parse_header(&[str_cookie.to_string().into_bytes()]).unwrap();
+        let SetCookie(cookies) = header;
+        for bare_cookie in cookies {
+            let cookie = Cookie::new_wrapped(bare_cookie, &url, source).unwrap();
+            storage.push(cookie, source);
+        }
+    }
+    storage
+}
+
+fn same_site_cookies(context: SameSiteContext) -> String {
+    let mut storage = same_site_storage();
+    let url = ServoUrl::parse("http://example.com/").unwrap();
+    storage.cookies_for_url(&url, CookieSource::HTTP, context).unwrap_or("".to_string())
+}
+
+#[test]
+fn test_same_site_request_sends_all_cookies() {
+    assert_eq!(&same_site_cookies(SameSiteContext::SameSite),
+               "strict=1; lax=2; none=3; default=4");
+}
+
+#[test]
+fn test_cross_site_top_level_navigation_omits_strict_cookies() {
+    assert_eq!(&same_site_cookies(SameSiteContext::CrossSiteTopLevelNavigation),
+               "lax=2; none=3; default=4");
+}
+
+#[test]
+fn test_cross_site_subresource_or_post_only_sends_none_cookies() {
+    assert_eq!(&same_site_cookies(SameSiteContext::CrossSite), "none=3");
+}
+
+#[test]
+fn test_same_site_attribute_is_case_insensitive() {
+    let url = ServoUrl::parse("http://example.com/").unwrap();
+    let header = Header::parse_header(&[b"foo=bar; samesite=STRICT".to_vec()]).unwrap();
+    let SetCookie(mut cookies) = header;
+    let cookie = Cookie::new_wrapped(cookies.remove(0), &url, CookieSource::HTTP).unwrap();
+    assert_eq!(cookie.same_site(), SameSite::Strict);
+}
+
+#[test]
+fn test_is_same_site() {
+    assert!(Cookie::is_same_site("www.example.com", "static.example.com"));
+    assert!(!Cookie::is_same_site("example.com", "example.org"));
+}
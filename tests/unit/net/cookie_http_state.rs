@@ -5,7 +5,7 @@
 use hyper::header::{Header, SetCookie};
 use net::cookie::Cookie;
 use net::cookie_storage::CookieStorage;
-use net_traits::CookieSource;
+use net_traits::{CookieSource, SameSiteContext};
 use servo_url::ServoUrl;
 
 
@@ -29,7 +29,7 @@ fn run(set_location: &str, set_cookies: &[&str], final_location: &str) -> String
 
     // Get cookies for the test location
     let url = ServoUrl::parse(final_location).unwrap();
-    storage.cookies_for_url(&url, source).unwrap_or("".to_string())
+    storage.cookies_for_url(&url, source, SameSiteContext::SameSite).unwrap_or("".to_string())
 }
 
 // Following are all tests extracted from https://github.com/abarth/http-state.git
@@ -137,3 +137,47 @@ fn base64_charset() {
         Some("koi8-r"),
         Some(&[0xF0, 0xF2, 0xE5, 0xF7, 0xE5, 0xE4, 0x20, 0xED, 0xE5, 0xE4, 0xF7, 0xE5, 0xE4]));
 }
+
+#[test]
+fn base64_png() {
+    assert_parse(
+        "data:image/png;base64,\
+         iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==",
+        Some(ContentType(Mime(TopLevel::Image, SubLevel::Png, vec!()))),
+        None,
+        Some(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52,
+               0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1F, 0x15, 0xC4,
+               0x89, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x44, 0x41, 0x54, 0x78, 0xDA, 0x63, 0x64, 0x60, 0xF8, 0x5F,
+               0x0F, 0x00, 0x02, 0x87, 0x01, 0x80, 0xEB, 0x47, 0xBA, 0x92, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45,
+               0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82]));
+}
+
+#[test]
+fn base64_whitespace_and_case() {
+    assert_parse(
+        "data:application/octet-stream; BASE64 ,C62%0A+7w",
+        Some(ContentType(Mime(TopLevel::Application, SubLevel::Ext("octet-stream".to_owned()), vec!()))),
+        None,
+        Some(&[0x0B, 0xAD, 0xBE, 0xEF]));
+}
+
+#[test]
+fn plain_utf8_charset() {
+    assert_parse(
+        "data:text/plain;charset=utf-8,%E2%9C%93%20done",
+        Some(ContentType(Mime(TopLevel::Text, SubLevel::Plain, vec!((Attr::Charset, Value::Utf8))))),
+        Some("utf-8"),
+        Some("\u{2713} done".as_bytes()));
+}
+
+#[test]
+fn base64_truncated() {
+    let url = ServoUrl::parse("data:image/png;base64,iVBORw0KG").unwrap();
+    let origin = Origin::Origin(url.origin());
+    let request = Request::new(url, Some(origin), false, None);
+
+    let response = fetch_sync(request, None);
+
+    assert!(response.is_network_error());
+    assert_eq!(response.metadata().err(), Some(NetworkError::Internal("Invalid base64 in data URL".to_owned())));
+}
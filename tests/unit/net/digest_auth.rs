@@ -0,0 +1,92 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use net::test::{DigestAuthCache, DigestChallenge, digest_authorization, parse_digest_challenge};
+
+fn rfc_2617_challenge() -> DigestChallenge {
+    parse_digest_challenge("Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", \
+                            nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+                            opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"").unwrap()
+}
+
+#[test]
+fn test_parse_digest_challenge() {
+    assert_eq!(rfc_2617_challenge(), DigestChallenge {
+        realm: "testrealm@host.com".to_owned(),
+        nonce: "dcd98b7102dd2f0e8b11d0f600bfb0c093".to_owned(),
+        opaque: Some("5ccc069c403ebaf9f0171e9517f40e41".to_owned()),
+        qop_auth: true,
+        session_algorithm: false,
+        stale: false,
+    });
+    let challenge = parse_digest_challenge("digest realm=\"a, \\\"b\\\"\",nonce=n,algorithm=MD5-sess,stale=TRUE");
+    assert_eq!(challenge, Some(DigestChallenge {
+        realm: "a, \"b\"".to_owned(),
+        nonce: "n".to_owned(),
+        opaque: None,
+        qop_auth: false,
+        session_algorithm: true,
+        stale: true,
+    }));
+}
+
+#[test]
+fn test_parse_digest_challenge_that_cannot_be_answered() {
+    assert_eq!(parse_digest_challenge("Basic realm=\"a\""), None);
+    assert_eq!(parse_digest_challenge("Digest nonce=\"n\""), None);
+    assert_eq!(parse_digest_challenge("Digest realm=\"a\", nonce=\"n\", qop=\"auth-int\""), None);
+    assert_eq!(parse_digest_challenge("Digest realm=\"a\", nonce=\"n\", algorithm=SHA-256"), None);
+}
+
+#[test]
+fn test_digest_authorization_of_rfc_2617_example() {
+    let authorization = digest_authorization(&rfc_2617_challenge(), "0a4f113b", 1, "GET", "/dir/index.html",
+                                             "Mufasa", "Circle Of Life");
+    assert_eq!(authorization,
+               "Digest username=\"Mufasa\", realm=\"testrealm@host.com\", \
+                nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", uri=\"/dir/index.html\", algorithm=MD5, \
+                response=\"6629fae49393a05397450978507c4ef1\", qop=auth, nc=00000001, cnonce=\"0a4f113b\", \
+                opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"");
+}
+
+#[test]
+fn test_digest_auth_cache_counts_nonce_uses_by_origin() {
+    let mut cache = DigestAuthCache::new();
+    let origin = "http://example.com";
+    assert_eq!(cache.authorization(origin, "GET", "/", "user", "pass"), None);
+    cache.challenged(origin, rfc_2617_challenge());
+    let first = cache.authorization(origin, "GET", "/", "user", "pass").unwrap();
+    let second = cache.authorization(origin, "GET", "/", "user", "pass").unwrap();
+    assert!(first.contains("nc=00000001"));
+    assert!(second.contains("nc=00000002"));
+    assert_eq!(cache.authorization("http://example.org", "GET", "/", "user", "pass"), None);
+    // Another port of the same host is another origin.
+    assert_eq!(cache.authorization("http://example.com:8080", "GET", "/", "user", "pass"), None);
+    cache.challenged("http://example.com:8080", rfc_2617_challenge());
+    assert!(cache.authorization("http://example.com:8080", "GET", "/", "user", "pass").unwrap()
+                 .contains("nc=00000001"));
+    assert!(cache.authorization(origin, "GET", "/", "user", "pass").unwrap().contains("nc=00000003"));
+
+    cache.challenged(origin, rfc_2617_challenge());
+    assert!(cache.authorization(origin, "GET", "/", "user", "pass").unwrap().contains("nc=00000001"));
+    cache.clear_host("Example.com");
+    assert_eq!(cache.authorization(origin, "GET", "/", "user", "pass"), None);
+    assert_eq!(cache.authorization("http://example.com:8080", "GET", "/", "user", "pass"), None);
+}
+
+#[test]
+fn test_digest_auth_cache_keeps_a_nonce_count_for_each_realm() {
+    let mut cache = DigestAuthCache::new();
+    let origin = "http://example.com";
+    let other_realm = DigestChallenge {
+        realm: "other@example.com".to_owned(),
+        .. rfc_2617_challenge()
+    };
+    cache.challenged(origin, rfc_2617_challenge());
+    assert!(cache.authorization(origin, "GET", "/", "user", "pass").unwrap().contains("nc=00000001"));
+    cache.challenged(origin, other_realm);
+    let authorization = cache.authorization(origin, "GET", "/", "user", "pass").unwrap();
+    assert!(authorization.contains("realm=\"other@example.com\""));
+    assert!(authorization.contains("nc=00000001"));
+}
@@ -16,22 +16,27 @@ use hyper::header::{Encoding, Location, Pragma, Quality, QualityItem, SetCookie,
 use hyper::header::{Headers, Host, HttpDate, Referer as HyperReferer};
 use hyper::method::Method;
 use hyper::mime::{Mime, SubLevel, TopLevel};
-use hyper::server::{Request as HyperRequest, Response as HyperResponse};
+use hyper::server::{Listening, Request as HyperRequest, Response as HyperResponse};
 use hyper::status::StatusCode;
 use hyper::uri::RequestUri;
+use ipc_channel::ipc;
 use msg::constellation_msg::TEST_PIPELINE_ID;
-use net::fetch::cors_cache::CorsCache;
-use net::fetch::methods::{fetch, fetch_with_cors_cache};
-use net_traits::ReferrerPolicy;
-use net_traits::request::{Origin, RedirectMode, Referrer, Request, RequestMode};
+use net::fetch::cors_cache::{self, CorsCache};
+use net::fetch::methods::{BodyFlowControl, CancellationListener, Deadline, FetchContext, fetch};
+use net::url_rewrite::UrlRewriter;
+use net_traits::{FetchMetadata, FilteredMetadata, NetworkError, ReferrerPolicy, RewriteAction, RewriteRule};
+use net_traits::{FetchTaskTarget, SchemeRequest};
+use net_traits::request::{Destination, Origin, RedirectMode, Referrer, Request, RequestMode, Type};
 use net_traits::response::{CacheState, Response, ResponseBody, ResponseType};
+use profile_traits::time::{ProfilerCategory, ProfilerChan, ProfilerMsg};
 use servo_url::ServoUrl;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{Sender, channel};
+use std::thread;
 use time::{self, Duration};
 use unicase::UniCase;
 use url::Origin as UrlOrigin;
@@ -225,7 +230,6 @@ fn test_cors_preflight_cache_fetch() {
     static ACK: &'static [u8] = b"ACK";
     let state = Arc::new(AtomicUsize::new(0));
     let counter = state.clone();
-    let mut cache = CorsCache::new();
     let handler = move |request: HyperRequest, mut response: HyperResponse| {
         if request.method == Method::Options && state.clone().fetch_add(1, Ordering::SeqCst) == 0 {
             assert!(request.headers.has::<AccessControlRequestMethod>());
@@ -249,10 +253,12 @@ fn test_cors_preflight_cache_fetch() {
     let wrapped_request0 = Rc::new(request.clone());
     let wrapped_request1 = Rc::new(request);
 
-    let fetch_response0 = fetch_with_cors_cache(wrapped_request0.clone(), &mut cache,
-                                                &mut None, &new_fetch_context(None));
-    let fetch_response1 = fetch_with_cors_cache(wrapped_request1.clone(), &mut cache,
-                                                &mut None, &new_fetch_context(None));
+    // The cache is shared by the fetches of a resource group, through their `HttpState`.
+    let context0 = new_fetch_context(None);
+    let mut context1 = new_fetch_context(None);
+    context1.state = context0.state.clone();
+    let fetch_response0 = fetch(wrapped_request0.clone(), &mut None, &context0);
+    let fetch_response1 = fetch(wrapped_request1.clone(), &mut None, &context1);
     let _ = server.close();
 
     assert!(!fetch_response0.is_network_error() && !fetch_response1.is_network_error());
@@ -261,6 +267,7 @@ fn test_cors_preflight_cache_fetch() {
     assert_eq!(1, counter.load(Ordering::SeqCst));
 
     // The entry exists in the CORS-preflight cache
+    let mut cache = context0.state.cors_cache.write().unwrap();
     assert_eq!(true, cache.match_method(&*wrapped_request0, Method::Get));
     assert_eq!(true, cache.match_method(&*wrapped_request1, Method::Get));
 
@@ -274,6 +281,101 @@ fn test_cors_preflight_cache_fetch() {
     };
 }
 
+/// A server that answers preflights for `X-Custom` with a max-age of `max_age`, and counts
+/// them. Actual requests are allowed unless `fail_actual_requests` is set.
+fn make_cors_server(max_age: u32, fail_actual_requests: bool) -> (Listening, ServoUrl, Arc<AtomicUsize>) {
+    let preflights = Arc::new(AtomicUsize::new(0));
+    let counter = preflights.clone();
+    let handler = move |request: HyperRequest, mut response: HyperResponse| {
+        if request.method == Method::Options {
+            counter.fetch_add(1, Ordering::SeqCst);
+            response.headers_mut().set(AccessControlAllowOrigin::Any);
+            response.headers_mut().set(AccessControlAllowMethods(vec![Method::Put]));
+            response.headers_mut().set(AccessControlAllowHeaders(vec![UniCase("X-Custom".to_owned())]));
+            response.headers_mut().set(AccessControlMaxAge(max_age));
+        } else if !fail_actual_requests {
+            response.headers_mut().set(AccessControlAllowOrigin::Any);
+            response.send(b"ACK").unwrap();
+        }
+    };
+    let (server, url) = make_server(handler);
+    (server, url, preflights)
+}
+
+fn cors_put_request(url: &ServoUrl) -> Request {
+    let origin = Origin::Origin(ServoUrl::parse("http://example.com").unwrap().origin());
+    let mut request = Request::new(url.clone(), Some(origin), false, None);
+    *request.method.borrow_mut() = Method::Put;
+    request.headers.borrow_mut().set_raw("X-Custom", vec![b"1".to_vec()]);
+    *request.referrer.borrow_mut() = Referrer::NoReferrer;
+    request.unsafe_request = true;
+    request.mode = RequestMode::CorsMode;
+    request
+}
+
+#[test]
+fn test_cors_preflight_cache_is_shared_between_threads() {
+    let (mut server, url, preflights) = make_cors_server(600, false);
+    let context = new_fetch_context(None);
+    assert!(!fetch(Rc::new(cors_put_request(&url)), &mut None, &context).is_network_error());
+
+    let threads: Vec<_> = (0..4).map(|_| {
+        let state = context.state.clone();
+        let url = url.clone();
+        thread::spawn(move || {
+            let mut context = new_fetch_context(None);
+            context.state = state;
+            fetch(Rc::new(cors_put_request(&url)), &mut None, &context).is_network_error()
+        })
+    }).collect();
+    for handle in threads {
+        assert!(!handle.join().unwrap());
+    }
+    let _ = server.close();
+
+    assert_eq!(preflights.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_cors_preflight_cache_entry_expires_with_max_age_of_zero() {
+    let (mut server, url, preflights) = make_cors_server(0, false);
+    let context = new_fetch_context(None);
+    assert!(!fetch(Rc::new(cors_put_request(&url)), &mut None, &context).is_network_error());
+    assert!(!fetch(Rc::new(cors_put_request(&url)), &mut None, &context).is_network_error());
+    let _ = server.close();
+
+    assert_eq!(preflights.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_cors_preflight_cache_is_cleared_when_the_request_fails() {
+    let (mut server, url, preflights) = make_cors_server(600, true);
+    let context = new_fetch_context(None);
+    assert!(fetch(Rc::new(cors_put_request(&url)), &mut None, &context).is_network_error());
+    assert!(fetch(Rc::new(cors_put_request(&url)), &mut None, &context).is_network_error());
+    let _ = server.close();
+
+    assert_eq!(preflights.load(Ordering::SeqCst), 2);
+    let request = cors_put_request(&url);
+    assert!(!context.state.cors_cache.write().unwrap().match_method(&request, Method::Put));
+}
+
+#[test]
+fn test_cors_preflight_cache_is_capped() {
+    let mut cache = CorsCache::new();
+    let origin = Origin::Origin(UrlOrigin::new_opaque());
+    let first = Request::new(ServoUrl::parse("http://example.com/0").unwrap(), Some(origin.clone()), false, None);
+    for i in 0..cors_cache::MAX_ENTRIES + 1 {
+        let url = ServoUrl::parse(&format!("http://example.com/{}", i)).unwrap();
+        let request = Request::new(url, Some(origin.clone()), false, None);
+        assert!(!cache.match_method_and_update(&request, Method::Put, 600));
+    }
+
+    assert_eq!(cache.len(), cors_cache::MAX_ENTRIES);
+    // The oldest entry made room for the newest.
+    assert!(!cache.match_method(&first, Method::Put));
+}
+
 #[test]
 fn test_cors_preflight_fetch_network_error() {
     static ACK: &'static [u8] = b"ACK";
@@ -374,6 +476,7 @@ fn test_fetch_response_is_cors_filtered() {
     let headers = fetch_response.headers;
     assert!(headers.has::<CacheControl>());
     assert!(headers.has::<ContentLanguage>());
+    assert!(headers.has::<ContentLength>());
     assert!(headers.has::<ContentType>());
     assert!(headers.has::<Expires>());
     assert!(headers.has::<LastModified>());
@@ -406,6 +509,7 @@ fn test_fetch_response_is_opaque_filtered() {
     assert!(fetch_response.url_list.into_inner().len() == 0);
     // this also asserts that status message is "the empty byte sequence"
     assert!(fetch_response.status.is_none());
+    assert!(fetch_response.raw_status.is_none());
     assert_eq!(fetch_response.headers, Headers::new());
     match *fetch_response.body.lock().unwrap() {
         ResponseBody::Empty => { },
@@ -417,6 +521,32 @@ fn test_fetch_response_is_opaque_filtered() {
     }
 }
 
+#[test]
+fn test_fetch_opaque_response_keeps_internal_body() {
+    static MESSAGE: &'static [u8] = b"Yay!";
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        response.send(MESSAGE).unwrap();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let origin = Origin::Origin(UrlOrigin::new_opaque());
+    let request = Request::new(url, Some(origin), false, None);
+    *request.referrer.borrow_mut() = Referrer::NoReferrer;
+    let fetch_response = fetch_sync(request, None);
+    let _ = server.close();
+
+    assert_eq!(fetch_response.response_type, ResponseType::Opaque);
+    match fetch_response.metadata() {
+        Ok(FetchMetadata::Filtered { filtered: FilteredMetadata::Opaque, unsafe_ }) =>
+            assert_eq!(unsafe_.status, Some((200, b"OK".to_vec()))),
+        _ => panic!(),
+    }
+    match *fetch_response.actual_response().body.lock().unwrap() {
+        ResponseBody::Done(ref body) => assert_eq!(&**body, MESSAGE),
+        _ => panic!(),
+    }
+}
+
 #[test]
 fn test_fetch_response_is_opaque_redirect_filtered() {
     static MESSAGE: &'static [u8] = b"";
@@ -452,6 +582,7 @@ fn test_fetch_response_is_opaque_redirect_filtered() {
 
     // this also asserts that status message is "the empty byte sequence"
     assert!(fetch_response.status.is_none());
+    assert!(fetch_response.raw_status.is_none());
     assert_eq!(fetch_response.headers, Headers::new());
     match *fetch_response.body.lock().unwrap() {
         ResponseBody::Empty => { },
@@ -461,6 +592,26 @@ fn test_fetch_response_is_opaque_redirect_filtered() {
         CacheState::None => { },
         _ => panic!()
     }
+    // The redirect is still there for navigation to follow.
+    assert_eq!(fetch_response.actual_response().headers.get(), Some(&Location("1".to_owned())));
+}
+
+#[test]
+fn test_fetch_redirect_in_error_mode_is_network_error() {
+    let handler = move |_: HyperRequest, mut response: HyperResponse| {
+        *response.status_mut() = StatusCode::MovedPermanently;
+        response.headers_mut().set(Location("/elsewhere".to_owned()));
+    };
+    let (mut server, url) = make_server(handler);
+
+    let origin = Origin::Origin(url.origin());
+    let request = Request::new(url, Some(origin), false, None);
+    *request.referrer.borrow_mut() = Referrer::NoReferrer;
+    request.redirect_mode.set(RedirectMode::Error);
+    let fetch_response = fetch_sync(request, None);
+    let _ = server.close();
+
+    assert!(fetch_response.is_network_error());
 }
 
 #[test]
@@ -550,7 +701,7 @@ fn test_fetch_redirect_count_failure() {
 
     let fetch_response = setup_server_and_fetch(MESSAGE, redirect_cap);
 
-    assert!(fetch_response.is_network_error());
+    assert_eq!(fetch_response.get_network_error(), Some(&NetworkError::TooManyRedirects));
 
     match *fetch_response.body.lock().unwrap() {
         ResponseBody::Done(_) | ResponseBody::Receiving(_) => panic!(),
@@ -558,6 +709,275 @@ fn test_fetch_redirect_count_failure() {
     };
 }
 
+#[test]
+fn test_fetch_redirect_chain_is_in_metadata() {
+    let fetch_response = setup_server_and_fetch(b"landed", 2);
+
+    let metadata = match fetch_response.metadata().unwrap() {
+        FetchMetadata::Filtered { filtered: FilteredMetadata::Transparent(metadata), .. } => metadata,
+        _ => panic!(),
+    };
+    let paths: Vec<_> = metadata.url_list.iter().map(|url| url.path().to_owned()).collect();
+    assert_eq!(paths, vec!["/", "/1", "/2"]);
+    assert_eq!(metadata.final_url, metadata.url_list[2]);
+    assert_eq!(metadata.redirect_statuses, vec![302, 302]);
+}
+
+#[test]
+fn test_fetch_blocks_active_mixed_content() {
+    let requested = Arc::new(AtomicUsize::new(0));
+    let counter = requested.clone();
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        response.send(b"alert(1)").unwrap();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let origin = Origin::Origin(url.origin());
+    let mut request = Request::new(url, Some(origin), false, None);
+    *request.referrer.borrow_mut() = Referrer::NoReferrer;
+    request.type_ = Type::Script;
+    request.destination = Destination::Script;
+    request.document_scheme = Some("https".to_owned());
+    let fetch_response = fetch_sync(request, None);
+    let _ = server.close();
+
+    assert_eq!(fetch_response.get_network_error(), Some(&NetworkError::MixedContentBlocked));
+    assert_eq!(requested.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn test_fetch_allows_passive_mixed_content_and_labels_it() {
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        response.send(b"not really a png").unwrap();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let fetch_image = |document_scheme: &str| {
+        let origin = Origin::Origin(url.origin());
+        let mut request = Request::new(url.clone(), Some(origin), false, None);
+        *request.referrer.borrow_mut() = Referrer::NoReferrer;
+        request.type_ = Type::Image;
+        request.destination = Destination::Image;
+        request.document_scheme = Some(document_scheme.to_owned());
+        let fetch_response = fetch_sync(request, None);
+        assert!(!fetch_response.is_network_error());
+        match fetch_response.metadata().unwrap() {
+            FetchMetadata::Filtered { filtered: FilteredMetadata::Transparent(metadata), .. } => metadata,
+            _ => panic!(),
+        }
+    };
+    let mixed = fetch_image("https");
+    let unmixed = fetch_image("http");
+    let _ = server.close();
+
+    assert!(mixed.mixed_content);
+    assert!(!unmixed.mixed_content);
+}
+
+#[test]
+fn test_fetch_cancelled_before_network_fetch() {
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        response.send(b"unreachable").unwrap();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let (cancel_sender, cancel_receiver) = channel();
+    let mut context = new_fetch_context(None);
+    context.cancellation_listener = Arc::new(Mutex::new(CancellationListener::new(Some(cancel_receiver))));
+    cancel_sender.send(()).unwrap();
+
+    let origin = Origin::Origin(url.origin());
+    let request = Request::new(url, Some(origin), false, None);
+    *request.referrer.borrow_mut() = Referrer::NoReferrer;
+    let fetch_response = fetch(Rc::new(request), &mut None, &context);
+    let _ = server.close();
+
+    assert_eq!(fetch_response.get_network_error(), Some(&NetworkError::LoadCancelled));
+}
+
+/// A fetch target that cancels its fetch once the first chunk of the body arrives.
+struct CancelOnFirstChunk {
+    cancel_sender: Sender<()>,
+}
+
+impl FetchTaskTarget for CancelOnFirstChunk {
+    fn process_request_body(&mut self, _: &Request) {}
+    fn process_request_body_progress(&mut self, _: u64, _: Option<u64>) {}
+    fn process_request_eof(&mut self, _: &Request) {}
+    fn process_early_hints(&mut self, _: &[String]) {}
+    fn process_response(&mut self, _: &Response) {}
+    fn process_response_part(&mut self, _: &Response) {}
+    fn process_response_chunk(&mut self, _: Vec<u8>) {
+        let _ = self.cancel_sender.send(());
+    }
+    fn process_response_trailers(&mut self, _: &Headers) {}
+    fn process_response_eof(&mut self, _: &Response) {}
+}
+
+#[test]
+fn test_fetch_cancelled_while_receiving_the_body() {
+    use std::time::Duration;
+
+    // The server streams chunks until the connection is closed, and reports whether it was.
+    let (closed_sender, closed_receiver) = channel();
+    let closed_sender = Mutex::new(closed_sender);
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        let mut response = response.start().unwrap();
+        for _ in 0..200 {
+            if response.write_all(b"chunk").and_then(|_| response.flush()).is_err() {
+                let _ = closed_sender.lock().unwrap().send(true);
+                return;
+            }
+            thread::sleep(Duration::from_millis(25));
+        }
+        let _ = closed_sender.lock().unwrap().send(false);
+    };
+    let (mut server, url) = make_server(handler);
+
+    let (cancel_sender, cancel_receiver) = channel();
+    let mut context = new_fetch_context(None);
+    context.cancellation_listener = Arc::new(Mutex::new(CancellationListener::new(Some(cancel_receiver))));
+    let mut target: Option<Box<FetchTaskTarget + Send>> = Some(Box::new(CancelOnFirstChunk {
+        cancel_sender: cancel_sender,
+    }));
+
+    let origin = Origin::Origin(url.origin());
+    let request = Request::new(url, Some(origin), false, None);
+    *request.referrer.borrow_mut() = Referrer::NoReferrer;
+    let fetch_response = fetch(Rc::new(request), &mut target, &context);
+
+    assert_eq!(fetch_response.get_network_error(), Some(&NetworkError::LoadCancelled));
+    assert!(closed_receiver.recv().unwrap(), "the connection was kept open after the fetch was cancelled");
+    let _ = server.close();
+}
+
+/// A scheme handler that calls `on_request` for the first request it gets, and never answers.
+fn unanswering_scheme_handler<F: FnOnce() + Send + 'static>(on_request: F) -> ipc::IpcSender<SchemeRequest> {
+    let (handler, requests) = ipc::channel::<SchemeRequest>().unwrap();
+    thread::spawn(move || {
+        if let Ok(request) = requests.recv() {
+            on_request();
+            // Holding on to the request keeps the fetch waiting for the answer.
+            let _request: SchemeRequest = request;
+            let _ = requests.recv();
+        }
+    });
+    handler
+}
+
+#[test]
+fn test_scheme_handler_that_never_answers_is_given_up_on_cancel_or_timeout() {
+    let fetch_from = |handler: ipc::IpcSender<SchemeRequest>, context: &FetchContext| {
+        context.scheme_handlers.write().unwrap().insert("app".to_owned(), handler);
+        let url = ServoUrl::parse("app://servo/index.html").unwrap();
+        let mut request = Request::new(url.clone(), Some(Origin::Origin(url.origin())), false, None);
+        *request.referrer.borrow_mut() = Referrer::NoReferrer;
+        request.mode = RequestMode::Navigate;
+        fetch(Rc::new(request), &mut None, context)
+    };
+
+    let (cancel_sender, cancel_receiver) = channel();
+    let mut context = new_fetch_context(None);
+    context.cancellation_listener = Arc::new(Mutex::new(CancellationListener::new(Some(cancel_receiver))));
+    let response = fetch_from(unanswering_scheme_handler(move || cancel_sender.send(()).unwrap()), &context);
+    assert_eq!(response.get_network_error(), Some(&NetworkError::LoadCancelled));
+
+    let mut context = new_fetch_context(None);
+    context.deadline = Some(Deadline::after(100, false));
+    let response = fetch_from(unanswering_scheme_handler(|| ()), &context);
+    assert_eq!(response.get_network_error(), Some(&NetworkError::Timeout));
+}
+
+#[test]
+fn test_fetch_reports_network_timings() {
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        response.send(b"Yay!").unwrap();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let (profiler_sender, profiler_receiver) = ipc::channel().unwrap();
+    let mut context = new_fetch_context(None);
+    context.time_profiler_chan = Some(ProfilerChan(profiler_sender));
+
+    let origin = Origin::Origin(url.origin());
+    let request = Request::new(url.clone(), Some(origin), false, None);
+    *request.referrer.borrow_mut() = Referrer::NoReferrer;
+    let fetch_response = fetch(Rc::new(request), &mut None, &context);
+    let _ = server.close();
+    assert!(!fetch_response.is_network_error());
+
+    // The test server is plain HTTP, so no handshake is reported.
+    let expected = [ProfilerCategory::NetDNSConnect,
+                    ProfilerCategory::NetTimeToFirstByte,
+                    ProfilerCategory::NetBodyDownload];
+    for category in &expected {
+        match profiler_receiver.recv().unwrap() {
+            ProfilerMsg::Time((reported, Some(meta)), (start, end), _) => {
+                assert_eq!(reported, *category);
+                assert_eq!(meta.url, url.host_str().unwrap());
+                assert!(start <= end);
+            }
+            _ => panic!("expected a timing for {:?}", category),
+        }
+    }
+}
+
+#[test]
+fn test_body_flow_control_waits_for_acknowledgement() {
+    let (ack_sender, ack_receiver) = channel();
+    let mut flow_control = BodyFlowControl::new(10, ack_receiver);
+
+    flow_control.sent(8);
+    flow_control.wait_for_window(|| panic!("shouldn't wait while within the window"));
+
+    flow_control.sent(8);
+    ack_sender.send(8).unwrap();
+    flow_control.wait_for_window(|| false);
+}
+
+#[test]
+fn test_fetch_redirect_loop_is_network_error() {
+    let handler = move |_: HyperRequest, mut response: HyperResponse| {
+        *response.status_mut() = StatusCode::Found;
+        response.headers_mut().set(Location("/".to_owned()));
+    };
+    let (mut server, url) = make_server(handler);
+
+    let origin = Origin::Origin(url.origin());
+    let request = Request::new(url, Some(origin), false, None);
+    *request.referrer.borrow_mut() = Referrer::NoReferrer;
+    let fetch_response = fetch_sync(request, None);
+    let _ = server.close();
+
+    assert_eq!(fetch_response.get_network_error(), Some(&NetworkError::TooManyRedirects));
+}
+
+#[test]
+fn test_fetch_rewrites_url_before_request_is_sent() {
+    let handler = move |request: HyperRequest, response: HyperResponse| {
+        assert_eq!(request.uri, RequestUri::AbsolutePath("/?a=1".to_owned()));
+        response.send(b"Yay!").unwrap();
+    };
+    let (mut server, url) = make_server(handler);
+    let url = url.join("/?a=1&utm_source=x&fbclid=y").unwrap();
+
+    let origin = Origin::Origin(url.origin());
+    let request = Request::new(url.clone(), Some(origin), false, None);
+    *request.referrer.borrow_mut() = Referrer::NoReferrer;
+    let context = new_fetch_context(None);
+    *context.state.url_rewriter.write().unwrap() = UrlRewriter::new(vec![RewriteRule {
+        host: "*".to_owned(),
+        action: RewriteAction::RemoveQueryParams(vec!["utm_*".to_owned(), "fbclid".to_owned()]),
+    }]);
+    let fetch_response = fetch(Rc::new(request), &mut None, &context);
+    let _ = server.close();
+
+    assert!(!fetch_response.is_network_error());
+    // The URL reported in the response's metadata is the rewritten one.
+    assert_eq!(fetch_response.actual_response().url(), Some(&url.join("/?a=1").unwrap()));
+}
+
 fn test_fetch_redirect_updates_method_runner(tx: Sender<bool>, status_code: StatusCode, method: Method) {
     let handler_method = method.clone();
     let handler_tx = Arc::new(Mutex::new(tx));
@@ -647,6 +1067,59 @@ fn test_fetch_redirect_updates_method() {
     assert_eq!(rx.try_recv().is_err(), true);
 }
 
+/// The method, body and `Content-Type` that the target of a redirect with `status_code`
+/// receives when `method` is sent with a body.
+fn fetch_redirected_with_body(status_code: StatusCode, method: Method) -> (Method, Vec<u8>, Option<ContentType>) {
+    let (sender, receiver) = channel();
+    let sender = Mutex::new(sender);
+    let handler = move |mut request: HyperRequest, mut response: HyperResponse| {
+        if request.uri == RequestUri::AbsolutePath("/final".to_owned()) {
+            let mut body = vec![];
+            request.read_to_end(&mut body).unwrap();
+            let content_type = request.headers.get::<ContentType>().cloned();
+            sender.lock().unwrap().send((request.method.clone(), body, content_type)).unwrap();
+            response.send(b"").unwrap();
+        } else {
+            *response.status_mut() = status_code;
+            response.headers_mut().set(Location("/final".to_owned()));
+        }
+    };
+    let (mut server, url) = make_server(handler);
+
+    let origin = Origin::Origin(url.origin());
+    let request = Request::new(url, Some(origin), false, None);
+    *request.referrer.borrow_mut() = Referrer::NoReferrer;
+    *request.method.borrow_mut() = method;
+    *request.body.borrow_mut() = Some(b"payload".to_vec());
+    request.headers.borrow_mut().set(ContentType(Mime(TopLevel::Text, SubLevel::Plain, vec![])));
+    let response = fetch_sync(request, None);
+    let _ = server.close();
+
+    assert!(!response.is_network_error());
+    receiver.recv().unwrap()
+}
+
+#[test]
+fn test_fetch_redirect_drops_the_body_when_it_changes_the_method() {
+    for &status_code in &[StatusCode::MovedPermanently, StatusCode::Found, StatusCode::SeeOther] {
+        assert_eq!(fetch_redirected_with_body(status_code, Method::Post), (Method::Get, vec![], None));
+    }
+    // Only a 303 changes other methods.
+    let (method, body, _) = fetch_redirected_with_body(StatusCode::SeeOther, Method::Put);
+    assert_eq!((method, body), (Method::Get, vec![]));
+    let (method, body, _) = fetch_redirected_with_body(StatusCode::Found, Method::Put);
+    assert_eq!((method, body), (Method::Put, b"payload".to_vec()));
+}
+
+#[test]
+fn test_fetch_redirect_307_and_308_send_the_body_again() {
+    let content_type = ContentType(Mime(TopLevel::Text, SubLevel::Plain, vec![]));
+    for &status_code in &[StatusCode::TemporaryRedirect, StatusCode::PermanentRedirect] {
+        assert_eq!(fetch_redirected_with_body(status_code, Method::Post),
+                   (Method::Post, b"payload".to_vec(), Some(content_type.clone())));
+    }
+}
+
 fn response_is_done(response: &Response) -> bool {
     let response_complete = match response.response_type {
         ResponseType::Default | ResponseType::Basic | ResponseType::Cors => {
@@ -804,6 +1277,10 @@ fn test_fetch_with_devtools() {
 
     headers.set(UserAgent(DEFAULT_USER_AGENT.to_owned()));
 
+    headers.set_raw("Sec-Fetch-Site", vec![b"same-origin".to_vec()]);
+    headers.set_raw("Sec-Fetch-Mode", vec![b"no-cors".to_vec()]);
+    headers.set_raw("Sec-Fetch-Dest", vec![b"empty".to_vec()]);
+
     let httprequest = DevtoolsHttpRequest {
         url: url,
         method: Method::Get,
@@ -814,6 +1291,7 @@ fn test_fetch_with_devtools() {
         timeStamp: devhttprequest.timeStamp,
         connect_time: devhttprequest.connect_time,
         send_time: devhttprequest.send_time,
+        retransmit_time: None,
         is_xhr: true,
     };
 
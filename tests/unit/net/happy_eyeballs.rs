@@ -0,0 +1,90 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use net::test::{AddressFamily, HappyEyeballs, Resolver, race, sort_addresses};
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct MockResolver(Vec<SocketAddr>);
+
+impl Resolver for MockResolver {
+    fn resolve(&self, _host: &str, _port: u16) -> io::Result<Vec<SocketAddr>> {
+        Ok(self.0.clone())
+    }
+}
+
+fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_sort_addresses_alternates_families() {
+    let addrs = vec![addr("[::1]:80"), addr("[::2]:80"), addr("[::3]:80"),
+                     addr("10.0.0.1:80"), addr("10.0.0.2:80")];
+    assert_eq!(sort_addresses(addrs, None),
+               vec![addr("[::1]:80"), addr("10.0.0.1:80"), addr("[::2]:80"),
+                    addr("10.0.0.2:80"), addr("[::3]:80")]);
+}
+
+#[test]
+fn test_sort_addresses_starts_with_the_preferred_family() {
+    let addrs = vec![addr("[::1]:80"), addr("[::2]:80"), addr("10.0.0.1:80")];
+    assert_eq!(sort_addresses(addrs, Some(AddressFamily::Ipv4)),
+               vec![addr("10.0.0.1:80"), addr("[::1]:80"), addr("[::2]:80")]);
+}
+
+#[test]
+fn test_race_starts_the_next_attempt_after_the_delay() {
+    let slow = addr("[::1]:80");
+    let fast = addr("10.0.0.1:80");
+    let start = Instant::now();
+    let (_, winner) = race(vec![slow, fast], Duration::from_millis(50), move |addr| {
+        if addr == slow {
+            thread::sleep(Duration::from_secs(5));
+        }
+        Ok(())
+    }).unwrap();
+    assert_eq!(winner, fast);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_race_starts_the_next_attempt_as_soon_as_one_fails() {
+    let broken = addr("[::1]:80");
+    let working = addr("10.0.0.1:80");
+    let start = Instant::now();
+    let (_, winner) = race(vec![broken, working], Duration::from_secs(5), move |addr| {
+        if addr == broken {
+            Err(io::Error::new(ErrorKind::ConnectionRefused, "refused"))
+        } else {
+            Ok(())
+        }
+    }).unwrap();
+    assert_eq!(winner, working);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_race_fails_when_every_attempt_fails() {
+    let result = race(vec![addr("[::1]:80"), addr("10.0.0.1:80")], Duration::from_millis(50), |_| {
+        Err::<(), _>(io::Error::new(ErrorKind::ConnectionRefused, "refused"))
+    });
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::ConnectionRefused);
+}
+
+#[test]
+fn test_happy_eyeballs_falls_back_and_prefers_the_family_that_connected() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let addrs = vec![addr(&format!("[::1]:{}", port)), addr(&format!("127.0.0.1:{}", port))];
+    let happy_eyeballs = HappyEyeballs::new(Arc::new(MockResolver(addrs)));
+    assert_eq!(happy_eyeballs.preferred_family("example.com"), None);
+
+    let stream = happy_eyeballs.connect("example.com", port).unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), addr(&format!("127.0.0.1:{}", port)));
+    assert_eq!(happy_eyeballs.preferred_family("example.com"), Some(AddressFamily::Ipv4));
+}
@@ -234,6 +234,40 @@ fn test_hsts_list_with_subdomain_when_host_is_exact_match_is_is_host_secure() {
     assert!(hsts_list.is_host_secure("mozilla.org"));
 }
 
+#[test]
+fn test_hsts_list_with_idn_entry_is_host_secure_for_punycode_host() {
+    let hsts_list = HstsList {
+        entries: vec![HstsEntry::new("CAFÉ.example".to_owned(),
+            IncludeSubdomains::Included, None).unwrap()]
+    };
+
+    assert!(hsts_list.is_host_secure("xn--caf-dma.example"));
+    assert!(hsts_list.is_host_secure("www.xn--caf-dma.example"));
+    assert!(hsts_list.is_host_secure("www.café.example"));
+}
+
+#[test]
+fn test_hsts_list_with_include_subdomains_does_not_match_ipv6_host() {
+    let hsts_list = HstsList {
+        entries: vec![HstsEntry {
+            host: "0.1".to_owned(),
+            include_subdomains: true,
+            max_age: None,
+            timestamp: None
+        }]
+    };
+
+    assert!(!hsts_list.is_host_secure("::ffff:0.0.0.1"));
+    assert!(!hsts_list.is_host_secure("[::ffff:0.0.0.1]"));
+}
+
+#[test]
+fn test_hsts_entry_cant_be_created_with_bracketed_ipv6_address_as_host() {
+    let entry = HstsEntry::new("[::1]".to_owned(), IncludeSubdomains::Included, None);
+
+    assert!(entry.is_none(), "able to create HstsEntry with bracketed IPv6 host");
+}
+
 #[test]
 fn test_hsts_list_with_expired_entry_is_not_is_host_secure() {
     let hsts_list = HstsList {
@@ -0,0 +1,267 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use hyper::client::Request;
+use hyper::method::Method;
+use hyper::net::HttpStream;
+use hyper::server::{Request as HyperRequest, Response as HyperResponse};
+use hyper::status::StatusCode;
+use hyper::version::HttpVersion;
+use net::test::{ConnectionLimiter, Frame, Http2Session, HpackDecoder, HpackEncoder};
+use net::test::{DATA, END_HEADERS, END_STREAM, GOAWAY, HEADERS, PREFACE, SETTINGS, SETTINGS_MAX_CONCURRENT_STREAMS};
+use net::test::WINDOW_UPDATE;
+use net_traits::request::RequestPriority;
+use std::io::{ErrorKind, Read};
+use std::net::{TcpListener, TcpStream};
+use std::str;
+use std::sync::Arc;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+use url::Url;
+use {make_tls_server, tls_connection_pools};
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    hex.as_bytes().chunks(2).map(|pair| u8::from_str_radix(str::from_utf8(pair).unwrap(), 16).unwrap()).collect()
+}
+
+fn headers(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|&(name, value)| (name.to_owned(), value.to_owned())).collect()
+}
+
+#[test]
+fn test_hpack_decodes_huffman_coded_requests() {
+    // https://tools.ietf.org/html/rfc7541#appendix-C.4
+    let mut decoder = HpackDecoder::new();
+    assert_eq!(decoder.decode(&from_hex("828684418cf1e3c2e5f23a6ba0ab90f4ff")),
+               Ok(headers(&[(":method", "GET"), (":scheme", "http"), (":path", "/"),
+                            (":authority", "www.example.com")])));
+    assert_eq!(decoder.decode(&from_hex("828684be5886a8eb10649cbf")),
+               Ok(headers(&[(":method", "GET"), (":scheme", "http"), (":path", "/"),
+                            (":authority", "www.example.com"), ("cache-control", "no-cache")])));
+    assert_eq!(decoder.decode(&from_hex("828785bf408825a849e95ba97d7f8925a849e95bb8e8b4bf")),
+               Ok(headers(&[(":method", "GET"), (":scheme", "https"), (":path", "/index.html"),
+                            (":authority", "www.example.com"), ("custom-key", "custom-value")])));
+}
+
+#[test]
+fn test_hpack_evicts_the_oldest_entries_from_a_smaller_table() {
+    let mut decoder = HpackDecoder::new();
+    let response = headers(&[(":status", "302"), ("cache-control", "private"),
+                             ("date", "Mon, 21 Oct 2013 20:13:21 GMT"), ("location", "https://www.example.com")]);
+    // The block starts by shrinking the table to 256 bytes, which the four headers fill.
+    assert_eq!(decoder.decode(&from_hex("3fe101488264025885aec3771a4b6196d07abe941054d444a8200595040b8166e082a62d1bff\
+                                         6e919d29ad171863c78f0b97c8e9ae82ae43d3")),
+               Ok(response.clone()));
+    let mut redirect = response;
+    redirect[0].1 = "307".to_owned();
+    assert_eq!(decoder.decode(&from_hex("4883640effc1c0bf")), Ok(redirect));
+    // Adding `:status: 307` evicted `:status: 302`, so the newest entry is the new status.
+    assert_eq!(decoder.decode(&from_hex("be")), Ok(headers(&[(":status", "307")])));
+    // The table can't grow past the size allowed by the settings.
+    assert!(decoder.decode(&from_hex("3fe21f")).is_err());
+}
+
+#[test]
+fn test_hpack_encoded_headers_decode_to_the_same_headers() {
+    let request = headers(&[(":method", "GET"), (":path", "/"), (":authority", "www.example.com"),
+                            ("custom-key", "custom-value"), ("x", "yy")]);
+    let block = HpackEncoder::new().encode(&request);
+    // `:method: GET` and `:path: /` are in the static table.
+    assert_eq!(&block[..2], &[0x82, 0x84]);
+    assert_eq!(HpackDecoder::new().decode(&block), Ok(request));
+}
+
+/// Read the connection preface and send `SETTINGS` allowing `max_streams` at once.
+fn accept_http2_connection(listener: &TcpListener, max_streams: u32) -> TcpStream {
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut preface = vec![0; PREFACE.len()];
+    stream.read_exact(&mut preface).unwrap();
+    assert_eq!(preface, PREFACE);
+    Frame::settings(&[(SETTINGS_MAX_CONCURRENT_STREAMS, max_streams)]).write_to(&mut stream).unwrap();
+    stream
+}
+
+fn start_session(listener: &TcpListener, origin: &str) -> Http2Session {
+    start_session_idle_for(listener, origin, Duration::from_secs(60))
+}
+
+fn start_session_idle_for(listener: &TcpListener, origin: &str, idle_timeout: Duration) -> Http2Session {
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    Http2Session::new(HttpStream(stream), origin.to_owned(), idle_timeout)
+}
+
+#[test]
+fn test_http2_session_makes_requests_at_once_on_one_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let session = Arc::new(start_session(&listener, "https://multiplexed.example"));
+    let server = thread::spawn(move || {
+        let mut stream = accept_http2_connection(&listener, 10);
+        let mut decoder = HpackDecoder::new();
+        let mut requests = vec![];
+        while requests.len() < 2 {
+            let frame = Frame::read_from(&mut stream).unwrap();
+            if frame.kind == HEADERS {
+                assert_eq!(frame.flags, END_HEADERS | END_STREAM);
+                let headers = decoder.decode(&frame.payload).unwrap();
+                let path = headers.iter().find(|&&(ref name, _)| name == ":path").unwrap().1.clone();
+                requests.push((frame.stream_id, path));
+            } else {
+                assert!(frame.kind == SETTINGS);
+            }
+        }
+        // Both requests are in flight; the second is answered first.
+        let mut encoder = HpackEncoder::new();
+        for &(stream_id, ref path) in requests.iter().rev() {
+            let block = encoder.encode(&headers(&[(":status", "200"), ("content-type", "text/plain")]));
+            Frame::new(HEADERS, END_HEADERS, stream_id, block).write_to(&mut stream).unwrap();
+            let body = format!("response to {}", path).into_bytes();
+            Frame::new(DATA, END_STREAM, stream_id, body).write_to(&mut stream).unwrap();
+        }
+        // Only one connection is ever made.
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_err());
+    });
+
+    let requests: Vec<_> = ["/first", "/second"].iter().map(|&path| {
+        let session = session.clone();
+        thread::spawn(move || {
+            let url = Url::parse(&format!("https://multiplexed.example{}", path)).unwrap();
+            let request = Request::with_message(Method::Get, url, Box::new(session.message())).unwrap();
+            let mut response = request.start().unwrap().send().unwrap();
+            assert_eq!(response.status, StatusCode::Ok);
+            assert_eq!(response.headers.get_raw("content-type"), Some(&[b"text/plain".to_vec()][..]));
+            let mut body = String::new();
+            response.read_to_string(&mut body).unwrap();
+            body
+        })
+    }).collect();
+    let bodies: Vec<String> = requests.into_iter().map(|request| request.join().unwrap()).collect();
+    assert_eq!(bodies, vec!["response to /first", "response to /second"]);
+    server.join().unwrap();
+}
+
+#[test]
+fn test_connection_limiter_allows_as_many_fetches_as_http2_streams() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let origin = "https://streams.example";
+    let session = start_session(&listener, origin);
+    let _stream = accept_http2_connection(&listener, 20);
+    while session.max_concurrent_streams() != Some(20) {
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    let mut limiter = ConnectionLimiter::new();
+    let max_streams = session.max_concurrent_streams();
+    for fetch_id in 0..20 {
+        let job = Box::new(|_: bool| ());
+        assert!(limiter.start_or_queue(origin, "streams.example", fetch_id, RequestPriority::Normal, max_streams,
+                                       job).is_some());
+    }
+    let job = Box::new(|_: bool| ());
+    assert!(limiter.start_or_queue(origin, "streams.example", 20, RequestPriority::Normal, max_streams, job).is_none());
+}
+
+#[test]
+fn test_http2_session_lets_a_fetch_wait_for_a_stream_when_none_are_allowed() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let session = start_session(&listener, "https://busy.example");
+    let _stream = accept_http2_connection(&listener, 0);
+    while session.max_concurrent_streams() == Some(100) {
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(session.max_concurrent_streams(), Some(1));
+}
+
+#[test]
+fn test_idle_http2_session_is_closed() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let session = start_session_idle_for(&listener, "https://idle.example", Duration::from_millis(100));
+    let mut stream = accept_http2_connection(&listener, 10);
+    let mut kinds = vec![];
+    loop {
+        match Frame::read_from(&mut stream) {
+            Ok(frame) => kinds.push(frame.kind),
+            Err(ref error) if error.kind() == ErrorKind::UnexpectedEof => break,
+            Err(error) => panic!("{}", error),
+        }
+    }
+    // The client's settings and the acknowledgement of the server's, then a goodbye.
+    assert_eq!(kinds, vec![SETTINGS, SETTINGS, GOAWAY]);
+    assert!(session.is_closed());
+    assert_eq!(session.max_concurrent_streams(), None);
+}
+
+#[test]
+fn test_http2_stream_window_is_only_opened_once_the_data_is_read() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let session = start_session(&listener, "https://window.example");
+    let (read_sender, read_receiver) = channel();
+    let server = thread::spawn(move || {
+        let mut stream = accept_http2_connection(&listener, 10);
+        let mut frame = Frame::read_from(&mut stream).unwrap();
+        while frame.kind != HEADERS {
+            frame = Frame::read_from(&mut stream).unwrap();
+        }
+        let stream_id = frame.stream_id;
+        let block = HpackEncoder::new().encode(&headers(&[(":status", "200")]));
+        Frame::new(HEADERS, END_HEADERS, stream_id, block).write_to(&mut stream).unwrap();
+        Frame::new(DATA, 0, stream_id, vec![b'a'; 1000]).write_to(&mut stream).unwrap();
+
+        // Only the window of the connection is opened while the data waits to be read.
+        stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let mut updated_streams = vec![];
+        while let Ok(frame) = Frame::read_from(&mut stream) {
+            if frame.kind == WINDOW_UPDATE {
+                updated_streams.push(frame.stream_id);
+            }
+        }
+        assert_eq!(updated_streams, vec![0]);
+
+        read_sender.send(()).unwrap();
+        stream.set_read_timeout(None).unwrap();
+        let mut frame = Frame::read_from(&mut stream).unwrap();
+        while frame.kind != WINDOW_UPDATE {
+            frame = Frame::read_from(&mut stream).unwrap();
+        }
+        assert_eq!(frame.stream_id, stream_id);
+        assert_eq!(frame.payload, vec![0, 0, 0x03, 0xe8]);
+        Frame::new(DATA, END_STREAM, stream_id, vec![]).write_to(&mut stream).unwrap();
+    });
+
+    let url = Url::parse("https://window.example/").unwrap();
+    let request = Request::with_message(Method::Get, url, Box::new(session.message())).unwrap();
+    let mut response = request.start().unwrap().send().unwrap();
+    read_receiver.recv().unwrap();
+    let mut body = vec![];
+    response.read_to_end(&mut body).unwrap();
+    assert_eq!(body.len(), 1000);
+    server.join().unwrap();
+}
+
+#[test]
+fn test_http2_falls_back_to_http1_when_the_server_does_not_take_it_up() {
+    let handler = |_: HyperRequest, response: HyperResponse| {
+        response.send(b"over HTTP/1.1").unwrap();
+    };
+    let (mut server, url) = make_tls_server(handler);
+    let port = url.port().unwrap();
+    let pools = tls_connection_pools(&[port]);
+
+    // The server doesn't do ALPN, so the request is made over HTTP/1.1 on the connection
+    // that offered HTTP/2.
+    let message = pools.http2_message("localhost", port).unwrap().unwrap();
+    let request = Request::with_message(Method::Get, Url::parse(url.as_str()).unwrap(), message).unwrap();
+    let mut response = request.start().unwrap().send().unwrap();
+    assert_eq!(response.status, StatusCode::Ok);
+    assert_eq!(response.version, HttpVersion::Http11);
+    let mut body = String::new();
+    response.read_to_string(&mut body).unwrap();
+    assert_eq!(body, "over HTTP/1.1");
+
+    // Later requests to the server are left to the pool for the host.
+    assert!(pools.http2_message("localhost", port).unwrap().is_none());
+    assert_eq!(pools.max_concurrent_streams("localhost", port), None);
+    let _ = server.close();
+}
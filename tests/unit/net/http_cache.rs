@@ -0,0 +1,103 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use hyper::header::{Accept, AcceptLanguage, Headers, qitem};
+use hyper::method::Method;
+use net::http_cache::CacheDirectives;
+use net_traits::request::Request;
+use servo_url::ServoUrl;
+
+#[test]
+fn test_parse_response_directives() {
+    let directives = CacheDirectives::parse("private, Max-Age=60, s-maxage=600, must-revalidate");
+    assert_eq!(directives, CacheDirectives {
+        max_age: Some(60),
+        s_maxage: Some(600),
+        must_revalidate: true,
+        private: true,
+        .. CacheDirectives::default()
+    });
+}
+
+#[test]
+fn test_parse_request_directives() {
+    let directives = CacheDirectives::parse("max-age=0,min-fresh=30 , only-if-cached");
+    assert_eq!(directives, CacheDirectives {
+        max_age: Some(0),
+        min_fresh: Some(30),
+        only_if_cached: true,
+        .. CacheDirectives::default()
+    });
+}
+
+#[test]
+fn test_parse_quoted_arguments() {
+    let directives = CacheDirectives::parse("no-cache=\"Set-Cookie, Cookie\", max-age=\"120\"");
+    assert!(directives.no_cache);
+    assert_eq!(directives.max_age, Some(120));
+}
+
+#[test]
+fn test_parse_keeps_the_first_of_a_directive() {
+    assert_eq!(CacheDirectives::parse("max-age=10, max-age=20").max_age, Some(10));
+}
+
+#[test]
+fn test_parse_invalid_max_age_as_stale() {
+    assert_eq!(CacheDirectives::parse("max-age=soon").max_age, Some(0));
+    assert_eq!(CacheDirectives::parse("max-age").max_age, Some(0));
+    assert_eq!(CacheDirectives::parse("min-fresh=-1").min_fresh, None);
+}
+
+#[test]
+fn test_parse_clamps_large_delta_seconds() {
+    assert_eq!(CacheDirectives::parse("max-age=99999999999999999999").max_age, Some(1 << 31));
+}
+
+#[test]
+fn test_parse_ignores_unknown_directives() {
+    assert_eq!(CacheDirectives::parse("immutable, stale-if-error=60, ,"), CacheDirectives::default());
+}
+
+#[test]
+fn test_parse_stale_while_revalidate() {
+    assert_eq!(CacheDirectives::parse("max-age=0, stale-while-revalidate=60").stale_while_revalidate, Some(60));
+    assert_eq!(CacheDirectives::parse("stale-while-revalidate=soon").stale_while_revalidate, None);
+}
+
+#[test]
+fn test_directives_from_several_headers() {
+    let mut headers = Headers::new();
+    headers.set_raw("Cache-Control", vec![b"no-store".to_vec(), b"max-age=5".to_vec()]);
+    let directives = CacheDirectives::from_headers(&headers);
+    assert!(directives.no_store);
+    assert_eq!(directives.max_age, Some(5));
+    assert_eq!(CacheDirectives::from_headers(&Headers::new()), CacheDirectives::default());
+}
+
+fn new_request(url: &str) -> Request {
+    Request::new(ServoUrl::parse(url).unwrap(), None, false, None)
+}
+
+#[test]
+fn test_cache_key_ignores_fragment() {
+    assert_eq!(new_request("http://example.com/a?b#c").cache_key(),
+               new_request("http://example.com/a?b#d").cache_key());
+    assert_eq!(new_request("http://example.com/a?b").cache_key(), "GET http://example.com/a?b");
+}
+
+#[test]
+fn test_cache_key_depends_on_method_and_listed_headers() {
+    let get = new_request("http://example.com/");
+    let post = new_request("http://example.com/");
+    *post.method.borrow_mut() = Method::Post;
+    assert!(get.cache_key() != post.cache_key());
+
+    let request = new_request("http://example.com/");
+    request.headers.borrow_mut().set(Accept(vec![qitem("image/png".parse().unwrap())]));
+    request.headers.borrow_mut().set(AcceptLanguage(vec![qitem("fr".parse().unwrap())]));
+    // Not one of the headers that make up the key.
+    request.headers.borrow_mut().set_raw("X-Unrelated", vec![b"yes".to_vec()]);
+    assert_eq!(request.cache_key(), "GET http://example.com/\nAccept: image/png\nAccept-Language: fr");
+}
@@ -5,7 +5,6 @@
 #![feature(plugin)]
 #![plugin(plugins)]
 
-extern crate content_blocker;
 extern crate cookie as cookie_rs;
 extern crate devtools_traits;
 extern crate flate2;
@@ -22,28 +21,47 @@ extern crate unicase;
 extern crate url;
 extern crate util;
 
+#[cfg(test)] mod alt_svc;
+#[cfg(test)] mod certificate_exceptions;
 #[cfg(test)] mod chrome_loader;
+#[cfg(test)] mod connection_limiter;
 #[cfg(test)] mod cookie;
 #[cfg(test)] mod cookie_http_state;
 #[cfg(test)] mod data_loader;
+#[cfg(test)] mod digest_auth;
 #[cfg(test)] mod file_loader;
 #[cfg(test)] mod fetch;
+#[cfg(test)] mod happy_eyeballs;
 #[cfg(test)] mod mime_classifier;
+#[cfg(test)] mod multipart;
+#[cfg(test)] mod pac;
 #[cfg(test)] mod resource_thread;
+#[cfg(test)] mod tls_session_cache;
 #[cfg(test)] mod hsts;
+#[cfg(test)] mod http2;
+#[cfg(test)] mod http_cache;
 #[cfg(test)] mod http_loader;
 #[cfg(test)] mod filemanager_thread;
+#[cfg(test)] mod url_rewrite;
+#[cfg(test)] mod x509;
 
 use devtools_traits::DevtoolsControlMsg;
+use hyper::header::Headers;
+use hyper::net::Openssl;
 use hyper::server::{Handler, Listening, Server};
-use net::fetch::methods::{FetchContext, fetch};
+use net::fetch::methods::{CancellationListener, FetchContext, NetStats, fetch};
 use net::filemanager_thread::FileManager;
-use net::test::HttpState;
-use net_traits::FetchTaskTarget;
+use net::hsts::HstsList;
+use net::test::{CertificateExceptionCheck, CertificateExceptions, ConnectionPools, HttpState, PrivacySignals};
+use net::test::{ProxySettings, accept_language_header};
+use net_traits::{CertificateException, FetchTaskTarget};
 use net_traits::request::Request;
 use net_traits::response::Response;
 use servo_url::ServoUrl;
+use std::collections::HashMap;
+use std::env;
 use std::rc::Rc;
+use std::sync::{Arc, Mutex, RwLock};
 use std::sync::mpsc::Sender;
 use std::thread;
 
@@ -57,15 +75,32 @@ fn new_fetch_context(dc: Option<Sender<DevtoolsControlMsg>>) -> FetchContext {
     FetchContext {
         state: HttpState::new(),
         user_agent: DEFAULT_USER_AGENT.into(),
+        accept_language: accept_language_header("en-US, en"),
         devtools_chan: dc,
         filemanager: FileManager::new(),
+        cancellation_listener: Arc::new(Mutex::new(CancellationListener::new(None))),
+        body_flow_control: None,
+        time_profiler_chan: None,
+        net_stats: Arc::new(NetStats::new()),
+        scheme_handlers: Arc::new(RwLock::new(HashMap::new())),
+        scheduler: None,
+        storage_thread: None,
+        auth_prompt: None,
+        privacy_signals: PrivacySignals::default(),
+        allow_host_override: false,
+        deadline: None,
+        max_response_size: None,
     }
 }
 impl FetchTaskTarget for FetchResponseCollector {
     fn process_request_body(&mut self, _: &Request) {}
+    fn process_request_body_progress(&mut self, _: u64, _: Option<u64>) {}
     fn process_request_eof(&mut self, _: &Request) {}
+    fn process_early_hints(&mut self, _: &[String]) {}
     fn process_response(&mut self, _: &Response) {}
+    fn process_response_part(&mut self, _: &Response) {}
     fn process_response_chunk(&mut self, _: Vec<u8>) {}
+    fn process_response_trailers(&mut self, _: &Headers) {}
     /// Fired when the response is fully fetched
     fn process_response_eof(&mut self, response: &Response) {
         let _ = self.sender.send(response.clone());
@@ -89,3 +124,37 @@ fn make_server<H: Handler + 'static>(handler: H) -> (Listening, ServoUrl) {
     let url = ServoUrl::parse(&url_string).unwrap();
     (server, url)
 }
+
+/// The SHA-256 fingerprint of `self_signed_certificate.pem`, which is for `localhost`.
+const SELF_SIGNED_FINGERPRINT: [u8; 32] = [
+    0x42, 0x27, 0x7b, 0x9f, 0x78, 0xe6, 0x1f, 0x45, 0x47, 0x33, 0xd5, 0xd9, 0x78, 0xeb, 0x3c, 0x51,
+    0xd5, 0xf5, 0xb4, 0xd0, 0x07, 0x15, 0x0a, 0x66, 0x7f, 0x9a, 0x9f, 0xe8, 0x02, 0x8a, 0xf4, 0x6e,
+];
+
+/// `make_server`, speaking HTTPS with a self-signed certificate that only the pools of
+/// `tls_connection_pools` accept.
+fn make_tls_server<H: Handler + 'static>(handler: H) -> (Listening, ServoUrl) {
+    let dir = env::current_dir().expect("didn't get working dir");
+    let ssl = Openssl::with_cert_and_key(dir.join("self_signed_certificate.pem"),
+                                         dir.join("self_signed_key.pem")).unwrap();
+    let server = Server::https("0.0.0.0:0", ssl).unwrap().handle_threads(handler, 1).unwrap();
+    let url = ServoUrl::parse(&format!("https://localhost:{}", server.socket.port())).unwrap();
+    (server, url)
+}
+
+/// Connection pools with no proxy that accept the certificate of the `make_tls_server`
+/// servers at the given ports.
+fn tls_connection_pools(ports: &[u16]) -> ConnectionPools {
+    let mut exceptions = CertificateExceptions::new();
+    for &port in ports {
+        exceptions.add(CertificateException {
+            host: "localhost".to_owned(),
+            port: port,
+            cert_fingerprint: SELF_SIGNED_FINGERPRINT.to_vec(),
+            permanent: false,
+        });
+    }
+    let check = CertificateExceptionCheck::new(Arc::new(RwLock::new(exceptions)),
+                                               Arc::new(RwLock::new(HstsList::new())));
+    ConnectionPools::with_proxies(ProxySettings::default()).with_certificate_exceptions(check)
+}
@@ -2,9 +2,10 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use net::mime_classifier::{ApacheBugFlag, MimeClassifier, Mp4Matcher, NoSniffFlag};
+use net::mime_classifier::{ApacheBugFlag, MimeClassifier, MimeOverrides, Mp4Matcher, NoSniffFlag};
 use net::mime_classifier::as_string_option;
-use net_traits::LoadContext;
+use net_traits::{LoadContext, UrlPattern};
+use servo_url::ServoUrl;
 use std::env;
 use std::fs::File;
 use std::io::{self, Read};
@@ -558,3 +559,17 @@ fn test_sniff_mp4_video_apache_flag_on() {
                           NoSniffFlag::Off,
                           ApacheBugFlag::On);
 }
+
+#[test]
+fn test_mime_overrides_use_the_first_matching_pattern() {
+    let overrides = MimeOverrides::new(vec![
+        (UrlPattern("https://example.com/api/broken".to_owned()), "not a type".to_owned()),
+        (UrlPattern("https://example.com/api/*".to_owned()), "application/json".to_owned()),
+        (UrlPattern("https://example.com/*".to_owned()), "text/plain".to_owned()),
+    ]);
+    let lookup = |url: &str| overrides.lookup(&ServoUrl::parse(url).unwrap()).map(|mime| mime.to_string());
+    assert_eq!(lookup("https://example.com/api/users?id=1"), Some("application/json".to_owned()));
+    assert_eq!(lookup("https://example.com/api/broken"), Some("application/json".to_owned()));
+    assert_eq!(lookup("https://example.com/index.html"), Some("text/plain".to_owned()));
+    assert_eq!(lookup("https://example.org/api/users"), None);
+}
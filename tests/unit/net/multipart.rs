@@ -0,0 +1,76 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use hyper::header::Headers;
+use net::test::{MultipartEvent, MultipartSplitter, mixed_replace_boundary};
+
+/// The content type and body of each part found in `body` when it is given `step`
+/// bytes at a time.
+fn split(body: &[u8], step: usize) -> Vec<(String, Vec<u8>)> {
+    let mut splitter = MultipartSplitter::new("frame");
+    let mut parts: Vec<(String, Vec<u8>)> = vec![];
+    for piece in body.chunks(step) {
+        splitter.feed(piece).unwrap();
+        while let Some(event) = splitter.next_event() {
+            match event {
+                MultipartEvent::Part(headers) => {
+                    let content_type = headers.get_raw("content-type").map_or(vec![], |values| values[0].clone());
+                    parts.push((String::from_utf8(content_type).unwrap(), vec![]));
+                }
+                MultipartEvent::Data(data) => parts.last_mut().unwrap().1.extend_from_slice(&data),
+            }
+        }
+    }
+    splitter.finish();
+    while let Some(event) = splitter.next_event() {
+        if let MultipartEvent::Data(data) = event {
+            parts.last_mut().unwrap().1.extend_from_slice(&data);
+        }
+    }
+    parts
+}
+
+#[test]
+fn test_multipart_body_is_split_into_parts_whatever_the_pieces() {
+    let body = b"preamble\r\n--frame\r\nContent-Type: image/png\r\n\r\nfirst\r\n-frame-\r\n--frame  \r\n\
+                 Content-Type: text/plain\r\nX-Frame: 2\r\n\r\nsecond\r\n--frame--\r\nepilogue\r\n--frame\r\n";
+    for step in 1..body.len() + 1 {
+        assert_eq!(split(body, step), vec![
+            ("image/png".to_owned(), b"first\r\n-frame-".to_vec()),
+            ("text/plain".to_owned(), b"second".to_vec()),
+        ]);
+    }
+}
+
+#[test]
+fn test_multipart_part_cut_short_keeps_its_data() {
+    let body = b"--frame\r\nContent-Type: a\r\n\r\nx\r\n--frame\r\nContent-Type: b\r\n\r\nlast\r\n--fr";
+    for step in 1..body.len() + 1 {
+        assert_eq!(split(body, step), vec![
+            ("a".to_owned(), b"x".to_vec()),
+            ("b".to_owned(), b"last\r\n--fr".to_vec()),
+        ]);
+    }
+}
+
+#[test]
+fn test_multipart_part_head_that_is_too_long_fails() {
+    let mut splitter = MultipartSplitter::new("frame");
+    splitter.feed(b"--frame\r\nX-Long: ").unwrap();
+    assert!(splitter.feed(&[b'a'; 64 * 1024]).is_err());
+}
+
+#[test]
+fn test_mixed_replace_boundary() {
+    let boundary = |content_type: &str| {
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Type", vec![content_type.as_bytes().to_vec()]);
+        mixed_replace_boundary(&headers)
+    };
+    assert_eq!(boundary("multipart/x-mixed-replace; boundary=frame"), Some("frame".to_owned()));
+    assert_eq!(boundary("multipart/x-mixed-replace;boundary=\"ab\""), Some("ab".to_owned()));
+    assert_eq!(boundary("multipart/x-mixed-replace"), None);
+    assert_eq!(boundary("multipart/form-data; boundary=frame"), None);
+    assert_eq!(boundary("image/jpeg"), None);
+}
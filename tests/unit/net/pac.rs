@@ -0,0 +1,289 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use http_loader::describe_route;
+use hyper::server::{Request as HyperRequest, Response as HyperResponse};
+use make_server;
+use net::test::{HttpProxy, PacError, PacScript, ProxyAutoConfig, ProxyRoute, ProxySettings, Resolver};
+use net::test::route_for_pac_result;
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::iter;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+use time::{self, Timespec};
+use url::Url;
+
+const CORPORATE_PAC: &'static str = r#"
+// Hosts on the intranet are reached directly, everything else through the proxy.
+var proxy = "PROXY proxy.corp.example:3128";
+
+function isInternal(host) {
+    return isPlainHostName(host) || dnsDomainIs(host, ".corp.example");
+}
+
+function FindProxyForURL(url, host) {
+    host = host.toLowerCase();
+    if (isInternal(host))
+        return "DIRECT";
+    if (shExpMatch(url, "https://*.bank.example/*")) {
+        return "SOCKS5 socks.corp.example:1080; DIRECT";
+    }
+    if (isInNet(dnsResolve(host), "10.0.0.0", "255.0.0.0"))
+        return "DIRECT";
+    return proxy + "; DIRECT";
+}
+"#;
+
+/// Resolves `lab.example` to 10.1.2.3 and nothing else, counting the lookups.
+struct MockResolver(AtomicUsize);
+
+impl Resolver for MockResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        match host {
+            "lab.example" => Ok(vec![SocketAddr::new("10.1.2.3".parse().unwrap(), port)]),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, "no such host")),
+        }
+    }
+}
+
+fn url(url: &str) -> Url {
+    Url::parse(url).unwrap()
+}
+
+/// The route `route` gives once the script has been loaded in the background, taking a
+/// direct route to mean that it hasn't been yet, for up to a few seconds.
+fn route_once_loaded<F>(route: F) -> (&'static str, Option<String>) where F: Fn() -> ProxyRoute {
+    for _ in 0..500 {
+        let described = describe_route(route());
+        if described.0 != "direct" {
+            return described;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    describe_route(route())
+}
+
+#[test]
+fn test_pac_script_picks_proxies_with_the_standard_functions() {
+    let script = PacScript::parse(CORPORATE_PAC).unwrap();
+    let resolver = MockResolver(AtomicUsize::new(0));
+    let resolver: &Resolver = &resolver;
+    let find_proxy = |url: &str, host: &str| script.find_proxy(url, host, Some(resolver), time::get_time()).unwrap();
+    assert_eq!(find_proxy("http://wiki/", "wiki"), "DIRECT");
+    assert_eq!(find_proxy("http://build.CORP.example/", "build.CORP.example"), "DIRECT");
+    assert_eq!(find_proxy("https://www.bank.example/", "www.bank.example"), "SOCKS5 socks.corp.example:1080; DIRECT");
+    assert_eq!(find_proxy("http://lab.example/", "lab.example"), "DIRECT");
+    assert_eq!(find_proxy("http://servo.org/", "servo.org"), "PROXY proxy.corp.example:3128; DIRECT");
+}
+
+#[test]
+fn test_pac_script_resolves_nothing_when_the_proxy_resolves_host_names() {
+    let script = PacScript::parse(r#"
+        function FindProxyForURL(url, host) {
+            if (dnsResolve(host) === null && !isResolvable(host) && !isInNet(host, "10.0.0.0", "255.0.0.0"))
+                return "SOCKS5 socks.example:1080";
+            return "DIRECT";
+        }
+    "#).unwrap();
+    let resolver = Arc::new(MockResolver(AtomicUsize::new(0)));
+    let config = ProxyAutoConfig::with_script(script, resolver.clone(), true);
+    assert_eq!(describe_route(config.route(&url("http://lab.example/"), false)),
+               ("socks", Some("socks.example:1080".to_owned())));
+    assert_eq!(resolver.0.load(Ordering::SeqCst), 0);
+    // Addresses need no resolving.
+    assert_eq!(describe_route(config.route(&url("http://10.1.2.3/"), false)), ("direct", None));
+}
+
+#[test]
+fn test_pac_date_and_time_functions() {
+    let script = PacScript::parse(r#"
+        function FindProxyForURL(url, host) {
+            return check(host);
+        }
+        function check(test) {
+            if (test == "weekday") return "" + weekdayRange("TUE", "GMT");
+            if (test == "weekdays") return "" + weekdayRange("MON", "FRI", "GMT");
+            if (test == "weekend") return "" + weekdayRange("SAT", "SUN", "GMT");
+            if (test == "wrapping-weekdays") return "" + weekdayRange("FRI", "WED", "GMT");
+            if (test == "day") return "" + dateRange(14, "GMT");
+            if (test == "month") return "" + dateRange("MAR", "GMT");
+            if (test == "year") return "" + dateRange(2017, "GMT");
+            if (test == "days") return "" + dateRange(1, 15, "GMT");
+            if (test == "months") return "" + dateRange("JAN", "FEB", "GMT");
+            if (test == "days-and-months") return "" + dateRange(1, "MAR", 31, "MAR", "GMT");
+            if (test == "dates") return "" + dateRange(1, "DEC", 2016, 31, "MAR", 2017, "GMT");
+            if (test == "hour") return "" + timeRange(13, "GMT");
+            if (test == "hours") return "" + timeRange(9, 12, "GMT");
+            if (test == "minutes") return "" + timeRange(13, 0, 13, 29, "GMT");
+            if (test == "seconds") return "" + timeRange(13, 30, 1, 14, 0, 0, "GMT");
+            if (test == "night") return "" + timeRange(22, 6, "GMT");
+            return "unknown";
+        }
+    "#).unwrap();
+    // Tuesday the 14th of March 2017, 13:29:30 UTC.
+    let now = Timespec::new(1489498170, 0);
+    let evaluate = |test: &str| script.find_proxy("http://servo.org/", test, None, now).unwrap();
+    for test in &["weekday", "weekdays", "wrapping-weekdays", "day", "month", "year", "days",
+                  "days-and-months", "dates", "hour", "minutes"] {
+        assert_eq!((*test, &*evaluate(test)), (*test, "true"));
+    }
+    for test in &["weekend", "months", "hours", "seconds", "night"] {
+        assert_eq!((*test, &*evaluate(test)), (*test, "false"));
+    }
+}
+
+#[test]
+fn test_pac_script_is_given_the_whole_url_of_http_requests_only() {
+    let script = PacScript::parse(r#"
+        function FindProxyForURL(url, host) {
+            if (shExpMatch(url, "*/private/*"))
+                return "PROXY private.example:3128";
+            if (url == "https://servo.org/")
+                return "PROXY secure.example:3128";
+            return "DIRECT";
+        }
+    "#).unwrap();
+    let config = ProxyAutoConfig::with_script(script, Arc::new(MockResolver(AtomicUsize::new(0))), false);
+    assert_eq!(describe_route(config.route(&url("http://servo.org/private/page#fragment"), false)),
+               ("forward", Some("private.example:3128".to_owned())));
+    assert_eq!(describe_route(config.route(&url("http://servo.org/public/page"), false)), ("direct", None));
+    assert_eq!(describe_route(config.route(&url("https://servo.org/private/page?query"), true)),
+               ("tunnel", Some("secure.example:3128".to_owned())));
+}
+
+#[test]
+fn test_sh_exp_match() {
+    let script = PacScript::parse(r#"
+        function FindProxyForURL(url, host) {
+            return "" + shExpMatch(url, host);
+        }
+    "#).unwrap();
+    let matches = |string: &str, pattern: &str| {
+        script.find_proxy(string, pattern, None, time::get_time()).unwrap() == "true"
+    };
+    assert!(matches("http://servo.org/", "http://*.org/"));
+    assert!(matches("http://servo.org/", "*"));
+    assert!(matches("http://servo.org/", "http://?ervo.*/"));
+    assert!(matches("http://servo.org/a/b/c", "*/*/*/c"));
+    assert!(!matches("http://servo.org/", "http://*.com/"));
+    assert!(!matches("http://servo.org/", "http://servo.org"));
+    assert!(!matches("", "?"));
+    // Patterns with many `*`s that nearly match take no longer than the others.
+    let long_url = format!("http://servo.org/{}", iter::repeat('a').take(10000).collect::<String>());
+    assert!(!matches(&long_url, "*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b"));
+    assert!(matches(&long_url, "*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a"));
+}
+
+#[test]
+fn test_pac_results_give_the_first_usable_route() {
+    let result = "HTTPS secure.example:443; PROXY proxy.example:3128; DIRECT";
+    assert_eq!(describe_route(route_for_pac_result(result, false, false).unwrap()),
+               ("forward", Some("proxy.example:3128".to_owned())));
+    assert_eq!(describe_route(route_for_pac_result(result, true, false).unwrap()),
+               ("tunnel", Some("proxy.example:3128".to_owned())));
+    assert_eq!(describe_route(route_for_pac_result("socks socks.example:1080", true, false).unwrap()),
+               ("socks", Some("socks.example:1080".to_owned())));
+    assert_eq!(describe_route(route_for_pac_result(" DIRECT ", true, false).unwrap()), ("direct", None));
+    assert!(route_for_pac_result("HTTPS secure.example:443", false, false).is_none());
+}
+
+#[test]
+fn test_pac_scripts_outside_the_supported_language_are_rejected() {
+    assert_eq!(PacScript::parse("function FindProxy(url, host) { return 'DIRECT'; }").err(),
+               Some(PacError::Syntax("FindProxyForURL is not defined".to_owned())));
+    assert!(PacScript::parse("function FindProxyForURL(url, host) { for (;;) {} }").is_err());
+}
+
+#[test]
+fn test_failing_pac_script_connects_directly() {
+    let resolver = Arc::new(MockResolver(AtomicUsize::new(0)));
+    let script = PacScript::parse("function FindProxyForURL(url, host) { return isInNetEx(host, '::/0'); }").unwrap();
+    let config = ProxyAutoConfig::with_script(script, resolver.clone(), false);
+    assert_eq!(describe_route(config.route(&url("http://servo.org/"), false)), ("direct", None));
+
+    let script = PacScript::parse("function FindProxyForURL(url, host) { return 'BOGUS'; }").unwrap();
+    let config = ProxyAutoConfig::with_script(script, resolver.clone(), false);
+    assert_eq!(describe_route(config.route(&url("http://servo.org/"), false)), ("direct", None));
+
+    let missing = env::temp_dir().join("servo-test-missing.pac");
+    let resolver = Arc::new(MockResolver(AtomicUsize::new(0)));
+    let config = ProxyAutoConfig::new(missing.to_string_lossy().into_owned(), resolver, false);
+    assert_eq!(describe_route(config.route(&url("https://servo.org/"), true)), ("direct", None));
+}
+
+#[test]
+fn test_pac_routes_are_remembered_per_url() {
+    let resolver = Arc::new(MockResolver(AtomicUsize::new(0)));
+    let config = ProxyAutoConfig::with_script(PacScript::parse(CORPORATE_PAC).unwrap(), resolver.clone(), false);
+    // The script looks the host up each time it is asked.
+    let asked = |url: &str, tunnel: bool| {
+        let lookups = resolver.0.load(Ordering::SeqCst);
+        config.route(&self::url(url), tunnel);
+        resolver.0.load(Ordering::SeqCst) != lookups
+    };
+    assert!(asked("http://servo.org/", false));
+    assert!(!asked("http://servo.org/", false));
+    // Another scheme asks the script again, as do other paths of plain http URLs, but
+    // not of https ones.
+    assert!(asked("https://servo.org/", true));
+    assert!(asked("http://servo.org/other", false));
+    assert!(!asked("https://servo.org/other", true));
+    assert_eq!(describe_route(config.route(&url("https://servo.org/"), true)),
+               ("tunnel", Some("proxy.corp.example:3128".to_owned())));
+}
+
+#[test]
+fn test_pac_script_from_a_file_overrides_the_other_proxy_settings() {
+    let path = env::temp_dir().join("servo-test-proxy.pac");
+    File::create(&path).unwrap().write_all(CORPORATE_PAC.as_bytes()).unwrap();
+    let resolver = Arc::new(MockResolver(AtomicUsize::new(0)));
+    let settings = ProxySettings {
+        http: HttpProxy::from_pref("ignored.example:8080"),
+        pac: Some(Arc::new(ProxyAutoConfig::new(path.to_string_lossy().into_owned(), resolver, false))),
+        .. ProxySettings::default()
+    };
+
+    assert_eq!(route_once_loaded(|| settings.route("http", "servo.org")),
+               ("forward", Some("proxy.corp.example:3128".to_owned())));
+    assert_eq!(describe_route(settings.route("ws", "servo.org")),
+               ("tunnel", Some("proxy.corp.example:3128".to_owned())));
+    assert_eq!(describe_route(settings.route("http", "wiki")), ("direct", None));
+}
+
+#[test]
+fn test_pac_script_is_fetched_from_an_http_url() {
+    let handler = |_: HyperRequest, response: HyperResponse| {
+        response.send(b"function FindProxyForURL(url, host) { return 'PROXY fetched.example:3128'; }").unwrap();
+    };
+    let (mut server, url) = make_server(handler);
+    let config = ProxyAutoConfig::new(url.join("/proxy.pac").unwrap().into_string(),
+                                      Arc::new(MockResolver(AtomicUsize::new(0))), false);
+    assert_eq!(route_once_loaded(|| config.route(&self::url("http://servo.org/"), false)),
+               ("forward", Some("fetched.example:3128".to_owned())));
+    let _ = server.close();
+}
+
+#[test]
+fn test_connections_are_made_directly_while_the_pac_script_loads() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let location = format!("http://127.0.0.1:{}/proxy.pac", listener.local_addr().unwrap().port());
+    let (accepted_sender, accepted) = channel();
+    thread::spawn(move || {
+        let _ = accepted_sender.send(listener.accept().unwrap().0);
+    });
+    let config = ProxyAutoConfig::new(location, Arc::new(MockResolver(AtomicUsize::new(0))), false);
+    assert_eq!(describe_route(config.route(&url("http://servo.org/"), false)), ("direct", None));
+    // The script's server never answers, and the connection is held open until the test
+    // is over, but asking for routes doesn't wait for it.
+    let _stream = accepted.recv().unwrap();
+    assert_eq!(describe_route(config.route(&url("http://servo.org/"), false)), ("direct", None));
+    assert_eq!(describe_route(config.route(&url("https://servo.org/"), true)), ("direct", None));
+}
@@ -2,15 +2,48 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use cookie_rs;
+use hyper::header::{AcceptLanguage, Charset, ContentDisposition, DispositionParam, DispositionType, Headers};
+use hyper::header::{ContentType, UserAgent};
+use hyper::http::RawStatus;
+use hyper::mime::{Mime, SubLevel, TopLevel};
+use hyper::server::{Request as HyperRequest, Response as HyperResponse};
+use hyper_serde::Serde;
 use ipc_channel::ipc;
-use net::resource_thread::new_core_resource_thread;
-use net_traits::CoreResourceMsg;
+use make_server;
+use msg::constellation_msg::{PipelineId, PipelineIndex, TEST_NAMESPACE, TEST_PIPELINE_ID};
+use net::mime_classifier::{MimeClassifier, MimeOverrides};
+use net::resource_thread::{new_core_resource_thread, new_resource_threads, profile_config_dir};
+use net::resource_thread::{MimeSniffing, start_sending_sniffed_opt};
+use net::resource_thread::{AuthCache, AuthCacheEntry, read_json_from_file, write_json_to_file};
+use net::test::accept_language_header;
+use net_traits::{CookieAcceptPolicy, CookieSource, CoreResourceControlMsg, CoreResourceControlThread};
+use net_traits::{CoreResourceMsg, CoreResourceThread, CustomResponse};
+use net_traits::{DownloadProgress, IpcSend, SchemeRequest, UrlPattern};
+use net_traits::{FetchMetadata, FetchResponseMsg, FilteredMetadata, NetworkError};
+use net_traits::IncludeSubdomains;
+use net_traits::{LoadConsumer, LoadContext, Metadata, NetStatsSnapshot, ResourceGroupId, ResourceId, SameSite};
+use net_traits::SameSiteContext;
+use net_traits::SessionId;
+use net_traits::blob_url_store::{BlobBuf, BlobURLStoreError};
+use net_traits::filemanager_thread::{FileManagerThreadError, FileManagerThreadMsg};
+use net_traits::request::{Destination, RequestInit, RequestMode};
 use net_traits::hosts::{host_replacement, parse_hostsfile};
+use profile_traits::mem::{ProfilerChan as MemProfilerChan, ProfilerMsg, ReportsChan};
 use profile_traits::time::ProfilerChan;
 use servo_url::ServoUrl;
 use std::borrow::ToOwned;
 use std::collections::HashMap;
-use std::net::IpAddr;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::{Sender, channel};
+use std::thread;
+use std::time::Duration;
+use time;
 
 fn ip(s: &str) -> IpAddr {
     s.parse().unwrap()
@@ -20,12 +53,1422 @@ fn ip(s: &str) -> IpAddr {
 fn test_exit() {
     let (tx, _rx) = ipc::channel().unwrap();
     let (sender, receiver) = ipc::channel().unwrap();
-    let (resource_thread, _private_resource_thread) = new_core_resource_thread(
-        "".into(), None, ProfilerChan(tx), None);
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
     resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
     receiver.recv().unwrap();
 }
 
+#[test]
+fn test_exit_cancels_running_fetches_before_acknowledging() {
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        let mut response = response.start().unwrap();
+        for _ in 0..100 {
+            if response.write_all(&[0; 1024]).and_then(|_| response.flush()).is_err() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let _ = response.end();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let (fetch_sender, fetch_receiver) = ipc::channel().unwrap();
+    let request = RequestInit {
+        url: url.clone(),
+        origin: url.clone(),
+        .. RequestInit::default()
+    };
+    resource_thread.send(CoreResourceMsg::Fetch(request, fetch_sender)).unwrap();
+    loop {
+        if let FetchResponseMsg::ProcessResponseChunk(_) = fetch_receiver.recv().unwrap() {
+            break;
+        }
+    }
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+
+    // By the time exit is acknowledged, the fetch has already been told it was cancelled.
+    loop {
+        match fetch_receiver.try_recv().unwrap() {
+            FetchResponseMsg::ProcessResponseEOF(result, _) => {
+                assert_eq!(result, Err(NetworkError::LoadCancelled));
+                break;
+            }
+            _ => (),
+        }
+    }
+    let _ = server.close();
+}
+
+#[test]
+fn test_exit_unregisters_the_memory_reporter() {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (mem_profiler_sender, mem_profiler_receiver) = ipc::channel().unwrap();
+    let (resource_threads, _private_resource_threads, _control) = new_resource_threads(
+        "".into(), None, ProfilerChan(tx), MemProfilerChan(mem_profiler_sender), None, None, vec![]);
+    let reporter_name = match mem_profiler_receiver.recv().unwrap() {
+        ProfilerMsg::RegisterReporter(name, _) => name,
+        _ => panic!("the resource thread didn't register a memory reporter"),
+    };
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_threads.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+    match mem_profiler_receiver.recv().unwrap() {
+        ProfilerMsg::UnregisterReporter(name) => assert_eq!(name, reporter_name),
+        _ => panic!("the resource thread didn't unregister its memory reporter"),
+    }
+}
+
+#[test]
+fn test_exit_persists_each_group_to_its_own_dir() {
+    let public_dir = env::temp_dir().join("servo-test-public-profile");
+    let private_dir = env::temp_dir().join("servo-test-private-profile");
+    for dir in &[&public_dir, &private_dir] {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+    }
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (sender, receiver) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), Some(public_dir.clone()), Some(private_dir.clone()), vec![]);
+    resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+
+    // Only the public group has an HTTP cache.
+    assert!(public_dir.join("cache").join("index.json").is_file());
+    assert!(!private_dir.join("cache").exists());
+    for dir in &[&public_dir, &private_dir] {
+        assert!(dir.join("cookie_jar.json").is_file());
+        assert!(dir.join("auth_cache.json").is_file());
+        assert!(dir.join("hsts_list.json").is_file());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+#[test]
+fn test_reset_hsts_discards_the_entries_added_at_runtime() {
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        response.send(b"Yay!").unwrap();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    // The server only speaks plain HTTP, so a fetch upgraded to HTTPS fails.
+    let fetch_succeeds = || {
+        let (sender, receiver) = ipc::channel().unwrap();
+        let request = RequestInit {
+            url: url.clone(),
+            origin: url.clone(),
+            .. RequestInit::default()
+        };
+        resource_thread.send(CoreResourceMsg::Fetch(request, sender)).unwrap();
+        loop {
+            if let FetchResponseMsg::ProcessResponseEOF(result, _) = receiver.recv().unwrap() {
+                return result.is_ok();
+            }
+        }
+    };
+
+    assert!(fetch_succeeds());
+    resource_thread.send(CoreResourceMsg::SetHstsEntryForHost("localhost".to_owned(), IncludeSubdomains::NotIncluded,
+                                                              3600)).unwrap();
+    assert!(!fetch_succeeds());
+    resource_thread.send(CoreResourceMsg::ResetHsts).unwrap();
+    assert!(fetch_succeeds());
+    let _ = server.close();
+}
+
+fn certificate_exceptions(resource_thread: &CoreResourceThread) -> Vec<(String, u16, bool)> {
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::GetCertificateExceptions(sender)).unwrap();
+    receiver.recv().unwrap().into_iter().map(|exception| {
+        (exception.host, exception.port, exception.permanent)
+    }).collect()
+}
+
+#[test]
+fn test_only_permanent_certificate_exceptions_are_kept_across_restarts() {
+    let config_dir = env::temp_dir().join("servo-test-certificate-exceptions");
+    let _ = fs::remove_dir_all(&config_dir);
+    let start = || {
+        let (tx, _rx) = ipc::channel().unwrap();
+        let (resource_thread, _private_resource_thread, control) = new_core_resource_thread(
+            "".into(), None, ProfilerChan(tx), Some(config_dir.clone()), None, vec![]);
+        (resource_thread, control)
+    };
+
+    let (resource_thread, control) = start();
+    for &(host, port, permanent) in &[("intranet.example", 8443, true), ("test.example", 443, false),
+                                      ("removed.example", 443, true)] {
+        control.send(CoreResourceControlMsg::AddCertificateException {
+            group: ResourceGroupId::Public,
+            host: host.to_owned(),
+            port: port,
+            cert_fingerprint: vec![0; 32],
+            permanent: permanent,
+        }).unwrap();
+    }
+    control.send(CoreResourceControlMsg::RemoveCertificateException {
+        group: ResourceGroupId::Public,
+        host: "removed.example".to_owned(),
+        port: 443,
+    }).unwrap();
+    let (sender, receiver) = ipc::channel().unwrap();
+    control.send(CoreResourceControlMsg::Synchronize(sender)).unwrap();
+    receiver.recv().unwrap();
+    assert_eq!(certificate_exceptions(&resource_thread),
+               vec![("intranet.example".to_owned(), 8443, true), ("test.example".to_owned(), 443, false)]);
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+
+    let (resource_thread, _control) = start();
+    assert_eq!(certificate_exceptions(&resource_thread), vec![("intranet.example".to_owned(), 8443, true)]);
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+    let _ = fs::remove_dir_all(&config_dir);
+}
+
+#[test]
+fn test_clear_auth_cache_forgets_credentials_of_the_given_host_until_the_next_start() {
+    let config_dir = env::temp_dir().join("servo-test-clear-auth-cache");
+    let _ = fs::remove_dir_all(&config_dir);
+    fs::create_dir_all(&config_dir).unwrap();
+    let mut auth_cache = AuthCache::new();
+    for origin in &["http://signed-out.example", "https://signed-out.example:8443", "http://kept.example"] {
+        auth_cache.entries.insert((*origin).to_owned(), AuthCacheEntry {
+            user_name: "user".to_owned(),
+            password: "secret".to_owned(),
+        });
+    }
+    write_json_to_file(&auth_cache, &config_dir, "auth_cache.json");
+    let run = |msg: CoreResourceMsg| {
+        let (tx, _rx) = ipc::channel().unwrap();
+        let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+            "".into(), None, ProfilerChan(tx), Some(config_dir.clone()), None, vec![]);
+        resource_thread.send(msg).unwrap();
+        let (sender, receiver) = ipc::channel().unwrap();
+        resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
+        receiver.recv().unwrap();
+        let mut auth_cache = AuthCache::new();
+        read_json_from_file(&mut auth_cache, &config_dir, "auth_cache.json");
+        auth_cache.entries.keys().cloned().collect::<Vec<_>>()
+    };
+
+    assert_eq!(run(CoreResourceMsg::ClearAuthCache(Some("Signed-Out.EXAMPLE".to_owned()))),
+               vec!["http://kept.example".to_owned()]);
+    assert!(run(CoreResourceMsg::ClearAuthCache(None)).is_empty());
+    let _ = fs::remove_dir_all(&config_dir);
+}
+
+#[test]
+fn test_profiles_are_kept_in_their_own_directories() {
+    let config_dir = env::temp_dir().join("servo-test-profiles");
+    let _ = fs::remove_dir_all(&config_dir);
+
+    assert_eq!(profile_config_dir(&config_dir, None), config_dir);
+    let work_dir = profile_config_dir(&config_dir, Some("work"));
+    assert_eq!(work_dir, config_dir.join("profiles").join("work"));
+
+    // The profile directory doesn't exist yet, and is created on first write.
+    write_json_to_file(&HashMap::<String, String>::new(), &work_dir, "cookie_jar.json");
+    assert!(work_dir.join("cookie_jar.json").is_file());
+    assert!(!config_dir.join("cookie_jar.json").exists());
+    let _ = fs::remove_dir_all(&config_dir);
+}
+
+fn cookie_stored_with_policy(policy: CookieAcceptPolicy, first_party: Option<&str>) -> bool {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let url = ServoUrl::parse("http://tracker.example.com/").unwrap();
+    let first_party = first_party.map(|url| ServoUrl::parse(url).unwrap());
+
+    resource_thread.send(CoreResourceMsg::SetCookieAcceptPolicy(policy)).unwrap();
+    resource_thread.send(CoreResourceMsg::SetCookiesForUrl(
+        url.clone(), "mozillaIs=theBest".to_owned(), CookieSource::HTTP, first_party)).unwrap();
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::GetCookiesForUrl(
+        url, sender, CookieSource::HTTP, SameSiteContext::SameSite)).unwrap();
+    let stored = receiver.recv().unwrap().is_some();
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+    stored
+}
+
+#[test]
+fn test_cookie_accept_policy_all() {
+    assert!(cookie_stored_with_policy(CookieAcceptPolicy::All, Some("http://news.example.org/")));
+}
+
+#[test]
+fn test_cookie_accept_policy_none_at_all() {
+    assert!(!cookie_stored_with_policy(CookieAcceptPolicy::NoneAtAll, Some("http://www.example.com/")));
+}
+
+#[test]
+fn test_cookie_accept_policy_no_third_party() {
+    let policy = CookieAcceptPolicy::NoThirdParty;
+    assert!(cookie_stored_with_policy(policy, Some("http://www.example.com/")));
+    assert!(!cookie_stored_with_policy(policy, Some("http://news.example.org/")));
+    assert!(cookie_stored_with_policy(policy, None));
+}
+
+#[test]
+fn test_cookie_accept_policy_from_originating_site_only() {
+    let policy = CookieAcceptPolicy::FromOriginatingSiteOnly;
+    assert!(cookie_stored_with_policy(policy, Some("http://www.example.com/")));
+    assert!(!cookie_stored_with_policy(policy, Some("http://news.example.org/")));
+    assert!(!cookie_stored_with_policy(policy, None));
+}
+
+#[test]
+fn test_set_cookies_batch_applies_the_policy_to_each_cookie() {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let first_party = ServoUrl::parse("http://www.example.com/").unwrap();
+    let same_site = ServoUrl::parse("http://static.example.com/").unwrap();
+    let tracker = ServoUrl::parse("http://tracker.example.org/").unwrap();
+
+    resource_thread.send(CoreResourceMsg::SetCookieAcceptPolicy(CookieAcceptPolicy::NoThirdParty)).unwrap();
+    resource_thread.send(CoreResourceMsg::SetCookiesBatch(vec![
+        (first_party.clone(), "a=1".to_owned(), CookieSource::HTTP, Some(first_party.clone())),
+        (same_site.clone(), "b=2; c=3".to_owned(), CookieSource::HTTP, Some(first_party.clone())),
+        (tracker.clone(), "d=4".to_owned(), CookieSource::HTTP, Some(first_party.clone())),
+    ])).unwrap();
+    let cookies_for = |url: &ServoUrl| {
+        let (sender, receiver) = ipc::channel().unwrap();
+        resource_thread.send(CoreResourceMsg::GetCookiesForUrl(
+            url.clone(), sender, CookieSource::HTTP, SameSiteContext::SameSite)).unwrap();
+        receiver.recv().unwrap()
+    };
+    assert_eq!(cookies_for(&first_party), Some("a=1".to_owned()));
+    assert_eq!(cookies_for(&same_site), Some("b=2; c=3".to_owned()));
+    assert_eq!(cookies_for(&tracker), None);
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+}
+
+#[test]
+fn test_clear_site_data_only_clears_the_given_site_of_the_given_group() {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let cleared = ServoUrl::parse("http://www.example.com/").unwrap();
+    let kept = ServoUrl::parse("http://www.example.org/").unwrap();
+    for thread in &[&resource_thread, &private_resource_thread] {
+        for url in &[&cleared, &kept] {
+            thread.send(CoreResourceMsg::SetCookiesForUrl(
+                (*url).clone(), "a=1".to_owned(), CookieSource::HTTP, None)).unwrap();
+        }
+    }
+
+    resource_thread.send(CoreResourceMsg::ClearSiteData("www.example.com".to_owned())).unwrap();
+    let cookies_for = |thread: &CoreResourceThread, url: &ServoUrl| {
+        let (sender, receiver) = ipc::channel().unwrap();
+        thread.send(CoreResourceMsg::GetCookiesForUrl(
+            url.clone(), sender, CookieSource::HTTP, SameSiteContext::SameSite)).unwrap();
+        receiver.recv().unwrap()
+    };
+    assert_eq!(cookies_for(&resource_thread, &cleared), None);
+    assert_eq!(cookies_for(&resource_thread, &kept), Some("a=1".to_owned()));
+    assert_eq!(cookies_for(&private_resource_thread, &cleared), Some("a=1".to_owned()));
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+}
+
+#[test]
+fn test_memory_reports_include_cookie_jar() {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let url = ServoUrl::parse("http://mozilla.com/").unwrap();
+    resource_thread.send(CoreResourceMsg::SetCookiesForUrl(
+        url.clone(), "mozillaIs=theBest".to_owned(), CookieSource::HTTP, Some(url))).unwrap();
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::CollectMemoryReports(ReportsChan(sender))).unwrap();
+    let reports = receiver.recv().unwrap();
+    let cookie_jar = reports.iter().find(|report| report.path == ["network", "public", "cookie-jar"]).unwrap();
+    assert!(cookie_jar.size > 0);
+    let private_cookie_jar = reports.iter().find(|report| report.path == ["network", "private", "cookie-jar"]).unwrap();
+    assert_eq!(private_cookie_jar.size, 0);
+    assert!(reports.iter().any(|report| report.path == ["network", "file-manager"]));
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+}
+
+#[test]
+fn test_file_manager_messages_are_handled_in_order() {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let origin = "http://mozilla.com".to_owned();
+    let blob = BlobBuf {
+        filename: None,
+        type_string: "text/plain".to_owned(),
+        size: 3,
+        bytes: b"foo".to_vec(),
+    };
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::ToFileManager(
+        FileManagerThreadMsg::PromoteMemory(blob, true, sender, origin.clone()))).unwrap();
+    let id = receiver.recv().unwrap().unwrap();
+
+    // The read is only started once the revocation before it has been handled.
+    let (revoke_sender, _revoke_receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::ToFileManager(
+        FileManagerThreadMsg::RevokeBlobURL(id, origin.clone(), revoke_sender))).unwrap();
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::ToFileManager(
+        FileManagerThreadMsg::ReadFile(sender, id, true, origin))).unwrap();
+    match receiver.recv().unwrap() {
+        Err(FileManagerThreadError::BlobURLStoreError(BlobURLStoreError::InvalidFileID)) => {}
+        _ => panic!("Read a revoked blob URL"),
+    }
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+}
+
+#[test]
+fn test_fetch_to_file_saves_body_under_suggested_name() {
+    let handler = move |_: HyperRequest, mut response: HyperResponse| {
+        response.headers_mut().set(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(Charset::Us_Ascii, None, b"../report.txt".to_vec())],
+        });
+        response.send(b"quarterly numbers").unwrap();
+    };
+    let (mut server, url) = make_server(handler);
+    let dir = env::temp_dir().join("servo-test-downloads");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let (reply, progress) = ipc::channel().unwrap();
+    let init = RequestInit {
+        url: url.clone(),
+        origin: url.clone(),
+        .. RequestInit::default()
+    };
+    control.send(CoreResourceControlMsg::FetchToFile {
+        group: ResourceGroupId::Public,
+        init: init,
+        path: dir.clone(),
+        reply: reply,
+    }).unwrap();
+
+    let mut received = 0;
+    let path;
+    loop {
+        match progress.recv().unwrap() {
+            DownloadProgress::Progress { received: so_far, total } => {
+                received = so_far;
+                assert_eq!(total, Some(17));
+            }
+            DownloadProgress::Complete(saved_to) => {
+                path = saved_to;
+                break;
+            }
+            DownloadProgress::Failed(error) => panic!("Download failed: {:?}", error),
+        }
+    }
+    let _ = server.close();
+
+    assert_eq!(received, 17);
+    assert_eq!(path, dir.join("report.txt"));
+    let mut contents = String::new();
+    File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "quarterly numbers");
+    let _ = fs::remove_dir_all(&dir);
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+}
+
+#[test]
+fn test_fetch_to_file_never_overwrites_an_existing_file() {
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        response.send(b"new numbers").unwrap();
+    };
+    let (mut server, url) = make_server(handler);
+    let dir = env::temp_dir().join("servo-test-downloads-existing");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let existing = dir.join("report.txt");
+    File::create(&existing).unwrap().write_all(b"old numbers").unwrap();
+    let url = url.join("report.txt").unwrap();
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let download = |path: PathBuf| {
+        let (reply, progress) = ipc::channel().unwrap();
+        let init = RequestInit {
+            url: url.clone(),
+            origin: url.clone(),
+            .. RequestInit::default()
+        };
+        control.send(CoreResourceControlMsg::FetchToFile {
+            group: ResourceGroupId::Public,
+            init: init,
+            path: path,
+            reply: reply,
+        }).unwrap();
+        loop {
+            match progress.recv().unwrap() {
+                DownloadProgress::Progress { .. } => {}
+                DownloadProgress::Complete(saved_to) => return Ok(saved_to),
+                DownloadProgress::Failed(error) => return Err(error),
+            }
+        }
+    };
+
+    // Saving to the directory picks a name that isn't taken.
+    assert_eq!(download(dir.clone()).unwrap(), dir.join("report (1).txt"));
+    // Saving to the existing file fails, and leaves the file alone.
+    assert!(download(existing.clone()).is_err());
+    let _ = server.close();
+
+    let mut contents = String::new();
+    File::open(&existing).unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "old numbers");
+    let _ = fs::remove_dir_all(&dir);
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+}
+
+const SNIFFING: MimeSniffing = MimeSniffing { enabled: true, local_files: false };
+
+fn sniffed_content_type_with(url: &str, body: &[u8], overrides: &MimeOverrides, sniffing: MimeSniffing)
+                             -> String {
+    let (sender, receiver) = ipc::channel().unwrap();
+    let url = ServoUrl::parse(url).unwrap();
+    start_sending_sniffed_opt(LoadConsumer::Channel(sender), Metadata::default(url),
+                              Arc::new(MimeClassifier::new()), body, LoadContext::Browsing, overrides,
+                              sniffing).unwrap();
+    let metadata = receiver.recv().unwrap().metadata;
+    format!("{}", metadata.content_type.unwrap().into_inner().0)
+}
+
+fn sniffed_content_type_with_overrides(url: &str, body: &[u8], overrides: &MimeOverrides) -> String {
+    sniffed_content_type_with(url, body, overrides, SNIFFING)
+}
+
+fn sniffed_content_type(url: &str, body: &[u8]) -> String {
+    sniffed_content_type_with_overrides(url, body, &MimeOverrides::default())
+}
+
+#[test]
+fn test_sniffing_keeps_an_unusual_supplied_subtype() {
+    let (sender, receiver) = ipc::channel().unwrap();
+    let mut metadata = Metadata::default(ServoUrl::parse("http://example.com/").unwrap());
+    let mut headers = Headers::new();
+    headers.set_raw("X-Content-Type-Options", vec![b"nosniff".to_vec()]);
+    metadata.headers = Some(Serde(headers));
+    let subtype = SubLevel::Ext("vnd.odd+sub type;\u{e9}".to_owned());
+    metadata.content_type = Some(Serde(ContentType(Mime(TopLevel::Application, subtype, vec![]))));
+    start_sending_sniffed_opt(LoadConsumer::Channel(sender), metadata, Arc::new(MimeClassifier::new()),
+                              b"<html>", LoadContext::Browsing, &MimeOverrides::default(), SNIFFING).unwrap();
+    let metadata = receiver.recv().unwrap().metadata;
+    let ContentType(Mime(toplevel, sublevel, _)) = metadata.content_type.unwrap().into_inner();
+    assert_eq!(toplevel, TopLevel::Application);
+    assert_eq!(format!("{}", sublevel), "vnd.odd+sub type;\u{e9}");
+}
+
+#[test]
+fn test_mime_overrides_replace_the_sniffed_type_of_matching_urls() {
+    let overrides = MimeOverrides::new(vec![
+        (UrlPattern("http://example.com/api/*".to_owned()), "application/json".to_owned()),
+    ]);
+    let html = b"<!DOCTYPE html><html></html>";
+    assert_eq!(sniffed_content_type_with_overrides("http://example.com/api/users", html, &overrides),
+               "application/json");
+    assert_eq!(sniffed_content_type_with_overrides("http://example.com/index.html", html, &overrides),
+               "text/html");
+}
+
+#[test]
+fn test_local_files_are_typed_by_extension_before_sniffing() {
+    let html = b"<!DOCTYPE html><html></html>";
+    assert_eq!(sniffed_content_type("file:///tmp/thumbnail.png", html), "image/png");
+    // Vague extensions and non-file URLs are still sniffed.
+    assert_eq!(sniffed_content_type("file:///tmp/notes.txt", html), "text/html");
+    assert_eq!(sniffed_content_type("http://example.com/thumbnail.png", html), "text/html");
+    // Every file is sniffed if asked to be.
+    let sniff_local_files = MimeSniffing { local_files: true, .. SNIFFING };
+    assert_eq!(sniffed_content_type_with("file:///tmp/thumbnail.png", html, &MimeOverrides::default(),
+                                         sniff_local_files),
+               "text/html");
+}
+
+fn session_cookies(resource_thread: &CoreResourceThread, session_id: SessionId, url: &ServoUrl)
+                   -> Option<String> {
+    let (sender, receiver) = ipc::channel().unwrap();
+    let msg = CoreResourceMsg::GetCookiesForUrl(url.clone(), sender, CookieSource::HTTP,
+                                                SameSiteContext::SameSite);
+    resource_thread.send(CoreResourceMsg::ForSession(session_id, Box::new(msg))).unwrap();
+    receiver.recv().unwrap()
+}
+
+#[test]
+fn test_private_sessions_do_not_share_cookies() {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let url = ServoUrl::parse("http://example.com/").unwrap();
+    let (first, second) = (SessionId(1), SessionId(2));
+    resource_thread.send(CoreResourceMsg::CreatePrivateSession(first)).unwrap();
+    resource_thread.send(CoreResourceMsg::CreatePrivateSession(second)).unwrap();
+
+    let msg = CoreResourceMsg::SetCookiesForUrl(url.clone(), "mozillaIs=theBest".to_owned(),
+                                                CookieSource::HTTP, Some(url.clone()));
+    resource_thread.send(CoreResourceMsg::ForSession(first, Box::new(msg))).unwrap();
+    assert_eq!(session_cookies(&resource_thread, first, &url), Some("mozillaIs=theBest".to_owned()));
+    assert_eq!(session_cookies(&resource_thread, second, &url), None);
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    private_resource_thread.send(CoreResourceMsg::GetCookiesForUrl(
+        url.clone(), sender, CookieSource::HTTP, SameSiteContext::SameSite)).unwrap();
+    assert_eq!(receiver.recv().unwrap(), None);
+
+    // A removed session's state is gone, even if a new session reuses its id.
+    resource_thread.send(CoreResourceMsg::RemovePrivateSession(first)).unwrap();
+    resource_thread.send(CoreResourceMsg::CreatePrivateSession(first)).unwrap();
+    assert_eq!(session_cookies(&resource_thread, first, &url), None);
+}
+
+#[test]
+fn test_initial_cookies_are_only_set_in_the_public_group() {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let url = ServoUrl::parse("http://example.com/").unwrap();
+    let cookie = cookie_rs::Cookie::parse("restored=yes").unwrap();
+    let (resource_thread, private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![(url.clone(), cookie)]);
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::GetCookiesForUrl(
+        url.clone(), sender, CookieSource::HTTP, SameSiteContext::SameSite)).unwrap();
+    assert_eq!(receiver.recv().unwrap(), Some("restored=yes".to_owned()));
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    private_resource_thread.send(CoreResourceMsg::GetCookiesForUrl(
+        url, sender, CookieSource::HTTP, SameSiteContext::SameSite)).unwrap();
+    assert_eq!(receiver.recv().unwrap(), None);
+}
+
+#[test]
+fn test_dumped_cookie_jar_can_be_restored() {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let url = ServoUrl::parse("http://example.com/").unwrap();
+    // Messages on different channels aren't handled in order, so each step waits for a reply.
+    let cookies = || {
+        let (sender, receiver) = ipc::channel().unwrap();
+        resource_thread.send(CoreResourceMsg::GetCookiesForUrl(
+            url.clone(), sender, CookieSource::HTTP, SameSiteContext::SameSite)).unwrap();
+        receiver.recv().unwrap()
+    };
+    let dump = || {
+        let (sender, receiver) = ipc::channel().unwrap();
+        control.send(CoreResourceControlMsg::DumpCookieJar(sender)).unwrap();
+        receiver.recv().unwrap()
+    };
+    resource_thread.send(CoreResourceMsg::SetCookiesForUrl(url.clone(), "backed=up".to_owned(),
+                                                           CookieSource::HTTP, Some(url.clone()))).unwrap();
+    assert_eq!(cookies(), Some("backed=up".to_owned()));
+    let backup = dump();
+
+    resource_thread.send(CoreResourceMsg::SetCookiesForUrl(url.clone(), "backed=over; max-age=0".to_owned(),
+                                                           CookieSource::HTTP, Some(url.clone()))).unwrap();
+    resource_thread.send(CoreResourceMsg::SetCookiesForUrl(url.clone(), "later=yes".to_owned(),
+                                                           CookieSource::HTTP, Some(url.clone()))).unwrap();
+    assert_eq!(cookies(), Some("later=yes".to_owned()));
+
+    control.send(CoreResourceControlMsg::RestoreCookieJar(backup.clone())).unwrap();
+    assert_eq!(dump(), backup);
+    assert_eq!(cookies(), Some("backed=up".to_owned()));
+
+    // A jar that can't be decoded leaves the current one alone.
+    control.send(CoreResourceControlMsg::RestoreCookieJar("not a cookie jar".to_owned())).unwrap();
+    assert_eq!(dump(), backup);
+    assert_eq!(cookies(), Some("backed=up".to_owned()));
+}
+
+#[test]
+fn test_script_channels_cannot_read_http_only_cookies_that_the_control_channel_dumps() {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_threads, private_resource_threads, control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let url = ServoUrl::parse("http://example.com/").unwrap();
+    resource_threads.send(CoreResourceMsg::SetCookiesForUrl(url.clone(), "secret=yes; HttpOnly".to_owned(),
+                                                            CookieSource::HTTP, Some(url.clone()))).unwrap();
+
+    // Whatever script asks for on the channels it is given, the HttpOnly cookie isn't there.
+    for threads in &[&resource_threads, &private_resource_threads] {
+        let (sender, receiver) = ipc::channel().unwrap();
+        threads.send(CoreResourceMsg::GetCookiesDataForUrl(url.clone(), sender, CookieSource::NonHTTP)).unwrap();
+        assert!(receiver.recv().unwrap().is_empty());
+    }
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    control.send(CoreResourceControlMsg::DumpCookieJar(sender)).unwrap();
+    assert!(receiver.recv().unwrap().contains("secret"));
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_threads.send(CoreResourceMsg::Exit(sender)).unwrap();
+    receiver.recv().unwrap();
+}
+
+#[test]
+fn test_removing_private_session_cancels_its_fetches() {
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        let mut response = response.start().unwrap();
+        for _ in 0..100 {
+            if response.write_all(&[0; 1024]).and_then(|_| response.flush()).is_err() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let _ = response.end();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let session_id = SessionId(1);
+    resource_thread.send(CoreResourceMsg::CreatePrivateSession(session_id)).unwrap();
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    let request = RequestInit {
+        url: url.clone(),
+        origin: url.clone(),
+        .. RequestInit::default()
+    };
+    let msg = CoreResourceMsg::Fetch(request, sender);
+    resource_thread.send(CoreResourceMsg::ForSession(session_id, Box::new(msg))).unwrap();
+    loop {
+        if let FetchResponseMsg::ProcessResponseChunk(_) = receiver.recv().unwrap() {
+            break;
+        }
+    }
+
+    resource_thread.send(CoreResourceMsg::RemovePrivateSession(session_id)).unwrap();
+    loop {
+        if let FetchResponseMsg::ProcessResponseEOF(result, _) = receiver.recv().unwrap() {
+            assert_eq!(result, Err(NetworkError::LoadCancelled));
+            break;
+        }
+    }
+    let _ = server.close();
+}
+
+/// Start a fetch for `pipeline_id` whose body trickles in, and check that `cancel`
+/// cancels it once the body has started arriving.
+fn assert_streaming_fetch_cancelled_by(pipeline_id: Option<PipelineId>, cancel: CoreResourceControlMsg) {
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        let mut response = response.start().unwrap();
+        for _ in 0..100 {
+            if response.write_all(&[0; 1024]).and_then(|_| response.flush()).is_err() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let _ = response.end();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let (sender, receiver) = ipc::channel().unwrap();
+    let request = RequestInit {
+        url: url.clone(),
+        origin: url.clone(),
+        pipeline_id: pipeline_id,
+        .. RequestInit::default()
+    };
+    resource_thread.send(CoreResourceMsg::Fetch(request, sender)).unwrap();
+
+    loop {
+        match receiver.recv().unwrap() {
+            FetchResponseMsg::ProcessResponseChunk(_) => break,
+            FetchResponseMsg::ProcessResponseEOF(_, _) => panic!("fetch finished before it was cancelled"),
+            _ => (),
+        }
+    }
+    control.send(cancel).unwrap();
+
+    loop {
+        match receiver.recv().unwrap() {
+            FetchResponseMsg::ProcessResponseEOF(result, _) => {
+                assert_eq!(result, Err(NetworkError::LoadCancelled));
+                break;
+            }
+            _ => (),
+        }
+    }
+    let _ = server.close();
+}
+
+#[test]
+fn test_cancel_all_for_pipeline() {
+    assert_streaming_fetch_cancelled_by(Some(TEST_PIPELINE_ID),
+                                        CoreResourceControlMsg::CancelAll(Some(TEST_PIPELINE_ID)));
+}
+
+#[test]
+fn test_cancel_all_without_pipeline_cancels_every_fetch() {
+    assert_streaming_fetch_cancelled_by(None, CoreResourceControlMsg::CancelAll(None));
+    assert_streaming_fetch_cancelled_by(Some(TEST_PIPELINE_ID), CoreResourceControlMsg::CancelAll(None));
+}
+
+#[test]
+fn test_fetches_beyond_per_origin_limit_are_queued_and_cancellable() {
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        let mut response = response.start().unwrap();
+        for _ in 0..100 {
+            if response.write_all(&[0; 1024]).and_then(|_| response.flush()).is_err() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let _ = response.end();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let mut receivers = vec![];
+    for id in 0..7 {
+        let (sender, receiver) = ipc::channel().unwrap();
+        let request = RequestInit {
+            url: url.clone(),
+            origin: url.clone(),
+            pipeline_id: Some(TEST_PIPELINE_ID),
+            resource_id: Some(ResourceId(id)),
+            .. RequestInit::default()
+        };
+        resource_thread.send(CoreResourceMsg::Fetch(request, sender)).unwrap();
+        receivers.push(receiver);
+    }
+
+    let (stats_sender, stats_receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::GetNetworkStats(stats_sender.clone(), false)).unwrap();
+    let stats = stats_receiver.recv().unwrap();
+    let origin = url.origin().ascii_serialization();
+    assert_eq!(stats.active_per_origin.get(&origin), Some(&6));
+    assert_eq!(stats.queued_per_origin.get(&origin), Some(&1));
+
+    // Cancelling the queued fetch removes it from the queue and ends it at once.
+    resource_thread.send(CoreResourceMsg::Cancel(ResourceId(6))).unwrap();
+    loop {
+        match receivers[6].recv().unwrap() {
+            FetchResponseMsg::ProcessResponseEOF(result, _) => {
+                assert_eq!(result, Err(NetworkError::LoadCancelled));
+                break;
+            }
+            FetchResponseMsg::ProcessResponseChunk(_) => panic!("queued fetch was started"),
+            _ => (),
+        }
+    }
+    resource_thread.send(CoreResourceMsg::GetNetworkStats(stats_sender, false)).unwrap();
+    let stats = stats_receiver.recv().unwrap();
+    assert_eq!(stats.queued_per_origin.get(&origin), Some(&0));
+
+    control.send(CoreResourceControlMsg::CancelAll(Some(TEST_PIPELINE_ID))).unwrap();
+    let _ = server.close();
+}
+
+/// Listen on a port of our own, passing each connection to `accepted` without answering it.
+fn unanswering_listener(accepted: Sender<TcpStream>) -> ServoUrl {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = ServoUrl::parse(&format!("http://127.0.0.1:{}/", listener.local_addr().unwrap().port())).unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if accepted.send(stream.unwrap()).is_err() {
+                return;
+            }
+        }
+    });
+    url
+}
+
+#[test]
+fn test_fetch_cancelled_while_waiting_for_a_worker_is_never_started() {
+    // The default `network.fetch.pool-size`.
+    const FETCH_WORKERS: u32 = 16;
+    const MAX_PER_ORIGIN: u32 = 6;
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let fetch = |url: &ServoUrl, id: u32| {
+        let (sender, receiver) = ipc::channel().unwrap();
+        let request = RequestInit {
+            url: url.clone(),
+            origin: url.clone(),
+            pipeline_id: Some(TEST_PIPELINE_ID),
+            resource_id: Some(ResourceId(id)),
+            .. RequestInit::default()
+        };
+        resource_thread.send(CoreResourceMsg::Fetch(request, sender)).unwrap();
+        receiver
+    };
+
+    // Keep every worker busy, over enough origins that none is held back by the per-origin limit.
+    let (accepted_sender, accepted) = channel();
+    let busy_urls: Vec<_> = (0..3).map(|_| unanswering_listener(accepted_sender.clone())).collect();
+    let _busy: Vec<_> = (0..FETCH_WORKERS).map(|id| fetch(&busy_urls[(id / MAX_PER_ORIGIN) as usize], id))
+                                          .collect();
+    let streams: Vec<TcpStream> = (0..FETCH_WORKERS).map(|_| accepted.recv().unwrap()).collect();
+
+    let (queued_accepted_sender, queued_accepted) = channel();
+    let queued = fetch(&unanswering_listener(queued_accepted_sender), FETCH_WORKERS);
+    resource_thread.send(CoreResourceMsg::Cancel(ResourceId(FETCH_WORKERS))).unwrap();
+
+    // Free the workers, so the cancelled fetch reaches the front of the queue.
+    for mut stream in streams {
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+    }
+    loop {
+        match queued.recv().unwrap() {
+            FetchResponseMsg::ProcessResponseEOF(result, _) => {
+                assert_eq!(result, Err(NetworkError::LoadCancelled));
+                break;
+            }
+            FetchResponseMsg::ProcessResponseChunk(_) => panic!("cancelled fetch was started"),
+            _ => (),
+        }
+    }
+    assert!(queued_accepted.try_recv().is_err());
+}
+
+#[test]
+fn test_network_stats_count_traffic_per_group_until_reset() {
+    let (mut server, url) = make_server(send_yay);
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let request = RequestInit {
+        url: url.clone(),
+        origin: url.clone(),
+        pipeline_id: Some(TEST_PIPELINE_ID),
+        .. RequestInit::default()
+    };
+    assert!(fetch_metadata(&resource_thread, request).is_ok());
+    let _ = server.close();
+
+    let (stats_sender, stats_receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::GetNetworkStats(stats_sender.clone(), true)).unwrap();
+    let stats = stats_receiver.recv().unwrap();
+    let public = stats.per_group["public"];
+    assert_eq!(public.requests_completed, 1);
+    assert_eq!(public.requests_failed, 0);
+    assert_eq!(public.bytes_received, 4);
+    assert_eq!(public.active_fetches, 0);
+    assert_eq!(stats.per_group["private"], NetStatsSnapshot::default());
+
+    resource_thread.send(CoreResourceMsg::GetNetworkStats(stats_sender, false)).unwrap();
+    let stats = stats_receiver.recv().unwrap();
+    assert_eq!(stats.per_group["public"].requests_completed, 0);
+    assert_eq!(stats.per_group["public"].bytes_received, 0);
+}
+
+#[test]
+fn test_network_stats_count_evicted_cookies() {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let url = ServoUrl::parse("http://example.com/").unwrap();
+    for i in 0..151 {
+        resource_thread.send(CoreResourceMsg::SetCookiesForUrl(url.clone(), format!("cookie{}=1", i),
+                                                               CookieSource::HTTP, None)).unwrap();
+    }
+
+    let (stats_sender, stats_receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::GetNetworkStats(stats_sender, false)).unwrap();
+    let stats = stats_receiver.recv().unwrap();
+    assert_eq!(stats.per_group["public"].cookies_evicted, 1);
+}
+
+#[test]
+fn test_detailed_cookies_carry_the_derived_attributes() {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let url = ServoUrl::parse("http://www.example.com/").unwrap();
+    for cookie in &["lasting=1; Max-Age=3600; SameSite=Strict", "session=2; Domain=example.com",
+                    "odd=3; SameSite=Sometimes"] {
+        resource_thread.send(CoreResourceMsg::SetCookiesForUrl(url.clone(), cookie.to_string(),
+                                                               CookieSource::HTTP, None)).unwrap();
+    }
+
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::GetCookiesDetailedForUrl(url, sender, CookieSource::HTTP)).unwrap();
+    let mut cookies = receiver.recv().unwrap();
+    cookies.sort_by(|a, b| a.cookie.name.cmp(&b.cookie.name));
+    let now = time::get_time().sec;
+
+    let lasting = &cookies[0];
+    assert_eq!(lasting.cookie.name, "lasting");
+    let expires_in = lasting.expires_at.unwrap() - now;
+    assert!(expires_in > 3500 && expires_in <= 3600);
+    assert_eq!(lasting.same_site, SameSite::Strict);
+    assert!(!lasting.session);
+    assert!(lasting.host_only);
+
+    let odd = &cookies[1];
+    assert_eq!(odd.cookie.name, "odd");
+    assert_eq!(odd.same_site, SameSite::Lax);
+
+    let session = &cookies[2];
+    assert_eq!(session.cookie.name, "session");
+    assert_eq!(session.expires_at, None);
+    assert!(session.session);
+    assert!(!session.host_only);
+    assert!(session.created_at <= now && session.last_accessed_at >= session.created_at);
+}
+
+#[test]
+fn test_set_user_agent_applies_to_later_fetches() {
+    let handler = move |request: HyperRequest, response: HyperResponse| {
+        let user_agent = request.headers.get::<UserAgent>().unwrap().0.clone();
+        response.send(user_agent.as_bytes()).unwrap();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, private_resource_thread, _control) = new_core_resource_thread(
+        "Servo".into(), None, ProfilerChan(tx), None, None, vec![]);
+    resource_thread.send(CoreResourceMsg::SetUserAgent("Servo (Desktop)".into())).unwrap();
+
+    let fetch_body = |resource_thread: &CoreResourceThread| {
+        let (sender, receiver) = ipc::channel().unwrap();
+        let request = RequestInit {
+            url: url.clone(),
+            origin: url.clone(),
+            pipeline_id: Some(TEST_PIPELINE_ID),
+            .. RequestInit::default()
+        };
+        resource_thread.send(CoreResourceMsg::Fetch(request, sender)).unwrap();
+        let mut body = vec![];
+        loop {
+            match receiver.recv().unwrap() {
+                FetchResponseMsg::ProcessResponseChunk(chunk) => body.extend_from_slice(&chunk),
+                FetchResponseMsg::ProcessResponseEOF(_, _) => break,
+                _ => (),
+            }
+        }
+        String::from_utf8(body).unwrap()
+    };
+    assert_eq!(fetch_body(&resource_thread), "Servo (Desktop)");
+    // The private group keeps its own user agent.
+    assert_eq!(fetch_body(&private_resource_thread), "Servo");
+    let _ = server.close();
+}
+
+#[test]
+fn test_set_accept_language_applies_to_later_fetches() {
+    let handler = move |request: HyperRequest, response: HyperResponse| {
+        let accept_language = request.headers.get::<AcceptLanguage>().map_or(String::new(), |h| h.to_string());
+        response.send(accept_language.as_bytes()).unwrap();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, private_resource_thread, _control) = new_core_resource_thread(
+        "Servo".into(), None, ProfilerChan(tx), None, None, vec![]);
+    resource_thread.send(CoreResourceMsg::SetAcceptLanguage("fr-CA, fr".to_owned())).unwrap();
+
+    let fetch_body_in = |resource_thread: &CoreResourceThread, accept_language: Option<&str>| {
+        let (sender, receiver) = ipc::channel().unwrap();
+        let request = RequestInit {
+            url: url.clone(),
+            origin: url.clone(),
+            pipeline_id: Some(TEST_PIPELINE_ID),
+            accept_language: accept_language.map(str::to_owned),
+            .. RequestInit::default()
+        };
+        resource_thread.send(CoreResourceMsg::Fetch(request, sender)).unwrap();
+        let mut body = vec![];
+        loop {
+            match receiver.recv().unwrap() {
+                FetchResponseMsg::ProcessResponseChunk(chunk) => body.extend_from_slice(&chunk),
+                FetchResponseMsg::ProcessResponseEOF(_, _) => break,
+                _ => (),
+            }
+        }
+        String::from_utf8(body).unwrap()
+    };
+    let fetch_body = |resource_thread: &CoreResourceThread| fetch_body_in(resource_thread, None);
+    assert_eq!(fetch_body(&resource_thread), accept_language_header("fr-CA, fr").unwrap().to_string());
+    // Without the pref, the private group sends no `Accept-Language` at all.
+    assert_eq!(fetch_body(&private_resource_thread), "");
+    // A request's own languages replace the group's, or ask for none.
+    assert_eq!(fetch_body_in(&resource_thread, Some("de")), accept_language_header("de").unwrap().to_string());
+    assert_eq!(fetch_body_in(&resource_thread, Some("")), "");
+    assert_eq!(fetch_body_in(&private_resource_thread, Some("de")),
+               accept_language_header("de").unwrap().to_string());
+
+    resource_thread.send(CoreResourceMsg::SetAcceptLanguage(String::new())).unwrap();
+    assert_eq!(fetch_body(&resource_thread), "");
+    let _ = server.close();
+}
+
+#[test]
+fn test_registered_scheme_handler_answers_fetches_of_its_scheme() {
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        response.send(b"Yay!").unwrap();
+    };
+    let (mut server, http_url) = make_server(handler);
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _, control) = new_core_resource_thread(
+        "Servo".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let register = |scheme: &str, body: &'static [u8]| {
+        let (handler, requests) = ipc::channel::<SchemeRequest>().unwrap();
+        thread::spawn(move || {
+            while let Ok(request) = requests.recv() {
+                let response = CustomResponse::new(Headers::new(), RawStatus(200, "OK".into()), body.to_vec());
+                let _ = request.response_chan.send(Some(response));
+            }
+        });
+        control.send(CoreResourceControlMsg::RegisterSchemeHandler {
+            scheme: scheme.to_owned(),
+            handler: handler,
+        }).unwrap();
+        let (sender, receiver) = ipc::channel().unwrap();
+        control.send(CoreResourceControlMsg::Synchronize(sender)).unwrap();
+        receiver.recv().unwrap();
+    };
+    let fetch_body = |url: &ServoUrl| {
+        let (sender, receiver) = ipc::channel().unwrap();
+        let request = RequestInit {
+            url: url.clone(),
+            origin: url.clone(),
+            mode: RequestMode::Navigate,
+            destination: Destination::Document,
+            .. RequestInit::default()
+        };
+        resource_thread.send(CoreResourceMsg::Fetch(request, sender)).unwrap();
+        let mut body = vec![];
+        loop {
+            match receiver.recv().unwrap() {
+                FetchResponseMsg::ProcessResponseChunk(chunk) => body.extend_from_slice(&chunk),
+                FetchResponseMsg::ProcessResponseEOF(_, _) => break,
+                _ => (),
+            }
+        }
+        String::from_utf8(body).unwrap()
+    };
+
+    let app_url = ServoUrl::parse("app://servo/index.html").unwrap();
+    register("app", b"first");
+    assert_eq!(fetch_body(&app_url), "first");
+    // A handler registered again replaces the one before.
+    register("APP", b"second");
+    assert_eq!(fetch_body(&app_url), "second");
+    // Built-in schemes can't be taken over.
+    register("http", b"hijacked");
+    assert_eq!(fetch_body(&http_url), "Yay!");
+
+    let _ = server.close();
+}
+
+#[test]
+fn test_end_of_response_carries_when_the_body_arrived() {
+    let handler = move |_: HyperRequest, response: HyperResponse| {
+        let mut response = response.start().unwrap();
+        response.write_all(b"Y").unwrap();
+        response.flush().unwrap();
+        thread::sleep(Duration::from_millis(50));
+        response.write_all(b"ay!").unwrap();
+        let _ = response.end();
+    };
+    let (mut server, url) = make_server(handler);
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let (sender, receiver) = ipc::channel().unwrap();
+    let request = RequestInit {
+        url: url.clone(),
+        origin: url.clone(),
+        .. RequestInit::default()
+    };
+    resource_thread.send(CoreResourceMsg::Fetch(request, sender)).unwrap();
+    let mut head_timing = None;
+    let eof_timing;
+    loop {
+        match receiver.recv().unwrap() {
+            FetchResponseMsg::ProcessResponse(Ok(metadata)) => {
+                head_timing = Some(match metadata {
+                    FetchMetadata::Unfiltered(metadata) => metadata.timing,
+                    FetchMetadata::Filtered { unsafe_, .. } => unsafe_.timing,
+                });
+            }
+            FetchResponseMsg::ProcessResponseEOF(result, timing) => {
+                assert_eq!(result, Ok(()));
+                eof_timing = timing;
+                break;
+            }
+            _ => (),
+        }
+    }
+    let head_timing = head_timing.unwrap();
+    let _ = server.close();
+
+    // The body hadn't arrived when the head was passed on.
+    assert_eq!(head_timing.response_end, None);
+    assert_eq!(eof_timing.first_byte, head_timing.first_byte);
+    assert!(eof_timing.response_end.unwrap() >= eof_timing.first_byte.unwrap() + 50 * 1000 * 1000);
+}
+
+fn fetch_metadata(resource_thread: &CoreResourceThread, request: RequestInit)
+                  -> Result<FetchMetadata, NetworkError> {
+    let (sender, receiver) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::Fetch(request, sender)).unwrap();
+    let mut metadata = None;
+    loop {
+        match receiver.recv().unwrap() {
+            FetchResponseMsg::ProcessResponse(result) => metadata = Some(result),
+            FetchResponseMsg::ProcessResponseEOF(_, _) => return metadata.unwrap(),
+            _ => (),
+        }
+    }
+}
+
+fn send_yay(_: HyperRequest, response: HyperResponse) {
+    response.send(b"Yay!").unwrap();
+}
+
+/// Start a resource thread, and load a document from `url` in `TEST_PIPELINE_ID`.
+fn resource_thread_with_document(url: &ServoUrl) -> (CoreResourceThread, CoreResourceControlThread) {
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let navigation = RequestInit {
+        url: url.clone(),
+        origin: url.clone(),
+        destination: Destination::Document,
+        pipeline_id: Some(TEST_PIPELINE_ID),
+        .. RequestInit::default()
+    };
+    assert!(fetch_metadata(&resource_thread, navigation).is_ok());
+    (resource_thread, control)
+}
+
+#[test]
+fn test_same_origin_fetch_from_pipeline_is_allowed() {
+    let (mut server, url) = make_server(send_yay);
+
+    let (resource_thread, _control) = resource_thread_with_document(&url);
+    let request = RequestInit {
+        url: url.clone(),
+        origin: url.clone(),
+        destination: Destination::Image,
+        mode: RequestMode::SameOrigin,
+        pipeline_id: Some(TEST_PIPELINE_ID),
+        .. RequestInit::default()
+    };
+    assert!(fetch_metadata(&resource_thread, request).is_ok());
+    let _ = server.close();
+}
+
+#[test]
+fn test_cross_origin_no_cors_fetch_from_pipeline_is_opaque() {
+    let (mut server, url) = make_server(send_yay);
+    let (mut other_server, other_url) = make_server(send_yay);
+
+    let (resource_thread, _control) = resource_thread_with_document(&url);
+    let request = RequestInit {
+        url: other_url.clone(),
+        origin: url.clone(),
+        destination: Destination::Image,
+        mode: RequestMode::NoCors,
+        pipeline_id: Some(TEST_PIPELINE_ID),
+        .. RequestInit::default()
+    };
+    match fetch_metadata(&resource_thread, request) {
+        Ok(FetchMetadata::Filtered { filtered: FilteredMetadata::Opaque, .. }) => (),
+        _ => panic!("cross-origin no-cors response should be opaque"),
+    }
+    let _ = server.close();
+    let _ = other_server.close();
+}
+
+#[test]
+fn test_fetch_with_origin_other_than_pipeline_is_rejected() {
+    let (mut server, url) = make_server(send_yay);
+    let (mut other_server, other_url) = make_server(send_yay);
+
+    let (resource_thread, _control) = resource_thread_with_document(&url);
+    let request = RequestInit {
+        url: other_url.clone(),
+        origin: other_url.clone(),
+        destination: Destination::Image,
+        mode: RequestMode::SameOrigin,
+        pipeline_id: Some(TEST_PIPELINE_ID),
+        .. RequestInit::default()
+    };
+    assert!(fetch_metadata(&resource_thread, request).is_err());
+    let _ = server.close();
+    let _ = other_server.close();
+}
+
+#[test]
+fn test_fetch_from_pipeline_that_inherits_origin_is_checked() {
+    let (mut server, url) = make_server(send_yay);
+    let (mut other_server, other_url) = make_server(send_yay);
+
+    let (resource_thread, control) = resource_thread_with_document(&url);
+    let about_blank_id = PipelineId { namespace_id: TEST_NAMESPACE, index: PipelineIndex(1) };
+    control.send(CoreResourceControlMsg::InheritPipelineOrigin(about_blank_id, TEST_PIPELINE_ID)).unwrap();
+    synchronize(&control);
+    let request_with_origin = |origin: &ServoUrl| RequestInit {
+        url: url.clone(),
+        origin: origin.clone(),
+        destination: Destination::Image,
+        mode: RequestMode::SameOrigin,
+        pipeline_id: Some(about_blank_id),
+        .. RequestInit::default()
+    };
+    assert!(fetch_metadata(&resource_thread, request_with_origin(&url)).is_ok());
+    assert!(fetch_metadata(&resource_thread, request_with_origin(&other_url)).is_err());
+    let _ = server.close();
+    let _ = other_server.close();
+}
+
+fn synchronize(control: &CoreResourceControlThread) {
+    let (sender, receiver) = ipc::channel().unwrap();
+    control.send(CoreResourceControlMsg::Synchronize(sender)).unwrap();
+    receiver.recv().unwrap();
+}
+
+#[test]
+fn test_inheriting_an_origin_never_replaces_a_recorded_one() {
+    let (mut server, url) = make_server(send_yay);
+    let (mut other_server, other_url) = make_server(send_yay);
+
+    // The document of `other_pipeline_id` is from another origin, which shouldn't be
+    // taken by the document in `TEST_PIPELINE_ID`.
+    let (resource_thread, control) = resource_thread_with_document(&url);
+    let other_pipeline_id = PipelineId { namespace_id: TEST_NAMESPACE, index: PipelineIndex(2) };
+    let navigation = RequestInit {
+        url: other_url.clone(),
+        origin: other_url.clone(),
+        destination: Destination::Document,
+        pipeline_id: Some(other_pipeline_id),
+        .. RequestInit::default()
+    };
+    assert!(fetch_metadata(&resource_thread, navigation).is_ok());
+    control.send(CoreResourceControlMsg::InheritPipelineOrigin(TEST_PIPELINE_ID, other_pipeline_id)).unwrap();
+    synchronize(&control);
+
+    let request_with_origin = |origin: &ServoUrl| RequestInit {
+        url: url.clone(),
+        origin: origin.clone(),
+        destination: Destination::Image,
+        mode: RequestMode::SameOrigin,
+        pipeline_id: Some(TEST_PIPELINE_ID),
+        .. RequestInit::default()
+    };
+    assert!(fetch_metadata(&resource_thread, request_with_origin(&url)).is_ok());
+    assert!(fetch_metadata(&resource_thread, request_with_origin(&other_url)).is_err());
+    let _ = server.close();
+    let _ = other_server.close();
+}
+
+#[test]
+fn test_navigate_mode_for_subresource_is_rejected() {
+    let (mut server, url) = make_server(send_yay);
+    let (mut other_server, other_url) = make_server(send_yay);
+
+    let (resource_thread, _control) = resource_thread_with_document(&url);
+    let request = RequestInit {
+        url: other_url.clone(),
+        origin: url.clone(),
+        destination: Destination::Image,
+        mode: RequestMode::Navigate,
+        pipeline_id: Some(TEST_PIPELINE_ID),
+        .. RequestInit::default()
+    };
+    assert!(fetch_metadata(&resource_thread, request).is_err());
+    let _ = server.close();
+    let _ = other_server.close();
+}
+
+#[test]
+fn test_fetch_uses_preconnected_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = ServoUrl::parse(&format!("http://127.0.0.1:{}/", listener.local_addr().unwrap().port())).unwrap();
+    let (accepted_sender, accepted) = channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            accepted_sender.send(()).unwrap();
+            thread::spawn(move || {
+                let mut request = vec![];
+                let mut byte = [0];
+                while !request.ends_with(b"\r\n\r\n") {
+                    if stream.read_exact(&mut byte).is_err() {
+                        return;
+                    }
+                    request.push(byte[0]);
+                }
+                stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nYay!").unwrap();
+            });
+        }
+    });
+
+    let (tx, _rx) = ipc::channel().unwrap();
+    let (resource_thread, _private_resource_thread, _control) = new_core_resource_thread(
+        "".into(), None, ProfilerChan(tx), None, None, vec![]);
+    let (done_sender, done) = ipc::channel().unwrap();
+    resource_thread.send(CoreResourceMsg::Preconnect(url.clone(), Some(done_sender))).unwrap();
+    done.recv().unwrap();
+    accepted.recv().unwrap();
+
+    let request = RequestInit {
+        url: url.clone(),
+        origin: url.clone(),
+        pipeline_id: Some(TEST_PIPELINE_ID),
+        .. RequestInit::default()
+    };
+    assert!(fetch_metadata(&resource_thread, request).is_ok());
+    assert!(accepted.try_recv().is_err());
+}
+
 #[test]
 fn test_parse_hostsfile() {
     let mock_hosts_file_content = "127.0.0.1 foo.bar.com\n127.0.0.2 servo.test.server";
@@ -0,0 +1,87 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use hyper::method::Method;
+use hyper::server::{Request as HyperRequest, Response as HyperResponse};
+use net::fetch::methods::fetch;
+use net::test::{SessionStore, TlsSessionCache};
+use net_traits::request::{Request, RequestInit};
+use std::rc::Rc;
+use std::sync::Arc;
+use {make_tls_server, new_fetch_context, tls_connection_pools};
+
+#[test]
+fn test_session_store_drops_the_sessions_stored_longest_ago() {
+    let mut store = SessionStore::new(2);
+    store.insert("a.example", 443, "a");
+    store.insert("b.example", 443, "b");
+    // Storing a new session for a server makes it the most recent one.
+    store.insert("a.example", 443, "a2");
+    store.insert("c.example", 443, "c");
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.get("a.example", 443), Some(&"a2"));
+    assert_eq!(store.get("b.example", 443), None);
+    assert_eq!(store.get("c.example", 443), Some(&"c"));
+}
+
+#[test]
+fn test_session_store_keeps_a_session_per_host_and_port() {
+    let mut store = SessionStore::new(10);
+    store.insert("Servo.Example", 443, 1);
+    store.insert("servo.example", 8443, 2);
+    store.insert("servo.example.com", 443, 3);
+    assert_eq!(store.get("servo.example", 443), Some(&1));
+    assert_eq!(store.get("servo.example", 8443), Some(&2));
+
+    store.clear_host("SERVO.example");
+    assert_eq!(store.get("servo.example", 443), None);
+    assert_eq!(store.get("servo.example", 8443), None);
+    assert_eq!(store.get("servo.example.com", 443), Some(&3));
+
+    store.clear();
+    assert_eq!(store.len(), 0);
+}
+
+#[test]
+fn test_session_store_of_no_capacity_keeps_nothing() {
+    let mut store = SessionStore::new(0);
+    store.insert("servo.example", 443, ());
+    assert_eq!(store.get("servo.example", 443), None);
+}
+
+#[test]
+fn test_new_connection_resumes_the_last_tls_session() {
+    let handler = |_: HyperRequest, response: HyperResponse| {
+        response.send(b"Yay!").unwrap();
+    };
+    let (mut server, url) = make_tls_server(handler);
+    let mut context = new_fetch_context(None);
+    let tls_sessions = Arc::new(TlsSessionCache::new(context.net_stats.clone()));
+    let pools = tls_connection_pools(&[url.port().unwrap()]).with_tls_session_cache(tls_sessions.clone());
+    context.state.connection_pools = Arc::new(pools);
+
+    let fetch_on_new_connection = || {
+        let request = Request::from_init(RequestInit {
+            url: url.clone(),
+            method: Method::Get,
+            origin: url.clone(),
+            .. RequestInit::default()
+        });
+        let response = fetch(Rc::new(request), &mut None, &context);
+        assert!(!response.is_network_error());
+        // The idle connection is closed, so the next fetch makes a new one.
+        context.state.connection_pools.clear();
+        let stats = context.net_stats.snapshot();
+        (stats.tls_full_handshakes, stats.tls_sessions_resumed)
+    };
+    assert_eq!(fetch_on_new_connection(), (1, 0));
+    assert_eq!(tls_sessions.len(), 1);
+    assert_eq!(fetch_on_new_connection(), (1, 1));
+
+    // Once the sessions with the host are forgotten, the next handshake is a full one.
+    context.state.connection_pools.clear_tls_sessions("localhost");
+    assert_eq!(tls_sessions.len(), 0);
+    assert_eq!(fetch_on_new_connection(), (2, 1));
+    let _ = server.close();
+}
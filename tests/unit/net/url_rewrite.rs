@@ -0,0 +1,66 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use net::url_rewrite::UrlRewriter;
+use net_traits::{RewriteAction, RewriteRule};
+use servo_url::ServoUrl;
+
+fn rewrite(rules: Vec<RewriteRule>, url: &str) -> Option<String> {
+    let url = ServoUrl::parse(url).unwrap();
+    UrlRewriter::new(rules).rewrite(&url).map(|url| url.as_str().to_owned())
+}
+
+fn strip_tracking_params(host: &str) -> RewriteRule {
+    RewriteRule {
+        host: host.to_owned(),
+        action: RewriteAction::RemoveQueryParams(vec!["utm_*".to_owned(), "fbclid".to_owned()]),
+    }
+}
+
+#[test]
+fn test_remove_query_params_keeps_other_params() {
+    assert_eq!(rewrite(vec![strip_tracking_params("*")],
+                       "http://example.com/?a=1&utm_source=x&fbclid=y&utm_medium=z"),
+               Some("http://example.com/?a=1".to_owned()));
+}
+
+#[test]
+fn test_remove_every_query_param_drops_query() {
+    assert_eq!(rewrite(vec![strip_tracking_params("*")], "http://example.com/path?fbclid=y"),
+               Some("http://example.com/path".to_owned()));
+}
+
+#[test]
+fn test_unmatched_url_is_not_rewritten() {
+    assert_eq!(rewrite(vec![strip_tracking_params("*")], "http://example.com/?a=1"), None);
+    assert_eq!(rewrite(vec![strip_tracking_params("example.org")], "http://example.com/?fbclid=y"), None);
+}
+
+#[test]
+fn test_wildcard_host_pattern_matches_domain_and_subdomains() {
+    let rules = vec![strip_tracking_params("*.example.com")];
+    assert!(rewrite(rules.clone(), "http://example.com/?fbclid=y").is_some());
+    assert!(rewrite(rules.clone(), "http://www.example.com/?fbclid=y").is_some());
+    assert!(rewrite(rules, "http://notexample.com/?fbclid=y").is_none());
+}
+
+#[test]
+fn test_regex_replace() {
+    let rules = vec![RewriteRule {
+        host: "example.com".to_owned(),
+        action: RewriteAction::RegexReplace("/amp/(.*)$".to_owned(), "/$1".to_owned()),
+    }];
+    assert_eq!(rewrite(rules, "http://example.com/amp/article"),
+               Some("http://example.com/article".to_owned()));
+}
+
+#[test]
+fn test_invalid_regex_rule_is_ignored() {
+    let rules = vec![RewriteRule {
+        host: "*".to_owned(),
+        action: RewriteAction::RegexReplace("(".to_owned(), "".to_owned()),
+    }, strip_tracking_params("*")];
+    assert_eq!(rewrite(rules, "http://example.com/?fbclid=y"),
+               Some("http://example.com/".to_owned()));
+}
@@ -0,0 +1,52 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use net::test::certificate_details;
+
+/// A certificate for `intranet.example`, issued by a test CA.
+const LEAF_CERTIFICATE: &'static [&'static str] = &[
+    "3082019f30820146a003020102020102300a06082a8648ce3d0403023032",
+    "31163014060355040a0c0d536572766f2054657374204341311830160603",
+    "5504030c0f536572766f205465737420526f6f74301e170d323631303136",
+    "3033333033375a170d3236313131353033333033375a303d310b30090603",
+    "5504061302555331133011060355040a0c0a536572766f20546573743119",
+    "301706035504030c10696e7472616e65742e6578616d706c653059301306",
+    "072a8648ce3d020106082a8648ce3d03010703420004b4e0979796e8c88e",
+    "5370dd3b0cc6a22df4dc94ac0a5a4bb265e40d300908eb0c2ed720573da4",
+    "2c86ca4a989c329a2f2bab54a3389f0c4ce55544ed0f72fd4fc9a3423040",
+    "301d0603551d0e0416041456e528a596cbc2e73653f5b6b22b2a4977c794",
+    "e4301f0603551d23041830168014c0dc519eeb72396fd1b42ea53ef8676d",
+    "5f2ef1bb300a06082a8648ce3d0403020347003044022011660cb6a2a1f0",
+    "d716e08f156df9a683abb2937e4633d375c469efbb6e0ca19b022079b325",
+    "92d95bc0ba770b862b811582476bab22ca782dc357520ec90ae5da3c4c",
+];
+
+fn from_hex(lines: &[&str]) -> Vec<u8> {
+    let hex: String = lines.concat();
+    (0..hex.len() / 2).map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap()).collect()
+}
+
+#[test]
+fn test_certificate_details_describe_the_subject_issuer_and_validity() {
+    let der = from_hex(LEAF_CERTIFICATE);
+    let details = certificate_details(der.clone());
+    assert_eq!(details.der, der);
+    assert_eq!(details.subject, "C=US, O=Servo Test, CN=intranet.example");
+    assert_eq!(details.issuer, "O=Servo Test CA, CN=Servo Test Root");
+    assert_eq!(details.not_before, "2026-10-16T03:30:37Z");
+    assert_eq!(details.not_after, "2026-11-15T03:30:37Z");
+    assert_eq!(details.fingerprint,
+               from_hex(&["e1261e676159906a0b74f04d370abbff74dcdd3f191176ce85b0c034953f4366"]));
+}
+
+#[test]
+fn test_certificate_details_of_malformed_der_are_empty() {
+    let mut der = from_hex(LEAF_CERTIFICATE);
+    der.truncate(40);
+    let details = certificate_details(der);
+    assert_eq!(details.subject, "");
+    assert_eq!(details.issuer, "");
+    assert_eq!(details.not_before, "");
+    assert_eq!(details.not_after, "");
+}
@@ -0,0 +1,39 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use script::dom::htmltimeelement::parse_duration;
+
+const MINUTE: u64 = 60 * 1000;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+#[test]
+fn test_parse_iso_duration() {
+    assert_eq!(parse_duration("PT4H30M"), Some(4 * HOUR + 30 * MINUTE));
+    assert_eq!(parse_duration("P2D"), Some(2 * DAY));
+    assert_eq!(parse_duration("P1DT1.5S"), Some(DAY + 1500));
+    assert_eq!(parse_duration("pt90m"), Some(90 * MINUTE));
+    assert_eq!(parse_duration("PT1H1S"), Some(HOUR + 1000));
+}
+
+#[test]
+fn test_parse_informal_duration() {
+    assert_eq!(parse_duration("1h90m"), Some(2 * HOUR + 30 * MINUTE));
+    assert_eq!(parse_duration(" 2w 1d 3 s "), Some(15 * DAY + 3000));
+    assert_eq!(parse_duration("4s 1m"), Some(MINUTE + 4000));
+    assert_eq!(parse_duration("0.25s"), Some(250));
+}
+
+#[test]
+fn test_parse_invalid_duration() {
+    for value in &["", "P", "PT", "P1W", "P1M", "PT1S1M", "PT 1H", "1.5h", "1s 1s", "1x", "h", "5", "1.2345s",
+                   "1..2s", "2016-10-16"] {
+        assert_eq!(parse_duration(value), None, "{:?} is not a valid duration", value);
+    }
+}
+
+#[test]
+fn test_parse_duration_overflow() {
+    assert_eq!(parse_duration("99999999999999999999w"), None);
+}
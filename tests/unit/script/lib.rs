@@ -13,3 +13,4 @@ extern crate servo_url;
 #[cfg(all(test, target_pointer_width = "64"))] mod size_of;
 #[cfg(test)] mod textinput;
 #[cfg(test)] mod headers;
+#[cfg(test)] mod htmltimeelement;